@@ -0,0 +1,889 @@
+use crate::prelude::*;
+use crate::{
+    calculate_amount, check_total, check_tx_state, reverse, Account, Adjustment, ClientId, Error,
+    FundTransaction, Funds, Operation, Price4, ReasonCode, Receivable, Role, Side, Transaction,
+    TransactionId, TransactionProcessor, TransactionState,
+};
+use core::num::NonZeroU32;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// Funds moved from the available to the held funds by an administrator,
+/// independent of any dispute, e.g. for a legal hold or a garnishment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManualHold {
+    pub amount: Price4,
+    pub reason: ReasonCode,
+    /// Whether the funds were released back to the available funds.
+    pub released: bool,
+}
+
+/// Identifies a manual hold by its client and its number among the client's holds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+pub struct HoldId {
+    pub client_id: ClientId,
+    pub number: u32,
+}
+
+/// Unfreezes a frozen account once deposits bring its total funds back to a
+/// threshold, e.g. after the client repaid a chargeback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct UnfreezePolicy {
+    /// The total funds at or above which the account is unfrozen, zero by default.
+    pub threshold: Price4,
+}
+
+/// When a chargeback freezes the client's account.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FreezePolicy {
+    /// Every chargeback freezes the account.
+    #[default]
+    Always,
+    /// Chargebacks reverse the funds without freezing the account, e.g. for
+    /// low-risk internal accounts.
+    Never,
+    /// The client's chargeback that reaches this count freezes the account, and
+    /// so do the ones after it.
+    After(NonZeroU32),
+}
+
+impl FreezePolicy {
+    /// Whether a chargeback freezes an account once the client has had
+    /// `chargebacks` of them, including this one.
+    pub(crate) fn freezes(&self, chargebacks: u32) -> bool {
+        match self {
+            FreezePolicy::Always => true,
+            FreezePolicy::Never => false,
+            FreezePolicy::After(count) => chargebacks >= count.get(),
+        }
+    }
+}
+
+impl FromStr for FreezePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<FreezePolicy, String> {
+        match s {
+            "always" => Ok(FreezePolicy::Always),
+            "never" => Ok(FreezePolicy::Never),
+            _ => s.parse().map(FreezePolicy::After).map_err(|_| {
+                format!(
+                    "expected `always`, `never` or a number of chargebacks, got `{}`",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// An administrative operation applied to an account, or an automatic change
+/// such as the excess of a capped deposit or an unfreeze, for the audit log.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AuditEntry {
+    /// The client, unless the entry is about the processor as a whole, e.g. a
+    /// reloaded policy.
+    #[serde(rename = "client")]
+    pub client_id: Option<ClientId>,
+    /// The transaction, for operations on transactions.
+    #[serde(rename = "tx")]
+    pub tx_id: Option<TransactionId>,
+    /// The number of the manual hold, for operations on holds.
+    pub hold: Option<u32>,
+    /// The operation, e.g. `adjustment`, `cap` for a capped deposit, `shortfall`
+    /// for a capped dispute hold, `freeze`, `unfreeze`, `reactivate`, `merge`,
+    /// `dispute_limit` for an account flagged or frozen by a dispute that took it
+    /// over a limit, `unauthorized` for an operation a role is not permitted, or
+    /// `closed_period` for a rejected dispute or reversal of a closed period's
+    /// transaction.
+    pub action: &'static str,
+    /// The change of the available funds, negative if they were decreased.
+    pub amount: Price4,
+    pub reason: Option<ReasonCode>,
+    /// The role an `unauthorized` operation was attempted by.
+    pub role: Option<Role>,
+    /// The operation that was not permitted, for `unauthorized` and
+    /// `closed_period` entries.
+    pub operation: Option<Operation>,
+    /// The tag of the transaction, if it has one.
+    pub tag: Option<String>,
+    /// The client's available funds after the operation, so consumers can follow
+    /// an account's balances without replaying its transactions.
+    pub available: Option<Price4>,
+    /// The client's held funds after the operation, like `available`.
+    pub held: Option<Price4>,
+}
+
+impl TransactionProcessor {
+    /// Credits or debits the amount of `adjustment` to the client's available
+    /// funds as the transaction `tx_id`, and records it in the audit log.
+    ///
+    /// This is an administrative operation: it is not checked by the validators,
+    /// so it applies to frozen accounts and may leave the available balance
+    /// negative. The adjustment cannot be disputed.
+    /// Returns an error if the transaction id is already used from another
+    /// transaction (`Error::DuplicateTx` if that was the same adjustment).
+    ///
+    /// This function does not panic.
+    pub fn process_adjustment(&mut self, adjustment: Adjustment) -> Result<(), Error> {
+        let (client_id, tx_id) = (adjustment.client_id, adjustment.tx_id);
+        let (side, amount) = match adjustment.amount < Price4::ZERO {
+            true => (Side::Withdrawal, -adjustment.amount),
+            false => (Side::Deposit, adjustment.amount),
+        };
+        let period = self.periods.open();
+        let account = self.account_mut(client_id);
+        if let Some(existing_tx) = account.txs.get(tx_id) {
+            if existing_tx.state == TransactionState::Adjusted
+                && existing_tx.side == side
+                && existing_tx.amount == amount
+            {
+                return Err(Error::DuplicateTx(tx_id));
+            }
+            return Err(Error::InvalidTx(tx_id));
+        }
+        let available_funds = calculate_amount(account.funds.available, side, amount)?;
+        account.funds.set(available_funds, account.funds.held)?;
+        account.txs.insert(FundTransaction {
+            tx_id,
+            amount,
+            side,
+            state: TransactionState::Adjusted,
+            expires_at: None,
+            payee: None,
+            notes: Vec::new(),
+            held: None,
+            tag: None,
+            period,
+        });
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            tx_id: Some(tx_id),
+            action: "adjustment",
+            amount: adjustment.amount,
+            reason: Some(adjustment.reason),
+            ..AuditEntry::default()
+        });
+        self.track_receivables(&Transaction::Adjustment(adjustment));
+        Ok(())
+    }
+
+    /// Undoes the deposit or withdrawal `tx_id` of client `client_id`, e.g. one
+    /// applied by an operator mistake, by reversing its effect on the available
+    /// balance and marking it reversed.
+    ///
+    /// This is an administrative operation: it is not checked by the validators,
+    /// so it applies to frozen accounts and may leave the available balance
+    /// negative. It is recorded in the audit log.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not a processed deposit or withdrawal, e.g. because
+    ///    it was disputed, is pending or was already reversed
+    ///  - the transaction is in a closed accounting period
+    ///    (`Error::ClosedPeriod`)
+    ///
+    /// This function does not panic.
+    pub fn undo(&mut self, client_id: ClientId, tx_id: TransactionId) -> Result<(), Error> {
+        self.check_open_period(client_id, tx_id, Operation::Undo)?;
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, TransactionState::Processed)?;
+        let amount = reverse(account, tx_id)?;
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            tx_id: Some(tx_id),
+            action: "undo",
+            amount,
+            ..AuditEntry::default()
+        });
+        Ok(())
+    }
+
+    /// Moves `amount` from the available to the held funds of client `client_id`,
+    /// until it is released, and records it in the audit log.
+    ///
+    /// This is an administrative operation: it is not checked by the validators,
+    /// so it applies to frozen accounts and may leave the available balance
+    /// negative.
+    /// Returns an error if:
+    ///  - client `client_id` doesn't exist
+    ///  - `amount` is not positive
+    ///
+    /// This function does not panic.
+    pub fn hold(
+        &mut self,
+        client_id: ClientId,
+        amount: Price4,
+        reason: ReasonCode,
+    ) -> Result<HoldId, Error> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        if amount <= Price4::ZERO {
+            return Err(Error::InvalidPrice);
+        }
+        // Holds are never removed, so their numbers are not reused.
+        let number = account
+            .holds
+            .keys()
+            .next_back()
+            .map_or(Some(1), |last| last.checked_add(1))
+            .ok_or(Error::InvalidHold(HoldId {
+                client_id,
+                number: u32::MAX,
+            }))?;
+        let held_funds = calculate_amount(account.funds.held, Side::Deposit, amount)?;
+        let available_funds = calculate_amount(account.funds.available, Side::Withdrawal, amount)?;
+        account.funds.set(available_funds, held_funds)?;
+        account.holds.insert(
+            number,
+            ManualHold {
+                amount,
+                reason,
+                released: false,
+            },
+        );
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            hold: Some(number),
+            action: "hold",
+            amount: -amount,
+            reason: Some(reason),
+            ..AuditEntry::default()
+        });
+        Ok(HoldId { client_id, number })
+    }
+
+    /// Moves the funds of the manual hold `hold_id` back to the available funds,
+    /// and records it in the audit log. Like `hold`, this is not checked by the
+    /// validators.
+    /// Returns an error if the hold doesn't exist or was already released
+    /// (`Error::InvalidHold`).
+    ///
+    /// This function does not panic.
+    pub fn release(&mut self, hold_id: HoldId) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .get_mut(hold_id.client_id)
+            .ok_or(Error::InvalidHold(hold_id))?;
+        let hold = account
+            .holds
+            .get_mut(&hold_id.number)
+            .filter(|hold| !hold.released)
+            .ok_or(Error::InvalidHold(hold_id))?;
+        let held_funds = calculate_amount(account.funds.held, Side::Withdrawal, hold.amount)?;
+        let available_funds =
+            calculate_amount(account.funds.available, Side::Deposit, hold.amount)?;
+        account.funds.set(available_funds, held_funds)?;
+        hold.released = true;
+        let (amount, reason) = (hold.amount, hold.reason);
+        self.audit(AuditEntry {
+            client_id: Some(hold_id.client_id),
+            hold: Some(hold_id.number),
+            action: "release",
+            amount,
+            reason: Some(reason),
+            ..AuditEntry::default()
+        });
+        Ok(())
+    }
+
+    /// Freezes the account of client `client_id`, e.g. while it is investigated
+    /// for fraud, and records it in the audit log. Like `hold`, this is not
+    /// checked by the validators.
+    /// Returns an error if client `client_id` doesn't exist.
+    ///
+    /// This function does not panic.
+    pub fn freeze(&mut self, client_id: ClientId) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        account.is_frozen = true;
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            action: "freeze",
+            amount: Price4::ZERO,
+            ..AuditEntry::default()
+        });
+        Ok(())
+    }
+
+    /// Unfreezes the account of client `client_id`, e.g. after a chargeback was
+    /// investigated, and records it in the audit log. Like `hold`, this is not
+    /// checked by the validators.
+    /// Returns an error if client `client_id` doesn't exist.
+    ///
+    /// This function does not panic.
+    pub fn unfreeze(&mut self, client_id: ClientId) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        account.is_frozen = false;
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            action: "unfreeze",
+            amount: Price4::ZERO,
+            ..AuditEntry::default()
+        });
+        Ok(())
+    }
+
+    /// Merges the account of client `duplicate` into the account of `survivor`,
+    /// e.g. when the same customer was assigned two client ids upstream, and
+    /// removes it. The funds and the balances in other currencies are added up,
+    /// the transactions, manual holds and receivables move to the survivor, and
+    /// the survivor is frozen if either account was. Escrows opened for the
+    /// duplicate are paid to the survivor. The survivor's risk score, daily
+    /// withdrawals and balance history are kept.
+    ///
+    /// Transaction ids are unique across all clients, so transactions whose id
+    /// the survivor already uses get new ids after the highest id of any account
+    /// or scheduled transaction, in the order of their old ids. Later input must
+    /// not use the new ids. Manual holds whose number is taken get new numbers
+    /// after the highest of both accounts.
+    ///
+    /// This is an administrative operation: it is not checked by the validators.
+    /// It is recorded in the audit log for both clients, with the duplicate's
+    /// available funds as the amount.
+    /// Returns the remapped transaction ids as pairs of the old and the new id, or
+    /// an error if:
+    ///  - either client doesn't exist
+    ///  - both are the same client, or the duplicate has scheduled transactions
+    ///    (`Error::InvalidMerge`)
+    ///  - the combined funds overflow
+    ///
+    /// This function does not panic.
+    pub fn merge_accounts(
+        &mut self,
+        survivor: ClientId,
+        duplicate: ClientId,
+    ) -> Result<Vec<(TransactionId, TransactionId)>, Error> {
+        let invalid = |reason| Error::InvalidMerge {
+            survivor,
+            duplicate,
+            reason,
+        };
+        if survivor == duplicate {
+            return Err(invalid("the accounts are the same"));
+        }
+        let into = self
+            .accounts
+            .get(survivor)
+            .ok_or(Error::InvalidClientId(survivor))?;
+        let from = self
+            .accounts
+            .get(duplicate)
+            .ok_or(Error::InvalidClientId(duplicate))?;
+        if self
+            .schedule
+            .iter()
+            .any(|scheduled| scheduled.transaction.client_id() == duplicate)
+        {
+            return Err(invalid("it has scheduled transactions"));
+        }
+
+        // Everything that can fail is done before either account changes.
+        let funds = Funds {
+            available: calculate_amount(into.funds.available, Side::Deposit, from.funds.available)?,
+            held: calculate_amount(into.funds.held, Side::Deposit, from.funds.held)?,
+            pending: calculate_amount(into.funds.pending, Side::Deposit, from.funds.pending)?,
+            pending_out: calculate_amount(
+                into.funds.pending_out,
+                Side::Deposit,
+                from.funds.pending_out,
+            )?,
+            escrow: calculate_amount(into.funds.escrow, Side::Deposit, from.funds.escrow)?,
+        };
+        check_total(funds.available, funds.held, funds.pending_out, funds.escrow)?;
+        let mut balances = into.balances.clone();
+        for (currency, amount) in from.balances.iter() {
+            let balance = balances.entry(*currency).or_insert(Price4::ZERO);
+            *balance = calculate_amount(*balance, Side::Deposit, *amount)?;
+        }
+        let mut txs: Vec<_> = from.txs.transactions().collect();
+        txs.sort_by_key(|tx| tx.tx_id);
+        let scheduled = self
+            .schedule
+            .iter()
+            .map(|scheduled| scheduled.transaction.tx_id());
+        let mut next_id = self
+            .accounts
+            .iter()
+            .flat_map(|(_, account)| account.txs.transactions().map(|tx| tx.tx_id))
+            .chain(scheduled)
+            .map(|tx_id| tx_id.0)
+            .max()
+            .unwrap_or(0);
+        let mut remapped = Vec::new();
+        for tx in txs.iter_mut().filter(|tx| into.txs.contains(tx.tx_id)) {
+            next_id = next_id
+                .checked_add(1)
+                .ok_or_else(|| invalid("it runs out of transaction ids"))?;
+            remapped.push((tx.tx_id, TransactionId(next_id)));
+            tx.tx_id = TransactionId(next_id);
+        }
+        let remap = |tx_id| {
+            remapped
+                .iter()
+                .find(|(old, _)| *old == tx_id)
+                .map_or(tx_id, |(_, new)| *new)
+        };
+        let mut next_hold = into
+            .holds
+            .keys()
+            .chain(from.holds.keys())
+            .copied()
+            .max()
+            .unwrap_or(0);
+        let mut holds = into.holds.clone();
+        for (number, hold) in from.holds.iter() {
+            let number = match holds.contains_key(number) {
+                true => {
+                    next_hold = next_hold
+                        .checked_add(1)
+                        .ok_or_else(|| invalid("it runs out of hold numbers"))?;
+                    next_hold
+                }
+                false => *number,
+            };
+            holds.insert(number, *hold);
+        }
+        let receivables: Vec<_> = from
+            .receivables
+            .iter()
+            .map(|receivable| Receivable {
+                tx_id: remap(receivable.tx_id),
+                ..*receivable
+            })
+            .collect();
+        let moved = from.funds.available;
+
+        let from = self
+            .accounts
+            .remove(duplicate)
+            .expect("the duplicate exists");
+        let into = self
+            .accounts
+            .get_mut(survivor)
+            .expect("the survivor exists");
+        into.funds = funds;
+        for tx in txs {
+            into.txs.insert(tx);
+        }
+        into.holds = holds;
+        into.receivables.extend(receivables);
+        into.balances = balances;
+        into.is_frozen |= from.is_frozen;
+        into.chargebacks = into.chargebacks.saturating_add(from.chargebacks);
+        into.last_activity = into.last_activity.max(from.last_activity);
+        into.dormant &= from.dormant;
+        self.expiries = core::mem::take(&mut self.expiries)
+            .into_iter()
+            .map(
+                |(expires_at, client_id, tx_id)| match client_id == duplicate {
+                    true => (expires_at, survivor, remap(tx_id)),
+                    false => (expires_at, client_id, tx_id),
+                },
+            )
+            .collect();
+        for (_, account) in self.accounts.iter_mut() {
+            let escrows: Vec<_> = account
+                .txs
+                .transactions()
+                .filter(|tx| tx.payee == Some(duplicate))
+                .map(|tx| tx.tx_id)
+                .collect();
+            for tx_id in escrows {
+                account
+                    .txs
+                    .update(tx_id, &mut |tx| tx.payee = Some(survivor));
+            }
+        }
+        for (client_id, amount) in [(survivor, moved), (duplicate, -moved)] {
+            self.audit(AuditEntry {
+                client_id: Some(client_id),
+                action: "merge",
+                amount,
+                ..AuditEntry::default()
+            });
+        }
+        Ok(remapped)
+    }
+
+    /// Opens the account of client `client_id` with `available` and `held` funds,
+    /// frozen if `frozen`, e.g. to carry over balances from another system without
+    /// recording deposits. The held funds are not tied to any dispute or hold.
+    /// Returns an error if:
+    ///  - the account of client `client_id` already exists
+    ///  - `available` or `held` has more than 4 decimals, or their total overflows
+    ///
+    /// This function does not panic.
+    pub fn open_account(
+        &mut self,
+        client_id: ClientId,
+        available: Price4,
+        held: Price4,
+        frozen: bool,
+    ) -> Result<(), Error> {
+        if self.accounts.contains(client_id) {
+            return Err(Error::AccountExists(client_id));
+        }
+        if let Some(amount) = [available, held].iter().find(|amount| amount.scale() > 4) {
+            return Err(Error::TooManyDecimals(*amount));
+        }
+        let mut account = Account::new();
+        account.funds.set(available, held)?;
+        account.is_frozen = frozen;
+        self.accounts.insert(client_id, account);
+        Ok(())
+    }
+
+    /// Accepts deposits into frozen accounts and unfreezes them by `policy`, or
+    /// keeps frozen accounts closed to deposits, which is the default.
+    pub fn set_unfreeze_policy(&mut self, policy: Option<UnfreezePolicy>) {
+        self.policies.unfreeze_policy = policy;
+    }
+
+    /// Removes and returns the audit log entries of the administrative operations
+    /// applied since it was last taken, in order.
+    pub fn take_audit_log(&mut self) -> Vec<AuditEntry> {
+        let mut log = core::mem::take(&mut self.audit_log);
+        // Transactions are tagged after they are processed, so their entries are
+        // tagged when they are taken.
+        for entry in log.iter_mut() {
+            if let (Some(client_id), Some(tx_id)) = (entry.client_id, entry.tx_id) {
+                entry.tag = self.transaction_tag(client_id, tx_id);
+            }
+        }
+        log
+    }
+
+    /// Records `entry` in the audit log, with the balances of its client.
+    pub(crate) fn audit(&mut self, mut entry: AuditEntry) {
+        if let Some(account) = entry
+            .client_id
+            .and_then(|client_id| self.accounts.get(client_id))
+        {
+            entry.available = Some(account.funds.available);
+            entry.held = Some(account.funds.held);
+        }
+        self.audit_log.push(entry);
+    }
+
+    /// Records in the audit log that a policy, e.g. the fee schedule, was replaced
+    /// between transactions, as `action`.
+    pub fn record_policy_change(&mut self, action: &'static str) {
+        self.audit(AuditEntry {
+            action,
+            amount: Price4::ZERO,
+            ..AuditEntry::default()
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Chargeback, Deposit, Dispute, Withdrawal};
+
+    #[test]
+    fn test_open_account() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        processor
+            .open_account(client_id, Price4::new(105, 1), Price4::new(2, 0), true)
+            .unwrap();
+        let account = &processor.accounts()[&client_id];
+        assert_eq!(account.available_funds(), Price4::new(105, 1));
+        assert_eq!(account.total_funds(), Price4::new(125, 1));
+        assert!(account.is_frozen());
+        assert!(matches!(
+            processor.open_account(client_id, Price4::ZERO, Price4::ZERO, false),
+            Err(Error::AccountExists(_))
+        ));
+        let err = processor
+            .open_account(ClientId(2), Price4::new(1, 5), Price4::ZERO, false)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "amount 0.00001 has more than 4 decimals");
+        assert!(!processor.accounts().contains(ClientId(2)));
+    }
+
+    #[test]
+    fn test_undo() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| Deposit {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_deposit(deposit(1, 10)).unwrap();
+        processor.process_deposit(deposit(2, 5)).unwrap();
+        processor
+            .process_withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(3),
+                amount: Price4::new(12, 0),
+            })
+            .unwrap();
+
+        processor.undo(client_id, TransactionId(3)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(15, 0)
+        );
+        processor.undo(client_id, TransactionId(1)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(5, 0)
+        );
+        // A reversed transaction can be neither undone again nor disputed.
+        assert!(matches!(
+            processor.undo(client_id, TransactionId(1)),
+            Err(Error::InvalidTxState { .. })
+        ));
+        let dispute = |tx_id| Dispute {
+            client_id,
+            tx_id: TransactionId(tx_id),
+        };
+        assert!(processor.process_dispute(dispute(1)).is_err());
+
+        // A disputed transaction is left to the dispute.
+        processor.process_dispute(dispute(2)).unwrap();
+        assert!(matches!(
+            processor.undo(client_id, TransactionId(2)),
+            Err(Error::InvalidTxState { .. })
+        ));
+        assert!(matches!(
+            processor.undo(client_id, TransactionId(4)),
+            Err(Error::InvalidTx(_))
+        ));
+        let log = processor.take_audit_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].action, log[0].amount), ("undo", Price4::new(12, 0)));
+        assert_eq!(log[1].amount, Price4::new(-10, 0));
+    }
+
+    #[test]
+    fn test_adjustment() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let adjustment = |tx_id, amount| Adjustment {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+            reason: ReasonCode(7),
+        };
+        // A debit may leave the available funds negative.
+        processor.process_adjustment(adjustment(1, -5)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(-5, 0)
+        );
+        processor.process_adjustment(adjustment(2, 8)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(3, 0)
+        );
+        assert!(matches!(
+            processor.process_adjustment(adjustment(2, 8)),
+            Err(Error::DuplicateTx(_))
+        ));
+        assert!(matches!(
+            processor.process_dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(2)
+            }),
+            Err(Error::InvalidTxState { .. })
+        ));
+        let log = processor.take_audit_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(log[0].action, "adjustment");
+        assert_eq!(log[0].reason, Some(ReasonCode(7)));
+        assert_eq!(
+            (log[0].available, log[1].available, log[1].held),
+            (
+                Some(Price4::new(-5, 0)),
+                Some(Price4::new(3, 0)),
+                Some(Price4::ZERO)
+            )
+        );
+        assert!(processor.take_audit_log().is_empty());
+    }
+
+    #[test]
+    fn test_hold_release() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let reason = ReasonCode(2);
+        assert!(matches!(
+            processor.hold(client_id, Price4::new(1, 0), reason),
+            Err(Error::InvalidClientId(_))
+        ));
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let first = processor
+            .hold(client_id, Price4::new(4, 0), reason)
+            .unwrap();
+        let second = processor
+            .hold(client_id, Price4::new(1, 0), reason)
+            .unwrap();
+        assert_ne!(first, second);
+        let account = &processor.accounts()[&client_id];
+        assert_eq!(account.available_funds(), Price4::new(5, 0));
+        assert_eq!(account.held_funds(), Price4::new(5, 0));
+        // Held funds cannot be withdrawn.
+        assert!(processor
+            .process_withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(6, 0),
+            })
+            .is_err());
+
+        processor.release(first).unwrap();
+        assert!(matches!(
+            processor.release(first),
+            Err(Error::InvalidHold(_))
+        ));
+        let account = &processor.accounts()[&client_id];
+        assert_eq!(account.available_funds(), Price4::new(9, 0));
+        assert_eq!(account.held_funds(), Price4::new(1, 0));
+        assert!(account.holds()[&first.number].released);
+        assert!(processor.hold(client_id, Price4::ZERO, reason).is_err());
+        let actions: Vec<_> = processor
+            .take_audit_log()
+            .iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, ["hold", "hold", "release"]);
+    }
+
+    #[test]
+    fn test_unfreeze_policy() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| Deposit {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_deposit(deposit(1, 10)).unwrap();
+        processor
+            .process_withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(8, 0),
+            })
+            .unwrap();
+        processor
+            .process_dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(1),
+            })
+            .unwrap();
+        processor
+            .process_chargeback(Chargeback {
+                client_id,
+                tx_id: TransactionId(1),
+            })
+            .unwrap();
+        // Without a policy, the frozen account rejects deposits.
+        assert!(processor.process_deposit(deposit(3, 5)).is_err());
+
+        processor.set_unfreeze_policy(Some(UnfreezePolicy {
+            threshold: Price4::new(2, 0),
+        }));
+        processor.process_deposit(deposit(4, 9)).unwrap();
+        assert!(processor.accounts()[&client_id].is_frozen());
+        processor.process_deposit(deposit(5, 1)).unwrap();
+        assert!(!processor.accounts()[&client_id].is_frozen());
+        let log = processor.take_audit_log();
+        assert_eq!(
+            (log.len(), log[0].action, log[0].tx_id),
+            (1, "unfreeze", Some(TransactionId(5)))
+        );
+    }
+
+    #[test]
+    fn test_merge_accounts() {
+        let mut processor = TransactionProcessor::new();
+        let deposit = |client_id, tx_id, amount| Deposit {
+            client_id: ClientId(client_id),
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_deposit(deposit(1, 1, 10)).unwrap();
+        processor.process_deposit(deposit(1, 2, 5)).unwrap();
+        processor.process_deposit(deposit(2, 2, 7)).unwrap();
+        processor.process_deposit(deposit(2, 3, 1)).unwrap();
+        processor.process_deposit(deposit(3, 4, 1)).unwrap();
+        processor
+            .process_dispute(Dispute {
+                client_id: ClientId(2),
+                tx_id: TransactionId(2),
+            })
+            .unwrap();
+
+        let remapped = processor.merge_accounts(ClientId(1), ClientId(2)).unwrap();
+        // Transaction 4 is another client's.
+        assert_eq!(remapped, [(TransactionId(2), TransactionId(5))]);
+        assert!(!processor.accounts().contains(ClientId(2)));
+        let account = &processor.accounts()[&ClientId(1)];
+        assert_eq!(
+            (account.available_funds(), account.held_funds()),
+            (Price4::new(16, 0), Price4::new(7, 0))
+        );
+        // The dispute carries over to the remapped transaction.
+        processor
+            .process_resolve(crate::Resolve {
+                client_id: ClientId(1),
+                tx_id: TransactionId(5),
+                amount: None,
+            })
+            .unwrap();
+        assert_eq!(processor.take_audit_log().len(), 2);
+
+        assert!(matches!(
+            processor.merge_accounts(ClientId(1), ClientId(1)),
+            Err(Error::InvalidMerge { .. })
+        ));
+    }
+
+    #[test]
+    fn test_freeze_policy() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_freeze_policy("never".parse().unwrap());
+        let client_id = ClientId(1);
+        for tx_id in 1..=2 {
+            let tx_id = TransactionId(tx_id);
+            processor
+                .process_deposit(Deposit {
+                    client_id,
+                    tx_id,
+                    amount: Price4::new(5, 0),
+                })
+                .unwrap();
+            processor
+                .process_dispute(Dispute { client_id, tx_id })
+                .unwrap();
+            processor
+                .process_chargeback(Chargeback { client_id, tx_id })
+                .unwrap();
+        }
+        let account = &processor.accounts()[&client_id];
+        assert!(!account.is_frozen());
+        assert_eq!(account.chargebacks(), 2);
+        assert!("0".parse::<FreezePolicy>().is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{ClientId, Price4, Timestamp, Transaction, TransactionId};
+use serde::Serialize;
+
+const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The amounts above which deposits and withdrawals are reported as suspicious.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AmlThresholds {
+    /// The amount of a single transaction.
+    pub single_amount: Option<Price4>,
+    /// The total amount of a client's transactions on one (UTC) day.
+    pub daily_amount: Option<Price4>,
+}
+
+impl AmlThresholds {
+    pub fn is_empty(&self) -> bool {
+        self.single_amount.is_none() && self.daily_amount.is_none()
+    }
+}
+
+/// A processed transaction above an AML threshold, with the context needed to
+/// review it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SuspiciousActivity {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub amount: Price4,
+    pub timestamp: Option<Timestamp>,
+    /// The total amount of the client's transactions on the same day, including
+    /// this one.
+    pub daily_total: Price4,
+    /// Which thresholds were exceeded, e.g. `amount 12000 > 10000`.
+    pub reasons: String,
+}
+
+/// Checks processed deposits and withdrawals against `AmlThresholds`.
+///
+/// Days are derived from the transactions' timestamps. A transaction without a
+/// timestamp counts towards the day of the latest one with a timestamp, so
+/// without any timestamps the whole input is a single day.
+#[derive(Debug, Clone)]
+pub struct AmlMonitor {
+    thresholds: AmlThresholds,
+    /// The day of the latest timestamp, in days since the unix epoch.
+    current_day: u64,
+    /// The day and total amount of each client's latest transactions.
+    daily_totals: HashMap<ClientId, (u64, Price4)>,
+}
+
+impl AmlMonitor {
+    pub fn new(thresholds: AmlThresholds) -> AmlMonitor {
+        AmlMonitor {
+            thresholds,
+            current_day: 0,
+            daily_totals: HashMap::new(),
+        }
+    }
+
+    /// Records a successfully processed transaction, returning it as suspicious
+    /// activity if it exceeds a threshold. Disputes, resolves and chargebacks are
+    /// not checked.
+    pub fn check(
+        &mut self,
+        transaction: &Transaction,
+        timestamp: Option<Timestamp>,
+    ) -> Option<SuspiciousActivity> {
+        let (kind, amount) = match transaction {
+            Transaction::Deposit(deposit) => ("deposit", deposit.amount),
+            Transaction::Withdrawal(withdrawal) => ("withdrawal", withdrawal.amount),
+            _ => return None,
+        };
+        if let Some(timestamp) = timestamp {
+            self.current_day = timestamp.as_secs() / SECS_PER_DAY;
+        }
+        let day = self.current_day;
+        let (total_day, total) = self
+            .daily_totals
+            .entry(transaction.client_id())
+            .or_insert((day, Price4::ZERO));
+        if *total_day != day {
+            *total_day = day;
+            *total = Price4::ZERO;
+        }
+        *total = total.saturating_add(amount);
+        let daily_total = *total;
+
+        let mut reasons = Vec::new();
+        if let Some(limit) = self.thresholds.single_amount.filter(|l| amount > *l) {
+            reasons.push(format!("amount {} > {}", amount, limit));
+        }
+        if let Some(limit) = self.thresholds.daily_amount.filter(|l| daily_total > *l) {
+            reasons.push(format!("daily total {} > {}", daily_total, limit));
+        }
+        if reasons.is_empty() {
+            return None;
+        }
+        Some(SuspiciousActivity {
+            client_id: transaction.client_id(),
+            tx_id: transaction.tx_id(),
+            kind,
+            amount,
+            timestamp,
+            daily_total,
+            reasons: reasons.join("; "),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute, Withdrawal};
+
+    #[test]
+    fn test_aml_monitor() {
+        let mut monitor = AmlMonitor::new(AmlThresholds {
+            single_amount: Some(Price4::new(1000, 0)),
+            daily_amount: Some(Price4::new(1500, 0)),
+        });
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(amount, 0),
+            })
+        };
+        let day = |days| Some(Timestamp::from_secs(days * SECS_PER_DAY + 60));
+        assert_eq!(monitor.check(&deposit(1, 800), day(1)), None);
+        let activity = monitor.check(&deposit(2, 1200), day(1)).unwrap();
+        assert_eq!(activity.daily_total, Price4::new(2000, 0));
+        assert_eq!(
+            activity.reasons,
+            "amount 1200 > 1000; daily total 2000 > 1500"
+        );
+        // Without a timestamp, a transaction counts towards the latest day.
+        let withdrawal = Transaction::Withdrawal(Withdrawal {
+            client_id,
+            tx_id: TransactionId(3),
+            amount: Price4::new(10, 0),
+        });
+        let activity = monitor.check(&withdrawal, None).unwrap();
+        assert_eq!(activity.reasons, "daily total 2010 > 1500");
+        // A new day starts a new total.
+        assert_eq!(monitor.check(&deposit(4, 800), day(2)), None);
+        let dispute = Transaction::Dispute(Dispute {
+            client_id,
+            tx_id: TransactionId(4),
+        });
+        assert_eq!(monitor.check(&dispute, day(2)), None);
+    }
+}
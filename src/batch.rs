@@ -0,0 +1,139 @@
+use crate::prelude::*;
+use crate::{
+    Account, ClientId, Error, Timestamp, Transaction, TransactionId, TransactionProcessor,
+};
+use alloc::collections::BTreeSet;
+
+/// The state a batch can be rolled back to: the accounts of its clients, the
+/// expiries of authorizations, the audit log and the journal.
+struct Savepoint {
+    /// The accounts before the batch, or `None` if they did not exist.
+    accounts: Vec<(ClientId, Option<Account>)>,
+    expiries: BTreeSet<(Timestamp, ClientId, TransactionId)>,
+    /// The length of the audit log before the batch.
+    audit_log_len: usize,
+    /// The length of the journal before the batch.
+    journal_len: usize,
+}
+
+impl TransactionProcessor {
+    /// Processes `transactions` in order, all or nothing: if one fails, the
+    /// effects of the ones before it are rolled back.
+    /// Returns `Error::BatchFailed` with the index and error of the failed
+    /// transaction.
+    ///
+    /// This function does not panic.
+    pub fn process_batch(&mut self, transactions: &[Transaction]) -> Result<(), Error> {
+        self.process_batch_with(transactions, |processor, transaction| {
+            processor.process(transaction)
+        })
+        .map(|_| ())
+    }
+
+    /// Like `process_batch`, but processes each transaction with `process`, e.g.
+    /// to evaluate rules, returning its results if all of them succeed. Only the
+    /// processor's state is rolled back, not any state of `process` or of the
+    /// validators.
+    pub fn process_batch_with<T, F>(
+        &mut self,
+        transactions: &[Transaction],
+        mut process: F,
+    ) -> Result<Vec<T>, Error>
+    where
+        F: FnMut(&mut TransactionProcessor, Transaction) -> Result<T, Error>,
+    {
+        let savepoint = self.savepoint(transactions);
+        let mut results = Vec::with_capacity(transactions.len());
+        for (index, transaction) in transactions.iter().enumerate() {
+            match process(self, *transaction) {
+                Ok(result) => results.push(result),
+                Err(e) => {
+                    self.rollback(savepoint);
+                    return Err(Error::BatchFailed {
+                        index,
+                        source: Box::new(e),
+                    });
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn savepoint(&self, transactions: &[Transaction]) -> Savepoint {
+        let clients: BTreeSet<ClientId> = transactions
+            .iter()
+            .flat_map(|transaction| {
+                core::iter::once(transaction.client_id()).chain(self.counterparty(transaction))
+            })
+            .collect();
+        Savepoint {
+            accounts: clients
+                .into_iter()
+                .map(|client_id| (client_id, self.accounts.get(client_id).cloned()))
+                .collect(),
+            expiries: self.expiries.clone(),
+            audit_log_len: self.audit_log.len(),
+            journal_len: self.journal.as_ref().map_or(0, Vec::len),
+        }
+    }
+
+    fn rollback(&mut self, savepoint: Savepoint) {
+        for (client_id, account) in savepoint.accounts {
+            match account {
+                Some(account) => self.accounts.insert(client_id, account),
+                None => self.accounts.remove(client_id),
+            };
+        }
+        self.expiries = savepoint.expiries;
+        self.audit_log.truncate(savepoint.audit_log_len);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.truncate(savepoint.journal_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Price4, Withdrawal};
+
+    #[test]
+    fn test_batch_rollback() {
+        let mut processor = TransactionProcessor::new();
+        let deposit = |client, tx_id, amount| {
+            Transaction::Deposit(Deposit {
+                client_id: ClientId(client),
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(amount, 0),
+            })
+        };
+        let withdrawal = |client, tx_id, amount| {
+            Transaction::Withdrawal(Withdrawal {
+                client_id: ClientId(client),
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(amount, 0),
+            })
+        };
+        processor.process(deposit(1, 1, 10)).unwrap();
+        processor
+            .process_batch(&[withdrawal(1, 2, 4), withdrawal(1, 3, 4)])
+            .unwrap();
+        assert_eq!(
+            processor.accounts()[&ClientId(1)].available_funds(),
+            Price4::new(2, 0)
+        );
+
+        let result =
+            processor.process_batch(&[deposit(2, 4, 5), withdrawal(1, 5, 1), withdrawal(1, 6, 2)]);
+        assert!(matches!(result, Err(Error::BatchFailed { index: 2, .. })));
+        assert_eq!(
+            processor.accounts()[&ClientId(1)].available_funds(),
+            Price4::new(2, 0)
+        );
+        assert!(!processor.accounts()[&ClientId(1)]
+            .txs
+            .contains(TransactionId(5)));
+        // An account created by the batch is removed again.
+        assert!(!processor.accounts().contains(ClientId(2)));
+    }
+}
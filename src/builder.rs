@@ -0,0 +1,275 @@
+use crate::limits::SECS_PER_DAY;
+use crate::prelude::*;
+use crate::{
+    AccountRepository, BalanceCap, Clock, DailyWithdrawalLimit, DormancyPolicy, FeeSchedule,
+    FeesError, FreezePolicy, FxRates, HistoryInterval, Permissions, Price4, RiskConfig,
+    TransactionProcessor, TransactionStoreFactory, TransactionValidator, UnfreezePolicy,
+};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("negative balance cap {0}")]
+    NegativeBalanceCap(Price4),
+    #[error("negative daily withdrawal limit {0}")]
+    NegativeWithdrawalLimit(Price4),
+    #[error("day start {0} is not within a day")]
+    InvalidDayStart(u64),
+    #[error("negative unfreeze threshold {0}")]
+    NegativeUnfreezeThreshold(Price4),
+    #[error(transparent)]
+    Fees(#[from] FeesError),
+}
+
+/// The policies, limits and modes of a processor, which a `ProcessorBuilder`
+/// sets and checks. They are configuration rather than state, so they are not
+/// part of a serialized processor: a deserialized processor starts with the
+/// defaults until they are set again.
+#[derive(Default)]
+pub(crate) struct Policies {
+    pub(crate) risk_config: Option<RiskConfig>,
+    /// Whether withdrawals are pending until settled.
+    pub(crate) settle_withdrawals: bool,
+    pub(crate) balance_cap: Option<BalanceCap>,
+    /// The daily withdrawal limit; the withdrawals counted towards it are kept
+    /// with the accounts.
+    pub(crate) withdrawal_limit: Option<DailyWithdrawalLimit>,
+    pub(crate) unfreeze_policy: Option<UnfreezePolicy>,
+    pub(crate) freeze_policy: FreezePolicy,
+    /// The dormancy policy; the activity of the accounts is kept with them.
+    pub(crate) dormancy_policy: Option<DormancyPolicy>,
+    /// Whether chargebacks only request a chargeback that must be confirmed.
+    pub(crate) confirm_chargebacks: bool,
+    pub(crate) permissions: Option<Permissions>,
+    /// Whether disputes, resolves and chargebacks of frozen accounts are processed.
+    pub(crate) frozen_disputes: bool,
+    /// Whether negative deposits and withdrawals are processed as the opposite
+    /// operation.
+    pub(crate) signed_amounts: bool,
+    /// Whether disputes that would take the held funds negative are rejected.
+    pub(crate) forbid_negative_held: bool,
+    /// Whether disputes hold at most the available funds.
+    pub(crate) cap_dispute_holds: bool,
+    pub(crate) fx_rates: Option<FxRates>,
+    pub(crate) fee_schedule: Option<FeeSchedule>,
+    pub(crate) history_interval: Option<HistoryInterval>,
+}
+
+impl Policies {
+    /// Checks the policies as a whole, e.g. that no cap or limit is negative.
+    fn check(&self) -> Result<(), ConfigError> {
+        if let Some(cap) = self.balance_cap.as_ref() {
+            let caps = cap.default.iter().chain(cap.per_client.values());
+            if let Some(cap) = caps.copied().find(is_negative) {
+                return Err(ConfigError::NegativeBalanceCap(cap));
+            }
+        }
+        if let Some(limit) = self.withdrawal_limit.as_ref() {
+            let limits = limit.default.iter().chain(limit.per_client.values());
+            if let Some(limit) = limits.copied().find(is_negative) {
+                return Err(ConfigError::NegativeWithdrawalLimit(limit));
+            }
+            if limit.day_start >= SECS_PER_DAY {
+                return Err(ConfigError::InvalidDayStart(limit.day_start));
+            }
+        }
+        if let Some(policy) = self.unfreeze_policy.as_ref() {
+            if is_negative(&policy.threshold) {
+                return Err(ConfigError::NegativeUnfreezeThreshold(policy.threshold));
+            }
+        }
+        if let Some(schedule) = self.fee_schedule.as_ref() {
+            schedule.check()?;
+        }
+        Ok(())
+    }
+}
+
+/// Configures a `TransactionProcessor`, validating the configuration as a whole
+/// when it is built, e.g.:
+///
+/// ```
+/// # use transactions::TransactionProcessor;
+/// let processor = TransactionProcessor::builder()
+///     .settle_withdrawals(true)
+///     .frozen_disputes(true)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Options that are not set keep their defaults, or the configuration of the
+/// processor the builder was created from with `into_builder`.
+pub struct ProcessorBuilder {
+    processor: TransactionProcessor,
+}
+
+impl TransactionProcessor {
+    pub fn builder() -> ProcessorBuilder {
+        TransactionProcessor::new().into_builder()
+    }
+
+    /// Changes the configuration of the processor, e.g. of one restored from a
+    /// snapshot, keeping its accounts.
+    pub fn into_builder(self) -> ProcessorBuilder {
+        ProcessorBuilder { processor: self }
+    }
+}
+
+impl ProcessorBuilder {
+    pub fn settle_withdrawals(mut self, settle: bool) -> ProcessorBuilder {
+        self.processor.set_settle_withdrawals(settle);
+        self
+    }
+
+    pub fn confirm_chargebacks(mut self, confirm: bool) -> ProcessorBuilder {
+        self.processor.set_confirm_chargebacks(confirm);
+        self
+    }
+
+    pub fn frozen_disputes(mut self, allow: bool) -> ProcessorBuilder {
+        self.processor.set_frozen_disputes(allow);
+        self
+    }
+
+    pub fn signed_amounts(mut self, signed: bool) -> ProcessorBuilder {
+        self.processor.set_signed_amounts(signed);
+        self
+    }
+
+    pub fn forbid_negative_held(mut self, forbid: bool) -> ProcessorBuilder {
+        self.processor.set_forbid_negative_held(forbid);
+        self
+    }
+
+    pub fn cap_dispute_holds(mut self, cap: bool) -> ProcessorBuilder {
+        self.processor.set_cap_dispute_holds(cap);
+        self
+    }
+
+    pub fn freeze_policy(mut self, policy: FreezePolicy) -> ProcessorBuilder {
+        self.processor.set_freeze_policy(policy);
+        self
+    }
+
+    pub fn dormancy_policy(mut self, policy: Option<DormancyPolicy>) -> ProcessorBuilder {
+        self.processor.set_dormancy_policy(policy);
+        self
+    }
+
+    pub fn unfreeze_policy(mut self, policy: Option<UnfreezePolicy>) -> ProcessorBuilder {
+        self.processor.set_unfreeze_policy(policy);
+        self
+    }
+
+    pub fn balance_cap(mut self, cap: Option<BalanceCap>) -> ProcessorBuilder {
+        self.processor.set_balance_cap(cap);
+        self
+    }
+
+    pub fn withdrawal_limit(mut self, limit: Option<DailyWithdrawalLimit>) -> ProcessorBuilder {
+        self.processor.set_withdrawal_limit(limit);
+        self
+    }
+
+    pub fn clock(mut self, clock: Box<dyn Clock>) -> ProcessorBuilder {
+        self.processor.set_clock(clock);
+        self
+    }
+
+    pub fn permissions(mut self, permissions: Option<Permissions>) -> ProcessorBuilder {
+        self.processor.set_permissions(permissions);
+        self
+    }
+
+    pub fn fx_rates(mut self, rates: Option<FxRates>) -> ProcessorBuilder {
+        self.processor.set_fx_rates(rates);
+        self
+    }
+
+    pub fn fee_schedule(mut self, schedule: Option<FeeSchedule>) -> ProcessorBuilder {
+        self.processor.set_fee_schedule(schedule);
+        self
+    }
+
+    pub fn risk_config(mut self, config: RiskConfig) -> ProcessorBuilder {
+        self.processor.set_risk_config(config);
+        self
+    }
+
+    pub fn balance_history(mut self, interval: Option<HistoryInterval>) -> ProcessorBuilder {
+        self.processor.set_balance_history(interval);
+        self
+    }
+
+    pub fn journal(mut self, enabled: bool) -> ProcessorBuilder {
+        self.processor.set_journal(enabled);
+        self
+    }
+
+    /// Adds a validator, run after the built-in ones and the ones added before it.
+    pub fn validator(mut self, validator: Box<dyn TransactionValidator>) -> ProcessorBuilder {
+        self.processor.add_validator(validator);
+        self
+    }
+
+    pub fn transaction_store(
+        mut self,
+        transaction_store: TransactionStoreFactory,
+    ) -> ProcessorBuilder {
+        self.processor.set_transaction_store(transaction_store);
+        self
+    }
+
+    pub fn account_repository(
+        mut self,
+        repository: Box<dyn AccountRepository>,
+    ) -> ProcessorBuilder {
+        self.processor.set_account_repository(repository);
+        self
+    }
+
+    /// Checks the configuration, e.g. that no cap or limit is negative, and
+    /// returns the configured processor.
+    pub fn build(self) -> Result<TransactionProcessor, ConfigError> {
+        self.processor.policies.check()?;
+        Ok(self.processor)
+    }
+}
+
+fn is_negative(amount: &Price4) -> bool {
+    *amount < Price4::ZERO
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::CapMode;
+
+    #[test]
+    fn test_processor_builder() {
+        let processor = TransactionProcessor::builder()
+            .settle_withdrawals(true)
+            .unfreeze_policy(Some(UnfreezePolicy::default()))
+            .build()
+            .unwrap();
+        assert!(processor.policies.settle_withdrawals);
+        assert!(
+            !processor
+                .into_builder()
+                .build()
+                .unwrap()
+                .policies
+                .frozen_disputes
+        );
+
+        let cap = BalanceCap {
+            default: Some(Price4::new(-1, 0)),
+            per_client: Default::default(),
+            mode: CapMode::Reject,
+        };
+        let result = TransactionProcessor::builder()
+            .balance_cap(Some(cap))
+            .build();
+        assert!(matches!(result, Err(ConfigError::NegativeBalanceCap(_))));
+    }
+}
@@ -0,0 +1,261 @@
+use crate::prelude::*;
+use crate::{Adjustment, BalanceCap, ClientId, DailyWithdrawalLimit, Error, Price4};
+use crate::{HashMap, TransactionProcessor};
+#[cfg(feature = "io")]
+use crate::{RecordKind, Transaction, TransactionRecord};
+#[cfg(feature = "io")]
+use core::convert::TryFrom;
+
+/// Why a row of an adjustments file was not applied.
+#[cfg(feature = "io")]
+#[derive(thiserror::Error, Debug)]
+pub enum BulkError {
+    #[error("malformed row: {0}")]
+    Csv(#[from] csv::Error),
+    #[error("{0:?} records are not adjustments")]
+    NotAnAdjustment(RecordKind),
+    #[error(transparent)]
+    Failed(#[from] Error),
+}
+
+impl TransactionProcessor {
+    /// Freezes the accounts of `clients`, like `freeze` for each of them.
+    /// Returns the result of each client, in order; a failure does not stop the
+    /// clients after it.
+    pub fn freeze_all(&mut self, clients: &[ClientId]) -> Vec<Result<(), Error>> {
+        clients
+            .iter()
+            .map(|client_id| self.freeze(*client_id))
+            .collect()
+    }
+
+    /// Unfreezes the accounts of `clients`, like `unfreeze` for each of them.
+    /// Returns the result of each client, in order.
+    pub fn unfreeze_all(&mut self, clients: &[ClientId]) -> Vec<Result<(), Error>> {
+        clients
+            .iter()
+            .map(|client_id| self.unfreeze(*client_id))
+            .collect()
+    }
+
+    /// Applies `adjustments`, like `process_adjustment` for each of them.
+    /// Returns the result of each adjustment, in order; unlike `process_batch`,
+    /// a failure does not roll back the adjustments before it.
+    pub fn apply_adjustments(&mut self, adjustments: &[Adjustment]) -> Vec<Result<(), Error>> {
+        adjustments
+            .iter()
+            .map(|adjustment| self.process_adjustment(*adjustment))
+            .collect()
+    }
+
+    /// Applies the `adjustment` records of the csv `reader`, which has the
+    /// columns of an input file, like `apply_adjustments`.
+    /// Returns the result of each row, in order, including rows that cannot be
+    /// read or are not adjustments.
+    #[cfg(feature = "io")]
+    pub fn apply_adjustments_csv<R: std::io::Read>(
+        &mut self,
+        reader: R,
+    ) -> Vec<Result<(), BulkError>> {
+        csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .flexible(true)
+            .from_reader(reader)
+            .into_deserialize::<TransactionRecord>()
+            .map(|record| {
+                let record: TransactionRecord = record?;
+                if record.kind != RecordKind::Adjustment {
+                    return Err(BulkError::NotAnAdjustment(record.kind));
+                }
+                match Transaction::try_from(&record)? {
+                    Transaction::Adjustment(adjustment) => Ok(self.process_adjustment(adjustment)?),
+                    _ => Err(BulkError::NotAnAdjustment(record.kind)),
+                }
+            })
+            .collect()
+    }
+
+    /// Sets the balance caps of specific clients, or removes a client's own cap
+    /// if its cap is `None`, so the default applies again. Clients keep the caps
+    /// that are not in `caps`, and the default and mode are kept.
+    /// Returns the result of each client, in order: an error if the cap is
+    /// negative (`Error::InvalidPrice`) or has more than 4 decimals.
+    pub fn set_balance_caps(
+        &mut self,
+        caps: &[(ClientId, Option<Price4>)],
+    ) -> Vec<Result<(), Error>> {
+        let was_set = self.policies.balance_cap.is_some();
+        let balance_cap = self
+            .policies
+            .balance_cap
+            .get_or_insert_with(BalanceCap::default);
+        let results = set_per_client(&mut balance_cap.per_client, caps);
+        if !was_set && balance_cap.is_empty() {
+            self.policies.balance_cap = None;
+        }
+        results
+    }
+
+    /// Sets the daily withdrawal limits of specific clients, like
+    /// `set_balance_caps`.
+    pub fn set_withdrawal_limits(
+        &mut self,
+        limits: &[(ClientId, Option<Price4>)],
+    ) -> Vec<Result<(), Error>> {
+        let was_set = self.policies.withdrawal_limit.is_some();
+        let withdrawal_limit = self
+            .policies
+            .withdrawal_limit
+            .get_or_insert_with(DailyWithdrawalLimit::default);
+        let results = set_per_client(&mut withdrawal_limit.per_client, limits);
+        if !was_set && withdrawal_limit.is_empty() {
+            self.policies.withdrawal_limit = None;
+        }
+        results
+    }
+}
+
+/// Sets or removes the per-client amounts of a limit, validating each.
+fn set_per_client(
+    per_client: &mut HashMap<ClientId, Price4>,
+    amounts: &[(ClientId, Option<Price4>)],
+) -> Vec<Result<(), Error>> {
+    amounts
+        .iter()
+        .map(|(client_id, amount)| {
+            match amount {
+                Some(amount) if *amount < Price4::ZERO => return Err(Error::InvalidPrice),
+                Some(amount) if amount.scale() > 4 => return Err(Error::TooManyDecimals(*amount)),
+                Some(amount) => per_client.insert(*client_id, *amount),
+                None => per_client.remove(client_id),
+            };
+            Ok(())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_freeze_all() {
+        let mut processor = TransactionProcessor::new();
+        for client in 1..=2 {
+            processor
+                .process_deposit(Deposit {
+                    client_id: ClientId(client),
+                    tx_id: TransactionId(client.into()),
+                    amount: Price4::new(5, 0),
+                })
+                .unwrap();
+        }
+        let clients = [ClientId(1), ClientId(3), ClientId(2)];
+        let results = processor.freeze_all(&clients);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(matches!(results[1], Err(Error::InvalidClientId(_))));
+        assert!(processor.accounts()[&ClientId(2)].is_frozen());
+
+        let results = processor.unfreeze_all(&[ClientId(2)]);
+        assert!(results[0].is_ok());
+        assert!(!processor.accounts()[&ClientId(2)].is_frozen());
+        let actions: Vec<_> = processor
+            .take_audit_log()
+            .iter()
+            .map(|entry| entry.action)
+            .collect();
+        assert_eq!(actions, ["freeze", "freeze", "unfreeze"]);
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_apply_adjustments_csv() {
+        use crate::ReasonCode;
+
+        let mut processor = TransactionProcessor::new();
+        let csv = "type, client, tx, amount, reason\n\
+                   adjustment, 1, 1, 5.0, 3\n\
+                   deposit, 1, 2, 1.0,\n\
+                   adjustment, 1, 3, , 3\n\
+                   adjustment, 1, x, 1.0, 3\n\
+                   adjustment, 2, 4, -2.5, 4\n\
+                   adjustment, 1, 1, 5.0, 3\n";
+        let results = processor.apply_adjustments_csv(csv.as_bytes());
+        assert_eq!(results.len(), 6);
+        assert!(results[0].is_ok() && results[4].is_ok());
+        assert!(matches!(
+            results[1],
+            Err(BulkError::NotAnAdjustment(RecordKind::Deposit))
+        ));
+        assert!(matches!(
+            results[2],
+            Err(BulkError::Failed(Error::InvalidPrice))
+        ));
+        assert!(matches!(results[3], Err(BulkError::Csv(_))));
+        assert!(matches!(
+            results[5],
+            Err(BulkError::Failed(Error::DuplicateTx(_)))
+        ));
+        assert_eq!(
+            processor.accounts()[&ClientId(2)].available_funds(),
+            Price4::new(-25, 1)
+        );
+        assert_eq!(processor.take_audit_log().len(), 2);
+
+        let results = processor.apply_adjustments(&[Adjustment {
+            client_id: ClientId(1),
+            tx_id: TransactionId(5),
+            amount: Price4::new(1, 0),
+            reason: ReasonCode(3),
+        }]);
+        assert!(results[0].is_ok());
+        assert_eq!(
+            processor.accounts()[&ClientId(1)].available_funds(),
+            Price4::new(6, 0)
+        );
+    }
+
+    #[test]
+    fn test_set_withdrawal_limits() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let results = processor.set_withdrawal_limits(&[
+            (client_id, Some(Price4::new(2, 0))),
+            (ClientId(2), Some(Price4::new(-1, 0))),
+            (ClientId(3), Some(Price4::new(1, 5))),
+        ]);
+        assert!(results[0].is_ok());
+        assert!(matches!(results[1], Err(Error::InvalidPrice)));
+        assert!(matches!(results[2], Err(Error::TooManyDecimals(_))));
+        assert_eq!(processor.remaining_withdrawal_allowance(ClientId(2)), None);
+        let withdrawal = Withdrawal {
+            client_id,
+            tx_id: TransactionId(2),
+            amount: Price4::new(3, 0),
+        };
+        assert!(processor.process_withdrawal(withdrawal).is_err());
+
+        processor.set_withdrawal_limits(&[(client_id, None)]);
+        assert_eq!(processor.remaining_withdrawal_allowance(client_id), None);
+        processor.process_withdrawal(withdrawal).unwrap();
+
+        processor.set_balance_caps(&[(client_id, Some(Price4::new(7, 0)))]);
+        assert_eq!(
+            processor
+                .policies
+                .balance_cap
+                .as_ref()
+                .unwrap()
+                .cap(client_id),
+            Some(Price4::new(7, 0))
+        );
+    }
+}
@@ -0,0 +1,210 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A report on a running processing loop, e.g. to answer a readiness probe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Health {
+    /// The number of records read so far.
+    pub records_read: u64,
+    /// The number of records read but not applied yet, as they are held back for
+    /// reordering.
+    pub queue_depth: usize,
+    /// The time since the last checkpoint was written, if any was.
+    pub snapshot_age: Option<Duration>,
+    /// The last error reported by the loop, if any.
+    pub last_error: Option<String>,
+    pub paused: bool,
+    pub cancelled: bool,
+}
+
+impl Health {
+    /// Whether the loop takes new records, i.e. it is neither paused nor stopped.
+    pub fn is_ready(&self) -> bool {
+        !self.paused && !self.cancelled
+    }
+}
+
+/// The progress reported by the loop, for `RunControl::health`.
+#[derive(Default)]
+struct Progress {
+    records_read: u64,
+    queue_depth: usize,
+    last_checkpoint: Option<Instant>,
+    last_error: Option<String>,
+}
+
+/// Lets another thread cancel, pause or resume a long-running processing loop.
+///
+/// The loop checks the control between records, so the record in flight is always
+/// fully processed before the loop stops or pauses.
+#[derive(Default)]
+pub struct RunControl {
+    cancelled: AtomicBool,
+    aborted: AtomicBool,
+    paused: Mutex<bool>,
+    unpaused: Condvar,
+    snapshot_requested: AtomicBool,
+    progress: Mutex<Progress>,
+}
+
+impl RunControl {
+    pub fn new() -> RunControl {
+        RunControl::default()
+    }
+
+    /// Requests the loop to stop before the next record. Also wakes up a paused loop.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+        let _paused = self.paused.lock().expect("lock poisoned");
+        self.unpaused.notify_all();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Cancels the loop because it failed, e.g. as its input looks wrong, so the
+    /// caller can tell it apart from a requested stop.
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+        self.cancel();
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    /// Requests the loop to stop taking new records until `resume` is called.
+    pub fn pause(&self) {
+        *self.paused.lock().expect("lock poisoned") = true;
+    }
+
+    /// Like `pause`, and also requests the loop to write a checkpoint of its state
+    /// before it blocks, if it writes checkpoints. The checkpoint waits until no
+    /// batch is open.
+    pub fn pause_with_snapshot(&self) {
+        self.snapshot_requested.store(true, Ordering::SeqCst);
+        self.pause();
+    }
+
+    /// Whether a snapshot was requested by `pause_with_snapshot`, clearing the request.
+    pub fn take_snapshot_request(&self) -> bool {
+        self.snapshot_requested.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn resume(&self) {
+        *self.paused.lock().expect("lock poisoned") = false;
+        self.unpaused.notify_all();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.lock().expect("lock poisoned")
+    }
+
+    /// Reports that the loop read `records_read` records, of which `queue_depth`
+    /// are not applied yet.
+    pub fn report_progress(&self, records_read: u64, queue_depth: usize) {
+        let mut progress = self.progress.lock().expect("lock poisoned");
+        progress.records_read = records_read;
+        progress.queue_depth = queue_depth;
+    }
+
+    /// Reports that the loop wrote a checkpoint just now.
+    pub fn report_checkpoint(&self) {
+        self.progress.lock().expect("lock poisoned").last_checkpoint = Some(Instant::now());
+    }
+
+    /// Reports an error of the loop, replacing the previous one.
+    pub fn report_error(&self, error: String) {
+        self.progress.lock().expect("lock poisoned").last_error = Some(error);
+    }
+
+    /// The health of the loop, as last reported by it.
+    pub fn health(&self) -> Health {
+        let progress = self.progress.lock().expect("lock poisoned");
+        Health {
+            records_read: progress.records_read,
+            queue_depth: progress.queue_depth,
+            snapshot_age: progress.last_checkpoint.map(|time| time.elapsed()),
+            last_error: progress.last_error.clone(),
+            paused: self.is_paused(),
+            cancelled: self.is_cancelled(),
+        }
+    }
+
+    /// Blocks the calling thread while the control is paused and not cancelled.
+    pub fn wait_while_paused(&self) {
+        let mut paused = self.paused.lock().expect("lock poisoned");
+        while *paused && !self.is_cancelled() {
+            paused = self.unpaused.wait(paused).expect("lock poisoned");
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_resume_wakes_paused_loop() {
+        let control = Arc::new(RunControl::new());
+        control.pause();
+        let waiter = {
+            let control = control.clone();
+            std::thread::spawn(move || control.wait_while_paused())
+        };
+        control.resume();
+        waiter.join().unwrap();
+        assert!(!control.is_paused());
+    }
+
+    #[test]
+    fn test_cancel_wakes_paused_loop() {
+        let control = Arc::new(RunControl::new());
+        control.pause();
+        let waiter = {
+            let control = control.clone();
+            std::thread::spawn(move || control.wait_while_paused())
+        };
+        control.cancel();
+        waiter.join().unwrap();
+        assert!(control.is_cancelled());
+        assert!(!control.is_aborted());
+        control.abort();
+        assert!(control.is_aborted());
+    }
+
+    #[test]
+    fn test_pause_with_snapshot() {
+        let control = RunControl::new();
+        control.pause();
+        assert!(!control.take_snapshot_request());
+        control.pause_with_snapshot();
+        assert!(control.is_paused());
+        assert!(control.take_snapshot_request());
+        assert!(!control.take_snapshot_request());
+    }
+
+    #[test]
+    fn test_health() {
+        let control = RunControl::new();
+        assert_eq!(control.health(), Health::default());
+        assert!(control.health().is_ready());
+        control.report_progress(10, 2);
+        control.report_error("deserialize failed".to_string());
+        control.report_checkpoint();
+        control.pause();
+        let health = control.health();
+        assert_eq!(health.records_read, 10);
+        assert_eq!(health.queue_depth, 2);
+        assert_eq!(health.last_error.as_deref(), Some("deserialize failed"));
+        assert!(health.snapshot_age.is_some());
+        assert!(!health.is_ready());
+        control.resume();
+        assert!(control.health().is_ready());
+        control.cancel();
+        assert!(!control.health().is_ready());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::StoreError;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant};
+
+/// Remembers which records were already processed, so records redelivered by an
+/// at-least-once source can be filtered out before they reach the processor.
+///
+/// A key is remembered for at least the store's retention period after it was
+/// inserted.
+pub trait DedupStore {
+    /// Whether `key` was inserted within the retention period.
+    fn contains(&mut self, key: &str) -> Result<bool, StoreError>;
+
+    fn insert(&mut self, key: &str) -> Result<(), StoreError>;
+}
+
+/// The number of bits in each generation's bloom filter (128 KiB).
+const BLOOM_BITS: usize = 1 << 20;
+/// The number of bits set per key.
+const BLOOM_HASHES: u64 = 4;
+
+/// A fixed-size bloom filter, used to answer most lookups of unseen keys without
+/// touching the exact set.
+struct BloomFilter {
+    bits: Vec<u64>,
+}
+
+impl BloomFilter {
+    fn new() -> BloomFilter {
+        BloomFilter {
+            bits: vec![0; BLOOM_BITS / 64],
+        }
+    }
+
+    /// The bits for `key`, derived from one 64-bit hash by double hashing.
+    fn indexes(key: &str) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let (h1, h2) = (hash & 0xffff_ffff, hash >> 32);
+        (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) as usize) % BLOOM_BITS)
+    }
+
+    fn insert(&mut self, key: &str) {
+        for index in BloomFilter::indexes(key) {
+            self.bits[index / 64] |= 1 << (index % 64);
+        }
+    }
+
+    /// Returns `false` if `key` was definitely not inserted.
+    fn may_contain(&self, key: &str) -> bool {
+        BloomFilter::indexes(key).all(|index| self.bits[index / 64] & (1 << (index % 64)) != 0)
+    }
+}
+
+/// The keys inserted during one retention period.
+struct Generation {
+    started: Instant,
+    bloom: BloomFilter,
+    keys: HashSet<String>,
+}
+
+impl Generation {
+    fn new(started: Instant) -> Generation {
+        Generation {
+            started,
+            bloom: BloomFilter::new(),
+            keys: HashSet::new(),
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.bloom.may_contain(key) && self.keys.contains(key)
+    }
+}
+
+/// Keeps keys in memory, in generations spanning one retention period each. The
+/// previous generation is dropped when a new one starts, so keys are remembered
+/// for between one and two retention periods.
+pub struct MemoryDedupStore {
+    retention: Duration,
+    current: Generation,
+    previous: Option<Generation>,
+}
+
+impl MemoryDedupStore {
+    pub fn new(retention: Duration) -> MemoryDedupStore {
+        MemoryDedupStore {
+            retention,
+            current: Generation::new(Instant::now()),
+            previous: None,
+        }
+    }
+
+    /// Starts a new generation if the current one is older than the retention.
+    fn rotate(&mut self) {
+        let now = Instant::now();
+        let age = now.duration_since(self.current.started);
+        if age < self.retention {
+            return;
+        }
+        let expired = std::mem::replace(&mut self.current, Generation::new(now));
+        // After a long enough pause, the current generation has expired as well.
+        self.previous = Some(expired).filter(|_| age < 2 * self.retention);
+    }
+}
+
+impl DedupStore for MemoryDedupStore {
+    fn contains(&mut self, key: &str) -> Result<bool, StoreError> {
+        self.rotate();
+        let in_previous = self
+            .previous
+            .as_ref()
+            .is_some_and(|previous| previous.contains(key));
+        Ok(self.current.contains(key) || in_previous)
+    }
+
+    fn insert(&mut self, key: &str) -> Result<(), StoreError> {
+        self.rotate();
+        self.current.bloom.insert(key);
+        self.current.keys.insert(key.to_string());
+        Ok(())
+    }
+}
+
+/// Keeps keys in Redis, which expires them after the retention period, so several
+/// processes can share one store.
+#[cfg(feature = "redis")]
+pub struct RedisDedupStore {
+    connection: redis::Connection,
+    /// The retention in seconds, at least one.
+    retention_secs: u64,
+}
+
+#[cfg(feature = "redis")]
+impl RedisDedupStore {
+    /// Keys are stored with a `dedup:` prefix.
+    const PREFIX: &'static str = "dedup:";
+
+    /// Connects to Redis at `url`, e.g. `redis://host:6379/0`.
+    pub fn connect(url: &str, retention: Duration) -> Result<RedisDedupStore, StoreError> {
+        let backend_error = |e: redis::RedisError| StoreError::Backend(e.to_string());
+        let connection = redis::Client::open(url)
+            .and_then(|client| client.get_connection())
+            .map_err(backend_error)?;
+        Ok(RedisDedupStore {
+            connection,
+            retention_secs: retention.as_secs().max(1),
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+impl DedupStore for RedisDedupStore {
+    fn contains(&mut self, key: &str) -> Result<bool, StoreError> {
+        redis::cmd("EXISTS")
+            .arg(format!("{}{}", RedisDedupStore::PREFIX, key))
+            .query(&mut self.connection)
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+
+    fn insert(&mut self, key: &str) -> Result<(), StoreError> {
+        redis::cmd("SET")
+            .arg(format!("{}{}", RedisDedupStore::PREFIX, key))
+            .arg(1)
+            .arg("EX")
+            .arg(self.retention_secs)
+            .query(&mut self.connection)
+            .map_err(|e| StoreError::Backend(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut bloom = BloomFilter::new();
+        bloom.insert("deposit/1");
+        assert!(bloom.may_contain("deposit/1"));
+        assert!(!bloom.may_contain("deposit/2"));
+    }
+
+    #[test]
+    fn test_memory_dedup_store_retention() {
+        let mut store = MemoryDedupStore::new(Duration::from_millis(50));
+        store.insert("a").unwrap();
+        assert!(store.contains("a").unwrap());
+        assert!(!store.contains("b").unwrap());
+        // Still remembered in the previous generation.
+        std::thread::sleep(Duration::from_millis(60));
+        store.insert("b").unwrap();
+        assert!(store.contains("a").unwrap());
+        // Dropped along with the previous generation.
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!store.contains("a").unwrap());
+        assert!(store.contains("b").unwrap());
+        // After a long pause, both generations have expired.
+        std::thread::sleep(Duration::from_millis(110));
+        assert!(!store.contains("b").unwrap());
+    }
+}
@@ -0,0 +1,475 @@
+use crate::snapshot::save;
+use crate::{
+    Account, BalanceHistory, ClientId, ClosedPeriod, Currency, DailyWithdrawals, Error,
+    FundTransaction, Funds, ManualHold, Price4, Receivable, Risk, ScheduledTransaction, Snapshot,
+    SnapshotError, Timestamp, TransactionId, TransactionState, SNAPSHOT_VERSION,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// The changes between an older and a newer snapshot of the same input, which can
+/// be applied to the older snapshot to bring it up to date.
+///
+/// Processing never removes accounts or transactions, so the delta only records
+/// accounts whose balances or frozen state changed, and transactions that were
+/// added or changed state.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotDelta {
+    /// The number of records read by the snapshot the delta applies to.
+    pub from_records_read: u64,
+    /// The number of records read by the snapshot the delta brings it up to.
+    pub to_records_read: u64,
+    /// The changed accounts, ordered by client id.
+    pub accounts: Vec<AccountDelta>,
+    /// The new schedule, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Vec<ScheduledTransaction>>,
+    /// The periods closed since the older snapshot, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub closed_periods: Vec<ClosedPeriod>,
+}
+
+/// The changes to a single account.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct AccountDelta {
+    pub client_id: ClientId,
+    /// The change in available funds.
+    pub available: Price4,
+    /// The change in held funds.
+    pub held: Price4,
+    /// The change in pending funds.
+    #[serde(default, skip_serializing_if = "Price4::is_zero")]
+    pub pending: Price4,
+    /// The change in pending out funds.
+    #[serde(default, skip_serializing_if = "Price4::is_zero")]
+    pub pending_out: Price4,
+    /// The change in escrow funds.
+    #[serde(default, skip_serializing_if = "Price4::is_zero")]
+    pub escrow: Price4,
+    /// The new frozen state, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frozen: Option<bool>,
+    /// The new risk score, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub risk: Option<Risk>,
+    /// The new manual holds, if they changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub holds: Option<BTreeMap<u32, ManualHold>>,
+    /// The new withdrawals counted towards the daily limit, if they changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_withdrawals: Option<DailyWithdrawals>,
+    /// The new receivables, if they changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub receivables: Option<Vec<Receivable>>,
+    /// The new balances in other currencies, if they changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub balances: Option<BTreeMap<Currency, Price4>>,
+    /// The new balance history, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<BalanceHistory>,
+    /// The new number of chargebacks, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chargebacks: Option<u32>,
+    /// The new latest activity, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_activity: Option<Timestamp>,
+    /// The new dormant state, if it changed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dormant: Option<bool>,
+    /// The added or changed transactions, ordered by transaction id.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub transactions: Vec<TransactionDelta>,
+}
+
+/// A transaction that was added or changed state.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct TransactionDelta {
+    /// The state in the older snapshot, or `None` if the transaction is new.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    previous_state: Option<TransactionState>,
+    transaction: FundTransaction,
+}
+
+impl TransactionDelta {
+    pub fn tx_id(&self) -> TransactionId {
+        self.transaction.tx_id
+    }
+
+    pub fn previous_state(&self) -> Option<TransactionState> {
+        self.previous_state
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.transaction.state
+    }
+}
+
+impl SnapshotDelta {
+    /// Computes the changes from `from` to `to`.
+    ///
+    /// Returns an error if:
+    /// - `to` is not a later state of the same input as `from`, i.e. if it read
+    ///   fewer records or is missing an account or transaction.
+    /// - The change of a balance overflows.
+    ///
+    /// This function does not panic.
+    pub fn between(from: &Snapshot, to: &Snapshot) -> Result<SnapshotDelta, SnapshotError> {
+        if to.records_read < from.records_read {
+            return Err(SnapshotError::Unrelated(format!(
+                "newer snapshot read {} records, older snapshot {}",
+                to.records_read, from.records_read
+            )));
+        }
+        let diff = |new: Price4, old: Price4| {
+            new.checked_sub(old)
+                .ok_or(Error::PriceOverflow(new, old))
+                .map_err(SnapshotError::Diff)
+        };
+        let empty = Account::new();
+        let mut accounts = Vec::new();
+        for (client_id, old) in from.processor.accounts.iter() {
+            if !to.processor.accounts.contains(client_id) {
+                return Err(SnapshotError::Unrelated(format!(
+                    "account {:?} is missing from the newer snapshot",
+                    client_id
+                )));
+            }
+            for tx in old.txs.transactions() {
+                if !to.processor.accounts[&client_id].txs.contains(tx.tx_id) {
+                    return Err(SnapshotError::Unrelated(format!(
+                        "transaction {:?} is missing from the newer snapshot",
+                        tx.tx_id
+                    )));
+                }
+            }
+        }
+        for (client_id, new) in to.processor.accounts.iter() {
+            let old = from.processor.accounts.get(client_id).unwrap_or(&empty);
+            let mut transactions: Vec<_> = new
+                .txs
+                .transactions()
+                .filter_map(|tx| {
+                    let old_tx = old.txs.get(tx.tx_id);
+                    (old_tx.as_ref() != Some(&tx)).then(|| TransactionDelta {
+                        previous_state: old_tx.map(|old_tx| old_tx.state),
+                        transaction: tx,
+                    })
+                })
+                .collect();
+            transactions.sort_by_key(|delta| delta.tx_id());
+            let account = AccountDelta {
+                client_id,
+                available: diff(new.funds.available, old.funds.available)?,
+                held: diff(new.funds.held, old.funds.held)?,
+                pending: diff(new.funds.pending, old.funds.pending)?,
+                pending_out: diff(new.funds.pending_out, old.funds.pending_out)?,
+                escrow: diff(new.funds.escrow, old.funds.escrow)?,
+                frozen: Some(new.is_frozen).filter(|frozen| *frozen != old.is_frozen),
+                risk: Some(new.risk).filter(|risk| *risk != old.risk),
+                holds: Some(new.holds.clone()).filter(|holds| *holds != old.holds),
+                daily_withdrawals: new
+                    .daily_withdrawals
+                    .filter(|_| new.daily_withdrawals != old.daily_withdrawals),
+                receivables: Some(new.receivables.clone())
+                    .filter(|receivables| *receivables != old.receivables),
+                balances: Some(new.balances.clone()).filter(|balances| *balances != old.balances),
+                history: Some(new.history.clone()).filter(|history| *history != old.history),
+                chargebacks: Some(new.chargebacks).filter(|count| *count != old.chargebacks),
+                last_activity: new
+                    .last_activity
+                    .filter(|_| new.last_activity != old.last_activity),
+                dormant: Some(new.dormant).filter(|dormant| *dormant != old.dormant),
+                transactions,
+            };
+            let is_changed = !account.available.is_zero()
+                || !account.held.is_zero()
+                || !account.pending.is_zero()
+                || !account.pending_out.is_zero()
+                || !account.escrow.is_zero()
+                || account.frozen.is_some()
+                || account.risk.is_some()
+                || account.holds.is_some()
+                || account.daily_withdrawals.is_some()
+                || account.receivables.is_some()
+                || account.balances.is_some()
+                || account.history.is_some()
+                || account.chargebacks.is_some()
+                || account.last_activity.is_some()
+                || account.dormant.is_some()
+                || !account.transactions.is_empty();
+            // A new account without any effects (e.g. after only failed records)
+            // still needs to be created.
+            if is_changed || !from.processor.accounts.contains(client_id) {
+                accounts.push(account);
+            }
+        }
+        accounts.sort_by_key(|account| account.client_id);
+        Ok(SnapshotDelta {
+            from_records_read: from.records_read,
+            to_records_read: to.records_read,
+            accounts,
+            schedule: Some(to.processor.schedule.clone())
+                .filter(|schedule| *schedule != from.processor.schedule),
+            closed_periods: to
+                .processor
+                .closed_periods()
+                .get(from.processor.closed_periods().len()..)
+                .unwrap_or_default()
+                .to_vec(),
+        })
+    }
+
+    /// Applies the delta to `snapshot`, which must be the snapshot it was computed
+    /// from. The snapshot is left unchanged if an error is returned.
+    ///
+    /// Returns an error if:
+    /// - `snapshot` read a different number of records than the delta applies to.
+    /// - A balance would overflow.
+    ///
+    /// This function does not panic.
+    pub fn apply(&self, snapshot: &mut Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.records_read != self.from_records_read {
+            return Err(SnapshotError::DeltaMismatch {
+                expected: self.from_records_read,
+                actual: snapshot.records_read,
+            });
+        }
+        // Compute all balances first, so a failure leaves the snapshot unchanged.
+        let add = |funds: Price4, delta: Price4| {
+            funds
+                .checked_add(delta)
+                .ok_or(Error::PriceOverflow(funds, delta))
+                .map_err(SnapshotError::Apply)
+        };
+        let mut balances = Vec::with_capacity(self.accounts.len());
+        for delta in self.accounts.iter() {
+            let mut funds = match snapshot.processor.accounts.get(delta.client_id) {
+                Some(account) => account.funds,
+                None => Funds::new(),
+            };
+            funds.escrow = add(funds.escrow, delta.escrow)?;
+            funds
+                .set_with_pending_out(
+                    add(funds.available, delta.available)?,
+                    add(funds.held, delta.held)?,
+                    add(funds.pending_out, delta.pending_out)?,
+                )
+                .map_err(SnapshotError::Apply)?;
+            funds.pending = add(funds.pending, delta.pending)?;
+            balances.push(funds);
+        }
+        for (delta, funds) in self.accounts.iter().zip(balances) {
+            for tx in delta.transactions.iter() {
+                snapshot
+                    .processor
+                    .track_expiry(delta.client_id, &tx.transaction);
+            }
+            let account = snapshot.processor.account_mut(delta.client_id);
+            account.funds = funds;
+            if let Some(frozen) = delta.frozen {
+                account.is_frozen = frozen;
+            }
+            if let Some(risk) = delta.risk {
+                account.risk = risk;
+            }
+            if let Some(holds) = delta.holds.as_ref() {
+                account.holds = holds.clone();
+            }
+            if let Some(receivables) = delta.receivables.as_ref() {
+                account.receivables = receivables.clone();
+            }
+            if let Some(balances) = delta.balances.as_ref() {
+                account.balances = balances.clone();
+            }
+            if let Some(history) = delta.history.as_ref() {
+                account.history = history.clone();
+            }
+            if let Some(chargebacks) = delta.chargebacks {
+                account.chargebacks = chargebacks;
+            }
+            if let Some(last_activity) = delta.last_activity {
+                account.last_activity = Some(last_activity);
+            }
+            if let Some(dormant) = delta.dormant {
+                account.dormant = dormant;
+            }
+            if let Some(daily_withdrawals) = delta.daily_withdrawals {
+                account.daily_withdrawals = Some(daily_withdrawals);
+            }
+            for tx in delta.transactions.iter() {
+                account.txs.insert(tx.transaction.clone());
+            }
+        }
+        if let Some(schedule) = self.schedule.as_ref() {
+            snapshot.processor.schedule = schedule.clone();
+        }
+        for period in self.closed_periods.iter() {
+            snapshot.processor.periods.push(period.clone());
+        }
+        snapshot.records_read = self.to_records_read;
+        Ok(())
+    }
+
+    /// Reads a delta written by `write`.
+    pub fn read<R: Read>(reader: R) -> Result<SnapshotDelta, SnapshotError> {
+        let mut delta: Map<String, Value> = serde_json::from_reader(reader)?;
+        // Deltas are not migrated; they only need to be applied by the release
+        // that computed them.
+        let version = match delta.remove("version") {
+            None => 0,
+            Some(version) => u64::deserialize(version)?,
+        };
+        if version > SNAPSHOT_VERSION as u64 {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        Ok(serde_json::from_value(Value::Object(delta))?)
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), SnapshotError> {
+        crate::snapshot::write_versioned(writer, self)
+    }
+
+    pub fn load(path: &Path) -> Result<SnapshotDelta, SnapshotError> {
+        let file = std::fs::File::open(path).map_err(|e| SnapshotError::Io(path.to_owned(), e))?;
+        SnapshotDelta::read(std::io::BufReader::new(file))
+    }
+
+    /// Writes the delta to `path`, atomically like `Snapshot::save`.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        save(self, path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Chargeback, Deposit, Dispute, TransactionProcessor, Withdrawal};
+
+    fn ids(client_id: u16, tx_id: u32) -> (ClientId, TransactionId) {
+        (ClientId(client_id), TransactionId(tx_id))
+    }
+
+    fn deposit(processor: &mut TransactionProcessor, client_id: u16, tx_id: u32, amount: i64) {
+        let (client_id, tx_id) = ids(client_id, tx_id);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(amount, 0),
+            })
+            .unwrap();
+    }
+
+    /// Serializes a snapshot for comparing it with another.
+    fn to_value(snapshot: &Snapshot) -> Value {
+        serde_json::to_value(snapshot).unwrap()
+    }
+
+    #[test]
+    fn test_between_and_apply() {
+        let mut processor = TransactionProcessor::new();
+        deposit(&mut processor, 1, 1, 10);
+        deposit(&mut processor, 2, 2, 5);
+        deposit(&mut processor, 3, 3, 7);
+        let old = Snapshot {
+            records_read: 3,
+            processor,
+        };
+        let mut new = Snapshot::read(serde_json::to_vec(&old).unwrap().as_slice()).unwrap();
+        let (client_id, tx_id) = ids(1, 1);
+        new.processor
+            .process_dispute(Dispute { client_id, tx_id })
+            .unwrap();
+        let (client_id, tx_id) = ids(2, 4);
+        new.processor
+            .process_withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount: Price4::new(2, 0),
+            })
+            .unwrap();
+        let (client_id, tx_id) = ids(1, 1);
+        new.processor
+            .process_chargeback(Chargeback { client_id, tx_id })
+            .unwrap();
+        deposit(&mut new.processor, 4, 5, 1);
+        new.records_read = 7;
+
+        let delta = SnapshotDelta::between(&old, &new).unwrap();
+        assert_eq!(delta.from_records_read, 3);
+        assert_eq!(delta.to_records_read, 7);
+        // Client 3 did not change.
+        let clients: Vec<_> = delta.accounts.iter().map(|a| a.client_id).collect();
+        assert_eq!(clients, vec![ClientId(1), ClientId(2), ClientId(4)]);
+        let account = &delta.accounts[0];
+        assert_eq!(account.available, Price4::new(-10, 0));
+        assert_eq!(account.held, Price4::ZERO);
+        assert_eq!(account.frozen, Some(true));
+        assert_eq!(
+            account.transactions[0].previous_state(),
+            Some(TransactionState::Processed)
+        );
+        assert_eq!(
+            account.transactions[0].state(),
+            TransactionState::DisputeHandled
+        );
+        assert_eq!(delta.accounts[1].transactions[0].previous_state(), None);
+
+        let mut buf = Vec::new();
+        delta.write(&mut buf).unwrap();
+        let delta = SnapshotDelta::read(buf.as_slice()).unwrap();
+        let mut patched = old;
+        delta.apply(&mut patched).unwrap();
+        assert_eq!(to_value(&patched), to_value(&new));
+        // The delta no longer applies to the patched snapshot.
+        assert!(matches!(
+            delta.apply(&mut patched),
+            Err(SnapshotError::DeltaMismatch {
+                expected: 3,
+                actual: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn test_between_unrelated() {
+        let mut processor = TransactionProcessor::new();
+        deposit(&mut processor, 1, 1, 10);
+        let old = Snapshot {
+            records_read: 1,
+            processor,
+        };
+        let new = Snapshot {
+            records_read: 2,
+            processor: TransactionProcessor::new(),
+        };
+        assert!(matches!(
+            SnapshotDelta::between(&old, &new),
+            Err(SnapshotError::Unrelated(_))
+        ));
+        assert!(matches!(
+            SnapshotDelta::between(&new, &old),
+            Err(SnapshotError::Unrelated(_))
+        ));
+    }
+
+    #[test]
+    fn test_between_overflow() {
+        let mut processor = TransactionProcessor::new();
+        deposit(&mut processor, 1, 1, 10);
+        processor.account_mut(ClientId(1)).funds.available = Price4::MIN;
+        let old = Snapshot {
+            records_read: 1,
+            processor,
+        };
+        let mut new = Snapshot::read(serde_json::to_vec(&old).unwrap().as_slice()).unwrap();
+        new.processor.account_mut(ClientId(1)).funds.available = Price4::MAX;
+        assert!(matches!(
+            SnapshotDelta::between(&old, &new),
+            Err(SnapshotError::Diff(Error::PriceOverflow(..)))
+        ));
+    }
+}
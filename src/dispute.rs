@@ -0,0 +1,154 @@
+use crate::prelude::*;
+use crate::{
+    check_tx_state, ClientId, Error, Price4, TransactionId, TransactionProcessor, TransactionState,
+};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// A note attached to a dispute for its manual investigation.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DisputeNote {
+    /// A free-text note.
+    Text(String),
+    /// A reference to external evidence, e.g. a document id or URL.
+    Evidence(String),
+}
+
+impl fmt::Display for DisputeNote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DisputeNote::Text(text) => write!(f, "{}", text),
+            DisputeNote::Evidence(reference) => write!(f, "evidence: {}", reference),
+        }
+    }
+}
+
+/// A disputed transaction with its notes, for dispute reports.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OpenDispute {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Price4,
+    pub notes: Vec<DisputeNote>,
+}
+
+impl TransactionProcessor {
+    /// Attaches `note` to the open dispute of the transaction `tx_id` of client
+    /// `client_id`. The notes are kept with the transaction after the dispute is
+    /// resolved or charged back.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not disputed
+    ///
+    /// This function does not panic.
+    pub fn add_dispute_note(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        note: DisputeNote,
+    ) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        if tx.state != TransactionState::ChargebackRequested {
+            check_tx_state(tx.state, TransactionState::InDispute)?;
+        }
+        account
+            .txs
+            .update(tx_id, &mut |tx| tx.notes.push(note.clone()));
+        Ok(())
+    }
+
+    /// The notes attached to the disputes of the transaction `tx_id` of client
+    /// `client_id`, oldest first.
+    pub fn dispute_notes(&self, client_id: ClientId, tx_id: TransactionId) -> Vec<DisputeNote> {
+        self.accounts
+            .get(client_id)
+            .and_then(|account| account.txs.get(tx_id))
+            .map_or_else(Vec::new, |tx| tx.notes)
+    }
+
+    /// The transactions that are in dispute, including ones whose chargeback was
+    /// requested, with their notes, ordered by client and transaction id.
+    pub fn open_disputes(&self) -> Vec<OpenDispute> {
+        let mut disputes: Vec<_> = self
+            .accounts
+            .iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .txs
+                    .transactions()
+                    .filter(|tx| {
+                        matches!(
+                            tx.state,
+                            TransactionState::InDispute | TransactionState::ChargebackRequested
+                        )
+                    })
+                    .map(move |tx| OpenDispute {
+                        client_id,
+                        tx_id: tx.tx_id,
+                        amount: tx.amount,
+                        notes: tx.notes,
+                    })
+            })
+            .collect();
+        disputes.sort_by_key(|dispute| (dispute.client_id, dispute.tx_id));
+        disputes
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute, Resolve};
+
+    #[test]
+    fn test_dispute_notes() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let tx_id = TransactionId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let note = DisputeNote::Text("customer called".to_string());
+        assert!(matches!(
+            processor.add_dispute_note(client_id, tx_id, note.clone()),
+            Err(Error::InvalidTxState { .. })
+        ));
+
+        processor
+            .process_dispute(Dispute { client_id, tx_id })
+            .unwrap();
+        let evidence = DisputeNote::Evidence("https://example.com/doc/17".to_string());
+        processor
+            .add_dispute_note(client_id, tx_id, note.clone())
+            .unwrap();
+        processor
+            .add_dispute_note(client_id, tx_id, evidence.clone())
+            .unwrap();
+        let disputes = processor.open_disputes();
+        assert_eq!(disputes.len(), 1);
+        assert_eq!(disputes[0].notes, vec![note.clone(), evidence.clone()]);
+        assert_eq!(
+            disputes[0].notes[1].to_string(),
+            "evidence: https://example.com/doc/17"
+        );
+
+        // The notes are kept after the dispute is resolved.
+        processor
+            .process_resolve(Resolve {
+                client_id,
+                tx_id,
+                amount: None,
+            })
+            .unwrap();
+        assert!(processor.open_disputes().is_empty());
+        assert_eq!(processor.dispute_notes(client_id, tx_id), [note, evidence]);
+    }
+}
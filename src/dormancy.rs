@@ -0,0 +1,150 @@
+use crate::prelude::*;
+use crate::{Account, AuditEntry, ClientId, Error, Price4, Timestamp, TransactionProcessor};
+
+/// Flags accounts without any processed transaction for a period as dormant, by
+/// the processor's clock. An account stays dormant until it is reactivated, even
+/// if it has transactions again.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DormancyPolicy {
+    /// The seconds after the latest transaction of an account at which it is
+    /// dormant.
+    pub period: u64,
+    /// Whether withdrawals from dormant accounts are rejected until they are
+    /// reactivated.
+    pub block_withdrawals: bool,
+}
+
+impl DormancyPolicy {
+    pub(crate) fn is_dormant(&self, account: &Account, now: Timestamp) -> bool {
+        account.dormant
+            || account
+                .last_activity
+                .is_some_and(|last| now.as_secs().saturating_sub(last.as_secs()) >= self.period)
+    }
+}
+
+impl TransactionProcessor {
+    /// Tracks the activity of accounts to flag dormant ones, or stops tracking it.
+    /// Activity is only tracked while a policy is set, so accounts without any
+    /// transaction since are not dormant.
+    pub fn set_dormancy_policy(&mut self, policy: Option<DormancyPolicy>) {
+        self.policies.dormancy_policy = policy;
+    }
+
+    /// Whether the account of client `client_id` is dormant as of the clock. Without
+    /// a dormancy policy, no account is.
+    pub fn is_dormant(&self, client_id: ClientId) -> bool {
+        match (
+            self.policies.dormancy_policy.as_ref(),
+            self.accounts.get(client_id),
+        ) {
+            (Some(policy), Some(account)) => policy.is_dormant(account, self.clock.now()),
+            _ => false,
+        }
+    }
+
+    /// The clients whose accounts are dormant as of the clock, ordered by client id.
+    pub fn dormant_accounts(&self) -> Vec<ClientId> {
+        let policy = match self.policies.dormancy_policy.as_ref() {
+            Some(policy) => policy,
+            None => return Vec::new(),
+        };
+        let now = self.clock.now();
+        let mut clients: Vec<_> = self
+            .accounts
+            .iter()
+            .filter(|(_, account)| policy.is_dormant(account, now))
+            .map(|(client_id, _)| client_id)
+            .collect();
+        clients.sort();
+        clients
+    }
+
+    /// Reactivates the dormant account of client `client_id`, which counts as
+    /// activity, and records it in the audit log. Reactivating an account that is
+    /// not dormant does nothing.
+    /// Returns an error if client `client_id` doesn't exist.
+    ///
+    /// This function does not panic.
+    pub fn reactivate(&mut self, client_id: ClientId) -> Result<(), Error> {
+        if !self.accounts.contains(client_id) {
+            return Err(Error::InvalidClientId(client_id));
+        }
+        if !self.is_dormant(client_id) {
+            return Ok(());
+        }
+        let now = self.clock.now();
+        if let Some(account) = self.accounts.get_mut(client_id) {
+            account.dormant = false;
+            account.last_activity = Some(now);
+        }
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            action: "reactivate",
+            amount: Price4::ZERO,
+            ..AuditEntry::default()
+        });
+        Ok(())
+    }
+
+    /// Records a processed transaction of client `client_id` as its latest
+    /// activity, first flagging the account as dormant if the previous one was too
+    /// long ago.
+    pub(crate) fn record_activity(&mut self, client_id: ClientId) {
+        let policy = match self.policies.dormancy_policy {
+            Some(policy) => policy,
+            None => return,
+        };
+        let now = self.clock.now();
+        if let Some(account) = self.accounts.get_mut(client_id) {
+            account.dormant = policy.is_dormant(account, now);
+            account.last_activity = Some(now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, ManualClock, Transaction, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_dormancy() {
+        let clock = ManualClock::default();
+        let mut processor = TransactionProcessor::new();
+        processor.set_clock(Box::new(clock.clone()));
+        processor.set_dormancy_policy(Some(DormancyPolicy {
+            period: 100,
+            block_withdrawals: true,
+        }));
+        let client_id = ClientId(1);
+        let deposit = |tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(10, 0),
+            })
+        };
+        let withdrawal = Transaction::Withdrawal(Withdrawal {
+            client_id,
+            tx_id: TransactionId(3),
+            amount: Price4::new(1, 0),
+        });
+        processor.process(deposit(1)).unwrap();
+        clock.set(Timestamp::from_secs(99));
+        assert!(processor.dormant_accounts().is_empty());
+        clock.set(Timestamp::from_secs(100));
+        assert_eq!(processor.dormant_accounts(), [client_id]);
+
+        // A deposit is accepted, but the account stays dormant.
+        processor.process(deposit(2)).unwrap();
+        assert!(processor.is_dormant(client_id));
+        assert!(matches!(
+            processor.process(withdrawal),
+            Err(Error::Rejected(crate::RejectReason::AccountDormant))
+        ));
+        processor.reactivate(client_id).unwrap();
+        processor.process(withdrawal).unwrap();
+        assert_eq!(processor.take_audit_log()[0].action, "reactivate");
+    }
+}
@@ -0,0 +1,227 @@
+use crate::prelude::*;
+use crate::{
+    calculate_amount, check_tx_state, validate, ClientId, Error, FundTransaction, Funds, Price4,
+    Side, Transaction, TransactionId, TransactionProcessor, TransactionState,
+};
+use serde::{Deserialize, Serialize};
+
+/// Moves `amount` from the client's available funds into escrow for `payee`,
+/// until the escrow `tx_id` is released or refunded.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EscrowOpen {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub payee: ClientId,
+    pub amount: Price4,
+}
+
+/// Pays the funds of the escrow `tx_id` to its payee.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EscrowRelease {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Returns the funds of the escrow `tx_id` to the client that opened it.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct EscrowRefund {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+impl TransactionProcessor {
+    /// Moves `amount` from `client_id`'s available funds into escrow for `payee`
+    /// as part of the transaction `tx_id`. The escrowed funds are still part of
+    /// the client's total, and cannot be disputed.
+    /// Returns an error if:
+    ///  - the payee is the client itself
+    ///  - the transaction id is already used from another transaction
+    ///  - a validator rejects it, e.g. because the available balance in the
+    ///    account is less than `amount`, the account is frozen or `amount` is
+    ///    negative
+    ///
+    /// This function does not panic.
+    pub fn process_escrow_open(&mut self, open: EscrowOpen) -> Result<(), Error> {
+        if open.payee == open.client_id {
+            return Err(Error::InvalidClientId(open.payee));
+        }
+        self.process_tx(
+            FundTransaction {
+                tx_id: open.tx_id,
+                amount: open.amount,
+                side: Side::Withdrawal,
+                state: TransactionState::InEscrow,
+                expires_at: None,
+                payee: Some(open.payee),
+                notes: Vec::new(),
+                held: None,
+                tag: None,
+                period: 0,
+            },
+            Transaction::EscrowOpen(open),
+        )
+    }
+
+    /// Pays the funds of the escrow `tx_id` of client `client_id` to the
+    /// available funds of its payee, creating the payee's account if needed.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not an escrow, or was already released or refunded
+    ///  - a validator rejects it for client `client_id`, e.g. because the account
+    ///    is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_escrow_release(&mut self, release: EscrowRelease) -> Result<(), Error> {
+        self.close_escrow(
+            Transaction::EscrowRelease(release),
+            TransactionState::Released,
+        )
+    }
+
+    /// Returns the funds of the escrow `tx_id` of client `client_id` to its
+    /// available funds.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not an escrow, or was already released or refunded
+    ///  - a validator rejects it, e.g. because the account is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_escrow_refund(&mut self, refund: EscrowRefund) -> Result<(), Error> {
+        self.close_escrow(
+            Transaction::EscrowRefund(refund),
+            TransactionState::Refunded,
+        )
+    }
+
+    /// Moves the funds of an escrow out of it, to the payee if it is `Released`
+    /// and back to the payer otherwise.
+    fn close_escrow(
+        &mut self,
+        transaction: Transaction,
+        state: TransactionState,
+    ) -> Result<(), Error> {
+        let (client_id, tx_id) = (transaction.client_id(), transaction.tx_id());
+        let account = self
+            .accounts
+            .get(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, TransactionState::InEscrow)?;
+        validate(&self.validators, account, &transaction)?;
+        let (amount, payee) = (tx.amount, tx.payee.expect("escrows have a payee"));
+
+        // Compute all balances first, so a failure leaves both accounts unchanged.
+        let mut payer_funds = account.funds;
+        let escrow_funds = calculate_amount(payer_funds.escrow, Side::Withdrawal, amount)?;
+        let available_funds = match state {
+            TransactionState::Released => payer_funds.available,
+            _ => calculate_amount(payer_funds.available, Side::Deposit, amount)?,
+        };
+        payer_funds.set_with_escrow(available_funds, escrow_funds)?;
+        let payee_funds = match state {
+            TransactionState::Released => {
+                let mut funds = self
+                    .accounts
+                    .get(payee)
+                    .map_or_else(Funds::new, |account| account.funds);
+                let available_funds = calculate_amount(funds.available, Side::Deposit, amount)?;
+                funds.set(available_funds, funds.held)?;
+                Some(funds)
+            }
+            _ => None,
+        };
+
+        let account = self.accounts.get_mut(client_id).expect("checked above");
+        account.funds = payer_funds;
+        account.set_tx_state(tx_id, state);
+        if let Some(funds) = payee_funds {
+            self.account_mut(payee).funds = funds;
+        }
+        self.score_risk(&transaction);
+        Ok(())
+    }
+
+    /// The other client whose account `transaction` changes, if any: the payee of
+    /// an escrow that is released.
+    pub(crate) fn counterparty(&self, transaction: &Transaction) -> Option<ClientId> {
+        match transaction {
+            Transaction::EscrowOpen(open) => Some(open.payee),
+            Transaction::EscrowRelease(release) => {
+                self.accounts
+                    .get(release.client_id)?
+                    .txs
+                    .get(release.tx_id)?
+                    .payee
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute};
+
+    #[test]
+    fn test_escrow() {
+        let mut processor = TransactionProcessor::new();
+        let (payer, payee) = (ClientId(1), ClientId(2));
+        processor
+            .process_deposit(Deposit {
+                client_id: payer,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let open = |tx_id, amount| EscrowOpen {
+            client_id: payer,
+            tx_id: TransactionId(tx_id),
+            payee,
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_escrow_open(open(2, 6)).unwrap();
+        processor.process_escrow_open(open(3, 3)).unwrap();
+        assert!(processor.process_escrow_open(open(4, 2)).is_err());
+        let account = &processor.accounts()[&payer];
+        assert_eq!(account.available_funds(), Price4::new(1, 0));
+        assert_eq!(account.escrow_funds(), Price4::new(9, 0));
+        assert_eq!(account.total_funds(), Price4::new(10, 0));
+        assert!(processor
+            .process_dispute(Dispute {
+                client_id: payer,
+                tx_id: TransactionId(2)
+            })
+            .is_err());
+
+        let release = EscrowRelease {
+            client_id: payer,
+            tx_id: TransactionId(2),
+        };
+        processor.process_escrow_release(release).unwrap();
+        assert!(matches!(
+            processor.process_escrow_release(release),
+            Err(Error::InvalidTxState { .. })
+        ));
+        processor
+            .process_escrow_refund(EscrowRefund {
+                client_id: payer,
+                tx_id: TransactionId(3),
+            })
+            .unwrap();
+        let account = &processor.accounts()[&payer];
+        assert_eq!(account.available_funds(), Price4::new(4, 0));
+        assert_eq!(account.escrow_funds(), Price4::ZERO);
+        assert_eq!(
+            processor.accounts()[&payee].available_funds(),
+            Price4::new(6, 0)
+        );
+        assert!(matches!(
+            processor.process_escrow_open(EscrowOpen {
+                payee: payer,
+                ..open(5, 1)
+            }),
+            Err(Error::InvalidClientId(_))
+        ));
+    }
+}
@@ -0,0 +1,407 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{ClientId, Price4, Transaction, TransactionProcessor};
+use serde::Deserialize;
+#[cfg(feature = "io")]
+use std::path::Path;
+#[cfg(feature = "io")]
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum FeesError {
+    #[cfg(feature = "io")]
+    #[error("could not read fee schedule {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid fee schedule: {0}")]
+    Invalid(String),
+}
+
+/// The transactions fees are charged on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FeeOperation {
+    Deposit,
+    Withdrawal,
+}
+
+impl FeeOperation {
+    fn of(transaction: &Transaction) -> Option<FeeOperation> {
+        match transaction {
+            Transaction::Deposit(_) => Some(FeeOperation::Deposit),
+            Transaction::Withdrawal(_) => Some(FeeOperation::Withdrawal),
+            _ => None,
+        }
+    }
+}
+
+/// The fee of amounts from `from` up to the next tier: `fixed` plus `percent` of
+/// the amount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeTier {
+    #[serde(default)]
+    pub from: Price4,
+    #[serde(default)]
+    pub fixed: Price4,
+    #[serde(default)]
+    pub percent: Price4,
+}
+
+/// The fee of an operation, for the clients of a segment or for all clients.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fee {
+    pub operation: FeeOperation,
+    /// The segment of clients the fee applies to, which takes precedence over a
+    /// fee of the operation without a segment.
+    #[serde(default)]
+    pub segment: Option<String>,
+    pub tiers: Vec<FeeTier>,
+}
+
+/// The fees charged on deposits and withdrawals, e.g.:
+///
+/// ```toml
+/// [segments]
+/// premium = [1, 2]
+///
+/// [[fee]]
+/// operation = "withdrawal"
+/// tiers = [{ fixed = 1 }, { from = 1000, percent = 0.1 }]
+///
+/// [[fee]]
+/// operation = "withdrawal"
+/// segment = "premium"
+/// tiers = [{ fixed = 0 }]
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    /// The clients of each segment.
+    #[serde(default)]
+    pub segments: HashMap<String, Vec<ClientId>>,
+    #[serde(default, rename = "fee")]
+    pub fees: Vec<Fee>,
+}
+
+impl FeeSchedule {
+    #[cfg(feature = "io")]
+    pub fn from_toml(config: &str) -> Result<FeeSchedule, FeesError> {
+        let schedule: FeeSchedule =
+            toml::from_str(config).map_err(|e| FeesError::Invalid(e.to_string()))?;
+        schedule.check()?;
+        Ok(schedule)
+    }
+
+    /// Reads a fee schedule from JSON, e.g. without the `io` feature.
+    pub fn from_json(config: &str) -> Result<FeeSchedule, FeesError> {
+        let schedule: FeeSchedule =
+            serde_json::from_str(config).map_err(|e| FeesError::Invalid(e.to_string()))?;
+        schedule.check()?;
+        Ok(schedule)
+    }
+
+    /// Loads a fee schedule from a TOML file.
+    #[cfg(feature = "io")]
+    pub fn load(path: &Path) -> Result<FeeSchedule, FeesError> {
+        let config =
+            std::fs::read_to_string(path).map_err(|e| FeesError::Io(path.to_owned(), e))?;
+        FeeSchedule::from_toml(&config)
+    }
+
+    pub(crate) fn check(&self) -> Result<(), FeesError> {
+        for (i, fee) in self.fees.iter().enumerate() {
+            // Only the first fee of an operation and segment would be charged.
+            let is_duplicate =
+                |other: &Fee| other.operation == fee.operation && other.segment == fee.segment;
+            if self.fees[..i].iter().any(is_duplicate) {
+                return Err(FeesError::Invalid(match fee.segment.as_ref() {
+                    Some(segment) => format!(
+                        "a {:?} fee of segment `{}` is given twice",
+                        fee.operation, segment
+                    ),
+                    None => format!("a {:?} fee without a segment is given twice", fee.operation),
+                }));
+            }
+            if let Some(segment) = fee.segment.as_ref() {
+                if !self.segments.contains_key(segment) {
+                    return Err(FeesError::Invalid(format!("unknown segment `{}`", segment)));
+                }
+            }
+            if fee.tiers.is_empty() {
+                return Err(FeesError::Invalid(format!(
+                    "a {:?} fee has no tiers",
+                    fee.operation
+                )));
+            }
+            let is_negative = |tier: &FeeTier| {
+                tier.from < Price4::ZERO || tier.fixed < Price4::ZERO || tier.percent < Price4::ZERO
+            };
+            if fee.tiers.iter().any(is_negative) {
+                return Err(FeesError::Invalid(format!(
+                    "a {:?} fee has a negative tier",
+                    fee.operation
+                )));
+            }
+        }
+        // A client's fees must not depend on which of its segments comes first.
+        let mut segments: Vec<_> = self.segments.iter().collect();
+        segments.sort_by_key(|(segment, _)| *segment);
+        let mut seen = HashMap::new();
+        for (segment, clients) in segments {
+            for client_id in clients.iter() {
+                if let Some(other) = seen.insert(*client_id, segment) {
+                    if other != segment {
+                        return Err(FeesError::Invalid(format!(
+                            "client {} is in segments `{}` and `{}`",
+                            client_id, other, segment
+                        )));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The segment of `client_id`, if it is in one. A client is in at most one
+    /// segment of a checked schedule.
+    pub fn segment(&self, client_id: ClientId) -> Option<&str> {
+        self.segments
+            .iter()
+            .find(|(_, clients)| clients.contains(&client_id))
+            .map(|(segment, _)| segment.as_str())
+    }
+
+    /// The fee of `transaction` of `amount` for its client, rounded to at most 4
+    /// decimals. Only deposits and withdrawals are charged fees.
+    /// Returns `Error::PriceOverflow` if the fee overflows.
+    pub fn fee(&self, transaction: &Transaction, amount: Price4) -> Result<Price4, crate::Error> {
+        let operation = match FeeOperation::of(transaction) {
+            Some(operation) => operation,
+            None => return Ok(Price4::ZERO),
+        };
+        let segment = self.segment(transaction.client_id());
+        let fees = self.fees.iter().filter(|fee| fee.operation == operation);
+        let fee = match fees
+            .clone()
+            .find(|fee| segment.is_some() && fee.segment.as_deref() == segment)
+            .or_else(|| fees.clone().find(|fee| fee.segment.is_none()))
+        {
+            Some(fee) => fee,
+            None => return Ok(Price4::ZERO),
+        };
+        let tier = match fee
+            .tiers
+            .iter()
+            .filter(|tier| tier.from <= amount)
+            .max_by_key(|tier| tier.from)
+        {
+            Some(tier) => tier,
+            None => return Ok(Price4::ZERO),
+        };
+        amount
+            .checked_mul(tier.percent)
+            .and_then(|fee| fee.checked_div(Price4::ONE_HUNDRED))
+            .and_then(|fee| fee.checked_add(tier.fixed))
+            .map(|fee| fee.round_dp(4).normalize())
+            .ok_or(crate::Error::PriceOverflow(amount, tier.percent))
+    }
+}
+
+impl TransactionProcessor {
+    /// Charges fees on deposits and withdrawals by `schedule`, or charges no fees,
+    /// which is the default.
+    pub fn set_fee_schedule(&mut self, schedule: Option<FeeSchedule>) {
+        self.policies.fee_schedule = schedule;
+    }
+}
+
+#[cfg(all(test, feature = "io"))]
+mod test {
+    use super::*;
+    use crate::{Deposit, Error, RejectReason, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_fee_schedule() {
+        let schedule = FeeSchedule::from_toml(
+            "
+            [segments]
+            premium = [2]
+
+            [[fee]]
+            operation = \"withdrawal\"
+            tiers = [{ fixed = 1 }, { from = 100, fixed = 1, percent = 0.5 }]
+
+            [[fee]]
+            operation = \"withdrawal\"
+            segment = \"premium\"
+            tiers = [{ fixed = 0.25 }]
+            ",
+        )
+        .unwrap();
+        let mut processor = TransactionProcessor::new();
+        processor.set_fee_schedule(Some(schedule));
+        let (client, premium) = (ClientId(1), ClientId(2));
+        for client_id in [client, premium] {
+            processor
+                .process_deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId(1),
+                    amount: Price4::new(300, 0),
+                })
+                .unwrap();
+        }
+        let withdrawal = |client_id, tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        processor
+            .process_withdrawal(withdrawal(client, 2, 50))
+            .unwrap();
+        processor
+            .process_withdrawal(withdrawal(client, 3, 200))
+            .unwrap();
+        processor
+            .process_withdrawal(withdrawal(premium, 2, 50))
+            .unwrap();
+        let accounts = processor.accounts();
+        // 300 - 50 - 1 - 200 - (1 + 0.5% of 200)
+        assert_eq!(accounts[&client].available_funds(), Price4::new(47, 0));
+        assert_eq!(accounts[&premium].available_funds(), Price4::new(24975, 2));
+        let log = processor.take_audit_log();
+        assert_eq!((log[1].action, log[1].amount), ("fee", Price4::new(-2, 0)));
+        // The fee must be available as well.
+        assert!(matches!(
+            processor.process_withdrawal(withdrawal(client, 4, 47)),
+            Err(Error::Rejected(RejectReason::InsufficientFunds { .. }))
+        ));
+
+        let err = FeeSchedule::from_toml(
+            "
+            [[fee]]
+            operation = \"deposit\"
+            segment = \"vip\"
+            tiers = [{ fixed = 1 }]
+            ",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid fee schedule: unknown segment `vip`"
+        );
+
+        let err = FeeSchedule::from_toml(
+            "
+            [segments]
+            premium = [1, 2]
+            vip = [3, 2]
+            ",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid fee schedule: client 2 is in segments `premium` and `vip`"
+        );
+
+        let err = FeeSchedule::from_toml(
+            "
+            [segments]
+            premium = [1]
+
+            [[fee]]
+            operation = \"deposit\"
+            segment = \"premium\"
+            tiers = [{ fixed = 1 }]
+
+            [[fee]]
+            operation = \"withdrawal\"
+            segment = \"premium\"
+            tiers = [{ fixed = 1 }]
+
+            [[fee]]
+            operation = \"deposit\"
+            segment = \"premium\"
+            tiers = [{ fixed = 2 }]
+            ",
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid fee schedule: a Deposit fee of segment `premium` is given twice"
+        );
+    }
+
+    #[test]
+    fn test_withdrawal_fee_below_minimum_balance() {
+        let schedule = FeeSchedule::from_toml(
+            "
+            [[fee]]
+            operation = \"withdrawal\"
+            tiers = [{ fixed = 1 }]
+            ",
+        )
+        .unwrap();
+        let mut processor = TransactionProcessor::new();
+        processor.set_fee_schedule(Some(schedule));
+        processor.add_validator(Box::new(crate::MinimumBalance {
+            default: Some(Price4::new(5, 0)),
+            ..Default::default()
+        }));
+        let client_id = ClientId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let withdrawal = |tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        // 10 - 5 - 1 would be below the minimum of 5.
+        assert!(matches!(
+            processor.process_withdrawal(withdrawal(2, 5)),
+            Err(Error::Rejected(RejectReason::BelowMinimumBalance { .. }))
+        ));
+        processor.process_withdrawal(withdrawal(3, 4)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(5, 0)
+        );
+    }
+
+    #[test]
+    fn test_deposit_fee_exceeds_amount() {
+        let schedule = FeeSchedule::from_toml(
+            "
+            [[fee]]
+            operation = \"deposit\"
+            tiers = [{ fixed = 1 }]
+            ",
+        )
+        .unwrap();
+        let mut processor = TransactionProcessor::new();
+        processor.set_fee_schedule(Some(schedule));
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| Deposit {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount,
+        };
+        assert!(matches!(
+            processor.process_deposit(deposit(1, Price4::new(5, 1))),
+            Err(Error::Rejected(RejectReason::FeeExceedsAmount { .. }))
+        ));
+        processor
+            .process_deposit(deposit(2, Price4::new(1, 0)))
+            .unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::ZERO
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use crate::prelude::*;
+use crate::{
+    validate, Account, ClientId, Error, FundTransaction, Price4, RateTable, RejectReason, Side,
+    Timestamp, Transaction, TransactionId, TransactionProcessor, TransactionState,
+};
+use alloc::collections::BTreeMap;
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
+use serde::{Deserialize, Serialize};
+
+/// An ISO 4217 currency code, e.g. `EUR`.
+#[derive(Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Currency([u8; 3]);
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Currency, String> {
+        match s.as_bytes() {
+            &[a, b, c] if s.bytes().all(|byte| byte.is_ascii_uppercase()) => {
+                Ok(Currency([a, b, c]))
+            }
+            _ => Err(format!("expected a currency code like `EUR`, got `{}`", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for Currency {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Currency, String> {
+        s.parse()
+    }
+}
+
+impl From<Currency> for String {
+    fn from(currency: Currency) -> String {
+        currency.to_string()
+    }
+}
+
+impl fmt::Debug for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Currency({})", self)
+    }
+}
+
+impl fmt::Display for Currency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The code is checked to be ASCII when it is parsed.
+        write!(f, "{}", String::from_utf8_lossy(&self.0))
+    }
+}
+
+/// Converts `amount` of the client's `from` currency to its `to` currency, as the
+/// transaction `tx_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Convert {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub from: Currency,
+    pub to: Currency,
+    pub amount: Price4,
+}
+
+/// The exchange rates conversions use. The accounts' funds are in the `base`
+/// currency; conversions move funds between them and the balances in other
+/// currencies.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FxRates {
+    pub base: Currency,
+    pub rates: RateTable,
+    /// The fraction of each converted amount kept as a fee, e.g. `0.01`.
+    pub spread: Price4,
+}
+
+impl FxRates {
+    pub fn new(base: Currency) -> FxRates {
+        FxRates {
+            base,
+            rates: RateTable::default(),
+            spread: Price4::ZERO,
+        }
+    }
+
+    /// The amount of `to` that `amount` of `from` converts to at the rate valid at
+    /// `at`, after the spread, rounded to at most 4 decimals.
+    /// Returns `Error::NoRate` if no rate is valid at `at`.
+    pub fn convert(
+        &self,
+        from: Currency,
+        to: Currency,
+        amount: Price4,
+        at: Timestamp,
+    ) -> Result<Price4, Error> {
+        let rate = self
+            .rates
+            .as_of(from, to, at)
+            .ok_or(Error::NoRate { from, to })?;
+        amount
+            .checked_mul(rate)
+            .and_then(|converted| converted.checked_mul(Price4::ONE - self.spread))
+            .map(|converted| converted.round_dp(4).normalize())
+            .ok_or(Error::PriceOverflow(amount, rate))
+    }
+}
+
+impl Account {
+    /// The client's balances in other currencies than the base currency.
+    pub fn currency_balances(&self) -> &BTreeMap<Currency, Price4> {
+        &self.balances
+    }
+}
+
+impl TransactionProcessor {
+    /// Sets the exchange rates conversions use, or disables conversions, which is
+    /// the default.
+    pub fn set_fx_rates(&mut self, rates: Option<FxRates>) {
+        self.policies.fx_rates = rates;
+    }
+
+    /// Debits `amount` from the client's balance in the `from` currency, and
+    /// credits it converted at the rate valid at the processor's current time to
+    /// its balance in the `to` currency. The balance in the base currency is the available funds. The
+    /// conversion cannot be disputed.
+    /// Returns an error if:
+    ///  - no rate from `from` to `to` is valid at the current time
+    ///  - client `client_id` doesn't exist, or the transaction id is already used
+    ///  - the balance in the `from` currency is less than `amount`
+    ///  - a validator rejects it, e.g. because the account is frozen or `amount`
+    ///    is negative
+    ///
+    /// This function does not panic.
+    pub fn process_convert(&mut self, convert: Convert) -> Result<(), Error> {
+        let (client_id, tx_id) = (convert.client_id, convert.tx_id);
+        let (from, to, amount) = (convert.from, convert.to, convert.amount);
+        let rates = self
+            .policies
+            .fx_rates
+            .as_ref()
+            .ok_or(Error::NoRate { from, to })?;
+        let converted = rates.convert(from, to, amount, self.clock.now())?;
+        let base = rates.base;
+        let period = self.periods.open();
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        if account.txs.contains(tx_id) {
+            return Err(Error::InvalidTx(tx_id));
+        }
+        validate(&self.validators, account, &Transaction::Convert(convert))?;
+
+        let balance = |account: &Account, currency| match currency == base {
+            true => account.funds.available,
+            false => account.balances.get(&currency).copied().unwrap_or_default(),
+        };
+        let available = balance(account, from);
+        let debited = available
+            .checked_sub(amount)
+            .ok_or(Error::PriceOverflow(available, amount))?;
+        if debited < Price4::ZERO {
+            return Err(Error::Rejected(RejectReason::InsufficientFunds {
+                requested: amount,
+                available,
+            }));
+        }
+        let credited = balance(account, to)
+            .checked_add(converted)
+            .ok_or(Error::PriceOverflow(balance(account, to), converted))?;
+        for (currency, balance) in [(from, debited), (to, credited)].iter() {
+            match *currency == base {
+                true => account.funds.set(*balance, account.funds.held)?,
+                false => {
+                    account.balances.insert(*currency, *balance);
+                }
+            }
+        }
+        account.txs.insert(FundTransaction {
+            tx_id,
+            amount,
+            side: Side::Withdrawal,
+            state: TransactionState::Converted,
+            expires_at: None,
+            payee: None,
+            notes: Vec::new(),
+            held: None,
+            tag: None,
+            period,
+        });
+        self.track_receivables(&Transaction::Convert(convert));
+        self.score_risk(&Transaction::Convert(convert));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Rate};
+
+    #[test]
+    fn test_convert() {
+        let (usd, eur): (Currency, Currency) = ("USD".parse().unwrap(), "EUR".parse().unwrap());
+        assert!("usd".parse::<Currency>().is_err());
+        let mut rates = FxRates::new(usd);
+        rates
+            .rates
+            .insert(Rate {
+                from: usd,
+                to: eur,
+                rate: Price4::new(9, 1),
+                valid_from: None,
+                valid_until: None,
+            })
+            .unwrap();
+        rates.spread = Price4::new(1, 2);
+        let mut processor = TransactionProcessor::new();
+        processor.set_fx_rates(Some(rates));
+        let client_id = ClientId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(100, 0),
+            })
+            .unwrap();
+        let convert = |tx_id, from, to, amount| Convert {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            from,
+            to,
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_convert(convert(2, usd, eur, 50)).unwrap();
+        let account = &processor.accounts()[&client_id];
+        assert_eq!(account.available_funds(), Price4::new(50, 0));
+        // 50 * 0.9, less the 1% spread.
+        assert_eq!(account.currency_balances()[&eur], Price4::new(44_55, 2));
+
+        // The inverse rate converts back.
+        processor.process_convert(convert(3, eur, usd, 9)).unwrap();
+        let account = &processor.accounts()[&client_id];
+        assert_eq!(account.available_funds(), Price4::new(59_90, 2));
+        assert_eq!(account.currency_balances()[&eur], Price4::new(35_55, 2));
+        assert!(matches!(
+            processor.process_convert(convert(4, eur, usd, 36)),
+            Err(Error::Rejected(RejectReason::InsufficientFunds { .. }))
+        ));
+        let gbp = "GBP".parse().unwrap();
+        let err = processor
+            .process_convert(convert(5, usd, gbp, 1))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "no valid exchange rate from USD to GBP");
+    }
+}
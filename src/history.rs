@@ -0,0 +1,278 @@
+use crate::prelude::*;
+use crate::{
+    Account, ClientId, Operation, Price4, Timestamp, Transaction, TransactionId,
+    TransactionProcessor,
+};
+use core::num::NonZeroU64;
+use serde::{Deserialize, Serialize};
+
+/// When the balances of an account are recorded in its balance history.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HistoryInterval {
+    /// After each transaction that changed the balances.
+    #[default]
+    EveryChange,
+    /// After every this many transactions of the account.
+    Every(NonZeroU64),
+}
+
+/// A point in the processing of an account's transactions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AsOf {
+    /// After the account's `n`-th processed transaction.
+    Seq(u64),
+    /// After the account's transactions processed at or before a time.
+    Timestamp(Timestamp),
+}
+
+/// The balances of an account after its `seq`-th processed transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalancePoint {
+    pub seq: u64,
+    /// The processor's time when the transaction was processed.
+    pub timestamp: Timestamp,
+    /// The transaction, unless the point was recorded before transactions were.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tx_id: Option<TransactionId>,
+    /// The operation of the transaction, like `tx_id`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub operation: Option<Operation>,
+    pub available: Price4,
+    pub held: Price4,
+    pub total: Price4,
+}
+
+/// The recorded balances of an account, oldest first, and the number of its
+/// transactions processed while recording them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalanceHistory {
+    processed: u64,
+    points: Vec<BalancePoint>,
+}
+
+/// The balances of an account in a range of its transactions, e.g. for a
+/// statement to its client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BalanceStatement {
+    /// The balances before the range, if any were recorded.
+    pub opening: Option<BalancePoint>,
+    /// The balances recorded in the range, oldest first.
+    pub points: Vec<BalancePoint>,
+}
+
+impl BalanceHistory {
+    pub fn is_empty(&self) -> bool {
+        self.processed == 0
+    }
+
+    pub fn points(&self) -> &[BalancePoint] {
+        &self.points
+    }
+}
+
+impl Account {
+    /// The balances recorded after the account's transactions, oldest first, if
+    /// the processor records a balance history.
+    pub fn balance_history(&self) -> &[BalancePoint] {
+        self.history.points()
+    }
+}
+
+impl TransactionProcessor {
+    /// Records the balances of the accounts after their transactions passed to
+    /// `process` by `interval`, or records no balance history, which is the
+    /// default.
+    pub fn set_balance_history(&mut self, interval: Option<HistoryInterval>) {
+        self.policies.history_interval = interval;
+    }
+
+    /// The balances of client `client_id` as of `at`, reconstructed from its
+    /// balance history. They are exact if the history is recorded on every change,
+    /// and the latest recorded balances before `at` otherwise.
+    /// Returns `None` if the client has no balances recorded at or before `at`,
+    /// e.g. because no balance history is recorded.
+    pub fn balance_as_of(&self, client_id: ClientId, at: AsOf) -> Option<BalancePoint> {
+        let points = self.accounts.get(client_id)?.balance_history();
+        count_until(points, at)
+            .checked_sub(1)
+            .map(|index| points[index])
+    }
+
+    /// The balances of client `client_id` recorded after `after` up to and
+    /// including `until`, with the balances as of `after` as the opening ones. The
+    /// range is open-ended without them.
+    /// Returns `None` if the client doesn't exist.
+    pub fn balance_statement(
+        &self,
+        client_id: ClientId,
+        after: Option<AsOf>,
+        until: Option<AsOf>,
+    ) -> Option<BalanceStatement> {
+        let points = self.accounts.get(client_id)?.balance_history();
+        let start = after.map_or(0, |at| count_until(points, at));
+        let end = until
+            .map_or(points.len(), |at| count_until(points, at))
+            .max(start);
+        Some(BalanceStatement {
+            opening: start.checked_sub(1).map(|index| points[index]),
+            points: points[start..end].to_vec(),
+        })
+    }
+
+    /// Counts the processed `transaction` of client `client_id`, and records its
+    /// balances if they are due by the history interval.
+    pub(crate) fn record_balance(&mut self, client_id: ClientId, transaction: &Transaction) {
+        let interval = match self.policies.history_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let timestamp = self.clock.now();
+        let account = match self.accounts.get_mut(client_id) {
+            Some(account) => account,
+            None => return,
+        };
+        let point = BalancePoint {
+            seq: account.history.processed + 1,
+            timestamp,
+            tx_id: Some(transaction.tx_id()),
+            operation: Some(Operation::of(transaction)),
+            available: account.funds.available,
+            held: account.funds.held,
+            total: account.funds.total(),
+        };
+        let history = &mut account.history;
+        history.processed = point.seq;
+        let is_due = match interval {
+            HistoryInterval::EveryChange => history.points.last().is_none_or(|last| {
+                (last.available, last.held, last.total)
+                    != (point.available, point.held, point.total)
+            }),
+            HistoryInterval::Every(every) => point.seq.is_multiple_of(every.get()),
+        };
+        if is_due {
+            history.points.push(point);
+        }
+    }
+}
+
+/// The number of `points` recorded at or before `at`.
+fn count_until(points: &[BalancePoint], at: AsOf) -> usize {
+    match at {
+        AsOf::Seq(seq) => points.partition_point(|point| point.seq <= seq),
+        AsOf::Timestamp(timestamp) => points.partition_point(|point| point.timestamp <= timestamp),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute, Transaction, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_balance_history() {
+        let client_id = ClientId(1);
+        let transactions = [
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            }),
+            Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(4, 0),
+            }),
+            // Fails, so it is not counted.
+            Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(3),
+                amount: Price4::new(100, 0),
+            }),
+            Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(1),
+            }),
+        ];
+        let history = |interval| {
+            let mut processor = TransactionProcessor::new();
+            processor.set_balance_history(interval);
+            for transaction in transactions.iter() {
+                let _ = processor.process(*transaction);
+            }
+            processor.accounts()[&client_id].balance_history().to_vec()
+        };
+        assert!(history(None).is_empty());
+        let every_change = history(Some(HistoryInterval::EveryChange));
+        assert_eq!(
+            every_change
+                .iter()
+                .map(|point| (point.seq, point.available, point.held))
+                .collect::<Vec<_>>(),
+            vec![
+                (1, Price4::new(10, 0), Price4::ZERO),
+                (2, Price4::new(6, 0), Price4::ZERO),
+                (3, Price4::new(-4, 0), Price4::new(10, 0)),
+            ]
+        );
+        let every_two = history(Some(HistoryInterval::Every(NonZeroU64::new(2).unwrap())));
+        assert_eq!(every_two, vec![every_change[1]]);
+    }
+
+    #[test]
+    fn test_balance_as_of() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_balance_history(Some(HistoryInterval::EveryChange));
+        let clock = crate::ManualClock::default();
+        processor.set_clock(Box::new(clock.clone()));
+        let client_id = ClientId(1);
+        for (tx_id, at) in [(1, 100), (2, 200), (3, 200)] {
+            clock.set(Timestamp::from_secs(at));
+            processor
+                .process(Transaction::Deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId(tx_id),
+                    amount: Price4::new(10, 0),
+                }))
+                .unwrap();
+        }
+        let available = |at| {
+            processor
+                .balance_as_of(client_id, at)
+                .map(|point| point.available)
+        };
+        assert_eq!(available(AsOf::Seq(0)), None);
+        assert_eq!(available(AsOf::Seq(2)), Some(Price4::new(20, 0)));
+        assert_eq!(available(AsOf::Seq(10)), Some(Price4::new(30, 0)));
+        let at = |secs| AsOf::Timestamp(Timestamp::from_secs(secs));
+        assert_eq!(available(at(99)), None);
+        assert_eq!(available(at(150)), Some(Price4::new(10, 0)));
+        assert_eq!(available(at(200)), Some(Price4::new(30, 0)));
+        assert_eq!(processor.balance_as_of(ClientId(2), at(200)), None);
+
+        let statement = processor
+            .balance_statement(client_id, Some(at(100)), None)
+            .unwrap();
+        assert_eq!(statement.opening.map(|point| point.seq), Some(1));
+        assert_eq!(
+            statement
+                .points
+                .iter()
+                .map(|point| (point.tx_id, point.operation))
+                .collect::<Vec<_>>(),
+            [
+                (Some(TransactionId(2)), Some(Operation::Deposit)),
+                (Some(TransactionId(3)), Some(Operation::Deposit)),
+            ]
+        );
+        let statement = processor
+            .balance_statement(client_id, None, Some(AsOf::Seq(1)))
+            .unwrap();
+        assert_eq!((statement.opening, statement.points.len()), (None, 1));
+        assert!(processor
+            .balance_statement(client_id, Some(at(300)), Some(at(100)))
+            .unwrap()
+            .points
+            .is_empty());
+        assert_eq!(processor.balance_statement(ClientId(2), None, None), None);
+    }
+}
@@ -0,0 +1,247 @@
+use crate::statement::derive_tx_id;
+use crate::{Price4, StatementEntry, Timestamp};
+use roxmltree::Node;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum Iso20022Error {
+    #[error("could not read ISO 20022 message {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid ISO 20022 message: {0}")]
+    Xml(#[from] roxmltree::Error),
+    #[error("unsupported ISO 20022 message `{0}`, expected camt.053 or pain.001")]
+    Unsupported(String),
+    #[error("malformed ISO 20022 message: {0}")]
+    Malformed(String),
+}
+
+/// A transaction of an ISO 20022 message on the bank account `account`, e.g. an
+/// IBAN: a credit if its amount is positive, a debit otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Iso20022Entry {
+    pub account: String,
+    pub entry: StatementEntry,
+}
+
+/// The first descendant of `node` at the `path` of element names, ignoring
+/// namespaces.
+fn find<'a, 'input>(node: Node<'a, 'input>, path: &[&str]) -> Option<Node<'a, 'input>> {
+    path.iter().try_fold(node, |node, name| {
+        node.children()
+            .find(|child| child.is_element() && child.tag_name().name() == *name)
+    })
+}
+
+/// The children of `node` named `name`.
+fn children<'a, 'input: 'a>(
+    node: Node<'a, 'input>,
+    name: &'a str,
+) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children()
+        .filter(move |child| child.is_element() && child.tag_name().name() == name)
+}
+
+fn text<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    find(node, path)?.text().map(str::trim)
+}
+
+fn required<'a>(node: Node<'a, '_>, path: &[&str]) -> Result<&'a str, Iso20022Error> {
+    text(node, path).ok_or_else(|| {
+        Iso20022Error::Malformed(format!(
+            "`{}` without `{}`",
+            node.tag_name().name(),
+            path.join("/")
+        ))
+    })
+}
+
+/// The IBAN, or other id, of the account element `account`.
+fn account_id(account: Node) -> Result<String, Iso20022Error> {
+    text(account, &["Id", "IBAN"])
+        .or_else(|| text(account, &["Id", "Othr", "Id"]))
+        .map(str::to_string)
+        .ok_or_else(|| malformed("account without `Id`"))
+}
+
+fn malformed(message: &str) -> Iso20022Error {
+    Iso20022Error::Malformed(message.to_string())
+}
+
+fn parse_amount(amount: &str) -> Result<Price4, Iso20022Error> {
+    amount
+        .parse()
+        .map_err(|_| Iso20022Error::Malformed(format!("invalid amount `{}`", amount)))
+}
+
+/// Parses an ISO date like `2023-11-14`, or the day of a date and time like
+/// `2023-11-14T10:30:00+01:00`.
+fn parse_date(date: &str) -> Result<Timestamp, Iso20022Error> {
+    let number = |range: std::ops::Range<usize>| date.get(range)?.parse().ok();
+    number(0..4)
+        .zip(number(5..7).zip(number(8..10)))
+        .and_then(|(year, (month, day))| Timestamp::from_date(year, month as u32, day as u32))
+        .ok_or_else(|| Iso20022Error::Malformed(format!("invalid date `{}`", date)))
+}
+
+/// Reads the booked entries of the statements of a camt.053 message. Their ids
+/// are derived from their account and `AcctSvcrRef`, or `NtryRef`.
+fn read_camt053(message: Node) -> Result<Vec<Iso20022Entry>, Iso20022Error> {
+    let mut entries = Vec::new();
+    for statement in children(message, "Stmt") {
+        let account = account_id(
+            find(statement, &["Acct"]).ok_or_else(|| malformed("statement without `Acct`"))?,
+        )?;
+        for entry in children(statement, "Ntry") {
+            let status = text(entry, &["Sts", "Cd"]).or_else(|| text(entry, &["Sts"]));
+            if status.is_some_and(|status| status != "BOOK") {
+                continue;
+            }
+            let reference = text(entry, &["AcctSvcrRef"])
+                .or_else(|| text(entry, &["NtryRef"]))
+                .ok_or_else(|| malformed("entry without `AcctSvcrRef` or `NtryRef`"))?;
+            let date = text(entry, &["BookgDt", "Dt"])
+                .or_else(|| text(entry, &["BookgDt", "DtTm"]))
+                .or_else(|| text(entry, &["ValDt", "Dt"]))
+                .ok_or_else(|| malformed("entry without `BookgDt`"))?;
+            let amount = parse_amount(required(entry, &["Amt"])?)?;
+            let amount = match required(entry, &["CdtDbtInd"])? {
+                "CRDT" => amount,
+                "DBIT" => -amount,
+                indicator => {
+                    return Err(Iso20022Error::Malformed(format!(
+                        "invalid `CdtDbtInd` `{}`",
+                        indicator
+                    )))
+                }
+            };
+            entries.push(Iso20022Entry {
+                entry: StatementEntry {
+                    tx_id: derive_tx_id(&format!("camt.053:{}:{}", account, reference)),
+                    timestamp: parse_date(date)?,
+                    amount,
+                },
+                account: account.clone(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the credit transfers of a pain.001 message as debits of the debtor
+/// accounts on their requested execution dates. Their ids are derived from their
+/// account and `EndToEndId`, or `InstrId`.
+fn read_pain001(message: Node) -> Result<Vec<Iso20022Entry>, Iso20022Error> {
+    let mut entries = Vec::new();
+    for payment in children(message, "PmtInf") {
+        let account = account_id(
+            find(payment, &["DbtrAcct"]).ok_or_else(|| malformed("payment without `DbtrAcct`"))?,
+        )?;
+        // Since version 8, the date is wrapped in a `Dt` or `DtTm` element.
+        let date = text(payment, &["ReqdExctnDt", "Dt"])
+            .or_else(|| text(payment, &["ReqdExctnDt", "DtTm"]))
+            .or_else(|| text(payment, &["ReqdExctnDt"]))
+            .filter(|date| !date.is_empty())
+            .ok_or_else(|| malformed("payment without `ReqdExctnDt`"))?;
+        let timestamp = parse_date(date)?;
+        for transfer in children(payment, "CdtTrfTxInf") {
+            let reference = text(transfer, &["PmtId", "EndToEndId"])
+                .filter(|id| *id != "NOTPROVIDED")
+                .or_else(|| text(transfer, &["PmtId", "InstrId"]))
+                .ok_or_else(|| malformed("transfer without `EndToEndId` or `InstrId`"))?;
+            let amount = parse_amount(required(transfer, &["Amt", "InstdAmt"])?)?;
+            entries.push(Iso20022Entry {
+                entry: StatementEntry {
+                    tx_id: derive_tx_id(&format!("pain.001:{}:{}", account, reference)),
+                    timestamp,
+                    amount: -amount,
+                },
+                account: account.clone(),
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// Reads the transactions of an ISO 20022 bank-to-customer statement (camt.053)
+/// or customer credit transfer initiation (pain.001) message.
+pub fn read_iso20022(message: &str) -> Result<Vec<Iso20022Entry>, Iso20022Error> {
+    let document = roxmltree::Document::parse(message)?;
+    let root = document.root_element();
+    let message = root
+        .children()
+        .find(|child| child.is_element())
+        .unwrap_or(root);
+    match message.tag_name().name() {
+        "BkToCstmrStmt" => read_camt053(message),
+        "CstmrCdtTrfInitn" => read_pain001(message),
+        name => Err(Iso20022Error::Unsupported(name.to_string())),
+    }
+}
+
+/// Reads the transactions of the ISO 20022 message in the file at `path`.
+pub fn load_iso20022(path: &Path) -> Result<Vec<Iso20022Entry>, Iso20022Error> {
+    let message =
+        std::fs::read_to_string(path).map_err(|e| Iso20022Error::Io(path.to_owned(), e))?;
+    read_iso20022(&message)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_iso20022() {
+        let camt = read_iso20022(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.08">
+              <BkToCstmrStmt>
+                <Stmt>
+                  <Acct><Id><IBAN>DE89370400440532013000</IBAN></Id></Acct>
+                  <Ntry>
+                    <Amt Ccy="EUR">100.50</Amt><CdtDbtInd>CRDT</CdtDbtInd>
+                    <Sts><Cd>BOOK</Cd></Sts><BookgDt><Dt>2023-11-14</Dt></BookgDt>
+                    <AcctSvcrRef>REF-1</AcctSvcrRef>
+                  </Ntry>
+                  <Ntry>
+                    <Amt Ccy="EUR">20</Amt><CdtDbtInd>DBIT</CdtDbtInd>
+                    <Sts><Cd>PDNG</Cd></Sts><BookgDt><Dt>2023-11-14</Dt></BookgDt>
+                    <AcctSvcrRef>REF-2</AcctSvcrRef>
+                  </Ntry>
+                </Stmt>
+              </BkToCstmrStmt>
+            </Document>"#,
+        )
+        .unwrap();
+        // The pending entry is skipped.
+        assert_eq!(camt.len(), 1);
+        assert_eq!(camt[0].account, "DE89370400440532013000");
+        assert_eq!(camt[0].entry.amount, Price4::new(10050, 2));
+        assert_eq!(camt[0].entry.timestamp, Timestamp::from_secs(1_699_920_000));
+
+        let pain = read_iso20022(
+            r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:pain.001.001.03">
+              <CstmrCdtTrfInitn>
+                <PmtInf>
+                  <ReqdExctnDt>2023-11-15</ReqdExctnDt>
+                  <DbtrAcct><Id><Othr><Id>12345</Id></Othr></Id></DbtrAcct>
+                  <CdtTrfTxInf>
+                    <PmtId><InstrId>I-1</InstrId><EndToEndId>NOTPROVIDED</EndToEndId></PmtId>
+                    <Amt><InstdAmt Ccy="EUR">42.5</InstdAmt></Amt>
+                  </CdtTrfTxInf>
+                </PmtInf>
+              </CstmrCdtTrfInitn>
+            </Document>"#,
+        )
+        .unwrap();
+        assert_eq!(pain[0].account, "12345");
+        assert_eq!(pain[0].entry.amount, Price4::new(-425, 1));
+        assert_eq!(pain[0].entry.tx_id, derive_tx_id("pain.001:12345:I-1"));
+
+        let err = read_iso20022("<Document><CstmrPmtStsRpt/></Document>").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported ISO 20022 message `CstmrPmtStsRpt`, expected camt.053 or pain.001"
+        );
+    }
+}
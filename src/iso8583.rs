@@ -0,0 +1,357 @@
+use crate::prelude::*;
+use crate::{Authorize, Capture, Chargeback, ClientId, Deposit, Dispute, Price4, Reversal};
+use crate::{Transaction, TransactionId, Withdrawal};
+use alloc::collections::BTreeMap;
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Iso8583Error {
+    #[error("malformed ISO 8583 message: {0}")]
+    Malformed(String),
+    #[error("unsupported ISO 8583 data element {0}")]
+    UnsupportedField(u8),
+    #[error("unsupported ISO 8583 message type {0}")]
+    UnsupportedMessage(String),
+    #[error("ISO 8583 message type {0} requires data element {1}")]
+    MissingField(String, u8),
+}
+
+/// How a data element is encoded: a fixed number of characters, or up to a
+/// number of characters prefixed by their count in 2 digits.
+#[derive(Debug, Clone, Copy)]
+enum FieldFormat {
+    Fixed(usize),
+    LlVar(usize),
+}
+
+/// The format of the supported data elements, in ASCII.
+fn field_format(field: u8) -> Option<FieldFormat> {
+    use FieldFormat::*;
+    Some(match field {
+        // Primary account number.
+        2 => LlVar(19),
+        // Processing code.
+        3 => Fixed(6),
+        // Transaction amount, in minor units.
+        4 => Fixed(12),
+        // Transmission date and time.
+        7 => Fixed(10),
+        // System trace audit number.
+        11 => Fixed(6),
+        // Local transaction time and date.
+        12 => Fixed(6),
+        13 => Fixed(4),
+        // Point of service entry mode.
+        22 => Fixed(3),
+        // Acquiring institution id.
+        32 => LlVar(11),
+        // Retrieval reference number.
+        37 => Fixed(12),
+        // Authorization id response.
+        38 => Fixed(6),
+        // Response code.
+        39 => Fixed(2),
+        // Card acceptor terminal id.
+        41 => Fixed(8),
+        // Card acceptor id.
+        42 => Fixed(15),
+        // Transaction currency code.
+        49 => Fixed(3),
+        // Original data elements.
+        90 => Fixed(42),
+        // Account id 1.
+        102 => LlVar(28),
+        _ => return None,
+    })
+}
+
+/// An ISO 8583 (1987) message: its message type indicator, e.g. `0100`, and its
+/// data elements by number.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Iso8583Message {
+    pub mti: String,
+    pub fields: BTreeMap<u8, String>,
+}
+
+impl Iso8583Message {
+    pub fn new(mti: &str) -> Iso8583Message {
+        Iso8583Message {
+            mti: mti.to_string(),
+            fields: BTreeMap::new(),
+        }
+    }
+
+    /// Sets data element `field` to `value`.
+    pub fn with(mut self, field: u8, value: &str) -> Iso8583Message {
+        self.fields.insert(field, value.to_string());
+        self
+    }
+
+    pub fn field(&self, field: u8) -> Option<&str> {
+        self.fields.get(&field).map(String::as_str)
+    }
+
+    /// Parses a message in ASCII: the type indicator, the primary bitmap (and
+    /// the secondary one if bit 1 is set) in hex, and the present data elements.
+    /// Only the data elements with a known format are supported.
+    pub fn parse(message: &str) -> Result<Iso8583Message, Iso8583Error> {
+        let malformed = |what: &str| Iso8583Error::Malformed(what.to_string());
+        let mut rest = message;
+        let mut take = |count: usize, what: &str| {
+            let value = rest.get(..count).ok_or_else(|| malformed(what))?;
+            rest = &rest[count..];
+            Ok::<_, Iso8583Error>(value)
+        };
+        let mti = take(4, "truncated message type")?;
+        if !mti.bytes().all(|byte| byte.is_ascii_digit()) {
+            return Err(malformed("invalid message type"));
+        }
+        let mut bitmap = |what| {
+            u64::from_str_radix(take(16, what)?, 16).map_err(|_| malformed("invalid bitmap"))
+        };
+        let primary = bitmap("truncated bitmap")?;
+        let has_secondary = primary & (1 << 63) != 0;
+        let secondary = if has_secondary {
+            bitmap("truncated secondary bitmap")?
+        } else {
+            0
+        };
+        let mut fields = BTreeMap::new();
+        for field in 2..=128u8 {
+            let (bitmap, bit) = if field <= 64 {
+                (primary, 64 - field)
+            } else {
+                (secondary, 128 - field)
+            };
+            if bitmap & (1 << bit) == 0 {
+                continue;
+            }
+            let truncated = format!("truncated data element {}", field);
+            let value = match field_format(field).ok_or(Iso8583Error::UnsupportedField(field))? {
+                FieldFormat::Fixed(length) => take(length, &truncated)?,
+                FieldFormat::LlVar(max) => {
+                    let length = take(2, &truncated)?
+                        .parse()
+                        .ok()
+                        .filter(|length| *length <= max)
+                        .ok_or_else(|| {
+                            Iso8583Error::Malformed(format!(
+                                "invalid length of data element {}",
+                                field
+                            ))
+                        })?;
+                    take(length, &truncated)?
+                }
+            };
+            fields.insert(field, value.to_string());
+        }
+        if !rest.is_empty() {
+            return Err(malformed("trailing data"));
+        }
+        Ok(Iso8583Message {
+            mti: mti.to_string(),
+            fields,
+        })
+    }
+
+    /// Encodes the message in ASCII, the inverse of `parse`.
+    pub fn encode(&self) -> Result<String, Iso8583Error> {
+        let (mut primary, mut secondary) = (0u64, 0u64);
+        let mut data = String::new();
+        for (field, value) in self.fields.iter() {
+            let invalid = || Iso8583Error::Malformed(format!("invalid data element {}", field));
+            match field_format(*field).ok_or(Iso8583Error::UnsupportedField(*field))? {
+                FieldFormat::Fixed(length) if value.len() == length => data.push_str(value),
+                FieldFormat::LlVar(max) if value.len() <= max => {
+                    data.push_str(&format!("{:02}{}", value.len(), value))
+                }
+                _ => return Err(invalid()),
+            }
+            match field {
+                2..=64 => primary |= 1 << (64 - field),
+                65..=128 => secondary |= 1 << (128 - field),
+                _ => return Err(invalid()),
+            }
+        }
+        if secondary != 0 {
+            primary |= 1 << 63;
+        }
+        let mut message = format!("{}{:016X}", self.mti, primary);
+        if secondary != 0 {
+            message.push_str(&format!("{:016X}", secondary));
+        }
+        message.push_str(&data);
+        Ok(message)
+    }
+
+    fn required(&self, field: u8) -> Result<&str, Iso8583Error> {
+        self.field(field)
+            .ok_or_else(|| Iso8583Error::MissingField(self.mti.clone(), field))
+    }
+
+    /// The client of the message: its account id 1, or else its card acceptor id.
+    fn client_id(&self) -> Result<ClientId, Iso8583Error> {
+        let id = self.field(102).or_else(|| self.field(42));
+        let id = id.ok_or_else(|| Iso8583Error::MissingField(self.mti.clone(), 102))?;
+        id.trim()
+            .parse()
+            .map(ClientId)
+            .map_err(|_| Iso8583Error::Malformed(format!("invalid client id `{}`", id)))
+    }
+
+    /// The system trace audit number of the message.
+    fn trace_number(&self) -> Result<TransactionId, Iso8583Error> {
+        parse_trace_number(self.required(11)?)
+    }
+
+    /// The system trace audit number of the message the message refers to, from
+    /// its original data elements.
+    fn original_trace_number(&self) -> Result<TransactionId, Iso8583Error> {
+        let original = self.required(90)?;
+        parse_trace_number(original.get(4..10).unwrap_or(original))
+    }
+
+    /// The amount of the message, in the decimals of its currency.
+    fn amount(&self) -> Result<Price4, Iso8583Error> {
+        let amount = self.required(4)?;
+        let minor_units: i64 = amount
+            .parse()
+            .map_err(|_| Iso8583Error::Malformed(format!("invalid amount `{}`", amount)))?;
+        Ok(Price4::new(minor_units, currency_decimals(self.field(49))))
+    }
+
+    /// Maps the message onto the operations of the engine, for the merchant
+    /// whose client id is the message's account id 1 (or card acceptor id):
+    ///  - `0100` (authorization request): an authorization of its amount
+    ///  - `0200` (financial request): a deposit of its amount, or a withdrawal of
+    ///    it for a refund (processing code `20`)
+    ///  - `0220` (financial advice): a capture of the authorization it refers to
+    ///  - `0400` and `0420` (acquirer reversal): a reversal of the transaction it
+    ///    refers to
+    ///  - `0422` (issuer reversal advice): a dispute and a chargeback of the
+    ///    transaction it refers to
+    ///  - responses (`0110`, `0210`, `0230`, `0410`, `0430`): no operations
+    ///
+    /// Transactions are identified by the system trace audit number (data
+    /// element 11) of the message that started them, and other messages refer to
+    /// them by their original data elements (data element 90).
+    pub fn to_transactions(&self) -> Result<Vec<Transaction>, Iso8583Error> {
+        if matches!(
+            self.mti.as_str(),
+            "0110" | "0210" | "0230" | "0410" | "0430"
+        ) {
+            return Ok(Vec::new());
+        }
+        let client_id = self.client_id()?;
+        let transaction = match self.mti.as_str() {
+            "0100" => Transaction::Authorize(Authorize {
+                client_id,
+                tx_id: self.trace_number()?,
+                amount: self.amount()?,
+                expires_at: None,
+            }),
+            "0200" => {
+                let (tx_id, amount) = (self.trace_number()?, self.amount()?);
+                if self.field(3).is_some_and(|code| code.starts_with("20")) {
+                    Transaction::Withdrawal(Withdrawal {
+                        client_id,
+                        tx_id,
+                        amount,
+                    })
+                } else {
+                    Transaction::Deposit(Deposit {
+                        client_id,
+                        tx_id,
+                        amount,
+                    })
+                }
+            }
+            "0220" => Transaction::Capture(Capture {
+                client_id,
+                tx_id: self.original_trace_number()?,
+            }),
+            "0400" | "0420" => Transaction::Reversal(Reversal {
+                client_id,
+                tx_id: self.original_trace_number()?,
+            }),
+            "0422" => {
+                let tx_id = self.original_trace_number()?;
+                return Ok(vec![
+                    Transaction::Dispute(Dispute { client_id, tx_id }),
+                    Transaction::Chargeback(Chargeback { client_id, tx_id }),
+                ]);
+            }
+            mti => return Err(Iso8583Error::UnsupportedMessage(mti.to_string())),
+        };
+        Ok(vec![transaction])
+    }
+}
+
+fn parse_trace_number(number: &str) -> Result<TransactionId, Iso8583Error> {
+    number
+        .parse()
+        .map(TransactionId)
+        .map_err(|_| Iso8583Error::Malformed(format!("invalid trace number `{}`", number)))
+}
+
+/// The decimals of the minor unit of an ISO 4217 numeric currency code, which
+/// are 2 unless the currency is known to use others, or none is given.
+fn currency_decimals(currency: Option<&str>) -> u32 {
+    match currency {
+        // Japanese yen, South Korean won.
+        Some("392") | Some("410") => 0,
+        // Bahraini, Jordanian, Kuwaiti, Omani and Tunisian dinar.
+        Some("048") | Some("400") | Some("414") | Some("512") | Some("788") => 3,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_iso8583() {
+        let authorization = Iso8583Message::new("0100")
+            .with(3, "000000")
+            .with(4, "000000012550")
+            .with(11, "000042")
+            .with(49, "978")
+            .with(102, "7");
+        let encoded = authorization.encode().unwrap();
+        assert_eq!(
+            encoded,
+            "0100B0200000000080000000000004000000000000000000012550000042978017"
+        );
+        let parsed = Iso8583Message::parse(&encoded).unwrap();
+        assert_eq!(parsed, authorization);
+        assert_eq!(
+            parsed.to_transactions().unwrap(),
+            vec![Transaction::Authorize(Authorize {
+                client_id: ClientId(7),
+                tx_id: TransactionId(42),
+                amount: Price4::new(12550, 2),
+                expires_at: None,
+            })]
+        );
+
+        let original = format!("0100000042{}", "0".repeat(32));
+        let chargeback = Iso8583Message::new("0422")
+            .with(42, "7              ")
+            .with(90, &original);
+        let transactions = chargeback.to_transactions().unwrap();
+        assert!(matches!(
+            transactions[..],
+            [Transaction::Dispute(_), Transaction::Chargeback(Chargeback { tx_id, .. })]
+                if tx_id == TransactionId(42)
+        ));
+        assert_eq!(
+            Iso8583Message::parse("0100400000000000000012").unwrap_err(),
+            Iso8583Error::Malformed("truncated data element 2".to_string())
+        );
+        assert_eq!(
+            Iso8583Message::new("0800").with(42, "1").to_transactions(),
+            Err(Iso8583Error::UnsupportedMessage("0800".to_string()))
+        );
+    }
+}
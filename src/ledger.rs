@@ -0,0 +1,280 @@
+use crate::prelude::*;
+#[cfg(feature = "io")]
+use crate::Currency;
+use crate::{
+    Account, ClientId, Error, Operation, Price4, Timestamp, Transaction, TransactionId,
+    TransactionProcessor,
+};
+#[cfg(feature = "io")]
+use std::collections::HashSet;
+#[cfg(feature = "io")]
+use std::io::Write;
+#[cfg(feature = "io")]
+use std::str::FromStr;
+
+/// The account that balances the postings of funds entering or leaving the
+/// clients' accounts, e.g. deposits and withdrawals.
+pub const EXTERNAL_ACCOUNT: &str = "Equity:External";
+
+/// A plain-text accounting format journals are written in.
+#[cfg(feature = "io")]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LedgerFormat {
+    /// The format of ledger-cli and hledger.
+    #[default]
+    Ledger,
+    Beancount,
+}
+
+#[cfg(feature = "io")]
+impl FromStr for LedgerFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LedgerFormat, String> {
+        match s {
+            "ledger" => Ok(LedgerFormat::Ledger),
+            "beancount" => Ok(LedgerFormat::Beancount),
+            _ => Err(format!(
+                "expected `ledger` or `beancount` as ledger format, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// A change to the balance of an account in a journal entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Posting {
+    /// The account, e.g. `Assets:Clients:Client1:Available`.
+    pub account: String,
+    pub amount: Price4,
+}
+
+/// The balance effects of a processed transaction, one posting per changed kind
+/// of funds of each client, balanced by a posting to `EXTERNAL_ACCOUNT`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// The processor's time when the transaction was processed.
+    pub timestamp: Timestamp,
+    pub operation: Operation,
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub postings: Vec<Posting>,
+}
+
+/// The kinds of funds of an account, by the name of their ledger account.
+fn funds_of(account: Option<&Account>) -> [(&'static str, Price4); 5] {
+    let funds = |f: fn(&Account) -> Price4| account.map_or(Price4::ZERO, f);
+    [
+        ("Available", funds(Account::available_funds)),
+        ("Held", funds(Account::held_funds)),
+        ("Pending", funds(Account::pending_funds)),
+        ("PendingOut", funds(Account::pending_out_funds)),
+        ("Escrow", funds(Account::escrow_funds)),
+    ]
+}
+
+/// The funds of the clients a transaction may change, before it is processed.
+pub(crate) type FundsBefore = Vec<(ClientId, [(&'static str, Price4); 5])>;
+
+impl TransactionProcessor {
+    /// Records the balance effects of the transactions passed to `process` in a
+    /// journal if `enabled`, which is off by default.
+    pub fn set_journal(&mut self, enabled: bool) {
+        self.journal = if enabled { Some(Vec::new()) } else { None };
+    }
+
+    /// Removes and returns the journal entries of the transactions processed since
+    /// it was last taken, in order.
+    pub fn take_journal(&mut self) -> Vec<JournalEntry> {
+        self.journal
+            .as_mut()
+            .map(core::mem::take)
+            .unwrap_or_default()
+    }
+
+    /// The funds of `clients` if a journal is recorded, to post the changes of a
+    /// transaction to them once it is processed.
+    pub(crate) fn funds_before(&self, clients: &[ClientId]) -> Option<FundsBefore> {
+        self.journal.as_ref()?;
+        Some(
+            clients
+                .iter()
+                .map(|client_id| (*client_id, funds_of(self.accounts.get(*client_id))))
+                .collect(),
+        )
+    }
+
+    /// Records the changes of the funds from `before` made by `transaction`.
+    /// Returns `Error::PriceOverflow` if a change overflows, and records nothing.
+    pub(crate) fn record_journal(
+        &mut self,
+        transaction: &Transaction,
+        before: FundsBefore,
+    ) -> Result<(), Error> {
+        let mut postings = Vec::new();
+        for (client_id, funds) in before {
+            let after = funds_of(self.accounts.get(client_id));
+            for ((name, old), (_, new)) in funds.iter().zip(after.iter()) {
+                if new != old {
+                    postings.push(Posting {
+                        account: format!("Assets:Clients:Client{}:{}", client_id.0, name),
+                        amount: new
+                            .checked_sub(*old)
+                            .ok_or(Error::PriceOverflow(*new, *old))?,
+                    });
+                }
+            }
+        }
+        let external = postings.iter().try_fold(Price4::ZERO, |sum, posting| {
+            sum.checked_add(posting.amount)
+                .ok_or(Error::PriceOverflow(sum, posting.amount))
+        })?;
+        if !external.is_zero() {
+            postings.push(Posting {
+                account: EXTERNAL_ACCOUNT.to_string(),
+                amount: -external,
+            });
+        }
+        if postings.is_empty() {
+            return Ok(());
+        }
+        let timestamp = self.clock.now();
+        if let Some(journal) = self.journal.as_mut() {
+            journal.push(JournalEntry {
+                timestamp,
+                operation: Operation::of(transaction),
+                client_id: transaction.client_id(),
+                tx_id: transaction.tx_id(),
+                postings,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Writes journal entries in a plain-text accounting format. For Beancount, each
+/// account is opened on the date of its first entry.
+#[cfg(feature = "io")]
+pub struct LedgerWriter<W> {
+    writer: W,
+    format: LedgerFormat,
+    currency: Currency,
+    opened: HashSet<String>,
+}
+
+#[cfg(feature = "io")]
+impl<W: Write> LedgerWriter<W> {
+    pub fn new(writer: W, format: LedgerFormat, currency: Currency) -> LedgerWriter<W> {
+        LedgerWriter {
+            writer,
+            format,
+            currency,
+            opened: HashSet::new(),
+        }
+    }
+
+    pub fn write(&mut self, entry: &JournalEntry) -> std::io::Result<()> {
+        let (year, month, day) = entry.timestamp.date();
+        let date = format!("{:04}-{:02}-{:02}", year, month, day);
+        let description = format!(
+            "{} client {} tx {}",
+            entry.operation, entry.client_id.0, entry.tx_id.0
+        );
+        match self.format {
+            LedgerFormat::Ledger => writeln!(self.writer, "{} * {}", date, description)?,
+            LedgerFormat::Beancount => {
+                for posting in entry.postings.iter() {
+                    if self.opened.insert(posting.account.clone()) {
+                        writeln!(self.writer, "{} open {}", date, posting.account)?;
+                    }
+                }
+                writeln!(self.writer, "{} * \"{}\"", date, description)?;
+            }
+        }
+        for posting in entry.postings.iter() {
+            writeln!(
+                self.writer,
+                "    {}  {} {}",
+                posting.account, posting.amount, self.currency
+            )?;
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(all(test, feature = "io"))]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute};
+
+    #[test]
+    fn test_journal() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_journal(true);
+        processor.set_clock(Box::new(crate::ManualClock::new(Timestamp::from_secs(
+            1_700_000_000,
+        ))));
+        let (client_id, tx_id) = (ClientId(1), TransactionId(7));
+        processor
+            .process(Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(105, 1),
+            }))
+            .unwrap();
+        processor
+            .process(Transaction::Dispute(Dispute { client_id, tx_id }))
+            .unwrap();
+        let journal = processor.take_journal();
+        assert_eq!(journal.len(), 2);
+        assert_eq!(journal[1].postings.len(), 2);
+        assert!(processor.take_journal().is_empty());
+
+        let mut output = Vec::new();
+        let mut writer =
+            LedgerWriter::new(&mut output, LedgerFormat::Beancount, "EUR".parse().unwrap());
+        for entry in journal.iter() {
+            writer.write(entry).unwrap();
+        }
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "2023-11-14 open Assets:Clients:Client1:Available\n\
+             2023-11-14 open Equity:External\n\
+             2023-11-14 * \"deposit client 1 tx 7\"\n    \
+             Assets:Clients:Client1:Available  10.5 EUR\n    \
+             Equity:External  -10.5 EUR\n\
+             \n\
+             2023-11-14 open Assets:Clients:Client1:Held\n\
+             2023-11-14 * \"dispute client 1 tx 7\"\n    \
+             Assets:Clients:Client1:Available  -10.5 EUR\n    \
+             Assets:Clients:Client1:Held  10.5 EUR\n\
+             \n"
+        );
+    }
+
+    #[test]
+    fn test_journal_overflow() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_journal(true);
+        let (client_id, tx_id) = (ClientId(1), TransactionId(7));
+        let deposit = Transaction::Deposit(Deposit {
+            client_id,
+            tx_id,
+            amount: Price4::MAX,
+        });
+        processor.process(deposit).unwrap();
+        processor.take_journal();
+        let mut before = processor.funds_before(&[client_id]).unwrap();
+        before[0].1[0].1 = Price4::MIN;
+        assert!(matches!(
+            processor.record_journal(&deposit, before),
+            Err(Error::PriceOverflow(..))
+        ));
+        assert!(processor.take_journal().is_empty());
+    }
+}
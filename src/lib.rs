@@ -1,17 +1,196 @@
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::hash::Hash;
+use core::num::ParseIntError;
+use core::str::FromStr;
+#[cfg(not(feature = "std"))]
+use hashbrown::{HashMap, HashSet};
+use prelude::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, hash::Hash};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
 use thiserror::Error;
 
+/// The items of the standard prelude that are in `alloc`, which `no_std` builds
+/// import explicitly.
+mod prelude {
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::format;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec;
+    pub(crate) use alloc::vec::Vec;
+}
+
+mod admin;
+mod aml;
+mod batch;
+mod builder;
+mod bulk;
+#[cfg(feature = "server")]
+mod control;
+#[cfg(feature = "persistence")]
+mod dedup;
+#[cfg(feature = "persistence")]
+mod delta;
+mod dispute;
+mod dormancy;
+mod escrow;
+mod fees;
+mod fx;
+mod history;
+#[cfg(feature = "roxmltree")]
+mod iso20022;
+mod iso8583;
+mod ledger;
+mod limits;
+mod lint;
+#[cfg(feature = "io")]
+mod manifest;
+mod memory;
+#[cfg(any(feature = "bigdecimal", feature = "rusty-money"))]
+mod money;
+#[cfg(feature = "server")]
+mod parallel;
+mod period;
+mod rates;
+mod receivable;
+mod record;
+mod reorder;
+mod repository;
+mod risk;
+mod roles;
+mod rules;
+mod schedule;
+mod schema;
+#[cfg(feature = "rhai")]
+mod script;
+mod settlement;
+#[cfg(feature = "persistence")]
+mod snapshot;
+#[cfg(feature = "io")]
+mod statement;
+#[cfg(feature = "persistence")]
+mod store;
+mod tags;
+#[cfg(feature = "telemetry")]
+mod telemetry;
+#[cfg(feature = "io")]
+pub mod testing;
+#[cfg(feature = "server")]
+mod throttle;
+mod txstore;
+mod validator;
+
+pub use admin::{AuditEntry, FreezePolicy, HoldId, ManualHold, UnfreezePolicy};
+pub use aml::{AmlMonitor, AmlThresholds, SuspiciousActivity};
+pub use builder::{ConfigError, ProcessorBuilder};
+#[cfg(feature = "io")]
+pub use bulk::BulkError;
+#[cfg(feature = "server")]
+pub use control::{Health, RunControl};
+#[cfg(feature = "redis")]
+pub use dedup::RedisDedupStore;
+#[cfg(feature = "persistence")]
+pub use dedup::{DedupStore, MemoryDedupStore};
+#[cfg(feature = "persistence")]
+pub use delta::{AccountDelta, SnapshotDelta, TransactionDelta};
+pub use dispute::{DisputeNote, OpenDispute};
+pub use dormancy::DormancyPolicy;
+pub use escrow::{EscrowOpen, EscrowRefund, EscrowRelease};
+pub use fees::{Fee, FeeOperation, FeeSchedule, FeeTier, FeesError};
+pub use fx::{Convert, Currency, FxRates};
+pub use history::{AsOf, BalanceHistory, BalancePoint, BalanceStatement, HistoryInterval};
+#[cfg(feature = "roxmltree")]
+pub use iso20022::{load_iso20022, read_iso20022, Iso20022Entry, Iso20022Error};
+pub use iso8583::{Iso8583Error, Iso8583Message};
+pub use ledger::{JournalEntry, Posting, EXTERNAL_ACCOUNT};
+#[cfg(feature = "io")]
+pub use ledger::{LedgerFormat, LedgerWriter};
+#[cfg(feature = "std")]
+pub use limits::SystemClock;
+pub use limits::{BalanceCap, CapMode, Clock, DailyWithdrawalLimit, DailyWithdrawals, ManualClock};
+pub use lint::{Finding, Linter};
+#[cfg(feature = "io")]
+pub use manifest::{Manifest, ManifestEntry, ManifestError};
+pub use memory::MemoryStats;
+#[cfg(any(feature = "bigdecimal", feature = "rusty-money"))]
+pub use money::AmountError;
+#[cfg(feature = "bigdecimal")]
+pub use money::BigDecimalAmount;
+#[cfg(feature = "rusty-money")]
+pub use money::MoneyAmount;
+#[cfg(feature = "server")]
+pub use parallel::{JumpHashPartitioner, ModuloPartitioner, Partitioner, RangePartitioner};
+pub use period::{ClosedPeriod, ClosingBalance};
+pub use rates::{Rate, RateTable, RatesError};
+pub use receivable::Receivable;
+pub use record::{RecordKind, TransactionRecord};
+pub use reorder::{ReorderBuffer, ReorderWindow};
+pub use repository::{AccountRepository, MemoryAccountRepository};
+pub use risk::{DisputeLimitAction, Risk, RiskConfig, RiskConfigError};
+pub use roles::{Operation, Permissions, Role};
+pub use rules::{RuleOutcome, RuleSet, RulesError};
+pub use schedule::{Recurrence, ScheduledTransaction};
+pub use schema::{
+    check_record, record_schema, ColumnType, RecordColumn, Requirement, SchemaViolation,
+    RECORD_COLUMNS, RECORD_TYPES,
+};
+#[cfg(feature = "rhai")]
+pub use script::{Flag, Flags, ScriptError, ScriptValidator};
+pub use settlement::{Settlement, SettlementTracker};
+#[cfg(feature = "persistence")]
+pub use snapshot::{Checkpointer, Snapshot, SnapshotError, SNAPSHOT_VERSION};
+#[cfg(feature = "io")]
+pub use statement::{read_ofx, read_qif, read_statement, StatementEntry, StatementError};
+#[cfg(feature = "postgres")]
+pub use store::PostgresStore;
+#[cfg(feature = "rocksdb")]
+pub use store::RocksDbStore;
+#[cfg(feature = "sled")]
+pub use store::SledStore;
+#[cfg(feature = "persistence")]
+pub use store::{MemoryStore, StateStore, StoreEntry, StoreError};
+pub use tags::TagTotal;
+#[cfg(feature = "telemetry")]
+pub use telemetry::{LatencyHistogram, Telemetry};
+#[cfg(feature = "server")]
+pub use throttle::{ReplayPacer, ReplaySpeed, Throttle};
+pub use txstore::{
+    MemoryTransactionStore, SortedTransactionStore, TransactionStore, TransactionStoreFactory,
+};
+pub use validator::{
+    MinimumBalance, NonNegativeAmount, NotFrozen, RejectReason, SufficientFunds,
+    TransactionValidator,
+};
+
 // TODO: We should use a type that guarantees _exactly_ 4 digits behind the decimal.
 // `rust_decimal::Decimal` will accept arbitrary scale decimals -- these should be
 // rejected when parsing.
 pub type Price4 = rust_decimal::Decimal;
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 struct Funds {
     /// The funds available for withdrawing.
     available: Price4,
-    /// The funds that are put on a temporary hold for disputed transactions.
+    /// The funds that are put on a temporary hold for disputed transactions, or by
+    /// manual holds.
     held: Price4,
+    /// The funds of authorizations that are not captured yet. They are not part of
+    /// the total.
+    #[serde(default)]
+    pending: Price4,
+    /// The funds of withdrawals that are not settled yet. They are still part of
+    /// the total.
+    #[serde(default)]
+    pending_out: Price4,
+    /// The funds placed in escrow for another client, until they are released to
+    /// it or refunded. They are still part of the total.
+    #[serde(default)]
+    escrow: Price4,
 }
 
 impl Funds {
@@ -19,33 +198,135 @@ impl Funds {
         Funds {
             available: Price4::ZERO,
             held: Price4::ZERO,
+            pending: Price4::ZERO,
+            pending_out: Price4::ZERO,
+            escrow: Price4::ZERO,
         }
     }
 
     pub fn total(&self) -> Price4 {
+        // Adding to zero keeps the other operand's scale, so without pending out
+        // or escrow funds the total has the scale of the available and held funds.
         self.available
             .checked_add(self.held)
+            .and_then(|total| self.pending_out.checked_add(total))
+            .and_then(|total| self.escrow.checked_add(total))
             .expect("price overflow")
     }
 
     pub fn set(&mut self, available_funds: Price4, held_funds: Price4) -> Result<(), Error> {
-        if available_funds.checked_add(held_funds).is_none() {
-            return Err(Error::PriceOverflow(available_funds, held_funds));
-        }
+        self.set_with_pending_out(available_funds, held_funds, self.pending_out)
+    }
+
+    pub fn set_with_pending_out(
+        &mut self,
+        available_funds: Price4,
+        held_funds: Price4,
+        pending_out_funds: Price4,
+    ) -> Result<(), Error> {
+        check_total(available_funds, held_funds, pending_out_funds, self.escrow)?;
         self.available = available_funds;
         self.held = held_funds;
+        self.pending_out = pending_out_funds;
+        Ok(())
+    }
+
+    pub fn set_with_escrow(
+        &mut self,
+        available_funds: Price4,
+        escrow_funds: Price4,
+    ) -> Result<(), Error> {
+        check_total(available_funds, self.held, self.pending_out, escrow_funds)?;
+        self.available = available_funds;
+        self.escrow = escrow_funds;
         Ok(())
     }
 }
 
+/// Checks that the total of the funds does not overflow.
+fn check_total(
+    available_funds: Price4,
+    held_funds: Price4,
+    pending_out_funds: Price4,
+    escrow_funds: Price4,
+) -> Result<(), Error> {
+    let total = available_funds
+        .checked_add(held_funds)
+        .ok_or(Error::PriceOverflow(available_funds, held_funds))?;
+    let total = total
+        .checked_add(pending_out_funds)
+        .ok_or(Error::PriceOverflow(total, pending_out_funds))?;
+    total
+        .checked_add(escrow_funds)
+        .ok_or(Error::PriceOverflow(total, escrow_funds))?;
+    Ok(())
+}
+
 /// A client's latest account information.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Account {
     /// The funds in the account.
     funds: Funds,
     /// Whether or not the account is frozen.
     is_frozen: bool,
     /// The transactions made with this account.
-    txs: HashMap<TransactionId, FundTransaction>,
+    #[serde(with = "txstore")]
+    txs: Box<dyn TransactionStore>,
+    /// The client's risk score, if scored.
+    #[serde(default, skip_serializing_if = "Risk::is_default")]
+    risk: Risk,
+    /// The manual holds placed on the account, by their number.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    holds: BTreeMap<u32, ManualHold>,
+    /// The withdrawals of the latest day with any, if the client has a daily
+    /// withdrawal limit.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    daily_withdrawals: Option<DailyWithdrawals>,
+    /// The amounts the client owes after disputes and chargebacks, oldest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    receivables: Vec<Receivable>,
+    /// The balances in other currencies than the base currency, from conversions.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    balances: BTreeMap<Currency, Price4>,
+    /// The balances recorded after the account's transactions.
+    #[serde(default, skip_serializing_if = "BalanceHistory::is_empty")]
+    history: BalanceHistory,
+    /// The number of the client's transactions that were charged back.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    chargebacks: u32,
+    /// When the client's latest transaction was processed, if activity is tracked.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_activity: Option<Timestamp>,
+    /// Whether the account went dormant and was not reactivated since.
+    #[serde(default, skip_serializing_if = "is_false")]
+    dormant: bool,
+}
+
+pub(crate) fn is_zero(count: &u32) -> bool {
+    *count == 0
+}
+
+pub(crate) fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+/// Accounts are equal if they have the same state, however their transactions
+/// are stored.
+impl PartialEq for Account {
+    fn eq(&self, other: &Account) -> bool {
+        self.funds == other.funds
+            && self.is_frozen == other.is_frozen
+            && *self.txs == *other.txs
+            && self.risk == other.risk
+            && self.holds == other.holds
+            && self.daily_withdrawals == other.daily_withdrawals
+            && self.receivables == other.receivables
+            && self.balances == other.balances
+            && self.history == other.history
+            && self.chargebacks == other.chargebacks
+            && self.last_activity == other.last_activity
+            && self.dormant == other.dormant
+    }
 }
 
 impl Account {
@@ -53,7 +334,24 @@ impl Account {
         Account {
             funds: Funds::new(),
             is_frozen: false,
-            txs: HashMap::new(),
+            txs: Box::new(MemoryTransactionStore::default()),
+            risk: Risk::default(),
+            holds: BTreeMap::new(),
+            daily_withdrawals: None,
+            receivables: Vec::new(),
+            balances: BTreeMap::new(),
+            history: BalanceHistory::default(),
+            chargebacks: 0,
+            last_activity: None,
+            dormant: false,
+        }
+    }
+
+    /// Creates an account whose transactions are kept in `txs`.
+    pub fn with_transaction_store(txs: Box<dyn TransactionStore>) -> Account {
+        Account {
+            txs,
+            ..Account::new()
         }
     }
 
@@ -69,9 +367,63 @@ impl Account {
         self.funds.total()
     }
 
+    /// The funds of authorizations that are not captured yet.
+    pub fn pending_funds(&self) -> Price4 {
+        self.funds.pending
+    }
+
+    /// The funds of withdrawals that are not settled yet.
+    pub fn pending_out_funds(&self) -> Price4 {
+        self.funds.pending_out
+    }
+
+    /// The funds placed in escrow for other clients.
+    pub fn escrow_funds(&self) -> Price4 {
+        self.funds.escrow
+    }
+
     pub fn is_frozen(&self) -> bool {
         self.is_frozen
     }
+
+    /// The number of the client's transactions that were charged back.
+    pub fn chargebacks(&self) -> u32 {
+        self.chargebacks
+    }
+
+    /// When the client's latest transaction was processed, if a dormancy policy
+    /// tracked it.
+    pub fn last_activity(&self) -> Option<Timestamp> {
+        self.last_activity
+    }
+
+    pub fn risk(&self) -> Risk {
+        self.risk
+    }
+
+    /// The manual holds placed on the account, including released ones, by their
+    /// number.
+    pub fn holds(&self) -> &BTreeMap<u32, ManualHold> {
+        &self.holds
+    }
+
+    /// The withdrawals of the latest day with any, counted towards the client's
+    /// daily withdrawal limit. See `TransactionProcessor::remaining_withdrawal_allowance`
+    /// for what is left today.
+    pub fn daily_withdrawals(&self) -> Option<DailyWithdrawals> {
+        self.daily_withdrawals
+    }
+
+    /// Sets the state of the transaction `tx_id`, if it exists.
+    fn set_tx_state(&mut self, tx_id: TransactionId, state: TransactionState) {
+        self.txs.update(tx_id, &mut |tx| tx.state = state);
+    }
+}
+
+impl Default for Account {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 /// A unique id assigned to each client.
@@ -82,7 +434,125 @@ pub struct ClientId(u16);
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
 pub struct TransactionId(u32);
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+impl ClientId {
+    pub fn value(&self) -> u16 {
+        self.0
+    }
+}
+
+impl From<u16> for ClientId {
+    fn from(value: u16) -> ClientId {
+        ClientId(value)
+    }
+}
+
+/// Formats the id as its number, e.g. `1`.
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for ClientId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<ClientId, ParseIntError> {
+        s.parse().map(ClientId)
+    }
+}
+
+impl TransactionId {
+    pub fn value(&self) -> u32 {
+        self.0
+    }
+}
+
+impl From<u32> for TransactionId {
+    fn from(value: u32) -> TransactionId {
+        TransactionId(value)
+    }
+}
+
+/// Formats the id as its number, e.g. `1`.
+impl fmt::Display for TransactionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+impl FromStr for TransactionId {
+    type Err = ParseIntError;
+
+    fn from_str(s: &str) -> Result<TransactionId, ParseIntError> {
+        s.parse().map(TransactionId)
+    }
+}
+
+/// Why an administrative operation was made, as an operator-defined code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+pub struct ReasonCode(u16);
+
+/// A point in time, in seconds since the unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Ord, PartialOrd, Hash, Deserialize, Serialize)]
+pub struct Timestamp(u64);
+
+impl Timestamp {
+    pub fn from_secs(secs: u64) -> Timestamp {
+        Timestamp(secs)
+    }
+
+    pub fn as_secs(&self) -> u64 {
+        self.0
+    }
+
+    /// The UTC calendar date, as year, month and day.
+    pub fn date(&self) -> (u64, u32, u32) {
+        // Converts days since the epoch to a date in the proleptic Gregorian
+        // calendar, counting 400-year eras from 0000-03-01.
+        let days = self.0 / (24 * 60 * 60) + 719_468;
+        let era = days / 146_097;
+        let day_of_era = days % 146_097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_from_march = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_from_march + 2) / 5 + 1;
+        let month = if month_from_march < 10 {
+            month_from_march + 3
+        } else {
+            month_from_march - 9
+        };
+        let year = era * 400 + year_of_era + u64::from(month <= 2);
+        (year, month as u32, day as u32)
+    }
+
+    /// The start of a UTC calendar date. Returns `None` if the date is invalid,
+    /// before 1970-01-01 or too far in the future to be represented.
+    pub fn from_date(year: u64, month: u32, day: u32) -> Option<Timestamp> {
+        let leap =
+            year.is_multiple_of(4) && (!year.is_multiple_of(100) || year.is_multiple_of(400));
+        let days_in_month = match month {
+            2 if leap => 29,
+            2 => 28,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        if year < 1970 || day == 0 || day > days_in_month {
+            return None;
+        }
+        // The inverse of `date`.
+        let year = year - u64::from(month <= 2);
+        let (era, year_of_era) = (year / 400, year % 400);
+        let month_from_march = u64::from((month + 9) % 12);
+        let day_of_year = (153 * month_from_march + 2) / 5 + u64::from(day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era.checked_mul(146_097)?.checked_add(day_of_era)? - 719_468;
+        days.checked_mul(24 * 60 * 60).map(Timestamp)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 enum Side {
     Deposit,
     Withdrawal,
@@ -105,7 +575,7 @@ fn calculate_amount(x: Price4, op: Side, y: Price4) -> Result<Price4, Error> {
     res_opt.ok_or(Error::PriceOverflow(x, y))
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TransactionState {
     /// The transaction was successfully processed.
     Processed,
@@ -115,52 +585,340 @@ pub enum TransactionState {
     /// The dispute was handled. This either means the transaction was reversed
     /// successfully, or the transaction was deemed to not need to be reversed.
     DisputeHandled,
+    /// A chargeback of the disputed transaction was requested, and its funds stay
+    /// held until the chargeback is confirmed or the dispute is resolved.
+    ChargebackRequested,
+    /// The transaction is an authorization whose funds are pending until it is
+    /// captured.
+    Authorized,
+    /// The authorization expired before it was captured, releasing its funds.
+    Expired,
+    /// The transaction is a withdrawal whose funds are pending out until it is
+    /// settled.
+    PendingSettlement,
+    /// The withdrawal failed to settle, returning its funds.
+    Failed,
+    /// The transaction was reversed by a reversal or an administrator, undoing
+    /// its effect on the balance.
+    Reversed,
+    /// The transaction is an administrative adjustment, which cannot be disputed.
+    Adjusted,
+    /// The transaction placed funds in escrow for a payee, until they are
+    /// released or refunded.
+    InEscrow,
+    /// The escrowed funds were released to the payee.
+    Released,
+    /// The escrowed funds were refunded to the payer.
+    Refunded,
+    /// The transaction converted funds between currencies, which cannot be
+    /// disputed.
+    Converted,
 }
 
 /// A fund transaction represents either a deposit/withdraw.
-struct FundTransaction {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FundTransaction {
     tx_id: TransactionId,
     amount: Price4,
     side: Side,
     state: TransactionState,
+    /// When an authorization expires, if it does.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<Timestamp>,
+    /// The client escrowed funds are released to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    payee: Option<ClientId>,
+    /// The notes attached to the transaction's disputes.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    notes: Vec<DisputeNote>,
+    /// The amount its dispute holds, or held when it was closed, if less than the
+    /// transaction's amount, e.g. because the hold was capped at the available
+    /// funds or partly resolved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    held: Option<Price4>,
+    /// The free-form category of the transaction, e.g. `payroll` or `refund`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tag: Option<String>,
+    /// The accounting period the transaction was processed in.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    period: u32,
+}
+
+impl FundTransaction {
+    pub fn tx_id(&self) -> TransactionId {
+        self.tx_id
+    }
+
+    pub fn amount(&self) -> Price4 {
+        self.amount
+    }
+
+    pub fn state(&self) -> TransactionState {
+        self.state
+    }
+
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    pub fn period(&self) -> u32 {
+        self.period
+    }
 }
 
 /// Processes transactions and manages client account information.
+#[derive(Serialize, Deserialize)]
 pub struct TransactionProcessor {
-    accounts: HashMap<ClientId, Account>,
+    #[serde(deserialize_with = "repository::deserialize")]
+    accounts: Box<dyn AccountRepository>,
+    /// Validators are code, so they are not part of the serialized state; a
+    /// deserialized processor starts with the built-in ones.
+    #[serde(skip, default = "validator::builtin_validators")]
+    validators: Vec<Box<dyn TransactionValidator>>,
+    /// The policies, limits and modes the processor runs with.
+    #[serde(skip)]
+    policies: builder::Policies,
+    /// The authorizations that expire, ordered by when they do.
+    #[serde(default)]
+    expiries: BTreeSet<(Timestamp, ClientId, TransactionId)>,
+    /// The transactions held until their effective time, ordered by it.
+    #[serde(default)]
+    schedule: Vec<ScheduledTransaction>,
+    /// Whether the schedule changed since it was last persisted.
+    #[serde(skip)]
+    schedule_changed: bool,
+    /// The closed accounting periods.
+    #[serde(default)]
+    periods: period::Periods,
+    /// Whether a period was closed since the periods were last persisted.
+    #[serde(skip)]
+    periods_changed: bool,
+    /// The administrative operations applied since the log was last taken.
+    #[serde(skip)]
+    audit_log: Vec<AuditEntry>,
+    /// The balance effects of the transactions processed since the journal was
+    /// last taken, if a journal is recorded.
+    #[serde(skip)]
+    journal: Option<Vec<JournalEntry>>,
+    /// The clock daily limits are reset by, which is the system clock by default.
+    #[serde(skip, default = "default_clock")]
+    clock: Box<dyn Clock>,
+    /// The latencies of the processed transactions, which are not part of the
+    /// serialized state.
+    #[cfg(feature = "telemetry")]
+    #[serde(skip)]
+    telemetry: Telemetry,
+    /// Creates the transaction store of new accounts.
+    #[serde(skip, default = "default_transaction_store")]
+    transaction_store: TransactionStoreFactory,
+}
+
+#[cfg(feature = "std")]
+fn default_clock() -> Box<dyn Clock> {
+    Box::new(SystemClock)
 }
 
+/// Without `std` there is no system clock, so the clock stays at the unix epoch
+/// until one is set.
+#[cfg(not(feature = "std"))]
+fn default_clock() -> Box<dyn Clock> {
+    Box::new(ManualClock::default())
+}
+
+fn default_transaction_store() -> TransactionStoreFactory {
+    Box::new(|| Box::new(MemoryTransactionStore::default()))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Deposit {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
     pub amount: Price4,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Withdrawal {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
     pub amount: Price4,
 }
 
+/// Places `amount` in the client's pending funds until it is captured, or until
+/// `expires_at` if given.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Authorize {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Price4,
+    pub expires_at: Option<Timestamp>,
+}
+
+/// Makes the pending funds of the authorization `tx_id` available.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Capture {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Finalizes the pending withdrawal `tx_id`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Settle {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Returns the funds of the pending withdrawal `tx_id`, e.g. because the payout
+/// bounced.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Fail {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Dispute {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Resolve {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
+    /// The part of the held funds to release, keeping the rest held, or `None`
+    /// to release all of them.
+    #[serde(default)]
+    pub amount: Option<Price4>,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct Chargeback {
     pub client_id: ClientId,
     pub tx_id: TransactionId,
 }
 
+/// Proposes a chargeback of the disputed transaction `tx_id`, which keeps its
+/// funds held until a `ChargebackConfirm` charges it back.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChargebackRequest {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Charges back the transaction `tx_id` whose chargeback was requested.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChargebackConfirm {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Undoes the processed deposit or withdrawal `tx_id` immediately, e.g. a payout
+/// that was made twice, without a dispute.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Reversal {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+}
+
+/// Credits a positive `amount` to or debits a negative one from the client's
+/// available funds, as a manual correction outside the validators.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Adjustment {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: Price4,
+    pub reason: ReasonCode,
+}
+
+/// Any of the operations the processor applies to accounts.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Transaction {
+    Deposit(Deposit),
+    Withdrawal(Withdrawal),
+    Dispute(Dispute),
+    Resolve(Resolve),
+    Chargeback(Chargeback),
+    ChargebackRequest(ChargebackRequest),
+    ChargebackConfirm(ChargebackConfirm),
+    Authorize(Authorize),
+    Capture(Capture),
+    Settle(Settle),
+    Fail(Fail),
+    Reversal(Reversal),
+    Adjustment(Adjustment),
+    EscrowOpen(EscrowOpen),
+    EscrowRelease(EscrowRelease),
+    EscrowRefund(EscrowRefund),
+    Convert(Convert),
+}
+
+impl Transaction {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            Transaction::Deposit(deposit) => deposit.client_id,
+            Transaction::Withdrawal(withdrawal) => withdrawal.client_id,
+            Transaction::Dispute(dispute) => dispute.client_id,
+            Transaction::Resolve(resolve) => resolve.client_id,
+            Transaction::Chargeback(chargeback) => chargeback.client_id,
+            Transaction::ChargebackRequest(request) => request.client_id,
+            Transaction::ChargebackConfirm(confirm) => confirm.client_id,
+            Transaction::Authorize(authorize) => authorize.client_id,
+            Transaction::Capture(capture) => capture.client_id,
+            Transaction::Settle(settle) => settle.client_id,
+            Transaction::Fail(fail) => fail.client_id,
+            Transaction::Reversal(reversal) => reversal.client_id,
+            Transaction::Adjustment(adjustment) => adjustment.client_id,
+            Transaction::EscrowOpen(open) => open.client_id,
+            Transaction::EscrowRelease(release) => release.client_id,
+            Transaction::EscrowRefund(refund) => refund.client_id,
+            Transaction::Convert(convert) => convert.client_id,
+        }
+    }
+
+    /// The id of the deposit/withdrawal, or of the transaction referred to.
+    pub fn tx_id(&self) -> TransactionId {
+        match self {
+            Transaction::Deposit(deposit) => deposit.tx_id,
+            Transaction::Withdrawal(withdrawal) => withdrawal.tx_id,
+            Transaction::Dispute(dispute) => dispute.tx_id,
+            Transaction::Resolve(resolve) => resolve.tx_id,
+            Transaction::Chargeback(chargeback) => chargeback.tx_id,
+            Transaction::ChargebackRequest(request) => request.tx_id,
+            Transaction::ChargebackConfirm(confirm) => confirm.tx_id,
+            Transaction::Authorize(authorize) => authorize.tx_id,
+            Transaction::Capture(capture) => capture.tx_id,
+            Transaction::Settle(settle) => settle.tx_id,
+            Transaction::Fail(fail) => fail.tx_id,
+            Transaction::Reversal(reversal) => reversal.tx_id,
+            Transaction::Adjustment(adjustment) => adjustment.tx_id,
+            Transaction::EscrowOpen(open) => open.tx_id,
+            Transaction::EscrowRelease(release) => release.tx_id,
+            Transaction::EscrowRefund(refund) => refund.tx_id,
+            Transaction::Convert(convert) => convert.tx_id,
+        }
+    }
+
+    /// The amount of a deposit/withdrawal/authorization/adjustment/escrow, which
+    /// is negative for a debit adjustment.
+    pub fn amount(&self) -> Option<Price4> {
+        match self {
+            Transaction::Deposit(deposit) => Some(deposit.amount),
+            Transaction::Withdrawal(withdrawal) => Some(withdrawal.amount),
+            Transaction::Authorize(authorize) => Some(authorize.amount),
+            Transaction::Adjustment(adjustment) => Some(adjustment.amount),
+            Transaction::EscrowOpen(open) => Some(open.amount),
+            Transaction::Convert(convert) => Some(convert.amount),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("invalid transaction id {0:?}")]
     InvalidTx(TransactionId),
+    #[error("duplicate of already processed transaction {0:?}")]
+    DuplicateTx(TransactionId),
     #[error("invalid transaction state (expected {expected:?}, found {actual:?})")]
     InvalidTxState {
         actual: TransactionState,
@@ -170,62 +928,469 @@ pub enum Error {
     InvalidClientId(ClientId),
     #[error("invalid price provided")]
     InvalidPrice,
+    #[error("amount {0} has more than 4 decimals")]
+    TooManyDecimals(Price4),
+    #[error("account of client {0:?} already exists")]
+    AccountExists(ClientId),
     #[error("price overflow with {0:?} and {1:?}")]
     PriceOverflow(Price4, Price4),
-    #[error("account is frozen")]
-    AccountFrozen,
+    #[error("invalid or released hold {0:?}")]
+    InvalidHold(HoldId),
+    #[error(
+        "deposit of {requested:.4} would take total {total:.4} above the balance cap {cap:.4}"
+    )]
+    BalanceCapExceeded {
+        requested: Price4,
+        total: Price4,
+        cap: Price4,
+    },
+    #[error(
+        "withdrawal of {requested:.4} would take the {withdrawn:.4} withdrawn today above \
+         the daily limit {limit:.4}"
+    )]
+    DailyLimitExceeded {
+        requested: Price4,
+        withdrawn: Price4,
+        limit: Price4,
+    },
+    #[error(
+        "operation `{operation}` is not permitted {}",
+        .role.as_ref().map_or("without a role".to_string(), |role| format!("for role `{}`", role))
+    )]
+    Unauthorized {
+        role: Option<Role>,
+        operation: Operation,
+    },
+    #[error("no valid exchange rate from {from} to {to}")]
+    NoRate { from: Currency, to: Currency },
+    #[error("missing currency")]
+    MissingCurrency,
+    #[error("missing reason code")]
+    MissingReasonCode,
+    #[error("missing payee")]
+    MissingPayee,
+    #[error("missing note")]
+    MissingNote,
+    #[error("cannot merge account {duplicate:?} into {survivor:?}: {reason}")]
+    InvalidMerge {
+        survivor: ClientId,
+        duplicate: ClientId,
+        reason: &'static str,
+    },
+    #[error("resolve of {requested:.4} exceeds the {held:.4} held")]
+    ExcessiveResolve { requested: Price4, held: Price4 },
+    #[error("{0:?} records are not transactions")]
+    NotATransaction(RecordKind),
+    #[error("invalid recurring transaction: {0}")]
+    InvalidRecurrence(String),
+    #[error("transaction {tx_id:?} is in the closed period {period}")]
+    ClosedPeriod { tx_id: TransactionId, period: u32 },
+    #[error("batch rolled back: transaction {index} of the batch failed: {source}")]
+    BatchFailed {
+        index: usize,
+        #[source]
+        source: Box<Error>,
+    },
+    #[error(transparent)]
+    Rejected(#[from] RejectReason),
+}
+
+impl Error {
+    /// A stable name of the kind of error, e.g. `insufficient_funds`, for
+    /// machine-readable error reports.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Error::InvalidTx(_) => "invalid_tx",
+            Error::DuplicateTx(_) => "duplicate_tx",
+            Error::InvalidTxState { .. } => "invalid_tx_state",
+            Error::InvalidClientId(_) => "invalid_client",
+            Error::InvalidPrice => "invalid_price",
+            Error::TooManyDecimals(_) => "too_many_decimals",
+            Error::AccountExists(_) => "account_exists",
+            Error::PriceOverflow(..) => "overflow",
+            Error::InvalidHold(_) => "invalid_hold",
+            Error::BalanceCapExceeded { .. } => "balance_cap_exceeded",
+            Error::DailyLimitExceeded { .. } => "daily_limit_exceeded",
+            Error::Unauthorized { .. } => "unauthorized",
+            Error::NoRate { .. } => "no_rate",
+            Error::MissingCurrency => "missing_currency",
+            Error::MissingReasonCode => "missing_reason",
+            Error::MissingPayee => "missing_payee",
+            Error::MissingNote => "missing_note",
+            Error::InvalidMerge { .. } => "invalid_merge",
+            Error::ExcessiveResolve { .. } => "excessive_resolve",
+            Error::NotATransaction(_) => "not_a_transaction",
+            Error::InvalidRecurrence(_) => "invalid_recurrence",
+            Error::ClosedPeriod { .. } => "closed_period",
+            Error::BatchFailed { .. } => "batch_failed",
+            Error::Rejected(reason) => reason.code(),
+        }
+    }
+}
+
+/// The deposit or withdrawal of the opposite direction of a negative one.
+fn unsign(transaction: Transaction) -> Transaction {
+    match transaction {
+        Transaction::Deposit(deposit) if deposit.amount < Price4::ZERO => {
+            Transaction::Withdrawal(Withdrawal {
+                client_id: deposit.client_id,
+                tx_id: deposit.tx_id,
+                amount: -deposit.amount,
+            })
+        }
+        Transaction::Withdrawal(withdrawal) if withdrawal.amount < Price4::ZERO => {
+            Transaction::Deposit(Deposit {
+                client_id: withdrawal.client_id,
+                tx_id: withdrawal.tx_id,
+                amount: -withdrawal.amount,
+            })
+        }
+        transaction => transaction,
+    }
+}
+
+fn validate(
+    validators: &[Box<dyn TransactionValidator>],
+    account: &Account,
+    transaction: &Transaction,
+) -> Result<(), Error> {
+    for validator in validators.iter() {
+        validator.validate(account, transaction)?;
+    }
+    Ok(())
+}
+
+/// Validates a dispute, resolve or chargeback of `account`, as if the account was
+/// not frozen if `allow_frozen`.
+fn validate_dispute_step(
+    validators: &[Box<dyn TransactionValidator>],
+    account: &mut Account,
+    transaction: &Transaction,
+    allow_frozen: bool,
+) -> Result<(), Error> {
+    let is_frozen = account.is_frozen;
+    account.is_frozen &= !allow_frozen;
+    let result = validate(validators, account, transaction);
+    account.is_frozen = is_frozen;
+    result
+}
+
+/// Puts the funds of the processed transaction `tx_id` on hold and marks it as
+/// disputed.
+fn hold(account: &mut Account, tx_id: TransactionId) -> Result<(), Error> {
+    let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+    // Held funds are increased, available funds are decreased.
+    let opp_side = tx.side.opposite();
+    let held_funds = calculate_amount(account.funds.held, tx.side, tx.amount)?;
+    let available_funds = calculate_amount(account.funds.available, opp_side, tx.amount)?;
+    account.funds.set(available_funds, held_funds)?;
+    account.set_tx_state(tx_id, TransactionState::InDispute);
+    Ok(())
+}
+
+/// Like `hold`, but holds at most the available funds for a deposit, so they do
+/// not go negative. Returns the part of the amount that is not held.
+fn hold_capped(account: &mut Account, tx_id: TransactionId) -> Result<Price4, Error> {
+    let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+    let held = match tx.side {
+        Side::Deposit => tx.amount.min(account.funds.available.max(Price4::ZERO)),
+        Side::Withdrawal => tx.amount,
+    };
+    if held == tx.amount {
+        hold(account, tx_id)?;
+        return Ok(Price4::ZERO);
+    }
+    let held_funds = calculate_amount(account.funds.held, Side::Deposit, held)?;
+    let available_funds = calculate_amount(account.funds.available, Side::Withdrawal, held)?;
+    account.funds.set(available_funds, held_funds)?;
+    account.txs.update(tx_id, &mut |tx| {
+        tx.state = TransactionState::InDispute;
+        tx.held = Some(held);
+    });
+    Ok(tx.amount - held)
+}
+
+/// Undoes the effect of the processed transaction `tx_id` on the available funds
+/// and marks it as reversed. Returns the change of the available funds.
+fn reverse(account: &mut Account, tx_id: TransactionId) -> Result<Price4, Error> {
+    let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+    let available_funds = calculate_amount(account.funds.available, tx.side.opposite(), tx.amount)?;
+    account.funds.set(available_funds, account.funds.held)?;
+    account.set_tx_state(tx_id, TransactionState::Reversed);
+    Ok(match tx.side {
+        Side::Deposit => -tx.amount,
+        Side::Withdrawal => tx.amount,
+    })
 }
 
 fn check_tx_state(actual: TransactionState, expected: TransactionState) -> Result<(), Error> {
     if actual != expected {
         return Err(Error::InvalidTxState { actual, expected });
     }
-    return Ok(());
+    Ok(())
+}
+
+impl Default for TransactionProcessor {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl TransactionProcessor {
+    /// Creates a processor with the built-in validators, which reject negative
+    /// amounts, withdrawals of more than the available funds, and any transaction
+    /// on a frozen account.
     pub fn new() -> TransactionProcessor {
         TransactionProcessor {
-            accounts: HashMap::new(),
+            accounts: Box::new(MemoryAccountRepository::default()),
+            validators: validator::builtin_validators(),
+            policies: builder::Policies::default(),
+            expiries: BTreeSet::new(),
+            schedule: Vec::new(),
+            schedule_changed: false,
+            periods: Default::default(),
+            periods_changed: false,
+            audit_log: Vec::new(),
+            journal: None,
+            clock: default_clock(),
+            #[cfg(feature = "telemetry")]
+            telemetry: Telemetry::default(),
+            transaction_store: default_transaction_store(),
+        }
+    }
+
+    /// Makes withdrawals pending until a `Settle` finalizes them or a `Fail`
+    /// returns their funds. The default is to finalize them immediately.
+    pub fn set_settle_withdrawals(&mut self, settle: bool) {
+        self.policies.settle_withdrawals = settle;
+    }
+
+    /// Makes a `Chargeback` only request the chargeback, which a
+    /// `ChargebackConfirm` must confirm, e.g. so a malformed input file cannot
+    /// freeze accounts on its own. The default is to charge back immediately.
+    pub fn set_confirm_chargebacks(&mut self, confirm: bool) {
+        self.policies.confirm_chargebacks = confirm;
+    }
+
+    /// Processes disputes, resolves and chargebacks of frozen accounts, e.g. to
+    /// settle a client's remaining open disputes after a chargeback froze its
+    /// account. By default, frozen accounts reject them like any transaction.
+    pub fn set_frozen_disputes(&mut self, allow: bool) {
+        self.policies.frozen_disputes = allow;
+    }
+
+    /// Processes a deposit of a negative amount as a withdrawal of the amount, and
+    /// a negative withdrawal as a deposit, e.g. for ledgers that record refunds
+    /// as negative deposits. By default, negative amounts are rejected.
+    pub fn set_signed_amounts(&mut self, signed: bool) {
+        self.policies.signed_amounts = signed;
+    }
+
+    /// Rejects disputes of withdrawals that would take the held funds negative,
+    /// with `RejectReason::NegativeHeldFunds`. By default, disputing a withdrawal
+    /// holds its negated amount like any transaction.
+    pub fn set_forbid_negative_held(&mut self, forbid: bool) {
+        self.policies.forbid_negative_held = forbid;
+    }
+
+    /// Makes disputes of deposits hold at most the available funds, so they do not
+    /// go negative, and records the part that is not held in the audit log as a
+    /// `shortfall`. Resolves and chargebacks then release only what was held. By
+    /// default, disputes hold the whole amount, even if that takes the available
+    /// funds negative.
+    pub fn set_cap_dispute_holds(&mut self, cap: bool) {
+        self.policies.cap_dispute_holds = cap;
+    }
+
+    /// Sets when chargebacks freeze the client's account: always, the default,
+    /// never, or only once the client has had a number of them.
+    pub fn set_freeze_policy(&mut self, policy: FreezePolicy) {
+        self.policies.freeze_policy = policy;
+    }
+
+    /// Caps the total balance deposits can take clients to, or removes the cap.
+    pub fn set_balance_cap(&mut self, cap: Option<BalanceCap>) {
+        self.policies.balance_cap = cap;
+    }
+
+    /// Limits the total clients can withdraw per day, or removes the limit.
+    pub fn set_withdrawal_limit(&mut self, limit: Option<DailyWithdrawalLimit>) {
+        self.policies.withdrawal_limit = limit;
+    }
+
+    /// Sets the clock daily limits are reset by. The default is the system clock.
+    pub fn set_clock(&mut self, clock: Box<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets how the transactions of accounts are stored, and moves the
+    /// transactions of existing accounts to new stores. The default is a
+    /// `MemoryTransactionStore`.
+    pub fn set_transaction_store(&mut self, transaction_store: TransactionStoreFactory) {
+        for (_, account) in self.accounts.iter_mut() {
+            let mut txs = transaction_store();
+            for tx in account.txs.transactions() {
+                txs.insert(tx);
+            }
+            account.txs = txs;
         }
+        self.transaction_store = transaction_store;
+    }
+
+    /// Sets where the accounts are kept, and moves the existing accounts there.
+    /// The default is a `MemoryAccountRepository`.
+    pub fn set_account_repository(&mut self, mut repository: Box<dyn AccountRepository>) {
+        for (client_id, account) in self.accounts.drain() {
+            repository.insert(client_id, account);
+        }
+        self.accounts = repository;
+    }
+
+    /// The account of `client_id`, which is opened with a new transaction store
+    /// if it does not exist.
+    fn account_mut(&mut self, client_id: ClientId) -> &mut Account {
+        let transaction_store = &self.transaction_store;
+        self.accounts.get_or_create(client_id, &mut || {
+            Account::with_transaction_store(transaction_store())
+        })
+    }
+
+    /// What `client_id` can still withdraw today, if it has a daily withdrawal
+    /// limit.
+    pub fn remaining_withdrawal_allowance(&self, client_id: ClientId) -> Option<Price4> {
+        let limit = self.policies.withdrawal_limit.as_ref()?;
+        let account = self.accounts.get(client_id).cloned().unwrap_or_default();
+        limit.remaining(client_id, &account, self.clock.now())
+    }
+
+    /// Adds a validator, run after the ones added before it.
+    pub fn add_validator(&mut self, validator: Box<dyn TransactionValidator>) {
+        self.validators.push(validator);
+    }
+
+    /// Processes any kind of transaction, see the `process_*` functions. With a
+    /// journal, also returns `Error::PriceOverflow` if the changes of a processed
+    /// transaction overflow, and leaves it out of the journal.
+    pub fn process(&mut self, transaction: Transaction) -> Result<(), Error> {
+        #[cfg(feature = "telemetry")]
+        let started = std::time::Instant::now();
+        let transaction = if self.policies.signed_amounts {
+            unsign(transaction)
+        } else {
+            transaction
+        };
+        let client_id = transaction.client_id();
+        // Releasing an escrow changes the payee's balances as well.
+        let payee = match transaction {
+            Transaction::EscrowRelease(release) => self
+                .accounts
+                .get(client_id)
+                .and_then(|account| account.txs.get(release.tx_id))
+                .and_then(|tx| tx.payee),
+            _ => None,
+        };
+        let clients: Vec<_> = core::iter::once(client_id).chain(payee).collect();
+        let before = self.funds_before(&clients);
+        let result = match transaction {
+            Transaction::Deposit(deposit) => self.process_deposit(deposit),
+            Transaction::Withdrawal(withdrawal) => self.process_withdrawal(withdrawal),
+            Transaction::Dispute(dispute) => self.process_dispute(dispute),
+            Transaction::Resolve(resolve) => self.process_resolve(resolve),
+            Transaction::Chargeback(chargeback) => self.process_chargeback(chargeback),
+            Transaction::ChargebackRequest(request) => self.process_chargeback_request(request),
+            Transaction::ChargebackConfirm(confirm) => self.process_chargeback_confirm(confirm),
+            Transaction::Authorize(authorize) => self.process_authorize(authorize),
+            Transaction::Capture(capture) => self.process_capture(capture),
+            Transaction::Settle(settle) => self.process_settle(settle),
+            Transaction::Fail(fail) => self.process_fail(fail),
+            Transaction::Reversal(reversal) => self.process_reversal(reversal),
+            Transaction::Adjustment(adjustment) => self.process_adjustment(adjustment),
+            Transaction::EscrowOpen(open) => self.process_escrow_open(open),
+            Transaction::EscrowRelease(release) => self.process_escrow_release(release),
+            Transaction::EscrowRefund(refund) => self.process_escrow_refund(refund),
+            Transaction::Convert(convert) => self.process_convert(convert),
+        };
+        // The transaction is processed even if its journal entry overflows.
+        let result = result.and_then(|()| {
+            let journaled = match before {
+                Some(before) => self.record_journal(&transaction, before),
+                None => Ok(()),
+            };
+            self.record_balance(client_id, &transaction);
+            self.record_activity(client_id);
+            if let Some(payee) = payee {
+                self.record_balance(payee, &transaction);
+            }
+            journaled
+        });
+        #[cfg(feature = "telemetry")]
+        self.telemetry.record(Operation::of(&transaction), started);
+        result
     }
 
     /// Deposits `amount` value into `client_id`'s available balance as part of
-    /// the transaction `tx_id`.
+    /// the transaction `tx_id`. With a balance cap in `CapMode::Partial`, only
+    /// the part up to the cap is deposited. With an unfreeze policy, deposits are
+    /// accepted into frozen accounts, and unfreeze them once their total reaches
+    /// the policy's threshold.
     /// Returns an error if:
     ///  - the transaction id is already used from another deposit/withdrawal
-    ///  - the account is frozen
-    ///  - `amount` is negative
+    ///    (`Error::DuplicateTx` if that deposit had the same amount)
+    ///  - a validator rejects it, e.g. because the account is frozen or `amount`
+    ///    is negative
+    ///  - it would take the total above the client's balance cap
+    ///    (`Error::BalanceCapExceeded`)
+    ///
     /// This function does not panic.
     pub fn process_deposit(&mut self, deposit: Deposit) -> Result<(), Error> {
         self.process_tx(
-            deposit.client_id,
             FundTransaction {
                 tx_id: deposit.tx_id,
                 amount: deposit.amount,
                 side: Side::Deposit,
                 state: TransactionState::Processed,
+                expires_at: None,
+                payee: None,
+                notes: Vec::new(),
+                held: None,
+                tag: None,
+                period: 0,
             },
+            Transaction::Deposit(deposit),
         )
     }
 
     /// Withdraws `amount` value from `client_id`'s available balance as part of
-    /// the transaction `tx_id`.
+    /// the transaction `tx_id`. If withdrawals are settled, the funds are pending
+    /// out until the withdrawal is settled or fails.
     /// Returns an error if:
     ///  - the transaction id is already used from another deposit/withdrawal
-    ///  - the available balance in the account is less than `amount`
-    ///  - the account is frozen
-    ///  - `amount` is negative
+    ///    (`Error::DuplicateTx` if that withdrawal had the same amount)
+    ///  - a validator rejects it, e.g. because the available balance in the
+    ///    account is less than `amount`, the account is frozen or `amount` is
+    ///    negative
+    ///  - it would take the client's withdrawals today above its daily limit
+    ///    (`Error::DailyLimitExceeded`)
+    ///
     /// This function does not panic.
     pub fn process_withdrawal(&mut self, withdrawal: Withdrawal) -> Result<(), Error> {
         self.process_tx(
-            withdrawal.client_id,
             FundTransaction {
                 tx_id: withdrawal.tx_id,
                 amount: withdrawal.amount,
                 side: Side::Withdrawal,
-                state: TransactionState::Processed,
+                state: match self.policies.settle_withdrawals {
+                    true => TransactionState::PendingSettlement,
+                    false => TransactionState::Processed,
+                },
+                expires_at: None,
+                payee: None,
+                notes: Vec::new(),
+                held: None,
+                tag: None,
+                period: 0,
             },
+            Transaction::Withdrawal(withdrawal),
         )
     }
 
@@ -235,113 +1400,589 @@ impl TransactionProcessor {
     /// Returns an error if:
     ///  - the transaction id `tx_id` doesn't exist for client `client_id`
     ///  - the transaction was already disputed / resolved / chargebacked.
-    ///  - the account is frozen
+    ///  - the transaction is in a closed accounting period
+    ///    (`Error::ClosedPeriod`)
+    ///  - a validator rejects it, e.g. because the account is frozen, unless
+    ///    disputes of frozen accounts are allowed
+    ///  - it would take the held funds negative, if that is forbidden
+    ///
+    /// If dispute holds are capped, only the available funds are held.
+    ///
     /// This function does not panic.
     pub fn process_dispute(&mut self, dispute: Dispute) -> Result<(), Error> {
+        self.check_open_period(dispute.client_id, dispute.tx_id, Operation::Dispute)?;
         let (client_id, tx_id) = (dispute.client_id, dispute.tx_id);
-        let account = self.get_account(client_id)?;
-        let tx = account.txs.get_mut(&tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
         check_tx_state(tx.state, TransactionState::Processed)?;
-
-        // Held funds are increased, available funds are decreased.
-        let opp_side = tx.side.opposite();
-        let held_funds = calculate_amount(account.funds.held, tx.side, tx.amount)?;
-        let available_funds = calculate_amount(account.funds.available, opp_side, tx.amount)?;
-        account.funds.set(available_funds, held_funds)?;
-        tx.state = TransactionState::InDispute;
-
+        validate_dispute_step(
+            &self.validators,
+            account,
+            &Transaction::Dispute(dispute),
+            self.policies.frozen_disputes,
+        )?;
+        if self.policies.forbid_negative_held {
+            let held = calculate_amount(account.funds.held, tx.side, tx.amount)?;
+            if held < Price4::ZERO {
+                return Err(RejectReason::NegativeHeldFunds { held }.into());
+            }
+        }
+        if !self.policies.cap_dispute_holds {
+            hold(account, tx_id)?;
+        } else {
+            let shortfall = hold_capped(account, tx_id)?;
+            if !shortfall.is_zero() {
+                self.audit(AuditEntry {
+                    client_id: Some(client_id),
+                    tx_id: Some(tx_id),
+                    action: "shortfall",
+                    amount: -shortfall,
+                    ..AuditEntry::default()
+                });
+            }
+        }
+        self.track_receivables(&Transaction::Dispute(dispute));
+        self.score_risk(&Transaction::Dispute(dispute));
         Ok(())
     }
 
     /// Marks the dispute for transaction `tx_id` for client `client_id` as resolved.
     /// The funds associated with this transaction are removed from holding and placed
-    /// back into the client's available balance.
+    /// back into the client's available balance. A dispute whose chargeback was
+    /// requested can still be resolved. A resolve with an amount releases only that
+    /// part of the held funds, and the dispute stays open with the rest held until a
+    /// later resolve or chargeback.
     /// Returns an error if:
     ///  - the transaction id `tx_id` doesn't exist for client `client_id`
     ///  - the transaction is not disputed
-    ///  - the account is frozen
+    ///  - the amount is negative, or more than the dispute holds
+    ///    (`Error::ExcessiveResolve`)
+    ///  - a validator rejects it, e.g. because the account is frozen, unless
+    ///    disputes of frozen accounts are allowed
+    ///
     /// This function does not panic.
     pub fn process_resolve(&mut self, resolve: Resolve) -> Result<(), Error> {
         let (client_id, tx_id) = (resolve.client_id, resolve.tx_id);
-        let account = self.get_account(client_id)?;
-        let tx = account.txs.get_mut(&tx_id).ok_or(Error::InvalidTx(tx_id))?;
-        check_tx_state(tx.state, TransactionState::InDispute)?;
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        if tx.state != TransactionState::ChargebackRequested {
+            check_tx_state(tx.state, TransactionState::InDispute)?;
+        }
+        validate_dispute_step(
+            &self.validators,
+            account,
+            &Transaction::Resolve(resolve),
+            self.policies.frozen_disputes,
+        )?;
+
+        let held = tx.held.unwrap_or(tx.amount);
+        let amount = resolve.amount.unwrap_or(held);
+        if amount < Price4::ZERO {
+            return Err(RejectReason::NegativeAmount { amount }.into());
+        }
+        if amount > held {
+            return Err(Error::ExcessiveResolve {
+                requested: amount,
+                held,
+            });
+        }
 
         // Held funds are decreased, available funds are increased.
         let opp_side = tx.side.opposite();
-        let held_funds = calculate_amount(account.funds.held, opp_side, tx.amount)?;
-        let available_funds = calculate_amount(account.funds.available, tx.side, tx.amount)?;
+        let held_funds = calculate_amount(account.funds.held, opp_side, amount)?;
+        let available_funds = calculate_amount(account.funds.available, tx.side, amount)?;
         account.funds.set(available_funds, held_funds)?;
-        tx.state = TransactionState::DisputeHandled;
+        if amount < held {
+            account
+                .txs
+                .update(tx_id, &mut |tx| tx.held = Some(held - amount));
+        } else {
+            account.set_tx_state(tx_id, TransactionState::DisputeHandled);
+        }
 
+        self.track_receivables(&Transaction::Resolve(resolve));
+        self.score_risk(&Transaction::Resolve(resolve));
         Ok(())
     }
 
     /// Completes the dispute for transaction `tx_id` for client `client_id` by reversing
-    /// the transaction. The funds are removed from holding and the account is marked frozen.
+    /// the transaction. The funds are removed from holding and the account is marked frozen,
+    /// unless the freeze policy spares it. If chargebacks must be confirmed, this only requests the chargeback, like
+    /// `process_chargeback_request`.
     /// Returns an error if:
     ///  - the transaction id `tx_id` doesn't exist for client `client_id`
     ///  - the transaction is not disputed
-    ///  - the account is already frozen
+    ///  - a validator rejects it, e.g. because the account is already frozen, unless
+    ///    disputes of frozen accounts are allowed
+    ///
     /// This function does not panic.
     pub fn process_chargeback(&mut self, chargeback: Chargeback) -> Result<(), Error> {
-        let (client_id, tx_id) = (chargeback.client_id, chargeback.tx_id);
-        let account = self.get_account(client_id)?;
-        let tx = account.txs.get_mut(&tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        if self.policies.confirm_chargebacks {
+            return self.process_chargeback_request(ChargebackRequest {
+                client_id: chargeback.client_id,
+                tx_id: chargeback.tx_id,
+            });
+        }
+        self.charge_back(
+            Transaction::Chargeback(chargeback),
+            TransactionState::InDispute,
+        )
+    }
+
+    /// Requests a chargeback of the disputed transaction `tx_id` for client
+    /// `client_id`. The funds stay held and the account is not frozen until the
+    /// chargeback is confirmed.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not disputed, or its chargeback was already requested
+    ///  - a validator rejects it, e.g. because the account is frozen, unless
+    ///    disputes of frozen accounts are allowed
+    ///
+    /// This function does not panic.
+    pub fn process_chargeback_request(&mut self, request: ChargebackRequest) -> Result<(), Error> {
+        let (client_id, tx_id) = (request.client_id, request.tx_id);
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
         check_tx_state(tx.state, TransactionState::InDispute)?;
+        validate_dispute_step(
+            &self.validators,
+            account,
+            &Transaction::ChargebackRequest(request),
+            self.policies.frozen_disputes,
+        )?;
+        account.set_tx_state(tx_id, TransactionState::ChargebackRequested);
 
-        // Held funds are decreased and account marked frozen.
+        self.score_risk(&Transaction::ChargebackRequest(request));
+        Ok(())
+    }
+
+    /// Confirms the requested chargeback of transaction `tx_id` for client
+    /// `client_id`, which then completes like `process_chargeback`.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - no chargeback of the transaction was requested
+    ///  - a validator rejects it, e.g. because the account is already frozen, unless
+    ///    disputes of frozen accounts are allowed
+    ///
+    /// This function does not panic.
+    pub fn process_chargeback_confirm(&mut self, confirm: ChargebackConfirm) -> Result<(), Error> {
+        self.charge_back(
+            Transaction::ChargebackConfirm(confirm),
+            TransactionState::ChargebackRequested,
+        )
+    }
+
+    /// Charges back the transaction of `transaction`, which must be in the
+    /// `expected` state.
+    fn charge_back(
+        &mut self,
+        transaction: Transaction,
+        expected: TransactionState,
+    ) -> Result<(), Error> {
+        let (client_id, tx_id) = (transaction.client_id(), transaction.tx_id());
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, expected)?;
+        validate_dispute_step(
+            &self.validators,
+            account,
+            &transaction,
+            self.policies.frozen_disputes,
+        )?;
+
+        // Held funds are decreased and account marked frozen, unless the freeze
+        // policy spares it.
         let opp_side = tx.side.opposite();
-        let held_funds = calculate_amount(account.funds.held, opp_side, tx.amount)?;
+        let amount = tx.held.unwrap_or(tx.amount);
+        let held_funds = calculate_amount(account.funds.held, opp_side, amount)?;
         account.funds.set(account.funds.available, held_funds)?;
-        account.is_frozen = true;
-        tx.state = TransactionState::DisputeHandled;
+        account.chargebacks = account.chargebacks.saturating_add(1);
+        if self.policies.freeze_policy.freezes(account.chargebacks) {
+            account.is_frozen = true;
+        }
+        account.set_tx_state(tx_id, TransactionState::DisputeHandled);
 
+        self.track_receivables(&transaction);
+        self.score_risk(&transaction);
         Ok(())
     }
 
-    pub fn accounts(&self) -> &HashMap<ClientId, Account> {
-        &self.accounts
+    /// Reverses the transaction `tx_id` for client `client_id` immediately: a
+    /// deposit's funds are removed from the available balance, and a withdrawal's
+    /// funds are placed back into it. Unlike a chargeback, the funds are not held
+    /// first and the account is not frozen.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not a processed deposit or withdrawal, e.g. because
+    ///    it is disputed or was already reversed
+    ///  - the transaction is in a closed accounting period
+    ///    (`Error::ClosedPeriod`)
+    ///  - a validator rejects it, e.g. because the account is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_reversal(&mut self, reversal: Reversal) -> Result<(), Error> {
+        self.check_open_period(reversal.client_id, reversal.tx_id, Operation::Reversal)?;
+        let (client_id, tx_id) = (reversal.client_id, reversal.tx_id);
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, TransactionState::Processed)?;
+        validate(&self.validators, account, &Transaction::Reversal(reversal))?;
+        reverse(account, tx_id)?;
+        self.score_risk(&Transaction::Reversal(reversal));
+        Ok(())
     }
 
-    fn process_tx(&mut self, client_id: ClientId, tx: FundTransaction) -> Result<(), Error> {
-        if tx.amount < Price4::ZERO {
-            return Err(Error::InvalidPrice);
+    /// Places `amount` in `client_id`'s pending funds as part of the transaction
+    /// `tx_id`. The funds are not available until the authorization is captured,
+    /// and are released if it expires first.
+    /// Returns an error if:
+    ///  - the transaction id is already used from another transaction
+    ///  - a validator rejects it, e.g. because the account is frozen or `amount`
+    ///    is negative
+    ///
+    /// This function does not panic.
+    pub fn process_authorize(&mut self, authorize: Authorize) -> Result<(), Error> {
+        self.process_tx(
+            FundTransaction {
+                tx_id: authorize.tx_id,
+                amount: authorize.amount,
+                side: Side::Deposit,
+                state: TransactionState::Authorized,
+                expires_at: authorize.expires_at,
+                payee: None,
+                notes: Vec::new(),
+                held: None,
+                tag: None,
+                period: 0,
+            },
+            Transaction::Authorize(authorize),
+        )?;
+        if let Some(expires_at) = authorize.expires_at {
+            self.expiries
+                .insert((expires_at, authorize.client_id, authorize.tx_id));
         }
+        Ok(())
+    }
 
-        let account = self.get_or_create_account(client_id)?;
-        if account.txs.contains_key(&tx.tx_id) {
-            return Err(Error::InvalidTx(tx.tx_id));
-        }
+    /// Captures the authorization `tx_id` for client `client_id`, moving its funds
+    /// from pending to the available balance. The captured authorization can then
+    /// be disputed like a deposit.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not an authorization, or was already captured or
+    ///    expired
+    ///  - a validator rejects it, e.g. because the account is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_capture(&mut self, capture: Capture) -> Result<(), Error> {
+        let (client_id, tx_id) = (capture.client_id, capture.tx_id);
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, TransactionState::Authorized)?;
+        validate(&self.validators, account, &Transaction::Capture(capture))?;
+
+        // Pending funds are decreased, available funds are increased.
+        let pending_funds = calculate_amount(account.funds.pending, Side::Withdrawal, tx.amount)?;
         let available_funds = calculate_amount(account.funds.available, tx.side, tx.amount)?;
-        // Disallow withdrawing if it results in negative available funds
-        // This still allows depositing funds if there is a negative balance.
-        if available_funds < Price4::ZERO && tx.side != Side::Deposit {
-            return Err(Error::InvalidPrice);
-        }
         account.funds.set(available_funds, account.funds.held)?;
+        account.funds.pending = pending_funds;
+        account.set_tx_state(tx_id, TransactionState::Processed);
+        if let Some(expires_at) = tx.expires_at {
+            self.expiries.remove(&(expires_at, client_id, tx_id));
+        }
 
-        let old_tx = account.txs.insert(tx.tx_id, tx);
-        assert!(old_tx.is_none());
+        self.score_risk(&Transaction::Capture(capture));
         Ok(())
     }
 
-    fn get_or_create_account(&mut self, client_id: ClientId) -> Result<&mut Account, Error> {
-        let account = self.accounts.entry(client_id).or_insert_with(Account::new);
-        if account.is_frozen {
-            return Err(Error::AccountFrozen);
-        }
-        Ok(account)
+    /// Settles the pending withdrawal `tx_id` for client `client_id`, removing its
+    /// funds from the account. The settled withdrawal can then be disputed.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not a pending withdrawal
+    ///  - a validator rejects it, e.g. because the account is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_settle(&mut self, settle: Settle) -> Result<(), Error> {
+        self.finish_settlement(Transaction::Settle(settle), TransactionState::Processed)
     }
 
-    fn get_account(&mut self, client_id: ClientId) -> Result<&mut Account, Error> {
+    /// Fails the pending withdrawal `tx_id` for client `client_id`, placing its
+    /// funds back into the available balance.
+    /// Returns an error if:
+    ///  - the transaction id `tx_id` doesn't exist for client `client_id`
+    ///  - the transaction is not a pending withdrawal
+    ///  - a validator rejects it, e.g. because the account is frozen
+    ///
+    /// This function does not panic.
+    pub fn process_fail(&mut self, fail: Fail) -> Result<(), Error> {
+        self.finish_settlement(Transaction::Fail(fail), TransactionState::Failed)
+    }
+
+    /// Moves a pending withdrawal to `state`, returning its funds if it failed.
+    fn finish_settlement(
+        &mut self,
+        transaction: Transaction,
+        state: TransactionState,
+    ) -> Result<(), Error> {
+        let (client_id, tx_id) = (transaction.client_id(), transaction.tx_id());
         let account = self
             .accounts
-            .get_mut(&client_id)
+            .get_mut(client_id)
             .ok_or(Error::InvalidClientId(client_id))?;
-        if account.is_frozen {
-            return Err(Error::AccountFrozen);
+        let tx = account.txs.get(tx_id).ok_or(Error::InvalidTx(tx_id))?;
+        check_tx_state(tx.state, TransactionState::PendingSettlement)?;
+        validate(&self.validators, account, &transaction)?;
+
+        // Pending out funds are decreased, and available funds are increased if
+        // the withdrawal failed.
+        let pending_out_funds = calculate_amount(account.funds.pending_out, tx.side, tx.amount)?;
+        let available_funds = match state {
+            TransactionState::Failed => {
+                calculate_amount(account.funds.available, tx.side.opposite(), tx.amount)?
+            }
+            _ => account.funds.available,
+        };
+        account.funds.set_with_pending_out(
+            available_funds,
+            account.funds.held,
+            pending_out_funds,
+        )?;
+        account.set_tx_state(tx_id, state);
+
+        self.score_risk(&transaction);
+        Ok(())
+    }
+
+    /// Expires the authorizations that expire at or before `now` and were not
+    /// captured, releasing their pending funds. Returns the expired authorizations.
+    ///
+    /// This function does not panic.
+    pub fn expire_authorizations(&mut self, now: Timestamp) -> Vec<(ClientId, TransactionId)> {
+        let due: Vec<_> = self
+            .expiries
+            .range(..=(now, ClientId(u16::MAX), TransactionId(u32::MAX)))
+            .copied()
+            .collect();
+        let mut expired = Vec::with_capacity(due.len());
+        for (expires_at, client_id, tx_id) in due {
+            self.expiries.remove(&(expires_at, client_id, tx_id));
+            let account = match self.accounts.get_mut(client_id) {
+                Some(account) => account,
+                None => continue,
+            };
+            if let Some(tx) = account.txs.get(tx_id) {
+                if tx.state == TransactionState::Authorized {
+                    // The amount was added to the pending funds, so this cannot
+                    // overflow.
+                    account.funds.pending -= tx.amount;
+                    account.set_tx_state(tx_id, TransactionState::Expired);
+                    expired.push((client_id, tx_id));
+                }
+            }
         }
-        Ok(account)
+        expired
+    }
+
+    /// Tracks when `tx` expires if it is an authorization that was not captured,
+    /// or stops tracking it otherwise, for state restored without the processor.
+    #[cfg(feature = "persistence")]
+    fn track_expiry(&mut self, client_id: ClientId, tx: &FundTransaction) {
+        if let Some(expires_at) = tx.expires_at {
+            let key = (expires_at, client_id, tx.tx_id);
+            if tx.state == TransactionState::Authorized {
+                self.expiries.insert(key);
+            } else {
+                self.expiries.remove(&key);
+            }
+        }
+    }
+
+    pub fn accounts(&self) -> &dyn AccountRepository {
+        self.accounts.as_ref()
+    }
+
+    fn process_tx(&mut self, tx: FundTransaction, transaction: Transaction) -> Result<(), Error> {
+        // The account is created even if the transaction is rejected.
+        let transaction_store = &self.transaction_store;
+        let account = self
+            .accounts
+            .get_or_create(transaction.client_id(), &mut || {
+                Account::with_transaction_store(transaction_store())
+            });
+        if let Some(existing_tx) = account.txs.get(tx.tx_id) {
+            if existing_tx.side == tx.side && existing_tx.amount == tx.amount {
+                return Err(Error::DuplicateTx(tx.tx_id));
+            }
+            return Err(Error::InvalidTx(tx.tx_id));
+        }
+        // A withdrawal's fee is taken from the available funds as well, so it is
+        // validated including the fee, e.g. against a minimum balance.
+        let withdrawal_fee = match (self.policies.fee_schedule.as_ref(), transaction) {
+            (Some(schedule), Transaction::Withdrawal(_)) => {
+                schedule.fee(&transaction, tx.amount)?
+            }
+            _ => Price4::ZERO,
+        };
+        let validated = match transaction {
+            Transaction::Withdrawal(withdrawal) if withdrawal_fee > Price4::ZERO => {
+                Transaction::Withdrawal(Withdrawal {
+                    amount: calculate_amount(withdrawal.amount, Side::Deposit, withdrawal_fee)?,
+                    ..withdrawal
+                })
+            }
+            _ => transaction,
+        };
+        // With an unfreeze policy, deposits into frozen accounts are validated as
+        // if they were not frozen, so they can repay what the client owes.
+        let repays = account.is_frozen
+            && self.policies.unfreeze_policy.is_some()
+            && matches!(transaction, Transaction::Deposit(_));
+        account.is_frozen &= !repays;
+        let result = validate(&self.validators, account, &validated);
+        account.is_frozen |= repays;
+        result?;
+        let mut tx = tx;
+        let requested = tx.amount;
+        if let (Some(cap), Transaction::Deposit(_)) =
+            (self.policies.balance_cap.as_ref(), transaction)
+        {
+            tx.amount = cap.accept(transaction.client_id(), account, requested)?;
+        }
+        if let (Some(policy), Transaction::Withdrawal(_)) =
+            (self.policies.dormancy_policy.as_ref(), transaction)
+        {
+            if policy.block_withdrawals && policy.is_dormant(account, self.clock.now()) {
+                return Err(RejectReason::AccountDormant.into());
+            }
+        }
+        let mut daily_withdrawals = None;
+        if let (Some(limit), Transaction::Withdrawal(_)) =
+            (self.policies.withdrawal_limit.as_ref(), transaction)
+        {
+            daily_withdrawals = limit.withdraw(
+                transaction.client_id(),
+                account,
+                tx.amount,
+                self.clock.now(),
+            )?;
+        }
+        let fee = match (self.policies.fee_schedule.as_ref(), transaction) {
+            (Some(schedule), Transaction::Deposit(_)) => schedule.fee(&transaction, tx.amount)?,
+            _ => withdrawal_fee,
+        };
+        // A withdrawal's fee must be available as well.
+        if let (true, Transaction::Withdrawal(_)) = (fee > Price4::ZERO, transaction) {
+            let requested = calculate_amount(tx.amount, Side::Deposit, fee)?;
+            if account.funds.available < requested {
+                return Err(Error::Rejected(RejectReason::InsufficientFunds {
+                    requested,
+                    available: account.funds.available,
+                }));
+            }
+        }
+        // A deposit's fee is taken from the deposit, so it can't be more than it.
+        if let (true, Transaction::Deposit(_)) = (fee > tx.amount, transaction) {
+            return Err(Error::Rejected(RejectReason::FeeExceedsAmount {
+                fee,
+                amount: tx.amount,
+            }));
+        }
+        match tx.state {
+            TransactionState::Authorized => {
+                account.funds.pending =
+                    calculate_amount(account.funds.pending, tx.side, tx.amount)?;
+            }
+            TransactionState::PendingSettlement => {
+                let available_funds =
+                    calculate_amount(account.funds.available, tx.side, tx.amount)?;
+                let pending_out_funds =
+                    calculate_amount(account.funds.pending_out, tx.side.opposite(), tx.amount)?;
+                account.funds.set_with_pending_out(
+                    available_funds,
+                    account.funds.held,
+                    pending_out_funds,
+                )?;
+            }
+            TransactionState::InEscrow => {
+                let available_funds =
+                    calculate_amount(account.funds.available, tx.side, tx.amount)?;
+                let escrow_funds =
+                    calculate_amount(account.funds.escrow, tx.side.opposite(), tx.amount)?;
+                account
+                    .funds
+                    .set_with_escrow(available_funds, escrow_funds)?;
+            }
+            _ => {
+                let available_funds =
+                    calculate_amount(account.funds.available, tx.side, tx.amount)?;
+                account.funds.set(available_funds, account.funds.held)?;
+            }
+        }
+
+        if daily_withdrawals.is_some() {
+            account.daily_withdrawals = daily_withdrawals;
+        }
+        // The entries are recorded once the transaction is, with its balances.
+        let mut entries = Vec::new();
+        if !fee.is_zero() {
+            let available_funds = calculate_amount(account.funds.available, Side::Withdrawal, fee)?;
+            account.funds.set(available_funds, account.funds.held)?;
+            entries.push(AuditEntry {
+                client_id: Some(transaction.client_id()),
+                tx_id: Some(tx.tx_id),
+                action: "fee",
+                amount: -fee,
+                ..AuditEntry::default()
+            });
+        }
+        if tx.amount != requested {
+            entries.push(AuditEntry {
+                client_id: Some(transaction.client_id()),
+                tx_id: Some(tx.tx_id),
+                action: "cap",
+                amount: tx.amount - requested,
+                ..AuditEntry::default()
+            });
+        }
+        if let (true, Some(policy)) = (repays, self.policies.unfreeze_policy) {
+            if account.funds.total() >= policy.threshold {
+                account.is_frozen = false;
+                entries.push(AuditEntry {
+                    client_id: Some(transaction.client_id()),
+                    tx_id: Some(tx.tx_id),
+                    action: "unfreeze",
+                    amount: Price4::ZERO,
+                    ..AuditEntry::default()
+                });
+            }
+        }
+        tx.period = self.periods.open();
+        let old_tx = account.txs.insert(tx);
+        assert!(old_tx.is_none());
+        for entry in entries {
+            self.audit(entry);
+        }
+        self.track_receivables(&transaction);
+        self.score_risk(&transaction);
+        Ok(())
     }
 }
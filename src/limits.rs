@@ -0,0 +1,305 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{Account, ClientId, Error, Price4, Timestamp};
+use alloc::sync::Arc;
+use core::str::FromStr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "std")]
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub(crate) const SECS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// What happens to a deposit that would take a client's total above its cap.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CapMode {
+    /// The deposit is rejected with `Error::BalanceCapExceeded`.
+    #[default]
+    Reject,
+    /// The deposit is accepted up to the cap, and the excess is recorded in the
+    /// audit log. It is rejected if nothing can be accepted.
+    Partial,
+}
+
+impl FromStr for CapMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CapMode, String> {
+        match s {
+            "reject" => Ok(CapMode::Reject),
+            "partial" => Ok(CapMode::Partial),
+            _ => Err(format!("expected `reject` or `partial`, got `{}`", s)),
+        }
+    }
+}
+
+/// The maximum total balance of clients, e.g. for regulated wallet limits,
+/// enforced on deposits.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BalanceCap {
+    /// The cap of clients without their own.
+    pub default: Option<Price4>,
+    /// The caps of specific clients, which take precedence over the default.
+    pub per_client: HashMap<ClientId, Price4>,
+    pub mode: CapMode,
+}
+
+impl BalanceCap {
+    /// The balance cap of `client_id`, if it has one.
+    pub fn cap(&self, client_id: ClientId) -> Option<Price4> {
+        self.per_client.get(&client_id).copied().or(self.default)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.per_client.is_empty()
+    }
+
+    /// The part of a deposit of `requested` into `account` that is accepted.
+    pub(crate) fn accept(
+        &self,
+        client_id: ClientId,
+        account: &Account,
+        requested: Price4,
+    ) -> Result<Price4, Error> {
+        let cap = match self.cap(client_id) {
+            Some(cap) => cap,
+            None => return Ok(requested),
+        };
+        let total = account.total_funds();
+        // An overflow is left to the processor to report.
+        let room = cap.checked_sub(total).unwrap_or(Price4::ZERO);
+        if requested <= room {
+            return Ok(requested);
+        }
+        match self.mode {
+            CapMode::Partial if room > Price4::ZERO => Ok(room),
+            _ => Err(Error::BalanceCapExceeded {
+                requested,
+                total,
+                cap,
+            }),
+        }
+    }
+}
+
+/// The source of the current time for limits that reset over time.
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+/// The system's wall clock, with the `std` feature.
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Timestamp {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        Timestamp::from_secs(secs)
+    }
+}
+
+/// A clock that only moves when it is set, e.g. to the timestamps of the records
+/// being processed, or in tests. Clones share the same time.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock(Arc<AtomicU64>);
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock(Arc::new(AtomicU64::new(now.as_secs())))
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.0.store(now.as_secs(), Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_secs(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// The total a client withdrew on one day.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DailyWithdrawals {
+    /// The day, in days since the unix epoch, shifted by the limit's day start.
+    pub day: u64,
+    pub amount: Price4,
+}
+
+/// The maximum amount clients can withdraw per day, e.g. to limit the damage of
+/// a compromised account.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DailyWithdrawalLimit {
+    /// The limit of clients without their own.
+    pub default: Option<Price4>,
+    /// The limits of specific clients, which take precedence over the default.
+    pub per_client: HashMap<ClientId, Price4>,
+    /// When days start, in seconds after midnight UTC.
+    pub day_start: u64,
+}
+
+impl DailyWithdrawalLimit {
+    /// The daily withdrawal limit of `client_id`, if it has one.
+    pub fn limit(&self, client_id: ClientId) -> Option<Price4> {
+        self.per_client.get(&client_id).copied().or(self.default)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.per_client.is_empty()
+    }
+
+    /// The day `now` falls on.
+    pub fn day(&self, now: Timestamp) -> u64 {
+        now.as_secs().saturating_sub(self.day_start) / SECS_PER_DAY
+    }
+
+    /// What `client_id` can still withdraw from `account` on the day of `now`, if
+    /// it has a limit.
+    pub fn remaining(
+        &self,
+        client_id: ClientId,
+        account: &Account,
+        now: Timestamp,
+    ) -> Option<Price4> {
+        let limit = self.limit(client_id)?;
+        let withdrawn = self.withdrawn(account, now);
+        Some((limit - withdrawn).max(Price4::ZERO))
+    }
+
+    /// What was withdrawn from `account` on the day of `now`.
+    fn withdrawn(&self, account: &Account, now: Timestamp) -> Price4 {
+        account
+            .daily_withdrawals()
+            .filter(|withdrawals| withdrawals.day == self.day(now))
+            .map_or(Price4::ZERO, |withdrawals| withdrawals.amount)
+    }
+
+    /// Checks a withdrawal of `requested` from `account` at `now` against the
+    /// limit of `client_id`, returning the day's total including it, if the client
+    /// has a limit.
+    pub(crate) fn withdraw(
+        &self,
+        client_id: ClientId,
+        account: &Account,
+        requested: Price4,
+        now: Timestamp,
+    ) -> Result<Option<DailyWithdrawals>, Error> {
+        let limit = match self.limit(client_id) {
+            Some(limit) => limit,
+            None => return Ok(None),
+        };
+        let withdrawn = self.withdrawn(account, now);
+        // An overflow is left to the processor to report.
+        match withdrawn.checked_add(requested) {
+            Some(amount) if amount <= limit => Ok(Some(DailyWithdrawals {
+                day: self.day(now),
+                amount,
+            })),
+            Some(_) => Err(Error::DailyLimitExceeded {
+                requested,
+                withdrawn,
+                limit,
+            }),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, TransactionId, TransactionProcessor, Withdrawal};
+
+    #[test]
+    fn test_balance_cap() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| Deposit {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        let mut cap = BalanceCap {
+            default: Some(Price4::new(10, 0)),
+            ..BalanceCap::default()
+        };
+        processor.set_balance_cap(Some(cap.clone()));
+        processor.process_deposit(deposit(1, 8)).unwrap();
+        let err = processor.process_deposit(deposit(2, 3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "deposit of 3.0000 would take total 8.0000 above the balance cap 10.0000"
+        );
+
+        cap.mode = CapMode::Partial;
+        processor.set_balance_cap(Some(cap));
+        processor.process_deposit(deposit(3, 3)).unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].total_funds(),
+            Price4::new(10, 0)
+        );
+        let log = processor.take_audit_log();
+        assert_eq!((log[0].action, log[0].amount), ("cap", Price4::new(-1, 0)));
+        // Nothing can be accepted at the cap.
+        assert!(matches!(
+            processor.process_deposit(deposit(4, 1)),
+            Err(Error::BalanceCapExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_daily_withdrawal_limit() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let clock = ManualClock::new(Timestamp::from_secs(10 * SECS_PER_DAY + 12 * 60 * 60));
+        processor.set_clock(Box::new(clock.clone()));
+        processor.set_withdrawal_limit(Some(DailyWithdrawalLimit {
+            default: Some(Price4::new(10, 0)),
+            // Days start at 06:00 UTC.
+            day_start: 6 * 60 * 60,
+            ..DailyWithdrawalLimit::default()
+        }));
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(100, 0),
+            })
+            .unwrap();
+        let withdrawal = |tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        processor.process_withdrawal(withdrawal(2, 6)).unwrap();
+        assert_eq!(
+            processor.remaining_withdrawal_allowance(client_id),
+            Some(Price4::new(4, 0))
+        );
+        let err = processor.process_withdrawal(withdrawal(3, 5)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "withdrawal of 5.0000 would take the 6.0000 withdrawn today above the daily \
+             limit 10.0000"
+        );
+        // The day has not started yet at 05:00.
+        clock.set(Timestamp::from_secs(11 * SECS_PER_DAY + 5 * 60 * 60));
+        assert!(processor.process_withdrawal(withdrawal(4, 5)).is_err());
+        clock.set(Timestamp::from_secs(11 * SECS_PER_DAY + 6 * 60 * 60));
+        processor.process_withdrawal(withdrawal(5, 5)).unwrap();
+        assert_eq!(
+            processor.remaining_withdrawal_allowance(client_id),
+            Some(Price4::new(5, 0))
+        );
+        // A client without withdrawals has its full limit.
+        assert_eq!(
+            processor.remaining_withdrawal_allowance(ClientId(2)),
+            Some(Price4::new(10, 0))
+        );
+    }
+}
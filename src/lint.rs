@@ -0,0 +1,212 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{check_record, ClientId, RecordKind, Timestamp, TransactionId, TransactionRecord};
+use serde::Serialize;
+
+/// A problem with a record of an input file, found without processing it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    /// The index of the record in the input, from 0.
+    pub record: u64,
+    #[serde(rename = "client")]
+    pub client_id: Option<ClientId>,
+    #[serde(rename = "tx")]
+    pub tx_id: Option<TransactionId>,
+    /// A stable name of the problem, e.g. `duplicate_tx` or `missing_tx`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Checks the records of an input file for structural and semantic problems
+/// without processing them: records that break the schema, amounts with more
+/// than 4 decimals, transaction ids used twice, records referring to
+/// transactions that don't exist or only come later, and transaction ids or
+/// timestamps that decrease.
+#[derive(Debug, Clone, Default)]
+pub struct Linter {
+    /// The client and record index of each transaction introduced so far.
+    txs: HashMap<TransactionId, (ClientId, u64)>,
+    /// The records referring to a transaction not introduced before them.
+    unresolved: Vec<(u64, ClientId, TransactionId)>,
+    last_tx_id: Option<TransactionId>,
+    last_timestamp: Option<Timestamp>,
+    findings: Vec<Finding>,
+}
+
+impl Linter {
+    pub fn new() -> Linter {
+        Linter::default()
+    }
+
+    /// Checks `record`, the record with index `index` of the input. Records
+    /// must be checked in order.
+    pub fn check(&mut self, index: u64, record: &TransactionRecord) {
+        let (client_id, tx_id) = (record.client_id, record.tx_id);
+        let mut found = Vec::new();
+        let mut report = |code, message: String| found.push((code, message));
+        if let Err(e) = check_record(record) {
+            report("schema_violation", e.to_string());
+        }
+        if let Some(amount) = record.amount {
+            if amount.normalize().scale() > 4 {
+                report(
+                    "amount_precision",
+                    format!("amount {} has more than 4 decimals", amount),
+                );
+            }
+        }
+        if let Some(timestamp) = record.timestamp {
+            if let Some(last) = self.last_timestamp.filter(|last| timestamp < *last) {
+                report(
+                    "timestamp_decreasing",
+                    format!("timestamp {} is before {}", timestamp.0, last.0),
+                );
+            }
+            self.last_timestamp = Some(timestamp);
+        }
+        if introduces_tx(record.kind) {
+            if let Some(last) = self.last_tx_id.filter(|last| tx_id < *last) {
+                report(
+                    "tx_id_decreasing",
+                    format!("transaction id {} is below {}", tx_id, last),
+                );
+            }
+            self.last_tx_id = Some(tx_id);
+            if let Some((other_client, other)) = self.txs.get(&tx_id) {
+                report(
+                    "duplicate_tx",
+                    format!(
+                        "transaction id {} is already used by record {} of client {}",
+                        tx_id, other, other_client
+                    ),
+                );
+            } else {
+                self.txs.insert(tx_id, (client_id, index));
+            }
+        } else if refers_to_tx(record.kind) {
+            match self.txs.get(&tx_id) {
+                Some((other_client, _)) if *other_client != client_id => report(
+                    "wrong_client",
+                    format!("transaction {} belongs to client {}", tx_id, other_client),
+                ),
+                Some(_) => {}
+                None => self.unresolved.push((index, client_id, tx_id)),
+            }
+        }
+        self.findings
+            .extend(found.into_iter().map(|(code, message)| Finding {
+                record: index,
+                client_id: Some(client_id),
+                tx_id: Some(tx_id),
+                code,
+                message,
+            }));
+    }
+
+    /// Records that the record with index `index` of the input could not be read.
+    pub fn invalid(&mut self, index: u64, message: String) {
+        self.findings.push(Finding {
+            record: index,
+            client_id: None,
+            tx_id: None,
+            code: "invalid_record",
+            message,
+        });
+    }
+
+    /// The findings, ordered by record. A record referring to a transaction
+    /// introduced later is reported as `reference_before_tx`, and one referring to a
+    /// transaction that is never introduced as `missing_tx`.
+    pub fn finish(mut self) -> Vec<Finding> {
+        for (index, client_id, tx_id) in core::mem::take(&mut self.unresolved) {
+            let (code, message) = match self.txs.get(&tx_id) {
+                Some((_, later)) => (
+                    "reference_before_tx",
+                    format!(
+                        "transaction {} is only introduced by record {}",
+                        tx_id, later
+                    ),
+                ),
+                None => (
+                    "missing_tx",
+                    format!("transaction {} does not exist", tx_id),
+                ),
+            };
+            self.findings.push(Finding {
+                record: index,
+                client_id: Some(client_id),
+                tx_id: Some(tx_id),
+                code,
+                message,
+            });
+        }
+        self.findings.sort_by_key(|finding| finding.record);
+        self.findings
+    }
+}
+
+/// Whether records of `kind` introduce a new transaction id.
+fn introduces_tx(kind: RecordKind) -> bool {
+    kind.is_fund_transaction()
+        || matches!(
+            kind,
+            RecordKind::Escrow | RecordKind::Adjustment | RecordKind::Convert
+        )
+}
+
+/// Whether records of `kind` refer to a transaction introduced before them, as
+/// opposed to a batch or nothing.
+fn refers_to_tx(kind: RecordKind) -> bool {
+    !introduces_tx(kind)
+        && !matches!(
+            kind,
+            RecordKind::Begin | RecordKind::Commit | RecordKind::Reactivate
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Price4;
+    use core::str::FromStr;
+
+    #[test]
+    fn test_linter() {
+        let record = |kind, client, tx, amount: Option<&str>| TransactionRecord {
+            amount: amount.map(|amount| Price4::from_str(amount).unwrap()),
+            ..TransactionRecord::new(kind, ClientId(client), TransactionId(tx))
+        };
+        let records = [
+            record(RecordKind::Deposit, 1, 2, Some("1.0")),
+            record(RecordKind::Dispute, 1, 3, None),
+            record(RecordKind::Deposit, 1, 3, Some("1.00001")),
+            record(RecordKind::Withdrawal, 2, 1, Some("0.5")),
+            record(RecordKind::Deposit, 2, 2, Some("1.0")),
+            record(RecordKind::Resolve, 2, 3, None),
+            record(RecordKind::Chargeback, 1, 4, Some("1.0")),
+        ];
+        let mut linter = Linter::new();
+        for (index, record) in records.iter().enumerate() {
+            linter.check(index as u64, record);
+        }
+        linter.invalid(7, "deserialize failed".to_string());
+        let findings: Vec<_> = linter
+            .finish()
+            .into_iter()
+            .map(|finding| (finding.record, finding.code))
+            .collect();
+        assert_eq!(
+            findings,
+            [
+                (1, "reference_before_tx"),
+                (2, "amount_precision"),
+                (3, "tx_id_decreasing"),
+                (4, "duplicate_tx"),
+                (5, "wrong_client"),
+                (6, "schema_violation"),
+                (6, "missing_tx"),
+                (7, "invalid_record"),
+            ]
+        );
+    }
+}
@@ -1,351 +1,7084 @@
 use serde::{Deserialize, Serialize};
-use transactions::{Chargeback, Deposit, Dispute, Resolve, Withdrawal};
-use transactions::{ClientId, Error, Price4, TransactionId, TransactionProcessor};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::num::{NonZeroU32, NonZeroU64};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use transactions::Deposit;
+use transactions::Linter;
+use transactions::SettlementTracker;
+use transactions::StatementEntry;
+use transactions::TransactionId;
+use transactions::{AmlMonitor, AmlThresholds, MinimumBalance, RiskConfig, RuleOutcome, RuleSet};
+use transactions::{AsOf, BalancePoint, BalanceStatement, HistoryInterval};
+use transactions::{
+    BalanceCap, CapMode, DailyWithdrawalLimit, DormancyPolicy, FreezePolicy, ManualClock,
+    UnfreezePolicy,
+};
+use transactions::{Checkpointer, ClientId, Error, Manifest, Price4, Snapshot, Timestamp};
+use transactions::{Currency, FeeSchedule, FxRates, Rate, RateTable};
+use transactions::{
+    DedupStore, MemoryDedupStore, SnapshotDelta, SnapshotError, StateStore, StoreError,
+};
+use transactions::{DisputeNote, OpenDispute};
+use transactions::{LedgerFormat, LedgerWriter};
+use transactions::{Operation, Permissions};
+use transactions::{ProcessorBuilder, RecordKind, TransactionProcessor, TransactionRecord};
+use transactions::{ReorderBuffer, ReorderWindow, ReplayPacer, ReplaySpeed, RunControl, Throttle};
+use transactions::{Transaction, Withdrawal};
 
-#[derive(Debug, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum TransactionInfoKind {
-    Deposit,
-    Withdrawal,
-    Dispute,
-    Resolve,
-    Chargeback,
+/// The number of checkpoints kept unless `--checkpoint-keep` is given.
+const DEFAULT_CHECKPOINT_KEEP: usize = 3;
+
+/// How long the dedup store remembers records unless `--dedup-retention` is given.
+const DEFAULT_DEDUP_RETENTION_SECS: u64 = 24 * 60 * 60;
+
+/// The exit code of a run stopped by SIGINT or SIGTERM, after it wrote its
+/// output, reports and snapshot.
+const EXIT_INTERRUPTED: i32 = 130;
+
+/// The exit code of a run stopped by `--max-errors`, `--max-error-rate` or
+/// `--strict-schema`, after it wrote its output, reports and snapshot.
+const EXIT_TOO_MANY_ERRORS: i32 = 3;
+
+/// The exit code of `lint` when it found problems in the input.
+const EXIT_LINT_FINDINGS: i32 = 3;
+
+/// How many records a run processes before `--max-error-rate` can stop it, so a
+/// few early rejections do not.
+const MIN_RECORDS_FOR_ERROR_RATE: u64 = 100;
+
+const USAGE: &str = "\
+Usage: ./transactions [options] <csv filepath with transactions>
+       ./transactions [options] --manifest <manifest filepath>
+       ./transactions diff <old snapshot> <new snapshot> [--patch <path>]
+       ./transactions patch <snapshot> <patch> --output <path>
+       ./transactions schema [--format json|csv]
+       ./transactions statement <snapshot> --client <id> [--from <start>] [--to <end>]
+                                [--format text|csv]
+       ./transactions lint <csv filepath> [--format json|csv]
+
+Subcommands:
+  lint                      Check a transaction file for problems without processing
+                            it: records that break the schema, amounts with more than
+                            4 decimals, reused transaction ids, disputes and other
+                            records referring to missing transactions or to later
+                            ones, and decreasing transaction ids or timestamps; writes
+                            a finding per line as JSON, or with `--format csv` as csv,
+                            and exits with code 3 if there are any
+  diff                      Summarize the changes between two snapshots of the same
+                            input; `--patch` also writes them as a patch file
+  patch                     Apply a patch written by `diff` to the old snapshot and
+                            write the updated snapshot to `--output`
+  schema                    Print a JSON Schema of the input records, or with
+                            `--format csv` a csv spec of their columns
+  statement                 Print a client's transactions with its balances after each,
+                            from a snapshot of a run that recorded a balance history;
+                            `--from` and `--to` take a `YYYY-MM-DD` date or the number
+                            of the client's transaction, and `--format csv` prints csv
+
+Options:
+  --manifest <path>         Process the csv files listed in a `sha256sum`-style manifest,
+                            in order, after verifying their checksums
+  --manifest-mismatch <mode>
+                            `reject` (the default) refuses to process anything if a
+                            checksum does not match, `warn` only reports it
+  --snapshot <path>         Write the final state, including how many records were read,
+                            to a snapshot file (also when the run is cancelled)
+  --resume <path>           Continue from a snapshot of an earlier run over the same
+                            input, skipping the records it already read
+  --stop-after <records>    Stop after reading this many records, e.g. to write the state
+                            as of a record to `--snapshot`
+  --continue-from <path>    Continue from the snapshot of an earlier run over other
+                            input, e.g. the previous day's file, processing all records
+                            with its accounts and transaction history
+  --close-period            Close the accounting period of the `--continue-from`
+                            snapshot first: its transactions can no longer be disputed
+                            or reversed, which is recorded in the audit log instead
+  --state-store <store>     Persist the state after every record to `sled:<dir>`,
+                            `rocksdb:<dir>` or a `postgres://` database, and continue
+                            from the state found there
+  --dedup-store <store>     Filter out records that were already read, e.g. redelivered
+                            by an at-least-once source, using `memory` or a `redis://`
+                            server shared between runs
+  --dedup-retention <secs>  Remember read records and idempotency keys for at least this
+                            long (default 1 day)
+  --aml-amount <amount>     Report deposits and withdrawals above this amount as
+                            suspicious activity, still processing them
+  --aml-daily-amount <amount>
+                            Report a client's deposits and withdrawals once their total
+                            for the day (by `timestamp`) is above this amount
+  --aml-report <path>       Where to write the suspicious-activity report, as csv
+  --dispute-report <path>   Where to write the transactions still in dispute at the end,
+                            with their `note` and `evidence` records, as csv
+  --settlement-report <path>
+                            Where to write each client's deposits, withdrawals, dispute
+                            holds and chargebacks over the run, for settlement with the
+                            bank, as csv
+  --balance-history <path>  Where to write each client's balances after every change,
+                            with the `timestamp` of the record, as csv
+  --balance-history-every <transactions>
+                            Record a client's balances after every this many of its
+                            transactions instead of after every change
+  --tag-report <path>       Where to write the amounts of the tagged deposits and
+                            withdrawals per client and tag, and per tag of all clients
+  --tag-report-format <format>
+                            `csv` (the default) or `json`
+  --audit-log <path>        Where to write the applied `adjustment` records, the excess
+                            of capped deposits and accounts unfrozen by repayments, as
+                            csv
+  --ledger <path>           Where to write a journal of the balance effects of the
+                            processed transactions, one posting per changed kind of
+                            funds, in a plain-text accounting format
+  --ledger-format <format>  `ledger` (the default) for ledger-cli and hledger, or
+                            `beancount`
+  --ledger-currency <code>  The commodity of the journal's amounts (default the base
+                            currency of `--fx-rates`, or USD)
+  --rules <path>            Evaluate the declarative rules in a .toml or .yaml file before
+                            applying each transaction, and report how often each matched
+  --risk-config <path>      Score client risk with the weights and thresholds in a .toml
+                            file, flagging accounts for review or freezing them
+  --risk-column             Add each client's risk score as a `risk` output column
+  --authorization-ttl <secs>
+                            Expire authorizations that are not captured this many
+                            seconds after their `timestamp`
+  --pending-column          Add each client's funds in uncaptured authorizations as a
+                            `pending` output column
+  --settle-withdrawals      Keep withdrawn funds pending out until a `settle` record
+                            finalizes the withdrawal or a `fail` record returns them,
+                            and output them as a `pending_out` column
+  --min-balance <amount>    Reject withdrawals that would take a client's available funds
+                            below this amount
+  --min-balances <path>     Per-client minimum balances defined in a .toml file, which
+                            take precedence over `--min-balance`
+  --balance-cap <amount>    Reject deposits that would take a client's total above this
+                            amount
+  --balance-caps <path>     Per-client balance caps defined in a .toml file, which take
+                            precedence over `--balance-cap`
+  --balance-cap-mode <mode> `reject` (the default) rejects a deposit above the cap,
+                            `partial` deposits the part up to the cap
+  --daily-withdrawal-limit <amount>
+                            Reject withdrawals that would take a client's withdrawals
+                            for the day (by `timestamp`) above this amount
+  --daily-withdrawal-limits <path>
+                            Per-client daily withdrawal limits defined in a .toml file,
+                            which take precedence over `--daily-withdrawal-limit`
+  --day-start <HH:MM>       When days start for daily withdrawal limits, in UTC
+                            (default 00:00)
+  --unfreeze-at <amount>    Accept deposits into frozen accounts, and unfreeze them once
+                            their total is at least this amount, e.g. `0`
+  --chargeback-freeze <when>
+                            `always` (the default) freezes accounts on chargebacks,
+                            `never` only reverses the funds, and a number freezes
+                            them from the client's chargeback with that count on
+  --confirm-chargebacks     Only flag transactions on `chargeback` records, and charge
+                            them back on a later `chargeback_confirm` record
+  --fee-schedule <path>     Charge the fees defined in a .toml file on deposits and
+                            withdrawals, per operation, tiered by amount and per
+                            client segment, and record them in the audit log
+  --fx-rates <path>         Convert between currencies on `convert` records, at the
+                            exchange rates defined in a .toml file (or the .csv or
+                            .json rate table it refers to) as of the records'
+                            timestamps, and add each
+                            client's balances in other currencies as a `balances` column
+  --frozen-disputes         Process disputes, resolves and chargebacks of frozen accounts
+  --cap-dispute-holds       Hold at most a client's available funds on disputes of
+                            deposits, recording the rest in the audit log
+  --forbid-negative-held    Reject disputes of withdrawals that would take a client's
+                            held funds negative
+  --signed-amounts          Process negative deposits as withdrawals and negative
+                            withdrawals as deposits, instead of rejecting them
+  --permissions <path>      Restrict operations to the roles permitted them in a .toml
+                            file, checked against each record's `role` column
+  --escrow-column           Add each client's funds in escrow for other clients as an
+                            `escrow` output column
+  --receivable-column       Add the amount each client still owes after disputes and
+                            chargebacks took its available funds negative as a
+                            `receivable` output column
+  --dormant-after <secs>    Flag accounts without any transaction for this many seconds
+                            (by `timestamp`) as dormant until a `reactivate` record
+  --block-dormant-withdrawals
+                            Reject withdrawals from dormant accounts
+  --dormant-column          Add whether each account is dormant as a `dormant` output
+                            column
+  --statement-client <id>   The client the transactions of .ofx, .qfx and .qif bank
+                            statement input files are deposited to and withdrawn from
+  --statement-accounts <path>
+                            The clients of the accounts of .xml ISO 20022 camt.053 and
+                            pain.001 input files, as csv with `account` and `client`
+                            columns; other accounts are `--statement-client`'s
+  --initial-balances <path> Open the accounts in a .csv file with `client`, `available`,
+                            `held` and `frozen` (or `locked`) columns before processing,
+                            e.g. the output of another run
+  --recurring <path>        Schedule the recurring deposits and withdrawals defined in a
+                            .toml file, applied as records' timestamps reach them
+  --telemetry               Report the records processed per second and the latency of
+                            each operation at the end (requires the `telemetry`
+                            feature)
+  --rules-script <path>     Check every transaction with the `check` function of a rhai
+                            script, which can accept, reject or flag it (requires the
+                            `rhai` feature)
+  --checkpoint-dir <dir>    Periodically write snapshots to this directory as
+                            `checkpoint-<records read>.json`
+  --checkpoint-every <records>
+                            Write a checkpoint every this many records
+  --checkpoint-interval <secs>
+                            Write a checkpoint at least this often
+  --checkpoint-keep <count> Keep this many of the newest checkpoints (default 3)
+  --max-rate <records/sec>  Process at most this many records per second
+  --replay-speed <speed>    Pace records by their `timestamp` column, e.g. `1x`, `10x`
+                            or `max` (the default, which ignores timestamps)
+  --reorder-window <window> Hold records for a window of records (e.g. `100`) or
+                            seconds (e.g. `30s`) and apply them in timestamp order
+  --monotonic-tx-ids <mode> Check that deposit/withdrawal transaction ids never
+                            decrease; `warn` reports violations, `reject` also skips
+                            the record
+  --skip-duplicates         Count records that repeat an already processed deposit or
+                            withdrawal exactly instead of reporting them as errors
+  --encoding <encoding>     The text encoding of input files without a byte order mark,
+                            e.g. `utf-16le`, `latin1` or `windows-1252`; the default
+                            `auto` reads UTF-8, and UTF-16 by its byte order mark
+  --delimiter <char>        The field separator of input files, e.g. `;`, `tab`, or
+                            `auto` to detect a comma, tab, semicolon or `|` from each
+                            file's first line (default `,`)
+  --amount-format <format> `plain` (the default), `point` for amounts with commas or
+                            spaces as thousands separators like `1,234.5678`, or
+                            `comma` for a decimal comma with points or spaces as
+                            thousands separators like `1.234,5678`
+  --strict-headers          Reject input files with a column in their header row that
+                            is not exactly the name of a record column, instead of
+                            matching column names case-insensitively and ignoring
+                            unknown columns
+  --strict-schema           Stop, and exit with code 3, at the first record that breaks
+                            the schema: an input file with columns that are not
+                            exactly record columns, a record that cannot be read,
+                            or an amount that is missing or given for a type that
+                            takes none (see `schema`)
+  --no-header               Read input files without a header row, taking their
+                            columns in the order of `schema`: type, client, tx,
+                            amount, then the optional ones
+  --quarantine <path>       Write the rows that cannot be parsed to this file as read,
+                            under the header row of their input file
+  --error-format <format>   `text` (the default) or `json`, which writes errors as
+                            one JSON object per line with the `line` and raw `record`
+                            being processed, an error `code` and the `message`
+  --error-samples <count>   Write only this many errors of each code and shape (the
+                            message with numbers ignored), and report how many more
+                            there were at the end
+  --max-errors <count>      Stop, and exit with code 3, once more than this many
+                            records were rejected
+  --max-error-rate <rate>   Stop, and exit with code 3, once more than this share of
+                            the records were rejected, e.g. `5%`, checked after 100
+                            records and at the end of the input
+  --watch-config <secs>     Check the files of `--fee-schedule`, `--rules`,
+                            `--balance-caps` and `--daily-withdrawal-limits` for
+                            changes this often, and reload them between records,
+                            recording each reload in the audit log";
+
+/// Options controlling how `run` processes its input.
+#[derive(Debug, Default)]
+struct Options {
+    /// Stops after reading this many records, counted across all input files.
+    stop_after: Option<u64>,
+    /// Throttles processing to at most this many records per second.
+    max_rate: Option<NonZeroU32>,
+    /// Paces records relative to the gaps between their timestamps.
+    replay_speed: ReplaySpeed,
+    /// Buffers records for a window and applies them ordered by timestamp.
+    reorder_window: Option<ReorderWindow>,
+    /// Checks that deposit/withdrawal transaction ids are non-decreasing.
+    monotonic_tx_ids: Option<CheckMode>,
+    /// Counts exact repeats of processed deposits/withdrawals instead of reporting them.
+    skip_duplicates: bool,
+    /// The text encoding of input files.
+    encoding: InputEncoding,
+    /// The field separator of input files.
+    delimiter: Delimiter,
+    /// How the amounts of input records are written.
+    amount_format: AmountFormat,
+    /// Rejects input files with unknown or non-lowercase columns.
+    strict_headers: bool,
+    /// Stops the run at the first record that breaks the schema.
+    strict_schema: bool,
+    /// Reads input files without a header row, with positional columns.
+    no_header: bool,
+    /// How errors are written to the error stream.
+    error_format: ErrorFormat,
+    /// Writes only this many errors of each code and shape, and counts the others.
+    error_samples: Option<u64>,
+    /// Stops the run once too many records were rejected.
+    error_limit: ErrorLimit,
+    /// Reports the throughput and the latency of each operation at the end.
+    telemetry: bool,
+    /// How long read records and idempotency keys are remembered, in seconds, if
+    /// not `DEFAULT_DEDUP_RETENTION_SECS`.
+    dedup_retention: Option<NonZeroU64>,
+    /// Writes periodic checkpoints of the state to this directory.
+    checkpoint_dir: Option<PathBuf>,
+    /// Writes a checkpoint every this many records.
+    checkpoint_every: Option<NonZeroU64>,
+    /// Writes a checkpoint at least this many seconds after the previous one.
+    checkpoint_interval: Option<NonZeroU64>,
+    /// The number of newest checkpoints to keep.
+    checkpoint_keep: Option<usize>,
+    /// Declarative rules evaluated before applying each transaction.
+    rules: Option<RuleSet>,
+    /// Adds the risk score to the output.
+    risk_column: bool,
+    /// How long after their timestamp authorizations expire.
+    authorization_ttl: Option<NonZeroU64>,
+    /// Adds the pending funds to the output.
+    pending_column: bool,
+    /// Keeps withdrawals pending until settled, and adds the pending out funds to
+    /// the output.
+    settle_withdrawals: bool,
+    /// Adds the escrow funds to the output.
+    escrow_column: bool,
+    /// Adds the outstanding receivables to the output.
+    receivable_column: bool,
+    /// How long accounts without transactions take to go dormant, in seconds.
+    dormant_after: Option<NonZeroU64>,
+    /// Whether withdrawals from dormant accounts are rejected.
+    block_dormant_withdrawals: bool,
+    /// Adds whether accounts are dormant to the output.
+    dormant_column: bool,
+    /// The available funds withdrawals must leave.
+    min_balance: MinimumBalance,
+    /// The total deposits may take clients to.
+    balance_cap: BalanceCap,
+    /// The total clients may withdraw per day.
+    withdrawal_limit: DailyWithdrawalLimit,
+    /// The total at which frozen accounts are unfrozen by deposits.
+    unfreeze_at: Option<Price4>,
+    /// When chargebacks freeze accounts.
+    chargeback_freeze: FreezePolicy,
+    /// Whether chargebacks must be confirmed before they take effect.
+    confirm_chargebacks: bool,
+    /// The fees charged on deposits and withdrawals.
+    fee_schedule: Option<FeeSchedule>,
+    /// The policy files reloaded when they change.
+    watch_config: Option<WatchedConfig>,
+    /// When the accounts' balances are recorded in their balance history.
+    balance_history: Option<HistoryInterval>,
+    /// The format of the journal.
+    ledger_format: LedgerFormat,
+    /// The format of the tag report.
+    tag_report_format: ReportFormat,
+    /// The commodity of the journal's amounts.
+    ledger_currency: Option<Currency>,
+    /// The exchange rates of `convert` records.
+    fx_rates: Option<FxRates>,
+    /// Whether disputes of frozen accounts are processed.
+    frozen_disputes: bool,
+    /// Whether negative deposits and withdrawals are processed as the opposite
+    /// operation.
+    signed_amounts: bool,
+    /// Whether disputes that would take the held funds negative are rejected.
+    forbid_negative_held: bool,
+    /// Whether disputes hold at most the available funds.
+    cap_dispute_holds: bool,
+    /// The roles records must have for restricted operations.
+    permissions: Option<Permissions>,
+    /// The amounts above which transactions are reported as suspicious.
+    aml: AmlThresholds,
+}
+
+impl Options {
+    /// How long read records and idempotency keys are remembered.
+    fn dedup_retention(&self) -> Duration {
+        let secs = self.dedup_retention.map(NonZeroU64::get);
+        Duration::from_secs(secs.unwrap_or(DEFAULT_DEDUP_RETENTION_SECS))
+    }
+}
+
+/// What to do with a record that fails an opt-in input check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CheckMode {
+    /// Report the violation and process the record anyway.
+    Warn,
+    /// Report the violation and skip the record.
+    Reject,
+}
+
+impl FromStr for CheckMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CheckMode, String> {
+        match s {
+            "warn" => Ok(CheckMode::Warn),
+            "reject" => Ok(CheckMode::Reject),
+            _ => Err(format!("expected `warn` or `reject`, got `{}`", s)),
+        }
+    }
+}
+
+/// How errors are written to the error stream.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ErrorFormat {
+    /// A line of free text per error.
+    #[default]
+    Text,
+    /// A JSON object per line, with the line number and raw record being
+    /// processed, an error code and the message.
+    Json,
+}
+
+impl FromStr for ErrorFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorFormat, String> {
+        match s {
+            "text" => Ok(ErrorFormat::Text),
+            "json" => Ok(ErrorFormat::Json),
+            _ => Err(format!("expected `text` or `json`, got `{}`", s)),
+        }
+    }
+}
+
+/// The field separator of input files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Delimiter {
+    Byte(u8),
+    /// Detected per input file from its first line.
+    Auto,
+}
+
+impl Default for Delimiter {
+    fn default() -> Delimiter {
+        Delimiter::Byte(b',')
+    }
+}
+
+impl Delimiter {
+    /// The separators `Auto` detects.
+    const CANDIDATES: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+    /// The separator of an input file starting with `start`. `Auto` picks the
+    /// candidate that occurs most often on the first line, preferring the
+    /// earlier candidates on ties, and a comma if there is none.
+    fn detect(self, start: &[u8]) -> u8 {
+        let line = match self {
+            Delimiter::Byte(byte) => return byte,
+            Delimiter::Auto => start.split(|&b| b == b'\n').next().unwrap_or_default(),
+        };
+        let count = |candidate: u8| line.iter().filter(|&&b| b == candidate).count();
+        Delimiter::CANDIDATES
+            .iter()
+            .rev()
+            .copied()
+            .filter(|&candidate| count(candidate) > 0)
+            .max_by_key(|&candidate| count(candidate))
+            .unwrap_or(b',')
+    }
+}
+
+impl FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Delimiter, String> {
+        match s {
+            "auto" => Ok(Delimiter::Auto),
+            "tab" | "\t" => Ok(Delimiter::Byte(b'\t')),
+            _ if s.len() == 1 && s.is_ascii() && s != "\"" => Ok(Delimiter::Byte(s.as_bytes()[0])),
+            _ => Err(format!(
+                "expected a single character, `tab` or `auto`, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+/// The text encoding of input files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum InputEncoding {
+    /// UTF-8, or UTF-16 if the file starts with its byte order mark.
+    #[default]
+    Auto,
+    /// Transcoded from this encoding to UTF-8, unless the file starts with a
+    /// byte order mark.
+    Label(&'static encoding_rs::Encoding),
+}
+
+impl InputEncoding {
+    /// The encoding to transcode from when there is no byte order mark, if any.
+    fn encoding(self) -> Option<&'static encoding_rs::Encoding> {
+        match self {
+            InputEncoding::Auto => None,
+            InputEncoding::Label(encoding) => Some(encoding),
+        }
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<InputEncoding, String> {
+        if s == "auto" {
+            return Ok(InputEncoding::Auto);
+        }
+        encoding_rs::Encoding::for_label(s.as_bytes())
+            .map(InputEncoding::Label)
+            .ok_or_else(|| {
+                format!(
+                    "expected `auto` or an encoding like `utf-16le` or `latin1`, got `{}`",
+                    s
+                )
+            })
+    }
+}
+
+/// How the amounts of input records are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum AmountFormat {
+    /// A point as the decimal separator, without thousands separators.
+    #[default]
+    Plain,
+    /// A point as the decimal separator, with commas or spaces as thousands
+    /// separators, e.g. `1,234.5678`.
+    DecimalPoint,
+    /// A comma as the decimal separator, with points or spaces as thousands
+    /// separators, e.g. `1.234,5678`.
+    DecimalComma,
+}
+
+impl AmountFormat {
+    /// `amount` written plainly, or `None` if it is not in this format, e.g. if
+    /// its digits are not grouped by three.
+    fn normalize(self, amount: &str) -> Option<String> {
+        let (thousands, decimal) = match self {
+            AmountFormat::Plain => return Some(amount.to_string()),
+            AmountFormat::DecimalPoint => (',', '.'),
+            AmountFormat::DecimalComma => ('.', ','),
+        };
+        let (sign, unsigned) = match amount.strip_prefix('-') {
+            Some(unsigned) => ("-", unsigned),
+            None => ("", amount),
+        };
+        let (integer, fraction) = match unsigned.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (unsigned, None),
+        };
+        let is_digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+        let mut groups = integer.split([thousands, ' ']);
+        let first = groups.next().unwrap_or_default();
+        let mut digits = first.to_string();
+        for group in groups {
+            if first.is_empty() || first.len() > 3 || group.len() != 3 || !is_digits(group) {
+                return None;
+            }
+            digits.push_str(group);
+        }
+        if !is_digits(&digits) || !fraction.is_none_or(is_digits) {
+            return None;
+        }
+        Some(match fraction {
+            Some(fraction) => format!("{}{}.{}", sign, digits, fraction),
+            None => format!("{}{}", sign, digits),
+        })
+    }
+
+    /// `record` with its amount, the field at `index`, written plainly.
+    /// Returns an error if the amount is not in this format.
+    ///
+    /// This function does not panic.
+    fn normalize_field(
+        self,
+        record: &csv::StringRecord,
+        index: usize,
+    ) -> Result<csv::StringRecord, csv::Error> {
+        let field = match record.get(index) {
+            Some(field) => field,
+            None => return Ok(record.clone()),
+        };
+        let amount = self.normalize(field).ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!(
+                    "line {}: amount `{}` does not match `--amount-format {}`",
+                    record.position().map_or(0, csv::Position::line),
+                    field,
+                    self
+                ),
+            )
+        })?;
+        let mut normalized: csv::StringRecord = record
+            .iter()
+            .enumerate()
+            .map(|(i, field)| if i == index { &amount } else { field })
+            .collect();
+        normalized.set_position(record.position().cloned());
+        Ok(normalized)
+    }
+}
+
+impl std::fmt::Display for AmountFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AmountFormat::Plain => write!(f, "plain"),
+            AmountFormat::DecimalPoint => write!(f, "point"),
+            AmountFormat::DecimalComma => write!(f, "comma"),
+        }
+    }
+}
+
+impl FromStr for AmountFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AmountFormat, String> {
+        match s {
+            "plain" => Ok(AmountFormat::Plain),
+            "point" => Ok(AmountFormat::DecimalPoint),
+            "comma" => Ok(AmountFormat::DecimalComma),
+            _ => Err(format!("expected `plain`, `point` or `comma`, got `{}`", s)),
+        }
+    }
+}
+
+/// A share of records, written as a percentage like `5%`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct ErrorRate(f64);
+
+impl FromStr for ErrorRate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ErrorRate, String> {
+        let percent = s.strip_suffix('%').unwrap_or(s);
+        match percent.parse::<f64>() {
+            Ok(percent) if (0.0..=100.0).contains(&percent) => Ok(ErrorRate(percent)),
+            _ => Err(format!("expected a percentage like `5%`, got `{}`", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorRate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}%", self.0)
+    }
+}
+
+/// How many rejected records stop a run, as its input is likely wrong.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct ErrorLimit {
+    max_errors: Option<u64>,
+    max_rate: Option<ErrorRate>,
+}
+
+impl ErrorLimit {
+    /// Why `rejected` of `processed` records are too many, if they are. The rate
+    /// is only checked once `MIN_RECORDS_FOR_ERROR_RATE` records were processed,
+    /// or at the `end` of the input.
+    fn exceeded(&self, rejected: u64, processed: u64, end: bool) -> Option<String> {
+        if let Some(max_errors) = self.max_errors.filter(|max| rejected > *max) {
+            return Some(format!(
+                "too many errors: {} records rejected, more than {}",
+                rejected, max_errors
+            ));
+        }
+        let max_rate = self.max_rate?;
+        if processed == 0 || (processed < MIN_RECORDS_FOR_ERROR_RATE && !end) {
+            return None;
+        }
+        if rejected as f64 * 100.0 > max_rate.0 * processed as f64 {
+            return Some(format!(
+                "too many errors: {} of {} records rejected, more than {}",
+                rejected, processed, max_rate
+            ));
+        }
+        None
+    }
+}
+
+/// A time of day, in seconds after midnight, written as `HH:MM`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TimeOfDay(u64);
+
+impl FromStr for TimeOfDay {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimeOfDay, String> {
+        let parsed = s.split_once(':').and_then(|(hours, minutes)| {
+            let (hours, minutes) = (hours.parse::<u64>().ok()?, minutes.parse::<u64>().ok()?);
+            Some(hours * 60 * 60 + minutes * 60).filter(|_| hours < 24 && minutes < 60)
+        });
+        parsed
+            .map(TimeOfDay)
+            .ok_or_else(|| format!("expected a time like `06:00`, got `{}`", s))
+    }
+}
+
+/// How a report is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum ReportFormat {
+    /// A csv row per item, with a header.
+    #[default]
+    Csv,
+    /// A JSON array of the items.
+    Json,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ReportFormat, String> {
+        match s {
+            "csv" => Ok(ReportFormat::Csv),
+            "json" => Ok(ReportFormat::Json),
+            _ => Err(format!("expected `csv` or `json`, got `{}`", s)),
+        }
+    }
+}
+
+/// How the `schema` subcommand describes the input records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SchemaFormat {
+    /// A JSON Schema of a record.
+    Json,
+    /// A csv table of the columns.
+    Csv,
+}
+
+impl FromStr for SchemaFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SchemaFormat, String> {
+        match s {
+            "json" => Ok(SchemaFormat::Json),
+            "csv" => Ok(SchemaFormat::Csv),
+            _ => Err(format!("expected `json` or `csv`, got `{}`", s)),
+        }
+    }
+}
+
+/// How the `lint` subcommand writes its findings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LintFormat {
+    /// A JSON object per line.
+    Json,
+    Csv,
+}
+
+impl FromStr for LintFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<LintFormat, String> {
+        match s {
+            "json" => Ok(LintFormat::Json),
+            "csv" => Ok(LintFormat::Csv),
+            _ => Err(format!("expected `json` or `csv`, got `{}`", s)),
+        }
+    }
+}
+
+/// How the `statement` subcommand renders a client's statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatementFormat {
+    /// A csv row per transaction.
+    Csv,
+    /// A human-readable table, e.g. to paste into a reply to the client.
+    Text,
+}
+
+impl FromStr for StatementFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StatementFormat, String> {
+        match s {
+            "csv" => Ok(StatementFormat::Csv),
+            "text" => Ok(StatementFormat::Text),
+            _ => Err(format!("expected `csv` or `text`, got `{}`", s)),
+        }
+    }
+}
+
+/// An end of the range of a statement.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatementBound {
+    /// A UTC calendar date, given as `YYYY-MM-DD`.
+    Date(Timestamp),
+    /// The client's `n`-th processed transaction.
+    Transaction(u64),
+}
+
+impl StatementBound {
+    /// The point after which a statement starting at the bound begins.
+    fn after(self) -> Option<AsOf> {
+        match self {
+            StatementBound::Date(start) => start
+                .as_secs()
+                .checked_sub(1)
+                .map(|secs| AsOf::Timestamp(Timestamp::from_secs(secs))),
+            StatementBound::Transaction(n) => n.checked_sub(1).map(AsOf::Seq),
+        }
+    }
+
+    /// The last point of a statement ending at the bound.
+    fn until(self) -> AsOf {
+        match self {
+            StatementBound::Date(start) => {
+                AsOf::Timestamp(Timestamp::from_secs(start.as_secs() + 24 * 60 * 60 - 1))
+            }
+            StatementBound::Transaction(n) => AsOf::Seq(n),
+        }
+    }
+}
+
+impl FromStr for StatementBound {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StatementBound, String> {
+        if let Ok(n) = s.parse() {
+            return Ok(StatementBound::Transaction(n));
+        }
+        let invalid = || format!("expected a `YYYY-MM-DD` date or a number, got `{}`", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        match parts.as_slice() {
+            [year, month, day] if year.len() == 4 && month.len() == 2 && day.len() == 2 => {
+                let number = |part: &str| part.parse::<u32>().map_err(|_| invalid());
+                Timestamp::from_date(number(year)?.into(), number(month)?, number(day)?)
+                    .map(StatementBound::Date)
+                    .ok_or_else(invalid)
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// The parsed command line, either a run over csv input or a subcommand.
+enum Command {
+    Run(Box<Args>),
+    /// Computes the delta between two snapshots.
+    Diff {
+        from: PathBuf,
+        to: PathBuf,
+        /// Where to write the delta as a patch.
+        patch: Option<PathBuf>,
+    },
+    /// Applies a patch to a snapshot.
+    Patch {
+        snapshot: PathBuf,
+        patch: PathBuf,
+        output: PathBuf,
+    },
+    /// Describes the format of the input records.
+    Schema {
+        format: SchemaFormat,
+    },
+    /// Renders a client's transactions and balances from a snapshot.
+    Statement {
+        snapshot: PathBuf,
+        client_id: ClientId,
+        from: Option<StatementBound>,
+        to: Option<StatementBound>,
+        format: StatementFormat,
+    },
+    /// Checks an input file for problems without processing it.
+    Lint {
+        path: PathBuf,
+        format: LintFormat,
+    },
+}
+
+impl Command {
+    fn parse<I>(args: I) -> Result<Command, String>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut args = args.peekable();
+        let subcommand = match args.peek().map(String::as_str) {
+            Some(subcommand @ ("diff" | "lint" | "patch" | "schema" | "statement")) => {
+                subcommand.to_string()
+            }
+            _ => return Ok(Command::Run(Box::new(Args::parse(args)?))),
+        };
+        args.next();
+        if subcommand == "schema" {
+            let mut format = SchemaFormat::Json;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--format" => format = parse_value(&arg, args.next())?,
+                    _ => return Err(format!("unexpected argument `{}`", arg)),
+                }
+            }
+            return Ok(Command::Schema { format });
+        }
+        if subcommand == "lint" {
+            let mut path = None;
+            let mut format = LintFormat::Json;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--format" => format = parse_value(&arg, args.next())?,
+                    _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                    _ if path.is_none() => path = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument `{}`", arg)),
+                }
+            }
+            return Ok(Command::Lint {
+                path: path.ok_or("`lint` expects a csv filepath")?,
+                format,
+            });
+        }
+        if subcommand == "statement" {
+            let mut snapshot = None;
+            let mut client_id = None;
+            let (mut from, mut to) = (None, None);
+            let mut format = StatementFormat::Text;
+            while let Some(arg) = args.next() {
+                match arg.as_str() {
+                    "--client" => client_id = Some(parse_value(&arg, args.next())?),
+                    "--from" => from = Some(parse_value(&arg, args.next())?),
+                    "--to" => to = Some(parse_value(&arg, args.next())?),
+                    "--format" => format = parse_value(&arg, args.next())?,
+                    _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                    _ if snapshot.is_none() => snapshot = Some(PathBuf::from(arg)),
+                    _ => return Err(format!("unexpected argument `{}`", arg)),
+                }
+            }
+            return Ok(Command::Statement {
+                snapshot: snapshot.ok_or("`statement` expects a snapshot path")?,
+                client_id: client_id.ok_or("`statement` requires `--client`")?,
+                from,
+                to,
+                format,
+            });
+        }
+        let mut paths = Vec::new();
+        let mut option_path = None;
+        let option = if subcommand == "diff" {
+            "--patch"
+        } else {
+            "--output"
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                _ if arg == option => option_path = Some(parse_value(&arg, args.next())?),
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ => paths.push(PathBuf::from(arg)),
+            }
+        }
+        let [first, second]: [PathBuf; 2] = paths
+            .try_into()
+            .map_err(|_| format!("`{}` expects two paths", subcommand))?;
+        if subcommand == "diff" {
+            return Ok(Command::Diff {
+                from: first,
+                to: second,
+                patch: option_path,
+            });
+        }
+        Ok(Command::Patch {
+            snapshot: first,
+            patch: second,
+            output: option_path.ok_or("`patch` requires `--output`")?,
+        })
+    }
+}
+
+/// The parsed command line arguments for a run.
+struct Args {
+    /// The csv file to process, unless `manifest` is given.
+    filepath: Option<String>,
+    /// A manifest listing the csv files to process.
+    manifest: Option<String>,
+    /// What to do when a file in the manifest does not match its checksum.
+    manifest_mismatch: CheckMode,
+    /// Where to write a snapshot of the final state.
+    snapshot: Option<PathBuf>,
+    /// A snapshot of an earlier run to continue from.
+    resume: Option<PathBuf>,
+    /// The snapshot of an earlier run over other input to continue from.
+    continue_from: Option<PathBuf>,
+    /// Whether to close the accounting period of `continue_from`.
+    close_period: bool,
+    /// A state store to continue from and persist to.
+    state_store: Option<StoreSpec>,
+    /// A store of already read records, to filter out redeliveries.
+    dedup_store: Option<DedupSpec>,
+    /// A file with declarative rules.
+    rules: Option<PathBuf>,
+    /// A rhai script validating transactions.
+    rules_script: Option<PathBuf>,
+    /// Weights and thresholds for scoring client risk.
+    risk_config: Option<PathBuf>,
+    /// Where to write the suspicious-activity report.
+    aml_report: Option<PathBuf>,
+    /// Where to write the audit log of administrative operations.
+    audit_log: Option<PathBuf>,
+    /// Where to write the report of open disputes.
+    dispute_report: Option<PathBuf>,
+    /// Where to write the settlement report.
+    settlement_report: Option<PathBuf>,
+    /// Where to write the amounts per tag.
+    tag_report: Option<PathBuf>,
+    /// Where to write the balance history of the accounts.
+    balance_history: Option<PathBuf>,
+    /// Where to write the journal of the processed transactions.
+    ledger: Option<PathBuf>,
+    /// Where to write the rows that could not be parsed.
+    quarantine: Option<PathBuf>,
+    /// A file with the accounts to open before processing.
+    initial_balances: Option<PathBuf>,
+    /// The client of the transactions in bank statement input files.
+    statement_client: Option<u16>,
+    /// A file with the clients of the accounts in ISO 20022 input files.
+    statement_accounts: Option<PathBuf>,
+    /// A file defining recurring transactions.
+    recurring: Option<PathBuf>,
+    /// A file defining per-client minimum balances.
+    min_balances: Option<PathBuf>,
+    /// A file defining per-client balance caps.
+    balance_caps: Option<PathBuf>,
+    /// A file defining per-client daily withdrawal limits.
+    withdrawal_limits: Option<PathBuf>,
+    /// A file defining the operations each role is permitted.
+    permissions: Option<PathBuf>,
+    /// A file defining exchange rates.
+    fx_rates: Option<PathBuf>,
+    /// A file defining the fees charged.
+    fee_schedule: Option<PathBuf>,
+    options: Options,
+}
+
+/// A dedup store backend.
+#[derive(Debug, PartialEq, Eq)]
+enum DedupSpec {
+    Memory,
+    /// A connection url like `redis://host:6379/0`.
+    Redis(String),
+}
+
+impl FromStr for DedupSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<DedupSpec, String> {
+        if s == "memory" {
+            return Ok(DedupSpec::Memory);
+        }
+        if s.starts_with("redis://") || s.starts_with("rediss://") {
+            return Ok(DedupSpec::Redis(s.to_string()));
+        }
+        Err(format!(
+            "expected `memory` or a `redis://` url, got `{}`",
+            s
+        ))
+    }
+}
+
+impl DedupSpec {
+    fn open(&self, retention: Duration) -> Result<Box<dyn DedupStore>, StoreError> {
+        match self {
+            DedupSpec::Memory => Ok(Box::new(MemoryDedupStore::new(retention))),
+            #[cfg(feature = "redis")]
+            DedupSpec::Redis(url) => Ok(Box::new(transactions::RedisDedupStore::connect(
+                url, retention,
+            )?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(StoreError::Backend(format!(
+                "{:?} is not supported by this build, rebuild with its cargo feature",
+                self
+            ))),
+        }
+    }
+}
+
+/// A state store backend and where it keeps its data.
+#[derive(Debug, PartialEq, Eq)]
+enum StoreSpec {
+    Sled(PathBuf),
+    RocksDb(PathBuf),
+    /// A connection url like `postgres://user@host/db`.
+    Postgres(String),
+}
+
+impl FromStr for StoreSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<StoreSpec, String> {
+        if s.starts_with("postgres://") || s.starts_with("postgresql://") {
+            return Ok(StoreSpec::Postgres(s.to_string()));
+        }
+        match s.split_once(':') {
+            Some(("sled", path)) if !path.is_empty() => Ok(StoreSpec::Sled(PathBuf::from(path))),
+            Some(("rocksdb", path)) if !path.is_empty() => {
+                Ok(StoreSpec::RocksDb(PathBuf::from(path)))
+            }
+            _ => Err(format!(
+                "expected `sled:<dir>`, `rocksdb:<dir>` or a `postgres://` url, got `{}`",
+                s
+            )),
+        }
+    }
+}
+
+impl StoreSpec {
+    fn open(&self) -> Result<Box<dyn StateStore>, StoreError> {
+        match self {
+            #[cfg(feature = "sled")]
+            StoreSpec::Sled(path) => Ok(Box::new(transactions::SledStore::open(path)?)),
+            #[cfg(feature = "rocksdb")]
+            StoreSpec::RocksDb(path) => Ok(Box::new(transactions::RocksDbStore::open(path)?)),
+            #[cfg(feature = "postgres")]
+            StoreSpec::Postgres(url) => Ok(Box::new(transactions::PostgresStore::connect(url)?)),
+            #[allow(unreachable_patterns)]
+            _ => Err(StoreError::Backend(format!(
+                "{:?} is not supported by this build, rebuild with its cargo feature",
+                self
+            ))),
+        }
+    }
+}
+
+impl Args {
+    fn parse<I>(mut args: I) -> Result<Args, String>
+    where
+        I: Iterator<Item = String>,
+    {
+        let mut filepath = None;
+        let mut manifest = None;
+        let mut manifest_mismatch = CheckMode::Reject;
+        let mut snapshot = None;
+        let mut resume = None;
+        let mut continue_from = None;
+        let mut close_period = false;
+        let mut state_store = None;
+        let mut dedup_store = None;
+        let mut rules = None;
+        let mut rules_script = None;
+        let mut risk_config = None;
+        let mut aml_report = None;
+        let mut audit_log = None;
+        let mut dispute_report = None;
+        let mut settlement_report = None;
+        let mut tag_report = None;
+        let mut balance_history = None;
+        let mut ledger = None;
+        let mut quarantine = None;
+        let mut initial_balances = None;
+        let mut statement_client = None;
+        let mut statement_accounts = None;
+        let mut recurring = None;
+        let mut min_balances = None;
+        let mut permissions = None;
+        let mut fx_rates = None;
+        let mut fee_schedule = None;
+        let mut balance_caps = None;
+        let mut withdrawal_limits = None;
+        let mut watch_interval = None;
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--manifest" => manifest = Some(parse_value(&arg, args.next())?),
+                "--manifest-mismatch" => manifest_mismatch = parse_value(&arg, args.next())?,
+                "--snapshot" => snapshot = Some(parse_value(&arg, args.next())?),
+                "--resume" => resume = Some(parse_value(&arg, args.next())?),
+                "--continue-from" => continue_from = Some(parse_value(&arg, args.next())?),
+                "--close-period" => close_period = true,
+                "--state-store" => state_store = Some(parse_value(&arg, args.next())?),
+                "--dedup-store" => dedup_store = Some(parse_value(&arg, args.next())?),
+                "--dedup-retention" => {
+                    options.dedup_retention = Some(parse_value(&arg, args.next())?)
+                }
+                "--aml-amount" => options.aml.single_amount = Some(parse_value(&arg, args.next())?),
+                "--aml-daily-amount" => {
+                    options.aml.daily_amount = Some(parse_value(&arg, args.next())?)
+                }
+                "--aml-report" => aml_report = Some(parse_value(&arg, args.next())?),
+                "--audit-log" => audit_log = Some(parse_value(&arg, args.next())?),
+                "--dispute-report" => dispute_report = Some(parse_value(&arg, args.next())?),
+                "--settlement-report" => settlement_report = Some(parse_value(&arg, args.next())?),
+                "--tag-report" => tag_report = Some(parse_value(&arg, args.next())?),
+                "--tag-report-format" => {
+                    options.tag_report_format = parse_value(&arg, args.next())?
+                }
+                "--ledger" => ledger = Some(parse_value(&arg, args.next())?),
+                "--ledger-format" => options.ledger_format = parse_value(&arg, args.next())?,
+                "--ledger-currency" => {
+                    options.ledger_currency = Some(parse_value(&arg, args.next())?)
+                }
+                "--balance-history" => balance_history = Some(parse_value(&arg, args.next())?),
+                "--balance-history-every" => {
+                    let every = parse_value(&arg, args.next())?;
+                    options.balance_history = Some(HistoryInterval::Every(every));
+                }
+                "--risk-config" => risk_config = Some(parse_value(&arg, args.next())?),
+                "--risk-column" => options.risk_column = true,
+                "--authorization-ttl" => {
+                    options.authorization_ttl = Some(parse_value(&arg, args.next())?)
+                }
+                "--pending-column" => options.pending_column = true,
+                "--unfreeze-at" => options.unfreeze_at = Some(parse_value(&arg, args.next())?),
+                "--chargeback-freeze" => {
+                    options.chargeback_freeze = parse_value(&arg, args.next())?
+                }
+                "--confirm-chargebacks" => options.confirm_chargebacks = true,
+                "--fee-schedule" => fee_schedule = Some(parse_value(&arg, args.next())?),
+                "--fx-rates" => fx_rates = Some(parse_value(&arg, args.next())?),
+                "--frozen-disputes" => options.frozen_disputes = true,
+                "--signed-amounts" => options.signed_amounts = true,
+                "--forbid-negative-held" => options.forbid_negative_held = true,
+                "--cap-dispute-holds" => options.cap_dispute_holds = true,
+                "--permissions" => permissions = Some(parse_value(&arg, args.next())?),
+                "--escrow-column" => options.escrow_column = true,
+                "--receivable-column" => options.receivable_column = true,
+                "--dormant-after" => options.dormant_after = Some(parse_value(&arg, args.next())?),
+                "--block-dormant-withdrawals" => options.block_dormant_withdrawals = true,
+                "--dormant-column" => options.dormant_column = true,
+                "--min-balance" => {
+                    options.min_balance.default = Some(parse_value(&arg, args.next())?)
+                }
+                "--min-balances" => min_balances = Some(parse_value(&arg, args.next())?),
+                "--balance-cap" => {
+                    options.balance_cap.default = Some(parse_value(&arg, args.next())?)
+                }
+                "--balance-caps" => balance_caps = Some(parse_value(&arg, args.next())?),
+                "--daily-withdrawal-limit" => {
+                    options.withdrawal_limit.default = Some(parse_value(&arg, args.next())?)
+                }
+                "--daily-withdrawal-limits" => {
+                    withdrawal_limits = Some(parse_value(&arg, args.next())?)
+                }
+                "--day-start" => {
+                    options.withdrawal_limit.day_start =
+                        parse_value::<TimeOfDay>(&arg, args.next())?.0
+                }
+                "--balance-cap-mode" => {
+                    options.balance_cap.mode = parse_value::<CapMode>(&arg, args.next())?
+                }
+                "--settle-withdrawals" => options.settle_withdrawals = true,
+                "--rules" => rules = Some(parse_value(&arg, args.next())?),
+                "--initial-balances" => initial_balances = Some(parse_value(&arg, args.next())?),
+                "--statement-client" => statement_client = Some(parse_value(&arg, args.next())?),
+                "--statement-accounts" => {
+                    statement_accounts = Some(parse_value(&arg, args.next())?)
+                }
+                "--recurring" => recurring = Some(parse_value(&arg, args.next())?),
+                "--rules-script" => rules_script = Some(parse_value(&arg, args.next())?),
+                "--stop-after" => options.stop_after = Some(parse_value(&arg, args.next())?),
+                "--max-rate" => options.max_rate = Some(parse_value(&arg, args.next())?),
+                "--replay-speed" => options.replay_speed = parse_value(&arg, args.next())?,
+                "--monotonic-tx-ids" => {
+                    options.monotonic_tx_ids = Some(parse_value(&arg, args.next())?)
+                }
+                "--skip-duplicates" => options.skip_duplicates = true,
+                "--encoding" => options.encoding = parse_value(&arg, args.next())?,
+                "--delimiter" => options.delimiter = parse_value(&arg, args.next())?,
+                "--amount-format" => options.amount_format = parse_value(&arg, args.next())?,
+                "--strict-headers" => options.strict_headers = true,
+                "--strict-schema" => options.strict_schema = true,
+                "--no-header" => options.no_header = true,
+                "--error-format" => options.error_format = parse_value(&arg, args.next())?,
+                "--quarantine" => quarantine = Some(parse_value(&arg, args.next())?),
+                "--telemetry" => options.telemetry = true,
+                "--error-samples" => options.error_samples = Some(parse_value(&arg, args.next())?),
+                "--max-errors" => {
+                    options.error_limit.max_errors = Some(parse_value(&arg, args.next())?)
+                }
+                "--max-error-rate" => {
+                    options.error_limit.max_rate = Some(parse_value(&arg, args.next())?)
+                }
+                "--watch-config" => {
+                    watch_interval = Some(Duration::from_secs(parse_value(&arg, args.next())?))
+                }
+                "--checkpoint-dir" => {
+                    options.checkpoint_dir = Some(parse_value(&arg, args.next())?)
+                }
+                "--checkpoint-every" => {
+                    options.checkpoint_every = Some(parse_value(&arg, args.next())?)
+                }
+                "--checkpoint-interval" => {
+                    options.checkpoint_interval = Some(parse_value(&arg, args.next())?)
+                }
+                "--checkpoint-keep" => {
+                    options.checkpoint_keep = Some(parse_value(&arg, args.next())?)
+                }
+                "--reorder-window" => {
+                    options.reorder_window = Some(parse_value(&arg, args.next())?)
+                }
+                _ if arg.starts_with("--") => return Err(format!("unknown option `{}`", arg)),
+                _ if filepath.is_none() => filepath = Some(arg),
+                _ => return Err(format!("unexpected argument `{}`", arg)),
+            }
+        }
+        match (&filepath, &manifest) {
+            (None, None) => return Err("missing csv filepath".to_string()),
+            (Some(_), Some(_)) => {
+                return Err("a csv filepath cannot be combined with `--manifest`".to_string())
+            }
+            _ => {}
+        }
+        let has_checkpoint_trigger =
+            options.checkpoint_every.is_some() || options.checkpoint_interval.is_some();
+        if options.checkpoint_dir.is_some() != has_checkpoint_trigger {
+            return Err("`--checkpoint-dir` requires `--checkpoint-every` or \
+                 `--checkpoint-interval`, and vice versa"
+                .to_string());
+        }
+        // A checkpoint must only count records whose effects it contains, which
+        // records held back for reordering do not.
+        if options.checkpoint_dir.is_some() && options.reorder_window.is_some() {
+            return Err(
+                "`--checkpoint-dir` cannot be combined with `--reorder-window`".to_string(),
+            );
+        }
+        if balance_history.is_some() && options.balance_history.is_none() {
+            options.balance_history = Some(HistoryInterval::EveryChange);
+        }
+        if state_store.is_some() && resume.is_some() {
+            return Err("`--state-store` cannot be combined with `--resume`".to_string());
+        }
+        if close_period && continue_from.is_none() {
+            return Err("`--close-period` requires `--continue-from`".to_string());
+        }
+        if continue_from.is_some() && (resume.is_some() || state_store.is_some()) {
+            return Err(
+                "`--continue-from` cannot be combined with `--resume` or `--state-store`"
+                    .to_string(),
+            );
+        }
+        // Like checkpoints, the store must only count records whose effects it contains.
+        if state_store.is_some() && options.reorder_window.is_some() {
+            return Err("`--state-store` cannot be combined with `--reorder-window`".to_string());
+        }
+        if let Some(interval) = watch_interval {
+            let files: Vec<_> = [
+                (PolicyFile::FeeSchedule, &fee_schedule),
+                (PolicyFile::Rules, &rules),
+                (PolicyFile::BalanceCaps, &balance_caps),
+                (PolicyFile::WithdrawalLimits, &withdrawal_limits),
+            ]
+            .iter()
+            .filter_map(|(policy, path)| Some((*policy, (*path).clone()?)))
+            .collect();
+            if files.is_empty() {
+                return Err("`--watch-config` requires `--fee-schedule`, `--rules`, \
+                     `--balance-caps` or `--daily-withdrawal-limits`"
+                    .to_string());
+            }
+            options.watch_config = Some(WatchedConfig { interval, files });
+        }
+        if aml_report.is_some() == options.aml.is_empty() {
+            return Err(
+                "`--aml-report` requires `--aml-amount` or `--aml-daily-amount`, \
+                 and vice versa"
+                    .to_string(),
+            );
+        }
+        Ok(Args {
+            filepath,
+            manifest,
+            manifest_mismatch,
+            snapshot,
+            resume,
+            continue_from,
+            close_period,
+            state_store,
+            dedup_store,
+            rules,
+            rules_script,
+            risk_config,
+            aml_report,
+            audit_log,
+            dispute_report,
+            settlement_report,
+            tag_report,
+            balance_history,
+            ledger,
+            quarantine,
+            initial_balances,
+            statement_client,
+            statement_accounts,
+            recurring,
+            min_balances,
+            balance_caps,
+            withdrawal_limits,
+            permissions,
+            fx_rates,
+            fee_schedule,
+            options,
+        })
+    }
 }
 
+fn parse_value<T>(option: &str, value: Option<String>) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = value.ok_or_else(|| format!("missing value for `{}`", option))?;
+    value
+        .parse()
+        .map_err(|e| format!("invalid value `{}` for `{}`: {}", value, option, e))
+}
+
+/// Identifies a record for filtering out redeliveries: its idempotency key, if
+/// any, or else its type and transaction id. Transaction ids are unique, but a
+/// deposit shares its id with the disputes referring to it, and a dispute can
+/// have several notes.
+fn dedup_key(record: &TransactionRecord) -> String {
+    if let Some(key) = record.idempotency_key.as_ref() {
+        return format!("key/{}", key);
+    }
+    match record.note.as_ref() {
+        Some(note) => format!("{:?}/{:?}/{}", record.kind, record.tx_id, note),
+        None => format!("{:?}/{:?}", record.kind, record.tx_id),
+    }
+}
+
+/// A recurring deposit or withdrawal, as defined in a `--recurring` file.
 #[derive(Debug, Deserialize)]
-struct TransactionInfo {
+#[serde(deny_unknown_fields)]
+struct RecurringInfo {
     #[serde(rename = "type")]
-    kind: TransactionInfoKind,
+    kind: RecurringKind,
     #[serde(rename = "client")]
     client_id: ClientId,
+    /// The id of the first occurrence; later ones get the following ids.
     #[serde(rename = "tx")]
     tx_id: TransactionId,
-    amount: Option<Price4>,
+    amount: Price4,
+    /// When the first occurrence takes effect.
+    start: Timestamp,
+    /// The seconds between occurrences.
+    every: NonZeroU64,
+    count: NonZeroU32,
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-struct AccountInfo {
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum RecurringKind {
+    Deposit,
+    Withdrawal,
+}
+
+#[derive(Debug, Deserialize)]
+struct RecurringFile {
+    #[serde(default)]
+    recurring: Vec<RecurringInfo>,
+}
+
+/// Schedules the recurring transactions defined in the `--recurring` file at
+/// `path`. Series scheduled by an earlier run are skipped.
+fn schedule_recurring(processor: &mut TransactionProcessor, path: &Path) -> Result<(), String> {
+    let file: RecurringFile = read_toml(path)?;
+    for info in file.recurring.iter() {
+        let (client_id, tx_id, amount) = (info.client_id, info.tx_id, info.amount);
+        let transaction = match info.kind {
+            RecurringKind::Deposit => Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount,
+            }),
+            RecurringKind::Withdrawal => Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount,
+            }),
+        };
+        match processor.schedule_recurring(transaction, info.start, info.every, info.count) {
+            Ok(()) | Err(Error::DuplicateTx(_)) => {}
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(())
+}
+
+/// Loads the snapshot of an earlier run over other input as the opening state of
+/// a run over new input, so none of the new records are skipped.
+fn continue_from(path: &Path) -> Result<Snapshot, transactions::SnapshotError> {
+    let mut snapshot = Snapshot::load(path)?;
+    snapshot.records_read = 0;
+    Ok(snapshot)
+}
+
+/// An account to open, as defined in an `--initial-balances` file. Other columns,
+/// such as the `total` of an output file, are ignored.
+#[derive(Debug, Deserialize)]
+struct InitialBalanceInfo {
     #[serde(rename = "client")]
     client_id: ClientId,
-    #[serde(rename = "available")]
-    available_funds: Price4,
-    #[serde(rename = "held")]
-    held_funds: Price4,
-    #[serde(rename = "total")]
-    total_funds: Price4,
-    #[serde(rename = "locked")]
-    is_frozen: bool,
+    available: Price4,
+    held: Price4,
+    #[serde(default, alias = "locked")]
+    frozen: bool,
 }
 
-fn process(
-    transaction_processor: &mut TransactionProcessor,
-    tx_info: &TransactionInfo,
-) -> Result<(), Error> {
-    match tx_info.kind {
-        TransactionInfoKind::Deposit => transaction_processor.process_deposit(Deposit {
-            client_id: tx_info.client_id,
-            tx_id: tx_info.tx_id,
-            // TODO: Use separate error type and not a internal library error type.
-            amount: tx_info.amount.ok_or(Error::InvalidPrice)?,
-        }),
-        TransactionInfoKind::Withdrawal => transaction_processor.process_withdrawal(Withdrawal {
-            client_id: tx_info.client_id,
-            tx_id: tx_info.tx_id,
-            amount: tx_info.amount.ok_or(Error::InvalidPrice)?,
-        }),
-        TransactionInfoKind::Dispute => transaction_processor.process_dispute(Dispute {
-            client_id: tx_info.client_id,
-            tx_id: tx_info.tx_id,
-        }),
-        TransactionInfoKind::Resolve => transaction_processor.process_resolve(Resolve {
-            client_id: tx_info.client_id,
-            tx_id: tx_info.tx_id,
-        }),
-        TransactionInfoKind::Chargeback => transaction_processor.process_chargeback(Chargeback {
-            client_id: tx_info.client_id,
-            tx_id: tx_info.tx_id,
-        }),
+/// Opens the accounts in the `--initial-balances` file at `path`.
+fn open_accounts(processor: &mut TransactionProcessor, path: &Path) -> Result<(), String> {
+    let invalid = |e: &dyn std::fmt::Display| format!("invalid {}: {}", path.display(), e);
+    let reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    for info in reader.into_deserialize() {
+        let info: InitialBalanceInfo = info.map_err(|e| invalid(&e))?;
+        processor
+            .open_account(info.client_id, info.available, info.held, info.frozen)
+            .map_err(|e| invalid(&e))?;
     }
+    Ok(())
 }
 
-fn run<R, W, E>(instream: R, outstream: W, mut errstream: E)
-where
-    R: std::io::Read,
-    W: std::io::Write,
-    E: std::io::Write,
-{
-    // 1) Parse transactions from `instream` and process them.
-    let mut transaction_processor = TransactionProcessor::new();
-    let mut reader = csv::ReaderBuilder::new()
+/// A transaction of a bank statement, as a record of the input.
+#[derive(Debug, Serialize)]
+struct StatementRecord {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    client: u16,
+    tx: TransactionId,
+    amount: Price4,
+    timestamp: Timestamp,
+}
+
+/// The clients the transactions of bank statement input files are for.
+#[derive(Debug, Default)]
+struct StatementClients {
+    /// The client of OFX and QIF statements, and of ISO 20022 messages on accounts
+    /// not in `accounts`.
+    client: Option<u16>,
+    /// The client of each account of ISO 20022 messages, by IBAN or other id.
+    accounts: HashMap<String, u16>,
+}
+
+/// A client's bank account, as defined in a `--statement-accounts` file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct StatementAccountInfo {
+    account: String,
+    client: u16,
+}
+
+/// Reads the clients of the accounts in the `--statement-accounts` file at `path`.
+fn load_statement_accounts(path: &Path) -> Result<HashMap<String, u16>, String> {
+    let reader = csv::ReaderBuilder::new()
         .trim(csv::Trim::All)
-        .delimiter(b',')
-        .from_reader(instream);
-    for result in reader.deserialize() {
-        let tx_info: TransactionInfo = match result {
-            Ok(tx_info) => tx_info,
+        .from_path(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    reader
+        .into_deserialize()
+        .map(|info| {
+            let info: StatementAccountInfo =
+                info.map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+            Ok((info.account, info.client))
+        })
+        .collect()
+}
+
+/// Whether the input file at `path` is a bank statement, or an ISO 20022 message,
+/// rather than a csv file.
+fn is_statement(path: &Path) -> bool {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    matches!(
+        extension.map(str::to_ascii_lowercase).as_deref(),
+        Some("ofx") | Some("qfx") | Some("qif") | Some("xml")
+    )
+}
+
+/// Reads the transactions of the ISO 20022 message at `path`, with their accounts.
+#[cfg(feature = "roxmltree")]
+fn read_iso20022_entries(path: &Path) -> Result<Vec<(String, StatementEntry)>, String> {
+    let entries = transactions::load_iso20022(path).map_err(|e| e.to_string())?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| (entry.account, entry.entry))
+        .collect())
+}
+
+#[cfg(not(feature = "roxmltree"))]
+fn read_iso20022_entries(_path: &Path) -> Result<Vec<(String, StatementEntry)>, String> {
+    Err(
+        "ISO 20022 messages are not supported by this build, rebuild with the \
+         `roxmltree` feature"
+            .to_string(),
+    )
+}
+
+/// Reads the bank statement or ISO 20022 message at `path` as csv records of
+/// deposits and withdrawals of its clients.
+fn read_statement_records(path: &Path, clients: &StatementClients) -> Result<Vec<u8>, String> {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    let is_xml = extension.is_some_and(|extension| extension.eq_ignore_ascii_case("xml"));
+    let entries: Vec<(u16, StatementEntry)> = if is_xml {
+        let entries = read_iso20022_entries(path)?;
+        entries
+            .into_iter()
+            .map(|(account, entry)| {
+                let client = clients.accounts.get(&account).copied();
+                let client = client.or(clients.client).ok_or_else(|| {
+                    format!(
+                        "no client for account `{}` of {}, add it to `--statement-accounts`",
+                        account,
+                        path.display()
+                    )
+                })?;
+                Ok((client, entry))
+            })
+            .collect::<Result<_, String>>()?
+    } else {
+        let client = clients
+            .client
+            .ok_or_else(|| format!("{} requires `--statement-client`", path.display()))?;
+        let entries = transactions::read_statement(path).map_err(|e| e.to_string())?;
+        entries.into_iter().map(|entry| (client, entry)).collect()
+    };
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for (
+        client,
+        StatementEntry {
+            tx_id,
+            timestamp,
+            amount,
+        },
+    ) in entries
+    {
+        let record = StatementRecord {
+            kind: if amount.is_sign_negative() {
+                "withdrawal"
+            } else {
+                "deposit"
+            },
+            client,
+            tx: tx_id,
+            amount: amount.abs(),
+            timestamp,
+        };
+        writer.serialize(record).map_err(|e| e.to_string())?;
+    }
+    writer.into_inner().map_err(|e| e.to_string())
+}
+
+/// Reads a .toml file at `path`.
+fn read_toml<T: serde::de::DeserializeOwned>(path: &Path) -> Result<T, String> {
+    let file = std::fs::read_to_string(path)
+        .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+    toml::from_str(&file).map_err(|e| format!("invalid {}: {}", path.display(), e))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FxRatesFile {
+    base: Currency,
+    #[serde(default)]
+    spread: Price4,
+    /// A .csv or .json file with more rates, relative to the `--fx-rates` file.
+    #[serde(default)]
+    table: Option<PathBuf>,
+    #[serde(default)]
+    rate: Vec<Rate>,
+}
+
+/// Reads the exchange rates defined in the `--fx-rates` file at `path`, and in
+/// the rate table it refers to.
+fn load_fx_rates(path: &Path) -> Result<FxRates, String> {
+    let file: FxRatesFile = read_toml(path)?;
+    let mut rates = FxRates::new(file.base);
+    rates.spread = file.spread;
+    if let Some(table) = file.table {
+        let table = path.parent().unwrap_or_else(|| Path::new("")).join(table);
+        rates.rates = RateTable::load(&table).map_err(|e| e.to_string())?;
+    }
+    for rate in file.rate {
+        rates
+            .rates
+            .insert(rate)
+            .map_err(|e| format!("invalid {}: {}", path.display(), e))?;
+    }
+    Ok(rates)
+}
+
+/// A client's balance limit, as defined in a `--min-balances` or `--balance-caps`
+/// file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientBalanceInfo {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    balance: Price4,
+}
+
+#[derive(Debug, Deserialize)]
+struct MinBalancesFile {
+    #[serde(default)]
+    minimum: Vec<ClientBalanceInfo>,
+}
+
+/// A client's limit, as defined in a `--daily-withdrawal-limits` file.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ClientLimitInfo {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    amount: Price4,
+}
+
+#[derive(Debug, Deserialize)]
+struct WithdrawalLimitsFile {
+    #[serde(default)]
+    limit: Vec<ClientLimitInfo>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BalanceCapsFile {
+    #[serde(default)]
+    cap: Vec<ClientBalanceInfo>,
+}
+
+/// Reads the per-client minimum balances defined in the `--min-balances` file at
+/// `path` into `min_balance`.
+fn load_min_balances(min_balance: &mut MinimumBalance, path: &Path) -> Result<(), String> {
+    let file: MinBalancesFile = read_toml(path)?;
+    for info in file.minimum {
+        min_balance.per_client.insert(info.client_id, info.balance);
+    }
+    Ok(())
+}
+
+/// Reads the per-client balance caps defined in the `--balance-caps` file at
+/// `path` into `balance_cap`.
+fn load_balance_caps(balance_cap: &mut BalanceCap, path: &Path) -> Result<(), String> {
+    let file: BalanceCapsFile = read_toml(path)?;
+    for info in file.cap {
+        balance_cap.per_client.insert(info.client_id, info.balance);
+    }
+    Ok(())
+}
+
+/// Reads the per-client daily withdrawal limits defined in the
+/// `--daily-withdrawal-limits` file at `path` into `withdrawal_limit`.
+fn load_withdrawal_limits(
+    withdrawal_limit: &mut DailyWithdrawalLimit,
+    path: &Path,
+) -> Result<(), String> {
+    let file: WithdrawalLimitsFile = read_toml(path)?;
+    for info in file.limit {
+        withdrawal_limit
+            .per_client
+            .insert(info.client_id, info.amount);
+    }
+    Ok(())
+}
+
+/// A policy file `--watch-config` reloads.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PolicyFile {
+    FeeSchedule,
+    Rules,
+    BalanceCaps,
+    WithdrawalLimits,
+}
+
+impl PolicyFile {
+    /// The audit log action of reloading the file.
+    fn reload_action(self) -> &'static str {
+        match self {
+            PolicyFile::FeeSchedule => "reload_fee_schedule",
+            PolicyFile::Rules => "reload_rules",
+            PolicyFile::BalanceCaps => "reload_balance_caps",
+            PolicyFile::WithdrawalLimits => "reload_withdrawal_limits",
+        }
+    }
+}
+
+/// The policy files reloaded when they change, and how often they are checked.
+/// Minimum balances are not, as they are checked by a validator added once.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct WatchedConfig {
+    interval: Duration,
+    files: Vec<(PolicyFile, PathBuf)>,
+}
+
+/// Detects changes of the policy files by comparing their contents with those
+/// of the last check, as modification times can be too coarse for quick edits.
+struct ConfigWatcher {
+    interval: Duration,
+    files: Vec<(PolicyFile, PathBuf, Option<Vec<u8>>)>,
+    last_check: Instant,
+}
+
+impl ConfigWatcher {
+    fn new(config: &WatchedConfig) -> ConfigWatcher {
+        ConfigWatcher {
+            interval: config.interval,
+            files: config
+                .files
+                .iter()
+                .map(|(policy, path)| (*policy, path.clone(), std::fs::read(path).ok()))
+                .collect(),
+            last_check: Instant::now(),
+        }
+    }
+
+    /// The files changed since the last check, if one is due. A file that cannot
+    /// be read, e.g. while it is replaced, is checked again next time.
+    fn changed(&mut self) -> Vec<(PolicyFile, PathBuf)> {
+        if self.last_check.elapsed() < self.interval {
+            return Vec::new();
+        }
+        self.last_check = Instant::now();
+        let mut changed = Vec::new();
+        for (policy, path, contents) in self.files.iter_mut() {
+            if let Ok(current) = std::fs::read(&*path) {
+                if contents.as_ref() != Some(&current) {
+                    *contents = Some(current);
+                    changed.push((*policy, path.clone()));
+                }
+            }
+        }
+        changed
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+struct AccountInfo {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "available")]
+    available_funds: Price4,
+    #[serde(rename = "held")]
+    held_funds: Price4,
+    #[serde(rename = "total")]
+    total_funds: Price4,
+    #[serde(rename = "locked")]
+    is_frozen: bool,
+    #[serde(rename = "pending", default, skip_serializing_if = "Option::is_none")]
+    pending_funds: Option<Price4>,
+    #[serde(
+        rename = "pending_out",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pending_out_funds: Option<Price4>,
+    #[serde(rename = "escrow", default, skip_serializing_if = "Option::is_none")]
+    escrow_funds: Option<Price4>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    receivable: Option<Price4>,
+    #[serde(rename = "risk", default, skip_serializing_if = "Option::is_none")]
+    risk_score: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    dormant: Option<bool>,
+    /// The balances in other currencies, like `EUR 10; GBP 5`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    balances: Option<String>,
+}
+
+/// A row of the dispute report.
+#[derive(Debug, Serialize)]
+struct DisputeInfo {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    #[serde(rename = "tx")]
+    tx_id: TransactionId,
+    amount: Price4,
+    /// The notes, oldest first, separated by `; `.
+    notes: String,
+}
+
+impl From<OpenDispute> for DisputeInfo {
+    fn from(dispute: OpenDispute) -> DisputeInfo {
+        let notes: Vec<_> = dispute.notes.iter().map(|note| note.to_string()).collect();
+        DisputeInfo {
+            client_id: dispute.client_id,
+            tx_id: dispute.tx_id,
+            amount: dispute.amount,
+            notes: notes.join("; "),
+        }
+    }
+}
+
+/// A row of the balance history.
+#[derive(Debug, Serialize)]
+struct BalancePointInfo {
+    #[serde(rename = "client")]
+    client_id: ClientId,
+    seq: u64,
+    timestamp: u64,
+    available: Price4,
+    held: Price4,
+    total: Price4,
+}
+
+impl BalancePointInfo {
+    fn new(client_id: ClientId, point: &BalancePoint) -> BalancePointInfo {
+        BalancePointInfo {
+            client_id,
+            seq: point.seq,
+            timestamp: point.timestamp.as_secs(),
+            available: point.available,
+            held: point.held,
+            total: point.total,
+        }
+    }
+}
+
+/// Converts a record to a transaction. Authorizations expire `authorization_ttl`
+/// seconds after their timestamp, if both are given.
+fn to_transaction(
+    tx_info: &TransactionRecord,
+    authorization_ttl: Option<NonZeroU64>,
+) -> Result<Transaction, Error> {
+    let mut transaction = Transaction::try_from(tx_info)?;
+    if let Transaction::Authorize(authorize) = &mut transaction {
+        authorize.expires_at = tx_info
+            .timestamp
+            .zip(authorization_ttl)
+            .map(|(timestamp, ttl)| {
+                Timestamp::from_secs(timestamp.as_secs().saturating_add(ttl.get()))
+            });
+    }
+    Ok(transaction)
+}
+
+/// The tag of a deposit or withdrawal record, if it has one. Tags of other
+/// records are ignored.
+fn tag_of(record: &TransactionRecord) -> Option<&str> {
+    match record.kind {
+        RecordKind::Deposit | RecordKind::Withdrawal => record.tag.as_deref(),
+        _ => None,
+    }
+}
+
+fn process(
+    transaction_processor: &mut TransactionProcessor,
+    transaction: Transaction,
+    rules: Option<&mut RuleSet>,
+) -> Result<RuleOutcome, Error> {
+    match rules {
+        Some(rules) => transaction_processor.process_with_rules(transaction, rules),
+        None => transaction_processor
+            .process(transaction)
+            .map(|()| RuleOutcome::default()),
+    }
+}
+
+/// An input record as read, for errors reported as JSON.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct RecordSource {
+    /// The line the record starts on in its input file.
+    line: u64,
+    /// The record's fields as csv, before trimming.
+    raw: String,
+}
+
+impl RecordSource {
+    fn of(record: &csv::StringRecord) -> RecordSource {
+        let mut writer = csv::WriterBuilder::new()
+            .terminator(csv::Terminator::Any(b'\n'))
+            .from_writer(Vec::new());
+        writer
+            .write_record(record)
+            .expect("writing to memory cannot fail");
+        let raw = writer.into_inner().expect("writing to memory cannot fail");
+        RecordSource {
+            line: record.position().map_or(0, csv::Position::line),
+            raw: String::from_utf8_lossy(&raw).trim_end().to_string(),
+        }
+    }
+}
+
+/// An error reported on the error stream as JSON.
+#[derive(Serialize)]
+struct ErrorEvent<'a> {
+    line: Option<u64>,
+    record: Option<&'a str>,
+    code: &'a str,
+    message: String,
+}
+
+/// Errors of the same kind and shape, of which only the first few are written.
+struct ErrorGroup {
+    code: String,
+    /// The message of the errors with each number replaced by `#`.
+    shape: String,
+    count: u64,
+}
+
+/// Limits the errors written per code and shape, counting the others.
+struct ErrorSampler {
+    /// How many errors of each group are written.
+    samples: u64,
+    groups: Vec<ErrorGroup>,
+    /// The index in `groups` of each code and shape.
+    indices: HashMap<(String, String), usize>,
+}
+
+impl ErrorSampler {
+    fn new(samples: u64) -> ErrorSampler {
+        ErrorSampler {
+            samples,
+            groups: Vec::new(),
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Counts an error of `code` with `message`, and returns whether to write it.
+    fn admit(&mut self, code: &str, message: &str) -> bool {
+        let shape = error_shape(message);
+        let groups = &mut self.groups;
+        let index = *self
+            .indices
+            .entry((code.to_string(), shape))
+            .or_insert_with_key(|(code, shape)| {
+                groups.push(ErrorGroup {
+                    code: code.clone(),
+                    shape: shape.clone(),
+                    count: 0,
+                });
+                groups.len() - 1
+            });
+        let group = &mut self.groups[index];
+        group.count += 1;
+        group.count <= self.samples
+    }
+}
+
+/// `message` with each run of digits replaced by `#`, so errors that only differ
+/// in their amounts or ids have the same shape.
+fn error_shape(message: &str) -> String {
+    let mut shape = String::with_capacity(message.len());
+    for c in message.chars() {
+        if !c.is_ascii_digit() {
+            shape.push(c);
+        } else if !shape.ends_with('#') {
+            shape.push('#');
+        }
+    }
+    shape
+}
+
+/// Writes errors, and the summary of a run, to the error stream in an
+/// `ErrorFormat`.
+struct ErrorStream<E> {
+    writer: E,
+    format: ErrorFormat,
+    /// The record being processed, if any.
+    record: Option<RecordSource>,
+    /// Limits the errors written, if `--error-samples` is given.
+    sampler: Option<ErrorSampler>,
+    /// The number of records that were rejected so far.
+    rejected: u64,
+    /// The last error written, until it is reported to the run control.
+    last_error: Option<String>,
+}
+
+impl<E: std::io::Write> ErrorStream<E> {
+    fn new(writer: E, format: ErrorFormat, samples: Option<u64>) -> ErrorStream<E> {
+        ErrorStream {
+            writer,
+            format,
+            record: None,
+            sampler: samples.map(ErrorSampler::new),
+            rejected: 0,
+            last_error: None,
+        }
+    }
+
+    /// Whether to write an error of `code` with `message`. The summary of the run
+    /// is always written.
+    fn admit(&mut self, code: &str, message: &dyn std::fmt::Display) -> bool {
+        match self.sampler.as_mut() {
+            Some(sampler) if code != "summary" => sampler.admit(code, &message.to_string()),
+            _ => true,
+        }
+    }
+
+    /// Reports `message`, as an error of kind `code` in JSON.
+    fn report(&mut self, code: &str, message: impl std::fmt::Display) {
+        if self.admit(code, &message) {
+            self.write(code, message);
+        }
+    }
+
+    fn write(&mut self, code: &str, message: impl std::fmt::Display) {
+        if code != "summary" {
+            self.last_error = Some(message.to_string());
+        }
+        match self.format {
+            ErrorFormat::Text => writeln!(self.writer, "{}", message).expect("write failed"),
+            ErrorFormat::Json => {
+                let event = ErrorEvent {
+                    line: self.record.as_ref().map(|record| record.line),
+                    record: self.record.as_ref().map(|record| record.raw.as_str()),
+                    code,
+                    message: message.to_string(),
+                };
+                serde_json::to_writer(&mut self.writer, &event).expect("write failed");
+                writeln!(self.writer).expect("write failed");
+            }
+        }
+    }
+
+    /// Reports that `record` could not be processed because of `error`. Only the
+    /// text format quotes the record, as JSON has it as read.
+    /// Errors are sampled by `error`, as records rarely repeat exactly.
+    fn fail(&mut self, record: &TransactionRecord, code: &str, error: impl std::fmt::Display) {
+        self.rejected += 1;
+        if !self.admit(code, &error) {
+            return;
+        }
+        match self.format {
+            ErrorFormat::Text => self.write(
+                code,
+                format_args!("failed to process `{}`: {}", record, error),
+            ),
+            ErrorFormat::Json => self.write(code, error),
+        }
+    }
+
+    /// Reports how many errors of each group were not written.
+    fn report_suppressed(&mut self) {
+        let groups = match self.sampler.as_mut() {
+            Some(sampler) => std::mem::take(&mut sampler.groups),
+            None => return,
+        };
+        let samples = self.sampler.as_ref().map_or(0, |sampler| sampler.samples);
+        for group in groups.iter().filter(|group| group.count > samples) {
+            self.write(
+                "suppressed",
+                format_args!(
+                    "{} `{}` errors like `{}`, {} not shown",
+                    group.count,
+                    group.code,
+                    group.shape,
+                    group.count - samples
+                ),
+            );
+        }
+    }
+
+    /// Reports the errors that were not written, and flushes the stream.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.report_suppressed();
+        self.writer.flush()
+    }
+}
+
+/// The external stores a run reads from and writes to.
+#[derive(Default)]
+struct Stores<'a> {
+    /// Persists the state after every record.
+    state: Option<&'a mut dyn StateStore>,
+    /// Filters out records that were already applied.
+    dedup: Option<&'a mut dyn DedupStore>,
+    /// Receives the suspicious-activity report as csv.
+    aml_report: Option<&'a mut dyn std::io::Write>,
+    /// Receives the audit log as csv.
+    audit_log: Option<&'a mut dyn std::io::Write>,
+    /// Receives the report of open disputes as csv.
+    dispute_report: Option<&'a mut dyn std::io::Write>,
+    /// Receives the settlement report as csv.
+    settlement_report: Option<&'a mut dyn std::io::Write>,
+    /// Receives the amounts per tag.
+    tag_report: Option<&'a mut dyn std::io::Write>,
+    /// Receives the balance history of the accounts as csv.
+    balance_history: Option<&'a mut dyn std::io::Write>,
+    /// Receives the journal of the processed transactions.
+    ledger: Option<&'a mut dyn std::io::Write>,
+    /// Receives the rows that could not be parsed, verbatim.
+    quarantine: Option<&'a mut dyn std::io::Write>,
+}
+
+/// Applies parsed records to the transaction processor.
+struct Runner<'a> {
+    options: &'a Options,
+    processor: TransactionProcessor,
+    pacer: Option<ReplayPacer>,
+    duplicates_skipped: u64,
+    store: Option<&'a mut dyn StateStore>,
+    /// The number of records read as of the last write to `store`.
+    persisted_records_read: u64,
+    /// The accounts and transactions touched since the last write to `store`.
+    touched: Vec<(ClientId, TransactionId)>,
+    dedup: Option<&'a mut dyn DedupStore>,
+    /// Remembers the idempotency keys of read records without a dedup store.
+    idempotency_keys: Option<MemoryDedupStore>,
+    redeliveries_filtered: u64,
+    /// The dedup keys of read records whose effects are not persisted yet.
+    pending_dedup_keys: HashSet<String>,
+    rules: Option<RuleSet>,
+    /// Checks processed transactions against the AML thresholds, and reports
+    /// suspicious activity.
+    aml: Option<(AmlMonitor, csv::Writer<&'a mut dyn std::io::Write>)>,
+    /// Receives the administrative operations applied.
+    audit_log: Option<csv::Writer<&'a mut dyn std::io::Write>>,
+    /// The open batch of each client: its id and the records read so far.
+    batches: HashMap<ClientId, (TransactionId, Vec<TransactionRecord>)>,
+    /// The processor's clock, set to the records' timestamps.
+    clock: ManualClock,
+    /// Receives the open disputes at the end of the run.
+    dispute_report: Option<csv::Writer<&'a mut dyn std::io::Write>>,
+    /// Sums up the movement of funds of the clients, written at the end of the
+    /// run.
+    settlement: Option<(SettlementTracker, csv::Writer<&'a mut dyn std::io::Write>)>,
+    /// Receives the amounts per tag at the end of the run.
+    tag_report: Option<&'a mut dyn std::io::Write>,
+    /// Receives the accounts' balance history at the end of the run.
+    balance_history: Option<csv::Writer<&'a mut dyn std::io::Write>>,
+    /// Receives the journal entries of the processed transactions.
+    ledger: Option<LedgerWriter<&'a mut dyn std::io::Write>>,
+}
+
+/// Configures `processor` with the processing options.
+fn configure(processor: TransactionProcessor, options: &Options) -> ProcessorBuilder {
+    let mut builder = processor
+        .into_builder()
+        .settle_withdrawals(options.settle_withdrawals)
+        .balance_cap(Some(options.balance_cap.clone()).filter(|cap| !cap.is_empty()))
+        .withdrawal_limit(Some(options.withdrawal_limit.clone()).filter(|limit| !limit.is_empty()))
+        .unfreeze_policy(
+            options
+                .unfreeze_at
+                .map(|threshold| UnfreezePolicy { threshold }),
+        )
+        .freeze_policy(options.chargeback_freeze)
+        .dormancy_policy(options.dormant_after.map(|period| DormancyPolicy {
+            period: period.get(),
+            block_withdrawals: options.block_dormant_withdrawals,
+        }))
+        .confirm_chargebacks(options.confirm_chargebacks)
+        .permissions(options.permissions.clone())
+        .frozen_disputes(options.frozen_disputes)
+        .signed_amounts(options.signed_amounts)
+        .forbid_negative_held(options.forbid_negative_held)
+        .cap_dispute_holds(options.cap_dispute_holds)
+        .fx_rates(options.fx_rates.clone())
+        .fee_schedule(options.fee_schedule.clone())
+        .balance_history(options.balance_history);
+    if !options.min_balance.is_empty() {
+        builder = builder.validator(Box::new(options.min_balance.clone()));
+    }
+    builder
+}
+
+impl<'a> Runner<'a> {
+    fn new(options: &'a Options, start: Snapshot, stores: Stores<'a>) -> Runner<'a> {
+        // Daily limits follow the records' timestamps; without any, the whole
+        // input is a single day.
+        let clock = ManualClock::default();
+        let processor = configure(start.processor, options)
+            .clock(Box::new(clock.clone()))
+            .journal(stores.ledger.is_some())
+            .build()
+            .expect("the options are checked before running");
+        let currency = options
+            .ledger_currency
+            .or_else(|| options.fx_rates.as_ref().map(|rates| rates.base))
+            .unwrap_or_else(|| "USD".parse().expect("valid currency"));
+        Runner {
+            options,
+            processor,
+            pacer: ReplayPacer::new(options.replay_speed),
+            duplicates_skipped: 0,
+            store: stores.state,
+            persisted_records_read: start.records_read,
+            touched: Vec::new(),
+            dedup: stores.dedup,
+            idempotency_keys: None,
+            redeliveries_filtered: 0,
+            pending_dedup_keys: HashSet::new(),
+            rules: options.rules.clone(),
+            aml: stores.aml_report.map(|report| {
+                (
+                    AmlMonitor::new(options.aml),
+                    csv::Writer::from_writer(report),
+                )
+            }),
+            audit_log: stores.audit_log.map(csv::Writer::from_writer),
+            batches: HashMap::new(),
+            clock,
+            dispute_report: stores.dispute_report.map(csv::Writer::from_writer),
+            settlement: stores
+                .settlement_report
+                .map(|report| (SettlementTracker::new(), csv::Writer::from_writer(report))),
+            tag_report: stores.tag_report,
+            balance_history: stores.balance_history.map(csv::Writer::from_writer),
+            ledger: stores
+                .ledger
+                .map(|ledger| LedgerWriter::new(ledger, options.ledger_format, currency)),
+        }
+    }
+
+    /// Remembers the read records in the dedup store. With a state store, records
+    /// are only remembered once their effects are persisted, so a record is not
+    /// lost if the run stops in between.
+    fn remember_read(&mut self) -> Result<(), StoreError> {
+        let dedup = match self.dedup.as_mut() {
+            Some(dedup) => Some(&mut **dedup),
+            None => self
+                .idempotency_keys
+                .as_mut()
+                .map(|keys| keys as &mut dyn DedupStore),
+        };
+        if let Some(dedup) = dedup {
+            for key in self.pending_dedup_keys.drain() {
+                dedup.insert(&key)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the policy of `policy` with the one in the file at `path`, keeping
+    /// the current one if the file is invalid, and records the reload in the audit
+    /// log.
+    fn reload(&mut self, policy: PolicyFile, path: &Path) -> Result<(), String> {
+        match policy {
+            PolicyFile::FeeSchedule => {
+                let schedule = FeeSchedule::load(path).map_err(|e| e.to_string())?;
+                self.processor.set_fee_schedule(Some(schedule));
+            }
+            PolicyFile::Rules => {
+                self.rules = Some(RuleSet::load(path).map_err(|e| e.to_string())?);
+            }
+            PolicyFile::BalanceCaps => {
+                let mut cap = self.options.balance_cap.clone();
+                cap.per_client.clear();
+                load_balance_caps(&mut cap, path)?;
+                self.processor
+                    .set_balance_cap(Some(cap).filter(|cap| !cap.is_empty()));
+            }
+            PolicyFile::WithdrawalLimits => {
+                let mut limit = self.options.withdrawal_limit.clone();
+                limit.per_client.clear();
+                load_withdrawal_limits(&mut limit, path)?;
+                self.processor
+                    .set_withdrawal_limit(Some(limit).filter(|limit| !limit.is_empty()));
+            }
+        }
+        self.processor.record_policy_change(policy.reload_action());
+        Ok(())
+    }
+
+    /// Persists the effect of the records read since the last call to the state
+    /// store, if any, and then remembers them in the dedup store.
+    fn persist(&mut self, records_read: u64) -> Result<(), StoreError> {
+        // An open batch's records are read but not applied yet.
+        if !self.batches.is_empty() {
+            return Ok(());
+        }
+        let touched = std::mem::take(&mut self.touched);
+        if let Some(store) = self.store.as_mut() {
+            if records_read > self.persisted_records_read {
+                self.processor
+                    .persist(&mut **store, records_read, &touched)?;
+                self.persisted_records_read = records_read;
+            }
+        }
+        self.remember_read()
+    }
+
+    /// Persists the remaining records and makes everything written durable.
+    fn finish_persisting(&mut self, records_read: u64) -> Result<(), StoreError> {
+        if let Some((_, report)) = self.aml.as_mut() {
+            report
+                .flush()
+                .map_err(|e| StoreError::Backend(format!("AML report failed: {}", e)))?;
+        }
+        if let Some(log) = self.audit_log.as_mut() {
+            log.flush()
+                .map_err(|e| StoreError::Backend(format!("audit log failed: {}", e)))?;
+        }
+        self.persist(records_read)?;
+        match self.store.as_mut() {
+            Some(store) => store.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether `tx_info` was already read according to the dedup store, or has
+    /// the idempotency key of a record read before, in which case it is counted
+    /// and should be skipped.
+    fn is_redelivery(&mut self, tx_info: &TransactionRecord) -> Result<bool, StoreError> {
+        let retention = self.options.dedup_retention();
+        let dedup: &mut dyn DedupStore = match self.dedup.as_mut() {
+            Some(dedup) => &mut **dedup,
+            None if tx_info.idempotency_key.is_some() => self
+                .idempotency_keys
+                .get_or_insert_with(|| MemoryDedupStore::new(retention)),
+            None => return Ok(false),
+        };
+        let key = dedup_key(tx_info);
+        if self.pending_dedup_keys.contains(&key) || dedup.contains(&key)? {
+            self.redeliveries_filtered += 1;
+            return Ok(true);
+        }
+        self.pending_dedup_keys.insert(key);
+        Ok(false)
+    }
+
+    /// Applies `tx_info`, reporting a failure on `errstream`. A record that takes
+    /// effect after its timestamp (or without one) is scheduled instead.
+    fn apply<E: std::io::Write>(
+        &mut self,
+        tx_info: &TransactionRecord,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        if let (Some(pacer), Some(timestamp)) = (self.pacer.as_mut(), tx_info.timestamp) {
+            pacer.wait(timestamp);
+        }
+        if let Some(timestamp) = tx_info.timestamp {
+            self.advance(timestamp, errstream);
+        }
+        match tx_info.kind {
+            RecordKind::Begin => return self.begin_batch(tx_info, errstream),
+            RecordKind::Commit => return self.commit_batch(tx_info, errstream),
+            RecordKind::Note | RecordKind::Evidence => return self.add_note(tx_info, errstream),
+            RecordKind::Reactivate => return self.reactivate(tx_info, errstream),
+            _ => {}
+        }
+        if let Err(e) = self.authorize(tx_info) {
+            errstream.fail(tx_info, e.code(), e);
+            self.write_audit_log(errstream);
+            return;
+        }
+        if let Some((_, records)) = self.batches.get_mut(&tx_info.client_id) {
+            records.push(tx_info.clone());
+            return;
+        }
+        self.touched.push((tx_info.client_id, tx_info.tx_id));
+        let result = to_transaction(tx_info, self.options.authorization_ttl);
+        if let (Ok(transaction), Some(effective_at)) = (&result, tx_info.effective) {
+            if tx_info
+                .timestamp
+                .is_none_or(|timestamp| effective_at > timestamp)
+            {
+                self.processor.schedule(*transaction, effective_at);
+                return;
+            }
+        }
+        self.apply_transaction(
+            result,
+            tx_info,
+            tag_of(tx_info),
+            tx_info.client_id,
+            tx_info.timestamp,
+            errstream,
+        );
+    }
+
+    /// Checks that the role of `tx_info` is permitted its operation. Records that
+    /// are not valid transactions are left to fail when they are applied.
+    fn authorize(&mut self, tx_info: &TransactionRecord) -> Result<(), Error> {
+        let transaction = match to_transaction(tx_info, self.options.authorization_ttl) {
+            Ok(transaction) => transaction,
+            Err(_) => return Ok(()),
+        };
+        self.processor.authorize(
+            tx_info.role.as_ref(),
+            Operation::of(&transaction),
+            tx_info.client_id,
+            Some(tx_info.tx_id),
+        )
+    }
+
+    /// Opens a batch for the client of `begin`, whose records are held until it is
+    /// committed.
+    fn begin_batch<E: std::io::Write>(
+        &mut self,
+        begin: &TransactionRecord,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        if let Some((batch_id, _)) = self.batches.get(&begin.client_id) {
+            let reason = format!("batch {:?} is still open", batch_id);
+            errstream.fail(begin, "batch_open", reason);
+            return;
+        }
+        self.batches
+            .insert(begin.client_id, (begin.tx_id, Vec::new()));
+    }
+
+    /// Applies the records of the batch closed by `commit`, all or nothing.
+    /// Records in a batch take effect when it is committed.
+    fn commit_batch<E: std::io::Write>(
+        &mut self,
+        commit: &TransactionRecord,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        let records = match self.batches.remove(&commit.client_id) {
+            Some((batch_id, records)) if batch_id == commit.tx_id => records,
+            open => {
+                let reason = match open.as_ref() {
+                    Some((batch_id, _)) => format!("batch {:?} is open instead", batch_id),
+                    None => "no batch is open".to_string(),
+                };
+                if let Some(open) = open {
+                    self.batches.insert(commit.client_id, open);
+                }
+                errstream.fail(commit, "batch_not_open", reason);
+                return;
+            }
+        };
+        self.touched.extend(
+            records
+                .iter()
+                .map(|record| (record.client_id, record.tx_id)),
+        );
+        let authorization_ttl = self.options.authorization_ttl;
+        let transactions = records
+            .iter()
+            .enumerate()
+            .map(|(index, record)| {
+                to_transaction(record, authorization_ttl).map_err(|e| Error::BatchFailed {
+                    index,
+                    source: Box::new(e),
+                })
+            })
+            .collect::<Result<Vec<_>, _>>();
+        let was_under_review = self.is_under_review(commit.client_id);
+        let (processor, rules) = (&mut self.processor, &mut self.rules);
+        let result = transactions.and_then(|transactions| {
+            let outcomes = processor
+                .process_batch_with(&transactions, |processor, transaction| {
+                    process(processor, transaction, rules.as_mut())
+                })?;
+            Ok(transactions.into_iter().zip(outcomes).collect::<Vec<_>>())
+        });
+        self.report_review(commit.client_id, was_under_review, errstream);
+        match result {
+            Ok(applied) => {
+                for record in records.iter() {
+                    if let Some(tag) = tag_of(record) {
+                        // The batch's deposits and withdrawals were just processed.
+                        let _ =
+                            self.processor
+                                .set_tag(record.client_id, record.tx_id, tag.to_string());
+                    }
+                }
+                for (record, (transaction, outcome)) in records.iter().zip(applied) {
+                    self.report_outcome(record, &transaction, outcome, record.timestamp, errstream);
+                }
+            }
+            Err(e) => errstream.fail(commit, e.code(), e),
+        }
+    }
+
+    /// Attaches the note of a `note` or `evidence` record to the open dispute of
+    /// its transaction. Notes take effect immediately, also within a batch.
+    fn add_note<E: std::io::Write>(
+        &mut self,
+        record: &TransactionRecord,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        let text = match record.note.as_ref().filter(|note| !note.is_empty()) {
+            Some(text) => text.clone(),
+            None => {
+                let e = Error::MissingNote;
+                errstream.fail(record, e.code(), e);
+                return;
+            }
+        };
+        let note = match record.kind {
+            RecordKind::Evidence => DisputeNote::Evidence(text),
+            _ => DisputeNote::Text(text),
+        };
+        self.touched.push((record.client_id, record.tx_id));
+        if let Err(e) = self
+            .processor
+            .add_dispute_note(record.client_id, record.tx_id, note)
+        {
+            errstream.fail(record, e.code(), e);
+        }
+    }
+
+    /// Reactivates the dormant account of a `reactivate` record, if its role is
+    /// permitted to. Like notes, reactivations take effect immediately.
+    fn reactivate<E: std::io::Write>(
+        &mut self,
+        record: &TransactionRecord,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        self.touched.push((record.client_id, record.tx_id));
+        let result = self
+            .processor
+            .authorize(
+                record.role.as_ref(),
+                Operation::Reactivate,
+                record.client_id,
+                None,
+            )
+            .and_then(|()| self.processor.reactivate(record.client_id));
+        if let Err(e) = result {
+            errstream.fail(record, e.code(), e);
+        }
+        self.write_audit_log(errstream);
+    }
+
+    /// Writes the transactions still in dispute, with their notes, to the dispute
+    /// report.
+    fn write_dispute_report<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let report = match self.dispute_report.as_mut() {
+            Some(report) => report,
+            None => return,
+        };
+        let result = self
+            .processor
+            .open_disputes()
+            .into_iter()
+            .try_for_each(|dispute| report.serialize(DisputeInfo::from(dispute)))
+            .and_then(|_| report.flush().map_err(csv::Error::from));
+        if let Err(e) = result {
+            errstream.report(
+                "dispute_report_failed",
+                format_args!("dispute report failed: {}", e),
+            );
+        }
+    }
+
+    /// Writes the movement of funds of each client to the settlement report.
+    fn write_settlement_report<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let (tracker, report) = match self.settlement.as_mut() {
+            Some(settlement) => settlement,
+            None => return,
+        };
+        let result = tracker
+            .settlements()
+            .try_for_each(|settlement| report.serialize(settlement))
+            .and_then(|_| report.flush().map_err(csv::Error::from));
+        if let Err(e) = result {
+            errstream.report(
+                "settlement_report_failed",
+                format_args!("settlement report failed: {}", e),
+            );
+        }
+    }
+
+    /// Writes the amounts of the tagged transactions to the tag report.
+    fn write_tag_report<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let report = match self.tag_report.as_mut() {
+            Some(report) => report,
+            None => return,
+        };
+        let totals = match self.processor.tag_totals() {
+            Ok(totals) => totals,
             Err(e) => {
-                writeln!(errstream, "deserialize failed: {}", e).expect("write failed");
-                continue;
+                errstream.report(
+                    "tag_report_failed",
+                    format_args!("tag report failed: {}", e),
+                );
+                return;
             }
         };
-        if let Err(e) = process(&mut transaction_processor, &tx_info) {
-            writeln!(errstream, "failed to process `{:?}`: {}", tx_info, e).expect("write failed");
-        }
+        let result = match self.options.tag_report_format {
+            ReportFormat::Csv => {
+                let mut writer = csv::Writer::from_writer(&mut **report);
+                totals
+                    .iter()
+                    .try_for_each(|total| writer.serialize(total))
+                    .and_then(|_| writer.flush().map_err(csv::Error::from))
+                    .map_err(|e| e.to_string())
+            }
+            ReportFormat::Json => serde_json::to_writer_pretty(&mut **report, &totals)
+                .map_err(|e| e.to_string())
+                .and_then(|_| writeln!(report).map_err(|e| e.to_string())),
+        };
+        if let Err(e) = result {
+            errstream.report(
+                "tag_report_failed",
+                format_args!("tag report failed: {}", e),
+            );
+        }
+    }
+
+    /// Writes the balance history of each account, ordered by client.
+    fn write_balance_history<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let history = match self.balance_history.as_mut() {
+            Some(history) => history,
+            None => return,
+        };
+        let mut accounts: Vec<_> = self.processor.accounts().iter().collect();
+        accounts.sort_by_key(|(client_id, _)| *client_id);
+        let result = accounts
+            .into_iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .balance_history()
+                    .iter()
+                    .map(move |point| BalancePointInfo::new(client_id, point))
+            })
+            .try_for_each(|point| history.serialize(point))
+            .and_then(|_| history.flush().map_err(csv::Error::from));
+        if let Err(e) = result {
+            errstream.report(
+                "balance_history_failed",
+                format_args!("balance history failed: {}", e),
+            );
+        }
+    }
+
+    /// Reports and drops the batches that were not committed.
+    fn discard_open_batches<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let mut batches: Vec<_> = self.batches.drain().collect();
+        batches.sort_by_key(|(client_id, _)| *client_id);
+        for (client_id, (batch_id, records)) in batches {
+            errstream.report(
+                "batch_discarded",
+                format_args!(
+                    "batch {:?} of client {:?} was not committed, discarding {} records",
+                    batch_id,
+                    client_id,
+                    records.len()
+                ),
+            );
+        }
+    }
+
+    /// Expires authorizations and applies the scheduled transactions that are
+    /// due at `now`.
+    fn advance<E: std::io::Write>(&mut self, now: Timestamp, errstream: &mut ErrorStream<E>) {
+        self.clock.set(now);
+        for (client_id, tx_id) in self.processor.expire_authorizations(now) {
+            self.touched.push((client_id, tx_id));
+            errstream.report(
+                "authorization_expired",
+                format_args!(
+                    "authorization {:?} of client {:?} expired",
+                    tx_id, client_id
+                ),
+            );
+        }
+        for scheduled in self.processor.take_due(now) {
+            let transaction = scheduled.transaction;
+            self.touched
+                .push((transaction.client_id(), transaction.tx_id()));
+            self.apply_transaction(
+                Ok(transaction),
+                &TransactionRecord::from(transaction),
+                None,
+                transaction.client_id(),
+                Some(scheduled.effective_at),
+                errstream,
+            );
+        }
+    }
+
+    /// Applies `transaction`, parsed from `record`, tagging it with `tag`,
+    /// reporting a failure on `errstream`.
+    fn apply_transaction<E: std::io::Write>(
+        &mut self,
+        transaction: Result<Transaction, Error>,
+        record: &TransactionRecord,
+        tag: Option<&str>,
+        client_id: ClientId,
+        timestamp: Option<Timestamp>,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        let was_under_review = self.is_under_review(client_id);
+        let result = transaction.and_then(|transaction| {
+            let outcome = process(&mut self.processor, transaction, self.rules.as_mut())?;
+            if let Some(tag) = tag {
+                self.processor
+                    .set_tag(client_id, transaction.tx_id(), tag.to_string())?;
+            }
+            Ok((transaction, outcome))
+        });
+        self.report_review(client_id, was_under_review, errstream);
+        match result {
+            Ok((transaction, outcome)) => {
+                self.report_outcome(record, &transaction, outcome, timestamp, errstream);
+            }
+            Err(Error::DuplicateTx(_)) if self.options.skip_duplicates => {
+                self.duplicates_skipped += 1;
+            }
+            Err(e) => {
+                errstream.fail(record, e.code(), e);
+                // Rejections can be logged too, e.g. disputes of closed periods.
+                self.write_audit_log(errstream);
+            }
+        }
+    }
+
+    /// Reports a client that was flagged for review by the latest transactions.
+    fn report_review<E: std::io::Write>(
+        &self,
+        client_id: ClientId,
+        was_under_review: bool,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        if !was_under_review && self.is_under_review(client_id) {
+            let score = self.processor.accounts()[&client_id].risk().score();
+            errstream.report(
+                "flagged_for_review",
+                format_args!(
+                    "client {:?} flagged for review with risk score {}",
+                    client_id, score
+                ),
+            );
+        }
+    }
+
+    /// Reports the rules that matched an applied transaction, and checks it for
+    /// suspicious activity.
+    fn report_outcome<E: std::io::Write>(
+        &mut self,
+        record: &TransactionRecord,
+        transaction: &Transaction,
+        outcome: RuleOutcome,
+        timestamp: Option<Timestamp>,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        for rule in outcome.flagged_by.iter() {
+            errstream.report(
+                "rule_flagged",
+                format_args!("flagged `{}` by rule `{}`", record, rule),
+            );
+        }
+        if let Some(rule) = outcome.held_by {
+            errstream.report(
+                "rule_held",
+                format_args!("held `{}` by rule `{}`", record, rule),
+            );
+        }
+        if let Some((tracker, _)) = self.settlement.as_mut() {
+            if let Err(e) = tracker.record(&self.processor, transaction) {
+                errstream.report(
+                    "settlement_report_failed",
+                    format_args!("settlement report failed: {}", e),
+                );
+            }
+        }
+        if let Some((monitor, report)) = self.aml.as_mut() {
+            if let Some(activity) = monitor.check(transaction, timestamp) {
+                if let Err(e) = report.serialize(activity) {
+                    errstream.report(
+                        "aml_report_failed",
+                        format_args!("AML report failed: {}", e),
+                    );
+                }
+            }
+        }
+        self.write_audit_log(errstream);
+        self.write_journal(errstream);
+    }
+
+    /// Writes the journal entries of the latest transactions.
+    fn write_journal<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        let ledger = match self.ledger.as_mut() {
+            Some(ledger) => ledger,
+            None => return,
+        };
+        for entry in self.processor.take_journal() {
+            if let Err(e) = ledger.write(&entry) {
+                errstream.report("ledger_failed", format_args!("ledger failed: {}", e));
+            }
+        }
+    }
+
+    /// Writes the audit log entries of the latest operations.
+    fn write_audit_log<E: std::io::Write>(&mut self, errstream: &mut ErrorStream<E>) {
+        // The log is taken even without an audit log, so it does not grow.
+        for entry in self.processor.take_audit_log() {
+            if let Some(log) = self.audit_log.as_mut() {
+                if let Err(e) = log.serialize(entry) {
+                    errstream.report("audit_log_failed", format_args!("audit log failed: {}", e));
+                }
+            }
+        }
+    }
+
+    fn is_under_review(&self, client_id: ClientId) -> bool {
+        self.processor
+            .accounts()
+            .get(client_id)
+            .is_some_and(|account| account.risk().is_under_review())
+    }
+
+    /// Writes the records processed per second, `processed` in `elapsed`, and the
+    /// latencies of the processed transactions by operation.
+    #[cfg(feature = "telemetry")]
+    fn report_telemetry<E: std::io::Write>(
+        &self,
+        processed: u64,
+        elapsed: Duration,
+        errstream: &mut ErrorStream<E>,
+    ) {
+        let telemetry = self.processor.telemetry();
+        errstream.report(
+            "summary",
+            format_args!(
+                "records processed: {} in {:.3}s ({:.0} records/s)",
+                processed,
+                elapsed.as_secs_f64(),
+                processed as f64 / elapsed.as_secs_f64()
+            ),
+        );
+        if let Some(throughput) = telemetry.throughput() {
+            errstream.report(
+                "summary",
+                format_args!(
+                    "transactions processed: {} ({:.0} transactions/s)",
+                    telemetry.count(),
+                    throughput
+                ),
+            );
+        }
+        for (operation, latency) in telemetry.latencies() {
+            errstream.report(
+                "summary",
+                format_args!(
+                    "  {}: {} in {:?} on average, p50 <= {:?}, p99 <= {:?}, max {:?}",
+                    operation,
+                    latency.count(),
+                    latency.mean(),
+                    latency.quantile(0.5),
+                    latency.quantile(0.99),
+                    latency.max()
+                ),
+            );
+        }
+    }
+
+    /// Writes a summary of the run to `errstream`.
+    fn report<E: std::io::Write>(&self, errstream: &mut ErrorStream<E>) {
+        if self.options.skip_duplicates {
+            errstream.report(
+                "summary",
+                format_args!("duplicates skipped: {}", self.duplicates_skipped),
+            );
+        }
+        if self.dedup.is_some() || self.idempotency_keys.is_some() {
+            errstream.report(
+                "summary",
+                format_args!("redeliveries filtered: {}", self.redeliveries_filtered),
+            );
+        }
+        let scheduled = self.processor.scheduled().len();
+        if scheduled > 0 {
+            errstream.report(
+                "summary",
+                format_args!("scheduled transactions pending: {}", scheduled),
+            );
+        }
+        if let Some(rules) = self.rules.as_ref() {
+            errstream.report("summary", "rule hits:");
+            for (name, hits) in rules.hits() {
+                errstream.report("summary", format_args!("  {}: {}", name, hits));
+            }
+        }
+    }
+}
+
+/// Keeps the bytes read from `inner`, so rows can be quarantined as read.
+struct RecordingReader<R> {
+    inner: R,
+    /// The bytes read since they were last taken, if they are kept.
+    recorded: Option<Rc<RefCell<Vec<u8>>>>,
+}
+
+impl<R: std::io::Read> std::io::Read for RecordingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if let Some(recorded) = self.recorded.as_ref() {
+            recorded.borrow_mut().extend_from_slice(&buf[..n]);
+        }
+        Ok(n)
+    }
+}
+
+/// A record of an input file.
+struct ReadRecord {
+    /// The record as read, for errors reported as JSON.
+    source: Option<RecordSource>,
+    /// The header row and the record's row as read, if they are kept and the
+    /// record could not be parsed.
+    bytes: Option<RowBytes>,
+    result: Result<TransactionRecord, csv::Error>,
+}
+
+/// A row of an input file as read, with the header row of the file.
+struct RowBytes {
+    header: Rc<[u8]>,
+    row: Vec<u8>,
+}
+
+/// The column names of the header row `headers`, lowercased to match the record
+/// columns unless `strict`.
+/// Returns an error if `strict` and a column is not one of the record columns.
+///
+/// This function does not panic.
+fn normalize_headers(
+    headers: &csv::StringRecord,
+    strict: bool,
+) -> Result<csv::StringRecord, csv::Error> {
+    if !strict {
+        return Ok(headers.iter().map(str::to_lowercase).collect());
+    }
+    let is_column = |name: &str| {
+        transactions::RECORD_COLUMNS
+            .iter()
+            .any(|column| column.name == name)
+    };
+    match headers.iter().find(|name| !is_column(name)) {
+        Some(name) => Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("invalid header row: unknown column `{}`", name),
+        )
+        .into()),
+        None => Ok(headers.clone()),
+    }
+}
+
+/// Reads the records of the csv `instream` as `options` configure, keeping each
+/// record's csv for errors reported as JSON, and the bytes of records that cannot
+/// be parsed if `keep_bytes`.
+/// Columns are matched by name as `normalize_headers` does with
+/// `options.strict_headers`; if the header row is rejected, its error is the only
+/// record of the input. Without a header row (`options.no_header`), the columns
+/// are the record columns in order.
+fn read_records<R: std::io::Read>(
+    instream: R,
+    options: &Options,
+    keep_bytes: bool,
+) -> impl Iterator<Item = ReadRecord> {
+    let keep_sources = options.error_format == ErrorFormat::Json;
+    // A byte order mark is stripped, and UTF-16 is detected by it.
+    let instream = encoding_rs_io::DecodeReaderBytesBuilder::new()
+        .encoding(options.encoding.encoding())
+        .build(instream);
+    let mut instream = std::io::BufReader::new(instream);
+    // The first line is read ahead and put back in front, so it is still
+    // recorded. A read error is left for the csv reader to report.
+    let mut first_line = Vec::new();
+    std::io::BufRead::read_until(&mut instream, b'\n', &mut first_line).ok();
+    let delimiter = options.delimiter.detect(&first_line);
+    let instream = std::io::Read::chain(std::io::Cursor::new(first_line), instream);
+    // The bytes are recorded even if they are not kept, to read rows that could
+    // not be parsed line by line.
+    let recorded = Rc::new(RefCell::new(Vec::new()));
+    let instream = RecordingReader {
+        inner: instream,
+        recorded: Some(recorded.clone()),
+    };
+    // Fields are trimmed after reading, so errors can quote records as read.
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .has_headers(!options.no_header)
+        .from_reader(instream);
+    let (headers, mut header_error) = if options.no_header {
+        let columns = transactions::RECORD_COLUMNS.iter();
+        (columns.map(|column| column.name).collect(), None)
+    } else {
+        let mut headers = reader.headers().cloned().unwrap_or_default();
+        headers.trim();
+        let strict = options.strict_headers || options.strict_schema;
+        match normalize_headers(&headers, strict) {
+            Ok(headers) => (headers, None),
+            Err(e) => (headers, Some(e)),
+        }
+    };
+    let rejected = header_error.is_some();
+    let amount_format = options.amount_format;
+    let amount_index = Some(())
+        .filter(|_| amount_format != AmountFormat::Plain)
+        .and_then(|_| headers.iter().position(|name| name == "amount"));
+    // The input offset of the first recorded byte, as the reader reads ahead.
+    let mut offset = 0;
+    // The bytes of a row up to `end`, the start of the next row, and the number of
+    // lines before the row. The reader stops between the `\r` and `\n` of a
+    // `\r\n`, and skips empty lines, so the line breaks a row starts with belong
+    // before it.
+    let mut take_until = move |end: u64| -> (Vec<u8>, u64) {
+        let mut recorded = recorded.borrow_mut();
+        let len = ((end - offset) as usize).min(recorded.len());
+        offset = end;
+        let mut row: Vec<u8> = recorded.drain(..len).collect();
+        let start = row
+            .iter()
+            .take_while(|b| matches!(b, b'\r' | b'\n'))
+            .count();
+        let skipped = row[..start].iter().filter(|&&b| b == b'\n').count();
+        row.drain(..start);
+        (row, skipped as u64)
+    };
+    let header: Rc<[u8]> = take_until(reader.position().byte()).0.into();
+    let parse = move |record: &csv::StringRecord| {
+        let source = Some(record).filter(|_| keep_sources).map(RecordSource::of);
+        let mut trimmed = record.clone();
+        trimmed.trim();
+        let result = match amount_index {
+            Some(index) => amount_format.normalize_field(&trimmed, index),
+            None => Ok(trimmed),
+        };
+        (
+            source,
+            result.and_then(|record| record.deserialize(Some(&headers))),
+        )
+    };
+    let read = move |(source, result): (_, Result<TransactionRecord, csv::Error>), row: Vec<u8>| {
+        ReadRecord {
+            source,
+            bytes: Some(row)
+                .filter(|_| keep_bytes && result.is_err())
+                .map(|row| RowBytes {
+                    header: header.clone(),
+                    row,
+                }),
+            result,
+        }
+    };
+    let mut record = csv::StringRecord::new();
+    let mut split = VecDeque::new();
+    std::iter::from_fn(move || {
+        if rejected {
+            return header_error.take().map(|e| ReadRecord {
+                source: None,
+                bytes: None,
+                result: Err(e),
+            });
+        }
+        if let Some(read) = split.pop_front() {
+            return Some(read);
+        }
+        let line = reader.position().line();
+        let result = reader.read_record(&mut record);
+        let (row, skipped) = take_until(reader.position().byte());
+        let parsed = match result {
+            Ok(false) => return None,
+            Ok(true) => parse(&record),
+            Err(e) => (None, Err(e)),
+        };
+        // An unbalanced quote makes the reader take the rows after it as part of
+        // its row, up to the next quote. The lines of a row spanning several
+        // lines that could not be parsed are read on their own instead, so the
+        // rows after the quote are not lost.
+        if parsed.1.is_err() && row.trim_ascii_end().contains(&b'\n') {
+            for (line_row, result) in split_lines(&row, line + skipped, delimiter) {
+                let parsed = match result {
+                    Ok(record) => parse(&record),
+                    Err(e) => (None, Err(e)),
+                };
+                split.push_back(read(parsed, line_row));
+            }
+            return split.pop_front();
+        }
+        Some(read(parsed, row))
+    })
+}
+
+/// The records of the lines of `row`, which starts on line `line` of its input,
+/// each read as a row of its own, with its bytes as read. Empty lines are
+/// skipped, and lines with an odd number of quotes are rejected, as their quote
+/// is the one that was not balanced.
+fn split_lines(
+    row: &[u8],
+    line: u64,
+    delimiter: u8,
+) -> Vec<(Vec<u8>, Result<csv::StringRecord, csv::Error>)> {
+    row.split_inclusive(|&b| b == b'\n')
+        .zip(line..)
+        .filter(|(bytes, _)| !bytes.trim_ascii().is_empty())
+        .map(|(bytes, line)| {
+            let invalid = |message: &str| -> csv::Error {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("line {}: {}", line, message),
+                )
+                .into()
+            };
+            if bytes.iter().filter(|&&b| b == b'"').count() % 2 != 0 {
+                return (bytes.to_vec(), Err(invalid("unbalanced quote")));
+            }
+            let mut reader = csv::ReaderBuilder::new()
+                .delimiter(delimiter)
+                .has_headers(false)
+                .from_reader(bytes);
+            let mut record = csv::ByteRecord::new();
+            let result = reader.read_byte_record(&mut record).and_then(|_| {
+                let mut position = csv::Position::new();
+                position.set_line(line);
+                record.set_position(Some(position));
+                csv::StringRecord::from_byte_record(record).map_err(|_| invalid("invalid UTF-8"))
+            });
+            (bytes.to_vec(), result)
+        })
+        .collect()
+}
+
+/// Writes the rows that could not be parsed verbatim, each under the header row
+/// of its input file unless the previous row had the same one or the file has
+/// none, so they can be fixed and processed again.
+struct Quarantine<'a> {
+    writer: &'a mut dyn std::io::Write,
+    /// The header row written last.
+    header: Option<Rc<[u8]>>,
+}
+
+impl<'a> Quarantine<'a> {
+    fn new(writer: &'a mut dyn std::io::Write) -> Quarantine<'a> {
+        Quarantine {
+            writer,
+            header: None,
+        }
+    }
+
+    fn write(&mut self, bytes: &RowBytes) -> std::io::Result<()> {
+        if self.header.as_deref() != Some(&*bytes.header) {
+            if !bytes.header.is_empty() {
+                self.write_line(&bytes.header)?;
+            }
+            self.header = Some(bytes.header.clone());
+        }
+        self.write_line(&bytes.row)
+    }
+
+    /// Writes `line`, ending it if it is the last line of its input.
+    fn write_line(&mut self, line: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(line)?;
+        if !line.ends_with(b"\n") {
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports the progress of the run and its last error to `control`.
+fn report_health<E>(
+    control: &RunControl,
+    records_read: u64,
+    queue_depth: usize,
+    errstream: &mut ErrorStream<E>,
+) {
+    control.report_progress(records_read, queue_depth);
+    if let Some(error) = errstream.last_error.take() {
+        control.report_error(error);
+    }
+}
+
+/// Processes the transactions in each of `instreams`, in order, starting from the
+/// `start` snapshot, and writes the resulting account infos to `outstream`. Records
+/// already read according to `start` are skipped. Returns a snapshot of the final
+/// state. Cancelling `control` stops processing before the next record; the
+/// accounts processed so far are still written, followed by a resume marker on
+/// `errstream`. Pausing `control` blocks before the next record until resumed,
+/// after writing a checkpoint if it was paused with a snapshot.
+/// Once more records are rejected than `options.error_limit` allows, processing
+/// stops the same way, and `control` is aborted.
+fn run<'a, I, R, W, E>(
+    instreams: I,
+    outstream: W,
+    errstream: E,
+    start: Snapshot,
+    options: &'a Options,
+    control: &RunControl,
+    mut stores: Stores<'a>,
+) -> Snapshot
+where
+    I: IntoIterator<Item = R>,
+    R: std::io::Read,
+    W: std::io::Write,
+    E: std::io::Write,
+{
+    // 1) Parse transactions from `instreams` and process them.
+    #[cfg(feature = "telemetry")]
+    let started = Instant::now();
+    let mut records_read = start.records_read;
+    let start_records_read = start.records_read;
+    let mut quarantine = stores.quarantine.take().map(Quarantine::new);
+    let keep_bytes = quarantine.is_some();
+    let mut runner = Runner::new(options, start, stores);
+    let mut errstream = ErrorStream::new(errstream, options.error_format, options.error_samples);
+    let records = instreams
+        .into_iter()
+        .flat_map(|instream| read_records(instream, options, keep_bytes));
+    let mut throttle = options.max_rate.map(Throttle::new);
+    let mut reorder_buffer = options.reorder_window.map(ReorderBuffer::new);
+    let mut last_tx_id = None;
+    let mut config_watcher = options.watch_config.as_ref().map(ConfigWatcher::new);
+    let mut checkpointer = options.checkpoint_dir.as_ref().map(|dir| {
+        Checkpointer::new(
+            dir.clone(),
+            options.checkpoint_every,
+            options
+                .checkpoint_interval
+                .map(|secs| Duration::from_secs(secs.get())),
+            options.checkpoint_keep.unwrap_or(DEFAULT_CHECKPOINT_KEEP),
+            records_read,
+        )
+    });
+    for (record_index, read) in records.enumerate() {
+        let ReadRecord {
+            source,
+            bytes,
+            result,
+        } = read;
+        if (record_index as u64) < start_records_read {
+            continue;
+        }
+        if options
+            .stop_after
+            .is_some_and(|stop_after| record_index as u64 >= stop_after)
+        {
+            break;
+        }
+        let processed = records_read - start_records_read;
+        if let Some(reason) = options
+            .error_limit
+            .exceeded(errstream.rejected, processed, false)
+        {
+            errstream.report(
+                "too_many_errors",
+                format_args!("{}: stopping before record {}", reason, record_index),
+            );
+            control.abort();
+            break;
+        }
+        errstream.record = source;
+        if let Err(e) = runner.persist(records_read) {
+            errstream.report(
+                "store_failed",
+                format_args!("{}: stopping before record {}", e, record_index),
+            );
+            break;
+        }
+        if let Some(throttle) = throttle.as_mut() {
+            throttle.wait();
+        }
+        let queue_depth = reorder_buffer.as_ref().map_or(0, ReorderBuffer::len);
+        report_health(control, records_read, queue_depth, &mut errstream);
+        if let Some(checkpointer) = checkpointer.as_mut() {
+            if runner.batches.is_empty() && control.take_snapshot_request() {
+                match checkpointer.write(records_read, &runner.processor) {
+                    Ok(_) => control.report_checkpoint(),
+                    Err(e) => errstream.report(
+                        "checkpoint_failed",
+                        format_args!("checkpoint failed: {}", e),
+                    ),
+                }
+            }
+        }
+        control.wait_while_paused();
+        if control.is_cancelled() {
+            errstream.report(
+                "cancelled",
+                format_args!("cancelled: resume from record {}", record_index),
+            );
+            break;
+        }
+        if let Some(checkpointer) = checkpointer.as_mut() {
+            // Like the state store, a checkpoint waits until no batch is open.
+            if runner.batches.is_empty() && checkpointer.is_due(records_read) {
+                match checkpointer.write(records_read, &runner.processor) {
+                    Ok(_) => control.report_checkpoint(),
+                    Err(e) => errstream.report(
+                        "checkpoint_failed",
+                        format_args!("checkpoint failed: {}", e),
+                    ),
+                }
+            }
+        }
+        if let Some(watcher) = config_watcher.as_mut() {
+            for (policy, path) in watcher.changed() {
+                if let Err(e) = runner.reload(policy, &path) {
+                    errstream.report(
+                        "reload_failed",
+                        format_args!("reload of {} failed: {}", path.display(), e),
+                    );
+                }
+            }
+        }
+        records_read = record_index as u64 + 1;
+        let tx_info = match result {
+            Ok(tx_info) => tx_info,
+            Err(e) => {
+                errstream.rejected += 1;
+                errstream.report("invalid_record", format_args!("deserialize failed: {}", e));
+                if let (Some(quarantine), Some(bytes)) = (quarantine.as_mut(), bytes) {
+                    if let Err(e) = quarantine.write(&bytes) {
+                        errstream.report(
+                            "quarantine_failed",
+                            format_args!("quarantine failed: {}", e),
+                        );
+                    }
+                }
+                if options.strict_schema {
+                    errstream.report(
+                        "schema_violation",
+                        format_args!("invalid record: stopping after record {}", record_index),
+                    );
+                    control.abort();
+                    break;
+                }
+                continue;
+            }
+        };
+        if options.strict_schema {
+            if let Err(e) = transactions::check_record(&tx_info) {
+                errstream.rejected += 1;
+                errstream.report(
+                    "schema_violation",
+                    format_args!("{}: stopping after record {}", e, record_index),
+                );
+                control.abort();
+                break;
+            }
+        }
+        match runner.is_redelivery(&tx_info) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(e) => {
+                errstream.report(
+                    "store_failed",
+                    format_args!("{}: stopping before record {}", e, record_index),
+                );
+                records_read = record_index as u64;
+                break;
+            }
+        }
+        if let Some(mode) = options.monotonic_tx_ids {
+            if tx_info.kind.is_fund_transaction() {
+                match last_tx_id {
+                    Some(last_tx_id) if tx_info.tx_id < last_tx_id => {
+                        let severity = match mode {
+                            CheckMode::Warn => "warning",
+                            CheckMode::Reject => "error",
+                        };
+                        errstream.report(
+                            "tx_id_decreasing",
+                            format_args!(
+                                "{}: record {}: transaction id {:?} is lower than previous transaction id {:?}",
+                                severity, record_index, tx_info.tx_id, last_tx_id
+                            ),
+                        );
+                        if mode == CheckMode::Reject {
+                            errstream.rejected += 1;
+                            continue;
+                        }
+                    }
+                    _ => last_tx_id = Some(tx_info.tx_id),
+                }
+            }
+        }
+        match reorder_buffer.as_mut() {
+            Some(buffer) => {
+                let source = errstream.record.take();
+                for (source, tx_info) in buffer.push(tx_info.timestamp, (source, tx_info)) {
+                    errstream.record = source;
+                    runner.apply(&tx_info, &mut errstream);
+                }
+            }
+            None => runner.apply(&tx_info, &mut errstream),
+        }
+    }
+    // Records still held for reordering have already been read, so they are
+    // applied even if the run was cancelled.
+    if let Some(buffer) = reorder_buffer.as_mut() {
+        for (source, tx_info) in buffer.drain() {
+            errstream.record = source;
+            runner.apply(&tx_info, &mut errstream);
+        }
+    }
+    errstream.record = None;
+    if let Some(Err(e)) = quarantine
+        .as_mut()
+        .map(|quarantine| quarantine.writer.flush())
+    {
+        errstream.report(
+            "quarantine_failed",
+            format_args!("quarantine failed: {}", e),
+        );
+    }
+    if !control.is_cancelled() {
+        let processed = records_read - start_records_read;
+        if let Some(reason) = options
+            .error_limit
+            .exceeded(errstream.rejected, processed, true)
+        {
+            errstream.report("too_many_errors", reason);
+            control.abort();
+        }
+    }
+    runner.discard_open_batches(&mut errstream);
+    if let Err(e) = runner.finish_persisting(records_read) {
+        errstream.report("store_failed", e);
+    }
+    report_health(control, records_read, 0, &mut errstream);
+    runner.report(&mut errstream);
+    #[cfg(feature = "telemetry")]
+    if options.telemetry {
+        let processed = records_read - start_records_read;
+        runner.report_telemetry(processed, started.elapsed(), &mut errstream);
+    }
+    runner.write_dispute_report(&mut errstream);
+    runner.write_settlement_report(&mut errstream);
+    runner.write_tag_report(&mut errstream);
+    runner.write_balance_history(&mut errstream);
+    // Scheduled transactions applied last are only in the journal so far.
+    runner.write_journal(&mut errstream);
+    if let Some(Err(e)) = runner.ledger.as_mut().map(LedgerWriter::flush) {
+        errstream.report("ledger_failed", format_args!("ledger failed: {}", e));
+    }
+    let transaction_processor = runner.processor;
+
+    // 2) Get all client account infos.
+    let mut account_infos = Vec::new();
+    for (client_id, account) in transaction_processor.accounts().iter() {
+        account_infos.push(AccountInfo {
+            client_id,
+            available_funds: account.available_funds(),
+            held_funds: account.held_funds(),
+            total_funds: account.total_funds(),
+            is_frozen: account.is_frozen(),
+            pending_funds: Some(account.pending_funds()).filter(|_| options.pending_column),
+            pending_out_funds: Some(account.pending_out_funds())
+                .filter(|_| options.settle_withdrawals),
+            escrow_funds: Some(account.escrow_funds()).filter(|_| options.escrow_column),
+            receivable: Some(account.outstanding_receivables())
+                .filter(|_| options.receivable_column),
+            risk_score: Some(account.risk().score()).filter(|_| options.risk_column),
+            dormant: Some(transaction_processor.is_dormant(client_id))
+                .filter(|_| options.dormant_column),
+            balances: options.fx_rates.as_ref().map(|_| {
+                let balances: Vec<_> = account
+                    .currency_balances()
+                    .iter()
+                    .map(|(currency, balance)| format!("{} {}", currency, balance))
+                    .collect();
+                balances.join("; ")
+            }),
+        });
+    }
+    // Sort the account infos by client id so the output is deterministic.
+    account_infos.sort_by_key(|account| account.client_id);
+
+    // 3) Print the account infos to outstream in csv format.
+    let mut writer = csv::Writer::from_writer(outstream);
+    for account_info in account_infos.iter() {
+        if let Err(e) = writer.serialize(account_info) {
+            errstream.report("serialize_failed", format_args!("serialize failed: {}", e));
+        }
+    }
+    writer.flush().expect("write failed");
+    errstream.flush().expect("write failed");
+
+    Snapshot {
+        records_read,
+        processor: transaction_processor,
+    }
+}
+
+/// Formats a delta with an explicit sign, e.g. `+1.5` or `-2`.
+fn signed(delta: Price4) -> String {
+    if delta.is_sign_negative() {
+        delta.to_string()
+    } else {
+        format!("+{}", delta)
+    }
+}
+
+/// Writes a summary of `delta` to `outstream`: the balance changes of each changed
+/// account, followed by its new and changed transactions.
+fn write_delta<W: std::io::Write>(delta: &SnapshotDelta, mut outstream: W) {
+    writeln!(
+        outstream,
+        "records read: {} -> {}",
+        delta.from_records_read, delta.to_records_read
+    )
+    .expect("write failed");
+    for account in delta.accounts.iter() {
+        let frozen = match account.frozen {
+            Some(true) => ", locked",
+            Some(false) => ", unlocked",
+            None => "",
+        };
+        let mut pending = String::new();
+        if !account.pending.is_zero() {
+            pending += &format!(", pending {}", signed(account.pending));
+        }
+        if !account.pending_out.is_zero() {
+            pending += &format!(", pending out {}", signed(account.pending_out));
+        }
+        if !account.escrow.is_zero() {
+            pending += &format!(", escrow {}", signed(account.escrow));
+        }
+        writeln!(
+            outstream,
+            "client {:?}: available {}, held {}{}{}",
+            account.client_id,
+            signed(account.available),
+            signed(account.held),
+            pending,
+            frozen
+        )
+        .expect("write failed");
+        for tx in account.transactions.iter() {
+            let previous_state = match tx.previous_state() {
+                Some(state) => format!("{:?}", state),
+                None => "new".to_string(),
+            };
+            writeln!(
+                outstream,
+                "  tx {:?}: {} -> {:?}",
+                tx.tx_id(),
+                previous_state,
+                tx.state()
+            )
+            .expect("write failed");
+        }
+    }
+    outstream.flush().expect("write failed");
+}
+
+/// Summarizes the changes between two snapshots on stdout, and writes them to
+/// `patch` if given.
+fn diff(from: &Path, to: &Path, patch: Option<&Path>) -> Result<(), SnapshotError> {
+    let delta = SnapshotDelta::between(&Snapshot::load(from)?, &Snapshot::load(to)?)?;
+    write_delta(&delta, std::io::stdout());
+    if let Some(patch) = patch {
+        delta.save(patch)?;
+    }
+    Ok(())
+}
+
+/// Writes a description of the input records in `format` to `writer`.
+fn write_schema<W: std::io::Write>(format: SchemaFormat, writer: W) -> Result<(), String> {
+    match format {
+        SchemaFormat::Json => serde_json::to_writer_pretty(writer, &transactions::record_schema())
+            .map_err(|e| e.to_string()),
+        SchemaFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            writer
+                .write_record(["column", "type", "required", "description"])
+                .map_err(|e| e.to_string())?;
+            for column in transactions::RECORD_COLUMNS.iter() {
+                writer
+                    .write_record([
+                        column.name,
+                        &column.kind.to_string(),
+                        &column.required.to_string(),
+                        column.description,
+                    ])
+                    .map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// A finding of the `lint` subcommand, with the line of its record.
+#[derive(Debug, Serialize)]
+struct LintRow {
+    /// The line the record starts on, unless it could not be read.
+    line: Option<u64>,
+    record: u64,
+    client: Option<ClientId>,
+    tx: Option<TransactionId>,
+    code: &'static str,
+    message: String,
+}
+
+/// Checks the records of the csv `instream` with a `Linter`, and writes its
+/// findings in `format` to `writer`.
+/// Returns the number of findings, or an error if they could not be written.
+fn lint<R: std::io::Read, W: std::io::Write>(
+    instream: R,
+    format: LintFormat,
+    mut writer: W,
+) -> Result<usize, String> {
+    // The records' sources are kept for their lines.
+    let options = Options {
+        error_format: ErrorFormat::Json,
+        ..Options::default()
+    };
+    let mut linter = Linter::new();
+    let mut lines = Vec::new();
+    for (index, read) in read_records(instream, &options, false).enumerate() {
+        lines.push(read.source.map(|source| source.line));
+        match read.result {
+            Ok(record) => linter.check(index as u64, &record),
+            Err(e) => linter.invalid(index as u64, format!("deserialize failed: {}", e)),
+        }
+    }
+    let rows: Vec<_> = linter
+        .finish()
+        .into_iter()
+        .map(|finding| LintRow {
+            line: lines[finding.record as usize],
+            record: finding.record,
+            client: finding.client_id,
+            tx: finding.tx_id,
+            code: finding.code,
+            message: finding.message,
+        })
+        .collect();
+    match format {
+        LintFormat::Json => {
+            for row in rows.iter() {
+                serde_json::to_writer(&mut writer, row).map_err(|e| e.to_string())?;
+                writeln!(writer).map_err(|e| e.to_string())?;
+            }
+        }
+        LintFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(&mut writer);
+            for row in rows.iter() {
+                writer.serialize(row).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())?;
+        }
+    }
+    Ok(rows.len())
+}
+
+/// A row of a client's statement.
+#[derive(Debug, Serialize)]
+struct StatementRow {
+    seq: u64,
+    date: String,
+    timestamp: u64,
+    tx: Option<TransactionId>,
+    operation: Option<&'static str>,
+    /// The amount of the transaction that the operation is on.
+    amount: Option<Price4>,
+    available: Price4,
+    held: Price4,
+    total: Price4,
+}
+
+impl StatementRow {
+    fn new(
+        processor: &TransactionProcessor,
+        client_id: ClientId,
+        point: &BalancePoint,
+    ) -> StatementRow {
+        let (year, month, day) = point.timestamp.date();
+        StatementRow {
+            seq: point.seq,
+            date: format!("{:04}-{:02}-{:02}", year, month, day),
+            timestamp: point.timestamp.as_secs(),
+            tx: point.tx_id,
+            operation: point.operation.map(|operation| operation.name()),
+            amount: point
+                .tx_id
+                .and_then(|tx_id| processor.transaction(client_id, tx_id))
+                .map(|tx| tx.amount()),
+            available: point.available,
+            held: point.held,
+            total: point.total,
+        }
+    }
+}
+
+/// Writes the statement of client `client_id` in the snapshot `processor`, for
+/// the transactions from `from` to `to`, in `format` to `writer`.
+fn write_statement<W: std::io::Write>(
+    processor: &TransactionProcessor,
+    client_id: ClientId,
+    from: Option<StatementBound>,
+    to: Option<StatementBound>,
+    format: StatementFormat,
+    writer: W,
+) -> Result<(), String> {
+    let statement = processor
+        .balance_statement(
+            client_id,
+            from.and_then(StatementBound::after),
+            to.map(StatementBound::until),
+        )
+        .ok_or_else(|| format!("client {} does not exist", client_id))?;
+    let rows = statement
+        .points
+        .iter()
+        .map(|point| StatementRow::new(processor, client_id, point));
+    match format {
+        StatementFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row).map_err(|e| e.to_string())?;
+            }
+            writer.flush().map_err(|e| e.to_string())
+        }
+        StatementFormat::Text => {
+            write_statement_text(client_id, &statement, rows, writer).map_err(|e| e.to_string())
+        }
+    }
+}
+
+/// Writes a statement as a table with its opening and closing balances.
+fn write_statement_text<W: std::io::Write>(
+    client_id: ClientId,
+    statement: &BalanceStatement,
+    rows: impl Iterator<Item = StatementRow>,
+    mut writer: W,
+) -> std::io::Result<()> {
+    let balances = |point: Option<&BalancePoint>| match point {
+        Some(point) => format!(
+            "available {}, held {}, total {}",
+            point.available, point.held, point.total
+        ),
+        None => "none".to_string(),
+    };
+    writeln!(writer, "Statement of client {}", client_id)?;
+    writeln!(
+        writer,
+        "Opening balances: {}",
+        balances(statement.opening.as_ref())
+    )?;
+    writeln!(
+        writer,
+        "{:>5}  {:<10}  {:>8}  {:<18}  {:>12}  {:>12}  {:>12}  {:>12}",
+        "#", "date", "tx", "operation", "amount", "available", "held", "total"
+    )?;
+    for row in rows {
+        let or_blank = |value: Option<String>| value.unwrap_or_default();
+        writeln!(
+            writer,
+            "{:>5}  {:<10}  {:>8}  {:<18}  {:>12}  {:>12}  {:>12}  {:>12}",
+            row.seq,
+            row.date,
+            or_blank(row.tx.map(|tx| tx.to_string())),
+            row.operation.unwrap_or_default(),
+            or_blank(row.amount.map(|amount| amount.to_string())),
+            row.available,
+            row.held,
+            row.total
+        )?;
+    }
+    writeln!(
+        writer,
+        "Closing balances: {}",
+        balances(statement.points.last().or(statement.opening.as_ref()))
+    )
+}
+
+/// Applies `patch` to `snapshot` and writes the result to `output`.
+fn patch(snapshot: &Path, patch: &Path, output: &Path) -> Result<(), SnapshotError> {
+    let mut snapshot = Snapshot::load(snapshot)?;
+    SnapshotDelta::load(patch)?.apply(&mut snapshot)?;
+    snapshot.save(output)
+}
+
+fn main() {
+    let command = match Command::parse(std::env::args().skip(1)) {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            std::process::exit(2);
+        }
+    };
+    let result = match command {
+        Command::Run(args) => return run_main(*args),
+        Command::Diff {
+            from,
+            to,
+            patch: patch_path,
+        } => diff(&from, &to, patch_path.as_deref()),
+        Command::Patch {
+            snapshot,
+            patch: patch_path,
+            output,
+        } => patch(&snapshot, &patch_path, &output),
+        Command::Schema { format } => {
+            if let Err(e) = write_schema(format, std::io::stdout()) {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Statement {
+            snapshot,
+            client_id,
+            from,
+            to,
+            format,
+        } => {
+            let result = Snapshot::load(&snapshot)
+                .map_err(|e| e.to_string())
+                .and_then(|snapshot| {
+                    write_statement(
+                        &snapshot.processor,
+                        client_id,
+                        from,
+                        to,
+                        format,
+                        std::io::stdout(),
+                    )
+                });
+            if let Err(e) = result {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Command::Lint { path, format } => {
+            let result = std::fs::File::open(&path)
+                .map_err(|e| format!("could not open {}: {}", path.display(), e))
+                .and_then(|file| lint(file, format, std::io::stdout()));
+            match result {
+                Ok(0) => return,
+                Ok(_) => std::process::exit(EXIT_LINT_FINDINGS),
+                Err(e) => {
+                    eprintln!("error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Processes the csv input given by `args`.
+fn run_main(mut args: Args) {
+    if let Some(path) = &args.rules {
+        let rules = RuleSet::load(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        });
+        args.options.rules = Some(rules);
+    }
+    if let Some(path) = &args.min_balances {
+        if let Err(e) = load_min_balances(&mut args.options.min_balance, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &args.balance_caps {
+        if let Err(e) = load_balance_caps(&mut args.options.balance_cap, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &args.fee_schedule {
+        match FeeSchedule::load(path) {
+            Ok(schedule) => args.options.fee_schedule = Some(schedule),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.fx_rates {
+        match load_fx_rates(path) {
+            Ok(rates) => args.options.fx_rates = Some(rates),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.permissions {
+        match read_toml(path) {
+            Ok(permissions) => args.options.permissions = Some(permissions),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+    if let Some(path) = &args.withdrawal_limits {
+        if let Err(e) = load_withdrawal_limits(&mut args.options.withdrawal_limit, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = configure(TransactionProcessor::new(), &args.options).build() {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+    let risk_config = args.risk_config.as_ref().map(|path| {
+        RiskConfig::load(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let filepaths: Vec<PathBuf> = match &args.manifest {
+        Some(manifest_path) => {
+            let manifest = Manifest::load(Path::new(manifest_path)).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            });
+            // Verify all files before processing any of them.
+            for entry in manifest.entries.iter() {
+                if let Err(e) = entry.verify() {
+                    match args.manifest_mismatch {
+                        CheckMode::Warn => eprintln!("warning: {}", e),
+                        CheckMode::Reject => {
+                            eprintln!("error: {}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+            manifest
+                .entries
+                .into_iter()
+                .map(|entry| entry.path)
+                .collect()
+        }
+        None => args.filepath.iter().map(PathBuf::from).collect(),
+    };
+    let statement_clients = StatementClients {
+        client: args.statement_client,
+        accounts: match &args.statement_accounts {
+            Some(path) => load_statement_accounts(path).unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }),
+            None => HashMap::new(),
+        },
+    };
+    // Read all statements before processing any of them.
+    let files: Vec<Box<dyn std::io::Read>> = filepaths
+        .iter()
+        .map(|filepath| -> Box<dyn std::io::Read> {
+            if is_statement(filepath) {
+                let records =
+                    read_statement_records(filepath, &statement_clients).unwrap_or_else(|e| {
+                        eprintln!("error: {}", e);
+                        std::process::exit(1);
+                    });
+                Box::new(std::io::Cursor::new(records))
+            } else {
+                let file = std::fs::File::open(filepath).unwrap_or_else(|e| {
+                    eprintln!("error: could not open {}: {}", filepath.display(), e);
+                    std::process::exit(1);
+                });
+                Box::new(file)
+            }
+        })
+        .collect();
+    let mut store = args.state_store.as_ref().map(|spec| {
+        spec.open().unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        })
+    });
+    let mut dedup = args.dedup_store.as_ref().map(|spec| {
+        spec.open(args.options.dedup_retention())
+            .unwrap_or_else(|e| {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            })
+    });
+    let mut aml_report = args.aml_report.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut audit_log = args.audit_log.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut quarantine = args.quarantine.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut dispute_report = args.dispute_report.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut settlement_report = args.settlement_report.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut tag_report = args.tag_report.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut ledger = args.ledger.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut balance_history = args.balance_history.as_ref().map(|path| {
+        let file = std::fs::File::create(path).unwrap_or_else(|e| {
+            eprintln!("error: could not create {}: {}", path.display(), e);
+            std::process::exit(1);
+        });
+        std::io::BufWriter::new(file)
+    });
+    let mut start = match (&args.resume, &args.continue_from, &store) {
+        (Some(path), _, _) => Snapshot::load(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        (None, Some(path), _) => continue_from(path).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        (None, None, Some(store)) => Snapshot::load_from(store.as_ref()).unwrap_or_else(|e| {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }),
+        (None, None, None) => Snapshot::default(),
+    };
+    if args.close_period {
+        start.processor.close_period();
+    }
+    if let Some(config) = risk_config {
+        start.processor.set_risk_config(config);
+    }
+    if let Some(path) = &args.initial_balances {
+        if let Err(e) = open_accounts(&mut start.processor, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Some(path) = &args.recurring {
+        if let Err(e) = schedule_recurring(&mut start.processor, path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    #[cfg(feature = "rhai")]
+    let (start, flags) = add_rules_script(start, args.rules_script.as_deref());
+    #[cfg(not(feature = "rhai"))]
+    if args.rules_script.is_some() {
+        eprintln!("error: `--rules-script` is not supported by this build, rebuild with the `rhai` feature");
+        std::process::exit(1);
+    }
+    #[cfg(not(feature = "telemetry"))]
+    if args.options.telemetry {
+        eprintln!("error: `--telemetry` is not supported by this build, rebuild with the `telemetry` feature");
+        std::process::exit(1);
+    }
+    let control = std::sync::Arc::new(RunControl::new());
+    let handler_control = control.clone();
+    // The first signal stops the run before the next record, a second one exits
+    // right away.
+    let handler = ctrlc::set_handler(move || {
+        if handler_control.is_cancelled() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+        handler_control.cancel();
+    });
+    if let Err(e) = handler {
+        eprintln!("warning: could not handle interrupts: {}", e);
+    }
+    let snapshot = run(
+        files,
+        std::io::stdout(),
+        std::io::stderr(),
+        start,
+        &args.options,
+        &control,
+        Stores {
+            state: store
+                .as_mut()
+                .map(|store| &mut **store as &mut dyn StateStore),
+            dedup: dedup
+                .as_mut()
+                .map(|dedup| &mut **dedup as &mut dyn DedupStore),
+            aml_report: aml_report
+                .as_mut()
+                .map(|report| report as &mut dyn std::io::Write),
+            audit_log: audit_log.as_mut().map(|log| log as &mut dyn std::io::Write),
+            dispute_report: dispute_report
+                .as_mut()
+                .map(|report| report as &mut dyn std::io::Write),
+            settlement_report: settlement_report
+                .as_mut()
+                .map(|report| report as &mut dyn std::io::Write),
+            tag_report: tag_report
+                .as_mut()
+                .map(|report| report as &mut dyn std::io::Write),
+            balance_history: balance_history
+                .as_mut()
+                .map(|history| history as &mut dyn std::io::Write),
+            ledger: ledger
+                .as_mut()
+                .map(|ledger| ledger as &mut dyn std::io::Write),
+            quarantine: quarantine
+                .as_mut()
+                .map(|quarantine| quarantine as &mut dyn std::io::Write),
+        },
+    );
+    #[cfg(feature = "rhai")]
+    for flag in flags.iter().flat_map(|flags| flags.take()) {
+        eprintln!("flagged `{:?}`: {}", flag.transaction, flag.reason);
+    }
+    if let Some(path) = &args.snapshot {
+        if let Err(e) = snapshot.save(path) {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if control.is_cancelled() {
+        // Exiting skips the destructors, which would flush the reports.
+        let mut reports = [
+            aml_report,
+            audit_log,
+            dispute_report,
+            settlement_report,
+            tag_report,
+            balance_history,
+            ledger,
+            quarantine,
+        ];
+        for report in reports.iter_mut().flatten() {
+            if let Err(e) = std::io::Write::flush(report) {
+                eprintln!("error: could not write report: {}", e);
+            }
+        }
+        if control.is_aborted() {
+            std::process::exit(EXIT_TOO_MANY_ERRORS);
+        }
+        std::process::exit(EXIT_INTERRUPTED);
+    }
+}
+
+/// Adds the validator of a `--rules-script` to the processor, returning the
+/// transactions it will flag.
+#[cfg(feature = "rhai")]
+fn add_rules_script(
+    mut start: Snapshot,
+    path: Option<&Path>,
+) -> (Snapshot, Option<transactions::Flags>) {
+    let path = match path {
+        Some(path) => path,
+        None => return (start, None),
+    };
+    let validator = transactions::ScriptValidator::load(path).unwrap_or_else(|e| {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    });
+    let flags = validator.flags();
+    start.processor.add_validator(Box::new(validator));
+    (start, Some(flags))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::BufWriter;
+    use transactions::MemoryStore;
+
+    fn run_snapshot_test(input: &str) {
+        run_snapshot_test_with(input, &Options::default(), &RunControl::new());
+    }
+
+    fn run_snapshot_test_with(input: &str, options: &Options, control: &RunControl) {
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            options,
+            control,
+            Stores::default(),
+        );
+    }
+
+    fn run_inputs_snapshot_test_with<'a>(
+        inputs: &[&str],
+        start: Snapshot,
+        options: &'a Options,
+        control: &RunControl,
+        stores: Stores<'a>,
+    ) {
+        let mut outstream = BufWriter::new(Vec::new());
+        let mut errstream = BufWriter::new(Vec::new());
+        let instreams = inputs.iter().map(|input| input.as_bytes());
+        run(
+            instreams,
+            &mut outstream,
+            &mut errstream,
+            start,
+            options,
+            control,
+            stores,
+        );
+        let outstring = String::from_utf8(outstream.into_inner().unwrap()).unwrap();
+        let errstring = String::from_utf8(errstream.into_inner().unwrap()).unwrap();
+        let all_output = format!("{}Stderr:\n{}", outstring, errstring);
+        insta::assert_snapshot!(snapshot_name(), all_output);
+    }
+
+    /// Names the snapshot after the calling test, e.g. `test::test_dispute` -> `dispute`.
+    fn snapshot_name() -> String {
+        let thread = std::thread::current();
+        let test_name = thread.name().expect("tests run on named threads");
+        let test_name = test_name.rsplit("::").next().unwrap();
+        test_name.trim_start_matches("test_").to_string()
+    }
+
+    #[test]
+    fn test_parse_args() {
+        let args = |s: &str| Args::parse(s.split_whitespace().map(String::from));
+        let parsed = args("--max-rate 100 input.csv").unwrap();
+        assert_eq!(parsed.filepath.as_deref(), Some("input.csv"));
+        assert_eq!(parsed.options.max_rate, NonZeroU32::new(100));
+        assert!(args("--max-rate 0 input.csv").is_err());
+        let parsed = args("--error-format json --error-samples 3 input.csv").unwrap();
+        assert_eq!(parsed.options.error_format, ErrorFormat::Json);
+        assert_eq!(parsed.options.error_samples, Some(3));
+        let parsed = args("--max-errors 10 --max-error-rate 2.5% input.csv").unwrap();
+        assert_eq!(parsed.options.error_limit.max_errors, Some(10));
+        assert_eq!(parsed.options.error_limit.max_rate, Some(ErrorRate(2.5)));
+        assert!(args("--max-error-rate 101% input.csv").is_err());
+        assert!(args("--error-format xml input.csv").is_err());
+        let parsed = args("input.csv --replay-speed 10x --reorder-window 30s").unwrap();
+        assert_eq!(
+            parsed.options.reorder_window,
+            Some(ReorderWindow::Seconds(30))
+        );
+        assert_eq!(parsed.options.replay_speed, ReplaySpeed::Multiplier(10.0));
+        assert_eq!(
+            args("--stop-after 9001 --snapshot a.json input.csv")
+                .unwrap()
+                .options
+                .stop_after,
+            Some(9001)
+        );
+        assert!(args("--max-rate").is_err());
+        assert!(args("--unknown input.csv").is_err());
+        assert!(args("").is_err());
+        let parsed = args("--manifest inputs.sha256 --manifest-mismatch warn").unwrap();
+        assert_eq!(parsed.manifest.as_deref(), Some("inputs.sha256"));
+        assert_eq!(parsed.manifest_mismatch, CheckMode::Warn);
+        assert!(args("--manifest inputs.sha256 input.csv").is_err());
+        let parsed = args("--continue-from a.json --snapshot b.json input.csv").unwrap();
+        assert_eq!(parsed.continue_from, Some(PathBuf::from("a.json")));
+        assert!(args("--continue-from a.json --resume a.json input.csv").is_err());
+        let parsed = args("--continue-from a.json --close-period input.csv").unwrap();
+        assert!(parsed.close_period);
+        assert!(args("--close-period input.csv").is_err());
+        let parsed = args("--resume a.json --snapshot b.json input.csv").unwrap();
+        assert_eq!(parsed.resume, Some(PathBuf::from("a.json")));
+        assert_eq!(parsed.snapshot, Some(PathBuf::from("b.json")));
+        let parsed = args("--checkpoint-dir cp --checkpoint-every 1000 input.csv").unwrap();
+        assert_eq!(parsed.options.checkpoint_dir, Some(PathBuf::from("cp")));
+        assert_eq!(parsed.options.checkpoint_every, NonZeroU64::new(1000));
+        assert!(args("--checkpoint-dir cp input.csv").is_err());
+        assert!(args("--checkpoint-interval 60 input.csv").is_err());
+        assert!(
+            args("--checkpoint-dir cp --checkpoint-every 10 --reorder-window 5 a.csv").is_err()
+        );
+        let parsed = args("--state-store sled:state input.csv").unwrap();
+        assert_eq!(
+            parsed.state_store,
+            Some(StoreSpec::Sled(PathBuf::from("state")))
+        );
+        let parsed = args("--state-store postgres://localhost/tx input.csv").unwrap();
+        assert_eq!(
+            parsed.state_store,
+            Some(StoreSpec::Postgres("postgres://localhost/tx".to_string()))
+        );
+        assert!(args("--state-store sled: input.csv").is_err());
+        assert!(args("--state-store redis:state input.csv").is_err());
+        let parsed = args("--state-store rocksdb:state input.csv").unwrap();
+        assert_eq!(
+            parsed.state_store,
+            Some(StoreSpec::RocksDb(PathBuf::from("state")))
+        );
+        assert!(args("--state-store rocksdb: input.csv").is_err());
+        assert!(args("--state-store sled:state --resume a.json input.csv").is_err());
+        let parsed = args("--aml-daily-amount 5000 --aml-report aml.csv input.csv").unwrap();
+        assert_eq!(parsed.options.aml.daily_amount, Some(Price4::new(5000, 0)));
+        assert_eq!(parsed.aml_report, Some(PathBuf::from("aml.csv")));
+        assert!(args("--aml-amount 5000 input.csv").is_err());
+        assert!(args("--aml-report aml.csv input.csv").is_err());
+        let parsed = args("--dispute-report disputes.csv input.csv").unwrap();
+        assert_eq!(parsed.dispute_report, Some(PathBuf::from("disputes.csv")));
+        let parsed = args("--settlement-report settlement.csv input.csv").unwrap();
+        assert_eq!(
+            parsed.settlement_report,
+            Some(PathBuf::from("settlement.csv"))
+        );
+        let parsed = args("--tag-report tags.json --tag-report-format json input.csv").unwrap();
+        assert_eq!(parsed.tag_report, Some(PathBuf::from("tags.json")));
+        assert_eq!(parsed.options.tag_report_format, ReportFormat::Json);
+        assert!(args("--tag-report-format xml input.csv").is_err());
+        let parsed =
+            args("--ledger out.beancount --ledger-format beancount --ledger-currency EUR in.csv")
+                .unwrap();
+        assert_eq!(parsed.ledger, Some(PathBuf::from("out.beancount")));
+        assert_eq!(parsed.options.ledger_format, LedgerFormat::Beancount);
+        assert_eq!(parsed.options.ledger_currency, Some("EUR".parse().unwrap()));
+        assert!(args("--ledger-format gnucash in.csv").is_err());
+        let parsed =
+            args("--balance-history history.csv --balance-history-every 10 input.csv").unwrap();
+        assert_eq!(parsed.balance_history, Some(PathBuf::from("history.csv")));
+        assert_eq!(
+            parsed.options.balance_history,
+            Some(HistoryInterval::Every(NonZeroU64::new(10).unwrap()))
+        );
+        let parsed = args("--risk-config risk.toml --risk-column input.csv").unwrap();
+        assert_eq!(parsed.risk_config, Some(PathBuf::from("risk.toml")));
+        assert!(parsed.options.risk_column);
+        let parsed = args("--authorization-ttl 3600 --pending-column input.csv").unwrap();
+        assert_eq!(parsed.options.authorization_ttl, NonZeroU64::new(3600));
+        assert!(parsed.options.pending_column);
+        let parsed = args("--settle-withdrawals input.csv").unwrap();
+        assert!(parsed.options.settle_withdrawals);
+        let parsed = args("--escrow-column input.csv").unwrap();
+        assert!(parsed.options.escrow_column);
+        let parsed = args("--strict-headers input.csv").unwrap();
+        assert!(parsed.options.strict_headers);
+        let parsed = args("--no-header input.csv").unwrap();
+        assert!(parsed.options.no_header);
+        let parsed = args("--strict-schema input.csv").unwrap();
+        assert!(parsed.options.strict_schema);
+        let parsed = args("--delimiter ; input.csv").unwrap();
+        assert_eq!(parsed.options.delimiter, Delimiter::Byte(b';'));
+        let parsed = args("--delimiter tab input.tsv").unwrap();
+        assert_eq!(parsed.options.delimiter, Delimiter::Byte(b'\t'));
+        let parsed = args("--delimiter auto input.csv").unwrap();
+        assert_eq!(parsed.options.delimiter, Delimiter::Auto);
+        assert!(args("--delimiter ,, input.csv").is_err());
+        let parsed = args("--amount-format comma input.csv").unwrap();
+        assert_eq!(parsed.options.amount_format, AmountFormat::DecimalComma);
+        let parsed = args("--encoding utf-16le input.csv").unwrap();
+        assert_eq!(
+            parsed.options.encoding,
+            InputEncoding::Label(encoding_rs::UTF_16LE)
+        );
+        assert!(args("--encoding ebcdic input.csv").is_err());
+        let parsed =
+            args("--dormant-after 86400 --block-dormant-withdrawals --dormant-column input.csv")
+                .unwrap();
+        assert_eq!(parsed.options.dormant_after, NonZeroU64::new(86400));
+        assert!(parsed.options.block_dormant_withdrawals && parsed.options.dormant_column);
+        let parsed = args("--unfreeze-at 0 input.csv").unwrap();
+        assert_eq!(parsed.options.unfreeze_at, Some(Price4::ZERO));
+        let parsed = args("--chargeback-freeze 2 input.csv").unwrap();
+        assert_eq!(
+            parsed.options.chargeback_freeze,
+            FreezePolicy::After(NonZeroU32::new(2).unwrap())
+        );
+        assert!(args("--chargeback-freeze 0 input.csv").is_err());
+        let parsed = args("--confirm-chargebacks input.csv").unwrap();
+        assert!(parsed.options.confirm_chargebacks);
+        let parsed = args("--fee-schedule fees.toml input.csv").unwrap();
+        assert_eq!(parsed.fee_schedule, Some(PathBuf::from("fees.toml")));
+        let parsed = args("--fee-schedule fees.toml --watch-config 5 input.csv").unwrap();
+        assert_eq!(
+            parsed.options.watch_config,
+            Some(WatchedConfig {
+                interval: Duration::from_secs(5),
+                files: vec![(PolicyFile::FeeSchedule, PathBuf::from("fees.toml"))],
+            })
+        );
+        assert!(args("--watch-config 5 input.csv").is_err());
+        let parsed = args("--fx-rates rates.toml input.csv").unwrap();
+        assert_eq!(parsed.fx_rates, Some(PathBuf::from("rates.toml")));
+        let parsed = args("--frozen-disputes input.csv").unwrap();
+        assert!(parsed.options.frozen_disputes);
+        let parsed = args("--signed-amounts input.csv").unwrap();
+        assert!(parsed.options.signed_amounts);
+        let parsed = args("--forbid-negative-held input.csv").unwrap();
+        assert!(parsed.options.forbid_negative_held);
+        let parsed = args("--cap-dispute-holds input.csv").unwrap();
+        assert!(parsed.options.cap_dispute_holds);
+        let parsed = args("--permissions roles.toml input.csv").unwrap();
+        assert_eq!(parsed.permissions, Some(PathBuf::from("roles.toml")));
+        let parsed = args("--receivable-column input.csv").unwrap();
+        assert!(parsed.options.receivable_column);
+        let parsed = args("--min-balance 100 --min-balances min.toml input.csv").unwrap();
+        assert_eq!(
+            parsed.options.min_balance.default,
+            Some(Price4::new(100, 0))
+        );
+        assert_eq!(parsed.min_balances, Some(PathBuf::from("min.toml")));
+        let parsed =
+            args("--balance-cap 100 --balance-caps caps.toml --balance-cap-mode partial input.csv")
+                .unwrap();
+        assert_eq!(
+            parsed.options.balance_cap.default,
+            Some(Price4::new(100, 0))
+        );
+        assert_eq!(parsed.options.balance_cap.mode, CapMode::Partial);
+        assert_eq!(parsed.balance_caps, Some(PathBuf::from("caps.toml")));
+        assert!(args("--balance-cap-mode some input.csv").is_err());
+        let parsed = args(
+            "--daily-withdrawal-limit 500 --daily-withdrawal-limits limits.toml \
+             --day-start 06:30 input.csv",
+        )
+        .unwrap();
+        assert_eq!(
+            parsed.options.withdrawal_limit.default,
+            Some(Price4::new(500, 0))
+        );
+        assert_eq!(
+            parsed.options.withdrawal_limit.day_start,
+            6 * 60 * 60 + 30 * 60
+        );
+        assert_eq!(parsed.withdrawal_limits, Some(PathBuf::from("limits.toml")));
+        assert!(args("--day-start 24:00 input.csv").is_err());
+        let parsed = args("--initial-balances accounts.csv input.csv").unwrap();
+        assert_eq!(parsed.initial_balances, Some(PathBuf::from("accounts.csv")));
+        let parsed = args("--statement-client 7 statement.qif").unwrap();
+        assert_eq!(parsed.statement_client, Some(7));
+        assert!(args("--statement-client 70000 statement.qif").is_err());
+        let parsed = args("--statement-accounts ibans.csv camt053.xml").unwrap();
+        assert_eq!(parsed.statement_accounts, Some(PathBuf::from("ibans.csv")));
+        let parsed = args("--recurring recurring.toml input.csv").unwrap();
+        assert_eq!(parsed.recurring, Some(PathBuf::from("recurring.toml")));
+        let parsed = args("--rules rules.toml input.csv").unwrap();
+        assert_eq!(parsed.rules, Some(PathBuf::from("rules.toml")));
+        let parsed = args("--rules-script rules.rhai input.csv").unwrap();
+        assert_eq!(parsed.rules_script, Some(PathBuf::from("rules.rhai")));
+        assert!(args("--telemetry input.csv").unwrap().options.telemetry);
+        let parsed = args("--dedup-store memory --dedup-retention 60 input.csv").unwrap();
+        assert_eq!(parsed.dedup_store, Some(DedupSpec::Memory));
+        assert_eq!(parsed.options.dedup_retention(), Duration::from_secs(60));
+        assert!(args("--dedup-store disk input.csv").is_err());
+    }
+
+    #[test]
+    fn test_parse_subcommands() {
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        assert!(matches!(command("input.csv"), Ok(Command::Run(_))));
+        match command("diff a.json b.json --patch p.json") {
+            Ok(Command::Diff { from, to, patch }) => {
+                assert_eq!(from, PathBuf::from("a.json"));
+                assert_eq!(to, PathBuf::from("b.json"));
+                assert_eq!(patch, Some(PathBuf::from("p.json")));
+            }
+            _ => panic!("expected a diff command"),
+        }
+        assert!(matches!(
+            command("patch a.json p.json --output b.json"),
+            Ok(Command::Patch { .. })
+        ));
+        assert!(command("diff a.json").is_err());
+        assert!(command("patch a.json p.json").is_err());
+        assert!(command("diff a.json b.json --output b.json").is_err());
+        assert!(matches!(
+            command("schema"),
+            Ok(Command::Schema {
+                format: SchemaFormat::Json
+            })
+        ));
+        assert!(matches!(
+            command("schema --format csv"),
+            Ok(Command::Schema {
+                format: SchemaFormat::Csv
+            })
+        ));
+        assert!(command("schema --format xml").is_err());
+        match command("statement s.json --client 2 --from 2024-02-29 --to 5 --format csv") {
+            Ok(Command::Statement {
+                snapshot,
+                client_id,
+                from,
+                to,
+                format,
+            }) => {
+                assert_eq!(snapshot, PathBuf::from("s.json"));
+                assert_eq!(client_id, ClientId::from(2));
+                assert_eq!(
+                    from,
+                    Some(StatementBound::Date(
+                        Timestamp::from_date(2024, 2, 29).unwrap()
+                    ))
+                );
+                assert_eq!(to, Some(StatementBound::Transaction(5)));
+                assert_eq!(format, StatementFormat::Csv);
+            }
+            _ => panic!("expected a statement command"),
+        }
+        assert!(command("statement s.json").is_err());
+        assert!(command("statement --client 1").is_err());
+        assert!(command("statement s.json --client 1 --from 2023-02-29").is_err());
+    }
+
+    #[test]
+    fn test_lint() {
+        // Tests that the findings are written with the lines of their records, and
+        // counted.
+        let input = "type,client,tx,amount,timestamp\n\
+                     deposit,1,2,1.0,100\n\
+                     dispute,1,5,,90\n\
+                     deposit,2,2,1.00001,110\n\
+                     withdrawal,1,x,1.0,\n\
+                     resolve,2,3,,\n\
+                     deposit,1,3,2.0,120\n\
+                     chargeback,1,2,,130\n";
+        let mut report = Vec::new();
+        assert_eq!(lint(input.as_bytes(), LintFormat::Csv, &mut report), Ok(6));
+        insta::assert_snapshot!(snapshot_name(), String::from_utf8(report).unwrap());
+        let mut report = Vec::new();
+        let clean = "type,client,tx,amount\ndeposit,1,1,1.0\ndispute,1,1,\n";
+        assert_eq!(lint(clean.as_bytes(), LintFormat::Json, &mut report), Ok(0));
+        assert!(report.is_empty());
+
+        let command = |s: &str| Command::parse(s.split_whitespace().map(String::from));
+        match command("lint input.csv --format csv") {
+            Ok(Command::Lint { path, format }) => {
+                assert_eq!(path, PathBuf::from("input.csv"));
+                assert_eq!(format, LintFormat::Csv);
+            }
+            _ => panic!("expected a lint command"),
+        }
+        assert!(command("lint").is_err());
+    }
+
+    #[test]
+    fn test_schema() {
+        // Tests that the described record types are the ones read, and the csv spec
+        // of the columns.
+        for record_type in transactions::RECORD_TYPES.iter() {
+            let kind: Result<RecordKind, _> =
+                serde_json::from_value(serde_json::json!(record_type));
+            assert!(kind.is_ok(), "unknown record type {}", record_type);
+        }
+        let mut spec = Vec::new();
+        write_schema(SchemaFormat::Csv, &mut spec).unwrap();
+        insta::assert_snapshot!("schema_csv", String::from_utf8(spec).unwrap());
+    }
+
+    #[test]
+    fn test_serde() {
+        // Tests that transaction type, integers, optional prices, booleans are correctly
+        // serialized and deserialized.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 3, 1
+            deposit,    1, 5, .5
+            deposit,    1, 6, 0.2
+            withdrawal, 1, 4, .0001
+            dispute,    1, 5,
+            resolve,    1, 5,
+            dispute,    1, 4,
+            dispute,    1, 6,
+            chargeback, 1, 4,
+            deposit,    2, 15, 100.03
+            dispute,    2, 15,
+            chargeback, 2, 15,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_unknown_transaction_id() {
+        // Tests that disputes, resolves, and chargebacks for unknown clients / transactions
+        // are ignored.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 1.5
+            dispute,    1, 6,
+            chargeback, 1, 6,
+            resolve,    1, 6,
+            deposit,    1, 6, 2.0
+            dispute,    1, 6,
+            dispute,    2, 5,
+            chargeback, 2, 5,
+            resolve,    2, 5,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_duplicate_tx_id_rejected() {
+        // Tests that duplicate transaction ids for a client are rejected
+        let input = "
+            type, client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 1, 0.5
+            deposit,    1, 1, 2.0";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_deposit_withdrawal() {
+        // Tests withdrawing tests the exact available balance works and withdrawing
+        // more than available fails.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 100, 1.3
+            deposit,    1, 1, 0.2
+            withdrawal, 1, 2, .0001
+            withdrawal, 1, 3, 1.4999
+            withdrawal, 1, 4, .0001
+            deposit,    1, 5, 2.0
+            withdrawal, 1, 6, 2.0001";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_dispute() {
+        // Tests that disputes result in balance being held which
+        // cannot be used for withdrawing.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 1.0
+            deposit,    1, 6, 2
+            dispute,    1, 5,
+            withdrawal, 1, 7, 2.5
+            withdrawal, 1, 8, 1.5";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_testing_run_csv() {
+        // Tests that the public test harness writes what the binary writes.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 1.0
+            deposit,    2, 6, 2
+            dispute,    1, 5,
+            withdrawal, 1, 7, 2.5
+            chargeback, 1, 5,
+            bogus,      1, 8, 1";
+        let mut outstream = BufWriter::new(Vec::new());
+        let mut errstream = BufWriter::new(Vec::new());
+        run(
+            std::iter::once(input.as_bytes()),
+            &mut outstream,
+            &mut errstream,
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let outstring = String::from_utf8(outstream.into_inner().unwrap()).unwrap();
+        let errstring = String::from_utf8(errstream.into_inner().unwrap()).unwrap();
+        let harness = transactions::testing::run_csv(input);
+        assert_eq!(harness.output, outstring);
+        assert_eq!(harness.errors, errstring);
+    }
+
+    #[test]
+    fn test_negative_available_on_dispute() {
+        // Tests that disputes can result in negative available
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 10
+            deposit,    1, 6, 20
+            withdrawal, 1, 7, 25
+            dispute,    1, 6,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_partial_resolve() {
+        // Tests that a resolve with an amount releases only that part of the held
+        // funds, keeping the dispute open, and that a chargeback then charges back
+        // only the rest.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10
+            dispute,    1, 1,
+            resolve,    1, 1, 4
+            resolve,    1, 1, 7
+            resolve,    1, 1, -1
+            withdrawal, 1, 2, 4
+            chargeback, 1, 1,
+            deposit,    2, 3, 10
+            dispute,    2, 3,
+            resolve,    2, 3, 3
+            resolve,    2, 3,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_negative_held_on_dispute() {
+        // Tests that disputes can result in negative held
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 10
+            withdrawal, 1, 6, 5
+            dispute,    1, 6,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_forbid_negative_held() {
+        // Tests that disputing a withdrawal is rejected if it would take held
+        // funds negative, while disputing a deposit still is not.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 10
+            withdrawal, 1, 6, 5
+            dispute,    1, 6,
+            dispute,    1, 5,
+            dispute,    1, 6,";
+        let options = Options {
+            forbid_negative_held: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_cap_dispute_holds() {
+        // Tests that a dispute holds only the available funds, recording the
+        // shortfall, and that its resolve and chargeback release only what it held.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10
+            deposit,    1, 2, 20
+            withdrawal, 1, 3, 25
+            dispute,    1, 2,
+            resolve,    1, 2,
+            deposit,    2, 4, 10
+            withdrawal, 2, 5, 4
+            dispute,    2, 4,
+            chargeback, 2, 4,";
+        let options = Options {
+            cap_dispute_holds: true,
+            ..Options::default()
+        };
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!(
+            "cap_dispute_holds_audit_log",
+            String::from_utf8(log).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_cannot_withdraw_on_negative_balance() {
+        // Tests that withdrawing when balance is negative fails, but depositing still works
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 10
+            deposit,    1, 6, 20
+            withdrawal, 1, 7, 25
+            dispute,    1, 6,
+            withdrawal, 1, 8, 4
+            deposit,    1, 9, 5
+            withdrawal, 1,10, 3
+            deposit,    1,11, 25
+            withdrawal, 1,12, 5";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_resolve() {
+        // Tests that resolves result in held money put back in available.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 2.0
+            dispute,    1, 5,
+            withdrawal, 1, 6, 1.0
+            resolve,    1, 5,
+            withdrawal, 1, 7, 0.5";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_multiple_dispute_disallowed() {
+        // Tests that multiple disputes are disallowed for a transaction,
+        // even if the transaction was resolved.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 5, 2.0
+            dispute,    1, 5,
+            dispute,    1, 5,
+            resolve,    1, 5,
+            dispute,    1, 5,
+            withdrawal, 1, 6, 0.5";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_chargeback() {
+        // Tests that chargebacks result in frozen accounts
+        // where no more transactions are allowed.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 4, 1.0
+            deposit,    1, 5, 2.0
+            dispute,    1, 5,
+            chargeback, 1, 5,
+            withdrawal, 1, 6, 0.5
+            deposit,    1, 7, 0.1
+            dispute,    1, 7,
+            resolve,    1, 7,
+            deposit,    2, 8, 1.0";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_negative_available_on_chargeback() {
+        // Tests that chargebacks can result in negative balances.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 3, 0.7
+            deposit,    1, 4, 0.3
+            deposit,    1, 5, 2.0
+            withdrawal, 1, 6, 2.5
+            dispute,    1, 4,
+            resolve,    1, 4,
+            withdrawal, 1, 7, 0.1
+            dispute,    1, 3,
+            dispute,    1, 5,
+            chargeback, 1, 5,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_multiple_clients() {
+        // Tests using multiple clients
+        let input = "
+            type,       client, tx, amount
+            withdrawal, 2, 1, 10
+            deposit,    1, 2, 100
+            deposit,    1,10, 50
+            withdrawal, 2, 3, 10
+            deposit,    2, 4, 200
+            withdrawal, 2, 5, 10
+            dispute,    1, 5,
+            resolve,    1, 5,
+            deposit,    3, 6, 75
+            deposit,    3, 7, 10
+            withdrawal, 3, 8, 80
+            dispute,    2, 6,
+            dispute,    3, 6,
+            chargeback, 3, 6,
+            dispute,    1,10,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_cancelled() {
+        // Tests that a cancelled run still writes the (empty) account report and
+        // a resume marker.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            deposit,    1, 2, 2.0";
+        let control = RunControl::new();
+        control.cancel();
+        run_snapshot_test_with(input, &Options::default(), &control);
+    }
+
+    #[test]
+    fn test_timestamp_column() {
+        // Tests that an optional timestamp column is accepted.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 1.0, 1600000000
+            withdrawal, 1, 2, 0.5, 1600000001
+            dispute,    1, 1,    , 1600000002
+            deposit,    1, 3, 1.0,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_reorder_window() {
+        // Tests that a dispute arriving before its deposit is applied after it
+        // when the records are reordered by timestamp.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 5.0, 100
+            dispute,    1, 2,    , 103
+            deposit,    1, 2, 2.0, 102
+            withdrawal, 1, 3, 1.0, 104
+            deposit,    1, 4, 1.0, 101";
+        let options = Options {
+            reorder_window: Some(ReorderWindow::Records(2)),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_monotonic_tx_ids_warn() {
+        // Tests that decreasing deposit/withdrawal ids are reported, while disputes
+        // referring to older transactions are not checked.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            deposit,    1, 5, 2.0
+            withdrawal, 1, 3, 0.5
+            dispute,    1, 1,
+            deposit,    1, 6, 1.0";
+        let options = Options {
+            monotonic_tx_ids: Some(CheckMode::Warn),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_monotonic_tx_ids_reject() {
+        // Tests that records with decreasing deposit/withdrawal ids are skipped.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            deposit,    1, 5, 2.0
+            withdrawal, 1, 3, 0.5
+            dispute,    1, 1,
+            deposit,    1, 6, 1.0";
+        let options = Options {
+            monotonic_tx_ids: Some(CheckMode::Reject),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_skip_duplicates() {
+        // Tests that exact repeats of deposits/withdrawals are counted, while
+        // conflicting reuses of a transaction id are still reported.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 0.5
+            deposit,    1, 1, 1.0000
+            withdrawal, 1, 2, 0.5
+            deposit,    1, 1, 2.0
+            deposit,    2, 1, 1.0";
+        let options = Options {
+            skip_duplicates: true,
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_tolerant_headers() {
+        // Tests that columns are matched case-insensitively in any order, and that
+        // unknown columns are ignored.
+        let input = "
+            Client, TX, Type,       Amount, Memo
+            1,      1,  deposit,    2.0,    salary
+            1,      2,  withdrawal, 0.5,
+            2,      3,  deposit,    1.0,    gift";
+        run_snapshot_test_with(input, &Options::default(), &RunControl::new());
+    }
+
+    #[test]
+    fn test_strict_headers() {
+        // Tests that an input file with an unknown column is rejected as a whole
+        // with `--strict-headers`, while the next file is still processed.
+        let first = "type,client,tx,amount,memo\ndeposit,1,1,2.0,salary";
+        let second = "type,client,tx,amount\ndeposit,2,2,1.0";
+        let options = Options {
+            strict_headers: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[first, second],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_delimiter() {
+        // Tests that tab-separated files are read with `--delimiter tab`, and that
+        // `auto` detects the separator of each file.
+        let tsv = "type\tclient\ttx\tamount\ndeposit\t1\t1\t2.0\nwithdrawal\t1\t2\t0.5";
+        let options = Options {
+            delimiter: Delimiter::Byte(b'\t'),
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[tsv],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let semicolons = "type;client;tx;amount\ndeposit;2;3;1.5";
+        let commas = "type,client,tx,amount,note\ndeposit,3,4,1.0,\"a;b;c\"";
+        let options = Options {
+            delimiter: Delimiter::Auto,
+            ..Options::default()
+        };
+        let mut output = Vec::new();
+        let mut errors = Vec::new();
+        run(
+            [tsv, semicolons, commas]
+                .iter()
+                .map(|input| input.as_bytes()),
+            &mut output,
+            &mut errors,
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+        assert_eq!(String::from_utf8(errors).unwrap(), "");
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "client,available,held,total,locked\n\
+             1,1.5,0,1.5,false\n\
+             2,1.5,0,1.5,false\n\
+             3,1,0,1,false\n"
+        );
+    }
+
+    #[test]
+    fn test_encoding() {
+        // Tests that byte order marks are stripped, UTF-16 is detected by its
+        // byte order mark, and Latin-1 is transcoded with `--encoding latin1`.
+        let run_bytes = |inputs: &[Vec<u8>], options: &Options| {
+            let mut output = Vec::new();
+            let mut errors = Vec::new();
+            run(
+                inputs.iter().map(|input| input.as_slice()),
+                &mut output,
+                &mut errors,
+                Snapshot::default(),
+                options,
+                &RunControl::new(),
+                Stores::default(),
+            );
+            (
+                String::from_utf8(output).unwrap(),
+                String::from_utf8(errors).unwrap(),
+            )
+        };
+        let utf8 = b"\xef\xbb\xbfdeposit,1,1,1.0\n".to_vec();
+        let mut utf16 = vec![0xff, 0xfe];
+        for unit in "type,client,tx,amount\r\ndeposit,2,2,2.0\r\n".encode_utf16() {
+            utf16.extend_from_slice(&unit.to_le_bytes());
+        }
+        let options = Options {
+            no_header: true,
+            ..Options::default()
+        };
+        let (_, errors) = run_bytes(&[utf8], &options);
+        assert_eq!(errors, "");
+        let (output, errors) = run_bytes(&[utf16], &Options::default());
+        assert_eq!(errors, "");
+        assert!(output.ends_with("\n2,2,0,2,false\n"));
+
+        let latin1 = b"type,client,tx,amount,note\ndeposit,3,3,1.0,caf\xe9\n".to_vec();
+        let (_, errors) = run_bytes(std::slice::from_ref(&latin1), &Options::default());
+        assert!(errors.contains("invalid utf-8"));
+        let options = Options {
+            encoding: "latin1".parse().unwrap(),
+            ..Options::default()
+        };
+        let (output, errors) = run_bytes(&[latin1], &options);
+        assert_eq!(errors, "");
+        assert!(output.ends_with("\n3,1,0,1,false\n"));
+    }
+
+    #[test]
+    fn test_amount_format() {
+        // Tests that amounts with thousands separators and decimal commas are
+        // read with `--amount-format comma`, and that badly grouped ones are not.
+        let input = "type;client;tx;amount\n\
+                     deposit;1;1;1.234,5678\n\
+                     deposit;1;2;1 000\n\
+                     withdrawal;1;3;0,5\n\
+                     deposit;2;4;12.34\n\
+                     deposit;2;5;2,25";
+        let options = Options {
+            delimiter: Delimiter::Byte(b';'),
+            amount_format: AmountFormat::DecimalComma,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+
+        let normalize = |format: AmountFormat, amount| format.normalize(amount);
+        assert_eq!(
+            normalize(AmountFormat::DecimalPoint, "-1,234,567.5"),
+            Some("-1234567.5".to_string())
+        );
+        assert_eq!(
+            normalize(AmountFormat::DecimalComma, ",5"),
+            Some(".5".to_string())
+        );
+        assert_eq!(normalize(AmountFormat::DecimalPoint, "1,5"), None);
+        assert_eq!(normalize(AmountFormat::DecimalComma, "1234.567"), None);
+        assert_eq!(normalize(AmountFormat::DecimalComma, "1,2,3"), None);
+    }
+
+    #[test]
+    fn test_strict_schema() {
+        // Tests that the run stops at the first record that breaks the schema, and
+        // that partial resolves are allowed.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 2.0
+            dispute,    1, 1,
+            resolve,    1, 1, 1.0
+            withdrawal, 1, 2, 0.5
+            chargeback, 1, 1, 1.0
+            deposit,    1, 3, 1.0";
+        let options = Options {
+            strict_schema: true,
+            ..Options::default()
+        };
+        let control = RunControl::new();
+        run_snapshot_test_with(input, &options, &control);
+        assert!(control.is_aborted());
+    }
+
+    #[test]
+    fn test_no_header() {
+        // Tests that without a header row the first row is a record, with the
+        // columns in the order of the record columns, and that rows that cannot
+        // be parsed are quarantined without a header.
+        let input = "deposit,1,1,2.0\n\
+                     withdrawal,1,2,0.5\n\
+                     deposit,2,x,1.0\n\
+                     dispute,1,1,\n";
+        let options = Options {
+            no_header: true,
+            ..Options::default()
+        };
+        let mut quarantine = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                quarantine: Some(&mut quarantine),
+                ..Stores::default()
+            },
+        );
+        assert_eq!(String::from_utf8(quarantine).unwrap(), "deposit,2,x,1.0\n");
+    }
+
+    #[test]
+    fn test_malformed_rows() {
+        // Tests that rows with a wrong field count, an unbalanced quote or binary
+        // garbage are rejected and quarantined on their own, and that the rows an
+        // unbalanced quote swallowed are still read, with their lines.
+        let input = "type,client,tx,amount\r\n\
+                     deposit,1,1,1.0\r\n\
+                     withdrawal,1,2\r\n\
+                     deposit,1,3,\"1.0\r\n\
+                     deposit,1,4,2.0\r\n\
+                     \r\n\
+                     dispute,1,4,\r\n\
+                     deposit,1,5,3.0\"\r\n\
+                     deposit,2,6,\u{1}\u{0}\u{7f}\r\n\
+                     deposit,2,7,4.0\r\n";
+        let options = Options {
+            error_format: ErrorFormat::Json,
+            ..Options::default()
+        };
+        let mut quarantine = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                quarantine: Some(&mut quarantine),
+                ..Stores::default()
+            },
+        );
+        assert_eq!(
+            String::from_utf8(quarantine).unwrap(),
+            "type,client,tx,amount\r\n\
+             withdrawal,1,2\r\n\
+             deposit,1,3,\"1.0\r\n\
+             deposit,1,5,3.0\"\r\n\
+             deposit,2,6,\u{1}\u{0}\u{7f}\r\n"
+        );
+
+        let lines: Vec<_> = split_lines(b"deposit,1,1,1.0\n\ndeposit,1,2,\xff\n", 7, b',')
+            .into_iter()
+            .map(|(row, result)| (row, result.map(|record| record.position().unwrap().line())))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].1.as_ref().unwrap(), &7);
+        assert_eq!(
+            lines[1].1.as_ref().unwrap_err().to_string(),
+            "line 9: invalid UTF-8"
+        );
+    }
+
+    #[test]
+    fn test_error_format_json() {
+        // Tests that errors are written as JSON objects with the line and raw
+        // record they were reported for, and the summary without them.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            deposit,    1, x, 1.0
+            resolve,    1, 1,
+            deposit,    1, 1, 1.0";
+        let options = Options {
+            error_format: ErrorFormat::Json,
+            skip_duplicates: true,
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_error_samples() {
+        // Tests that only the first errors of each code and shape are written,
+        // and that the others are counted.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            withdrawal, 1, 3, 6.0
+            withdrawal, 1, 4, 7.0
+            dispute,    1, 5,
+            dispute,    1, 6,
+            withdrawal, 2, 7, 1.0";
+        let options = Options {
+            error_samples: Some(1),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+        assert_eq!(
+            error_shape("requested 5.0000 > available 1.0000"),
+            "requested #.# > available #.#"
+        );
+    }
+
+    #[test]
+    fn test_quarantine() {
+        // Tests that rows that cannot be parsed are quarantined byte for byte,
+        // each under the header of its input, while rejected transactions are not.
+        let first = "type,client,tx,amount\r\n\
+                     deposit,1,1,1.0\r\n\
+                     deposit, 1 ,x,\"1.0\"\r\n\
+                     withdrawal,1,2,5.0\r\n\
+                     deposit,1,3\r\n";
+        let second = "type, client, tx, amount\ndeposit, 2, 4, 1,0";
+        let mut quarantine = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[first, second],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                quarantine: Some(&mut quarantine),
+                ..Stores::default()
+            },
+        );
+        assert_eq!(
+            String::from_utf8(quarantine).unwrap(),
+            "type,client,tx,amount\r\n\
+             deposit, 1 ,x,\"1.0\"\r\n\
+             deposit,1,3\r\n\
+             type, client, tx, amount\n\
+             deposit, 2, 4, 1,0\n"
+        );
+    }
+
+    #[test]
+    fn test_max_errors() {
+        // Tests that the run stops before the next record once more records were
+        // rejected than allowed.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            withdrawal, 1, 3, 6.0
+            deposit,    1, 4, 1.0";
+        let options = Options {
+            error_limit: ErrorLimit {
+                max_errors: Some(1),
+                max_rate: None,
+            },
+            ..Options::default()
+        };
+        let control = RunControl::new();
+        run_snapshot_test_with(input, &options, &control);
+        assert!(control.is_aborted());
+
+        // The rate of a short input is checked at its end.
+        let limit = ErrorLimit {
+            max_errors: None,
+            max_rate: Some("50%".parse().unwrap()),
+        };
+        assert_eq!(limit.exceeded(2, 3, false), None);
+        assert_eq!(
+            limit.exceeded(2, 3, true).as_deref(),
+            Some("too many errors: 2 of 3 records rejected, more than 50%")
+        );
+        assert!(limit.exceeded(50, 100, false).is_none());
+        assert!(limit.exceeded(51, 100, false).is_some());
+    }
+
+    #[test]
+    fn test_dispute_limits() {
+        // Tests that accounts are frozen once a dispute takes them over the number
+        // of open disputes or the amount they hold, which is recorded in the audit
+        // log, and that resolved disputes do not count.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 5.0
+            deposit,    1, 2, 5.0
+            deposit,    1, 3, 5.0
+            dispute,    1, 1,
+            resolve,    1, 1,
+            dispute,    1, 2,
+            dispute,    1, 3,
+            deposit,    2, 4, 20.0
+            dispute,    2, 4,
+            deposit,    3, 5, 5.0
+            dispute,    3, 5,
+            deposit,    3, 6, 1.0";
+        let mut start = Snapshot::default();
+        start.processor.set_risk_config(
+            RiskConfig::from_toml(
+                "
+                max_open_disputes = 1
+                max_disputed_amount = 15
+                dispute_limit_action = \"freeze\"
+                ",
+            )
+            .unwrap(),
+        );
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("dispute_limits_audit_log", String::from_utf8(log).unwrap());
+    }
+
+    #[test]
+    fn test_risk_score() {
+        // Tests that risk scores are output, and that accounts are flagged for
+        // review and frozen once their score reaches the thresholds.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            deposit,    1, 2, 10.0
+            dispute,    1, 1,
+            resolve,    1, 1,
+            dispute,    1, 2,
+            deposit,    2, 3, 10.0
+            withdrawal, 2, 4, 1.0
+            withdrawal, 2, 5, 1.0
+            withdrawal, 2, 6, 1.0";
+        let mut start = Snapshot::default();
+        start.processor.set_risk_config(
+            RiskConfig::from_toml(
+                "
+                review_threshold = 10
+                freeze_threshold = 20
+                ",
+            )
+            .unwrap(),
+        );
+        let options = Options {
+            risk_column: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_authorize_capture() {
+        // Tests that authorized funds are pending until captured, and released if
+        // the authorization expires first.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 1.0,  100
+            authorize,  1, 2, 5.0,  100
+            authorize,  1, 3, 2.0,  150
+            withdrawal, 1, 4, 3.0,  160
+            capture,    1, 2,     , 170
+            withdrawal, 1, 5, 3.0,  180
+            authorize,  2, 6, 4.0,  250
+            capture,    1, 3,     , 300
+            capture,    1, 2,     , 310
+            dispute,    1, 2,     , 320";
+        let options = Options {
+            authorization_ttl: NonZeroU64::new(100),
+            pending_column: true,
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_settle_withdrawals() {
+        // Tests that withdrawn funds are pending out until the withdrawal is
+        // settled, and returned if it fails.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            withdrawal, 1, 2, 4.0
+            withdrawal, 1, 3, 3.0
+            withdrawal, 1, 4, 4.0
+            dispute,    1, 2,
+            settle,     1, 2,
+            fail,       1, 3,
+            settle,     1, 3,
+            withdrawal, 1, 5, 1.0
+            dispute,    1, 2,";
+        let options = Options {
+            settle_withdrawals: true,
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_scheduled() {
+        // Tests that records taking effect after their timestamp are applied once
+        // a later record's timestamp passes their effective time.
+        let input = "
+            type,       client, tx, amount, timestamp, effective
+            deposit,    1, 1, 10.0, 100,
+            withdrawal, 1, 2, 5.0,  110, 200
+            deposit,    1, 3, 1.0,  150, 120
+            withdrawal, 1, 4, 20.0, 160, 300
+            deposit,    2, 5, 3.0,  210,
+            deposit,    2, 6, 1.0,  220, 500
+            withdrawal, 1, 7, 1.0,  310,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_batches() {
+        // Tests that a batch applies all of its records or none of them, and that
+        // other clients' records are applied while it is open.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            begin,      1, 100,
+            withdrawal, 1, 2, 4.0
+            deposit,    2, 3, 5.0
+            withdrawal, 1, 4, 4.0
+            commit,     1, 100,
+            begin,      1, 101,
+            withdrawal, 1, 5, 1.0
+            withdrawal, 1, 6, 2.0
+            withdrawal, 1, 7, 1.0
+            commit,     1, 101,
+            commit,     2, 102,
+            begin,      2, 103,
+            begin,      2, 104,
+            withdrawal, 2, 8, 1.0";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_recurring() {
+        // Tests that recurring transactions are applied as the records' timestamps
+        // reach them, with consecutive transaction ids.
+        let path = std::env::temp_dir().join(format!("recurring-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "
+            [[recurring]]
+            type = \"deposit\"
+            client = 1
+            tx = 100
+            amount = 10.0
+            start = 1000
+            every = 100
+            count = 3
+
+            [[recurring]]
+            type = \"withdrawal\"
+            client = 1
+            tx = 200
+            amount = 4.0
+            start = 1050
+            every = 1000
+            count = 2
+            ",
+        )
+        .unwrap();
+        let mut start = Snapshot::default();
+        schedule_recurring(&mut start.processor, &path).unwrap();
+        // Scheduling the same series again, e.g. when resuming, has no effect.
+        schedule_recurring(&mut start.processor, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 1.0, 1150
+            dispute,    1, 101,  , 1160
+            withdrawal, 1, 2, 1.0, 1300";
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_initial_balances() {
+        // Tests that accounts are opened with their initial balances and frozen
+        // state, without any transactions to dispute.
+        let path = std::env::temp_dir().join(format!("balances-{}.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            "client, available, held, total, locked
+             1,      100.5,     0,    100.5, false
+             2,      20,        5,    25,    true",
+        )
+        .unwrap();
+        let mut start = Snapshot::default();
+        open_accounts(&mut start.processor, &path).unwrap();
+        // Opening the same accounts again fails.
+        assert!(open_accounts(&mut start.processor, &path).is_err());
+        std::fs::write(
+            &path,
+            "client, available, held
+3, 1.00001, 0",
+        )
+        .unwrap();
+        let err = open_accounts(&mut start.processor, &path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert!(err.ends_with("amount 1.00001 has more than 4 decimals"));
+        let input = "
+            type,       client, tx, amount
+            withdrawal, 1, 2, 0.5
+            dispute,    1, 1,
+            deposit,    2, 3, 1.0";
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_statement() {
+        // Tests that the credits and debits of a bank statement are deposited and
+        // withdrawn, with tx ids derived from the statement.
+        let path = std::env::temp_dir().join(format!("statement-{}.qif", std::process::id()));
+        std::fs::write(
+            &path,
+            "!Type:Bank
+             D11/14/2023
+             T1,250.00
+             PSalary
+             ^
+             D11/15'23
+             T-42.50
+             PGroceries
+             ^
+             D11/15'23
+             T-42.50
+             PGroceries
+             ^",
+        )
+        .unwrap();
+        assert!(is_statement(&path));
+        let clients = StatementClients {
+            client: Some(1),
+            ..StatementClients::default()
+        };
+        let records = read_statement_records(&path, &clients).unwrap();
+        assert!(read_statement_records(&path, &StatementClients::default()).is_err());
+        std::fs::remove_file(&path).unwrap();
+        let input = String::from_utf8(records).unwrap();
+        insta::assert_snapshot!("statement_records", input);
+        run_snapshot_test(&input);
+    }
+
+    #[cfg(feature = "roxmltree")]
+    #[test]
+    fn test_iso20022_accounts() {
+        // Tests that the entries of an ISO 20022 message are for the clients of
+        // their accounts.
+        let path = std::env::temp_dir().join(format!("camt053-{}.xml", std::process::id()));
+        std::fs::write(
+            &path,
+            "<Document><BkToCstmrStmt>
+               <Stmt>
+                 <Acct><Id><IBAN>DE01</IBAN></Id></Acct>
+                 <Ntry><Amt>5</Amt><CdtDbtInd>CRDT</CdtDbtInd><NtryRef>1</NtryRef>
+                   <BookgDt><Dt>2023-11-14</Dt></BookgDt></Ntry>
+               </Stmt>
+               <Stmt>
+                 <Acct><Id><IBAN>DE02</IBAN></Id></Acct>
+                 <Ntry><Amt>7</Amt><CdtDbtInd>DBIT</CdtDbtInd><NtryRef>1</NtryRef>
+                   <BookgDt><Dt>2023-11-14</Dt></BookgDt></Ntry>
+               </Stmt>
+             </BkToCstmrStmt></Document>",
+        )
+        .unwrap();
+        let mut clients = StatementClients::default();
+        clients.accounts.insert("DE01".to_string(), 3);
+        let err = read_statement_records(&path, &clients).unwrap_err();
+        assert!(err.starts_with("no client for account `DE02`"));
+        clients.client = Some(4);
+        let records = read_statement_records(&path, &clients).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records = String::from_utf8(records).unwrap();
+        let clients: Vec<_> = records
+            .lines()
+            .skip(1)
+            .map(|record| record.split(',').take(2).collect::<Vec<_>>().join(","))
+            .collect();
+        assert_eq!(clients, ["deposit,3", "withdrawal,4"]);
+    }
+
+    #[test]
+    fn test_aml() {
+        // Tests that transactions above the AML thresholds are processed, and
+        // reported with their daily totals.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 900.0,  86400
+            deposit,    1, 2, 200.0,  86460
+            withdrawal, 1, 3, 1050.0, 86520
+            deposit,    2, 4, 2000.0, 86580
+            dispute,    2, 4,       , 86640
+            deposit,    1, 5, 100.0,  172800";
+        let options = Options {
+            aml: AmlThresholds {
+                single_amount: Some(Price4::new(1000, 0)),
+                daily_amount: Some(Price4::new(1000, 0)),
+            },
+            ..Options::default()
+        };
+        let mut report = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                aml_report: Some(&mut report),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("aml_report", String::from_utf8(report).unwrap());
+    }
+
+    #[test]
+    fn test_reversal() {
+        // Tests that a reversal undoes a deposit or withdrawal without holding its
+        // funds or freezing the account, and that it cannot be disputed after.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            withdrawal, 1, 2, 3.0
+            withdrawal, 1, 3, 3.0
+            reversal,   1, 3,
+            reversal,   1, 3,
+            dispute,    1, 3,
+            deposit,    1, 4, 2.0
+            dispute,    1, 4,
+            reversal,   1, 4,
+            reversal,   1, 5,
+            reversal,   2, 1,";
+        run_snapshot_test(input);
+    }
+
+    #[test]
+    fn test_escrow() {
+        // Tests that escrowed funds stay in the payer's total until they are
+        // released to the payee or refunded, and are output in a column.
+        let input = "
+            type,    client, tx, amount, payee
+            deposit, 1, 1, 10.0,
+            escrow,  1, 2, 4.0,  2
+            escrow,  1, 3, 5.0,  3
+            escrow,  1, 4, 2.0,  2
+            escrow,  1, 5, 1.0,
+            release, 1, 2,     ,
+            release, 1, 2,     ,
+            refund,  1, 3,     ,
+            dispute, 1, 2,     ,
+            escrow,  1, 6, 2.0,  3";
+        let options = Options {
+            escrow_column: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_balance_history() {
+        // Tests that each client's balances are written after every change, with
+        // the timestamps of the records, and not after failed records.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 10.0, 100
+            deposit,    2, 2, 5.0,  110
+            withdrawal, 1, 3, 4.0,  120
+            withdrawal, 2, 4, 9.0,  130
+            dispute,    1, 1,     , 140
+            deposit,    2, 5, 1.0,  150
+            chargeback, 1, 1,     , 160";
+        let options = Options {
+            balance_history: Some(HistoryInterval::EveryChange),
+            ..Options::default()
+        };
+        let mut history = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                balance_history: Some(&mut history),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("balance_history_csv", String::from_utf8(history).unwrap());
+    }
+
+    #[test]
+    fn test_ledger() {
+        // Tests that the balance effects of the processed transactions are written
+        // as a ledger-cli journal, and that failed records have none.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 10.0, 1700000000
+            withdrawal, 1, 2, 4.0,  1700000100
+            withdrawal, 1, 3, 40.0, 1700000200
+            dispute,    1, 1,     , 1700086400
+            chargeback, 1, 1,     , 1700086500";
+        let mut ledger = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                ledger: Some(&mut ledger),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("ledger_journal", String::from_utf8(ledger).unwrap());
+    }
+
+    #[test]
+    fn test_dispute_notes() {
+        // Tests that notes and evidence references are attached to open disputes
+        // and written to the dispute report.
+        let input = "
+            type,     client, tx, amount, note
+            deposit,  1, 1, 10.0,
+            deposit,  1, 2, 5.0,
+            note,     1, 1,     , customer says the card was stolen
+            dispute,  1, 1,     ,
+            dispute,  1, 2,     ,
+            note,     1, 1,     , customer says the card was stolen
+            evidence, 1, 1,     , https://docs.example.com/17
+            note,     1, 2,     ,
+            evidence, 1, 2,     ,\"doc 18, page 2\"
+            deposit,  1, 3, 1.0,
+            dispute,  1, 3,     ,
+            note,     1, 3,     , duplicate charge
+            resolve,  1, 3,     ,
+            note,     1, 3,     , resolved";
+        let mut report = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                dispute_report: Some(&mut report),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("dispute_report", String::from_utf8(report).unwrap());
+    }
+
+    #[test]
+    fn test_receivables() {
+        // Tests that a dispute taking the available funds negative opens a
+        // receivable, which later deposits reduce.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            withdrawal, 1, 2, 8.0
+            dispute,    1, 1,
+            deposit,    1, 3, 5.0
+            deposit,    2, 4, 5.0
+            withdrawal, 2, 5, 5.0
+            dispute,    2, 4,
+            deposit,    2, 6, 7.0";
+        let options = Options {
+            receivable_column: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_unfreeze_at() {
+        // Tests that a frozen account accepts deposits, and is unfrozen once they
+        // bring its total to zero, which is recorded in the audit log.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            withdrawal, 1, 2, 8.0
+            dispute,    1, 1,
+            chargeback, 1, 1,
+            withdrawal, 1, 3, 1.0
+            deposit,    1, 4, 5.0
+            deposit,    1, 5, 4.0
+            withdrawal, 1, 6, 1.0";
+        let options = Options {
+            unfreeze_at: Some(Price4::ZERO),
+            receivable_column: true,
+            ..Options::default()
+        };
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("unfreeze_at_audit_log", String::from_utf8(log).unwrap());
+    }
+
+    #[test]
+    fn test_dormant_accounts() {
+        // Tests that an account without transactions for the period is dormant,
+        // stays dormant after a deposit and rejects withdrawals until it is
+        // reactivated, and that dormancy is output.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 10, 0
+            deposit,    2, 2, 10, 50
+            deposit,    1, 3, 5, 100
+            withdrawal, 1, 4, 1, 110
+            reactivate, 1, 0, , 120
+            withdrawal, 1, 5, 1, 130
+            reactivate, 3, 0, , 140
+            deposit,    3, 6, 1, 150";
+        let options = Options {
+            dormant_after: NonZeroU64::new(100),
+            block_dormant_withdrawals: true,
+            dormant_column: true,
+            ..Options::default()
+        };
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!(
+            "dormant_accounts_audit_log",
+            String::from_utf8(log).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_chargeback_freeze() {
+        // Tests that chargebacks only freeze an account from the client's second
+        // one on.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10
+            deposit,    1, 2, 5
+            dispute,    1, 1,
+            chargeback, 1, 1,
+            deposit,    1, 3, 1
+            dispute,    1, 2,
+            chargeback, 1, 2,
+            deposit,    1, 4, 1";
+        let options = Options {
+            chargeback_freeze: FreezePolicy::After(NonZeroU32::new(2).unwrap()),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
+    }
+
+    #[test]
+    fn test_confirm_chargebacks() {
+        // Tests that chargebacks only take effect once confirmed: tx 1 is charged
+        // back after a withdrawal from the account it then freezes, tx 2 is resolved after its chargeback was requested, and the
+        // confirmation of tx 3 without a request is rejected.
+        let input = "
+            type,               client, tx, amount
+            deposit,            1, 1, 10.0
+            deposit,            2, 2, 10.0
+            deposit,            3, 3, 10.0
+            deposit,            1, 5, 5.0
+            dispute,            1, 1,
+            chargeback,         1, 1,
+            withdrawal,         1, 4, 1.0
+            chargeback_confirm, 1, 1,
+            dispute,            2, 2,
+            chargeback_request, 2, 2,
+            resolve,            2, 2,
+            dispute,            3, 3,
+            chargeback_confirm, 3, 3,";
+        let options = Options {
+            confirm_chargebacks: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_fee_schedule() {
+        // Tests that deposits and withdrawals are charged the fee of their tier,
+        // that a client's segment has its own fees, and that a withdrawal is
+        // rejected if its fee is not available.
+        let schedule = FeeSchedule::from_toml(
+            "
+            [segments]
+            business = [2]
+
+            [[fee]]
+            operation = \"deposit\"
+            tiers = [{ from = 1000, percent = 0.1 }]
+
+            [[fee]]
+            operation = \"withdrawal\"
+            tiers = [{ fixed = 0.5 }, { from = 100, fixed = 0.5, percent = 1 }]
+
+            [[fee]]
+            operation = \"withdrawal\"
+            segment = \"business\"
+            tiers = [{ fixed = 2 }]
+            ",
+        )
+        .unwrap();
+        let options = Options {
+            fee_schedule: Some(schedule),
+            ..Options::default()
+        };
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 2000.0
+            withdrawal, 1, 2, 10.0
+            withdrawal, 1, 3, 200.0
+            deposit,    2, 4, 100.0
+            withdrawal, 2, 5, 10.0
+            withdrawal, 2, 6, 88.0";
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("fee_schedule_audit_log", String::from_utf8(log).unwrap());
+    }
+
+    #[test]
+    fn test_tags() {
+        // Tests that the tags of deposits and withdrawals, also in a batch, are
+        // added to the audit log entries of their fees, and that tags of other
+        // records are ignored.
+        let schedule = FeeSchedule::from_toml(
+            "
+            [[fee]]
+            operation = \"withdrawal\"
+            tiers = [{ fixed = 0.5 }]
+            ",
+        )
+        .unwrap();
+        let options = Options {
+            fee_schedule: Some(schedule),
+            ..Options::default()
+        };
+        let input = "
+            type,       client, tx,  amount, tag
+            deposit,    1,      1,   100.0,  payroll
+            withdrawal, 1,      2,   10.0,   rent
+            withdrawal, 1,      3,   5.0,
+            begin,      1,      100, ,
+            withdrawal, 1,      4,   1.0,    refunds
+            commit,     1,      100, ,
+            dispute,    1,      1,   ,       disputes";
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("tags_audit_log", String::from_utf8(log).unwrap());
     }
 
-    // 2) Get all client account infos.
-    let mut account_infos = Vec::new();
-    for (client_id, account) in transaction_processor.accounts().iter() {
-        account_infos.push(AccountInfo {
-            client_id: *client_id,
-            available_funds: account.available_funds(),
-            held_funds: account.held_funds(),
-            total_funds: account.total_funds(),
-            is_frozen: account.is_frozen(),
-        });
+    #[test]
+    fn test_settlement_report() {
+        // Tests that the settlement report sums each client's movement of funds
+        // over the run, leaving out failed records.
+        let input = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            deposit,    2, 2, 5.0
+            withdrawal, 1, 3, 4.0
+            withdrawal, 2, 4, 9.0
+            dispute,    2, 2,
+            dispute,    1, 1,
+            resolve,    1, 1,
+            chargeback, 2, 2,";
+        let mut report = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                settlement_report: Some(&mut report),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("settlement_report_csv", String::from_utf8(report).unwrap());
     }
-    // Sort the account infos by client id so the output is deterministic.
-    account_infos.sort_by_key(|account| account.client_id);
 
-    // 3) Print the account infos to outstream in csv format.
-    let mut writer = csv::Writer::from_writer(outstream);
-    for account_info in account_infos.iter() {
-        if let Err(e) = writer.serialize(account_info) {
-            writeln!(errstream, "serialize failed: {}", e).expect("write failed");
+    #[test]
+    fn test_tag_report() {
+        // Tests that the tag report sums the tagged deposits and withdrawals per
+        // client and tag, then per tag, leaving out untagged and reversed ones.
+        let input = "
+            type,       client, tx, amount, tag
+            deposit,    1,      1,  100.0,  payroll
+            deposit,    2,      2,  50.0,   payroll
+            withdrawal, 1,      3,  10.0,   rent
+            withdrawal, 1,      4,  5.0,
+            deposit,    2,      5,  20.0,   refunds
+            reversal,   2,      5,  ,";
+        for format in [ReportFormat::Csv, ReportFormat::Json] {
+            let options = Options {
+                tag_report_format: format,
+                ..Options::default()
+            };
+            let mut report = Vec::new();
+            run_inputs_snapshot_test_with(
+                &[input],
+                Snapshot::default(),
+                &options,
+                &RunControl::new(),
+                Stores {
+                    tag_report: Some(&mut report),
+                    ..Stores::default()
+                },
+            );
+            let name = format!("tag_report_{:?}", format).to_lowercase();
+            insta::assert_snapshot!(name, String::from_utf8(report).unwrap());
         }
     }
-    writer.flush().expect("write failed");
-    errstream.flush().expect("write failed");
-}
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    let filepath = args
-        .get(1)
-        .expect("Usage: ./transactions <csv filepath with transactions>");
-    let file = std::fs::File::open(filepath).expect("could not open csv file");
-    run(file, std::io::stdout(), std::io::stderr());
-}
+    #[test]
+    fn test_watch_config() {
+        // Tests that a changed fee schedule is reloaded before the next record and
+        // recorded in the audit log, and that an invalid one is reported and the
+        // current one kept.
+        let path = std::env::temp_dir().join(format!("watched-fees-{}.toml", std::process::id()));
+        let fees = |fixed: &str| {
+            format!(
+                "[[fee]]\noperation = \"deposit\"\ntiers = [{{ fixed = {} }}]\n",
+                fixed
+            )
+        };
+        std::fs::write(&path, fees("1")).unwrap();
+        let options = Options {
+            fee_schedule: Some(FeeSchedule::load(&path).unwrap()),
+            watch_config: Some(WatchedConfig {
+                interval: Duration::ZERO,
+                files: vec![(PolicyFile::FeeSchedule, path.clone())],
+            }),
+            ..Options::default()
+        };
+        let inputs = [
+            "type, client, tx, amount\ndeposit, 1, 1, 10.0",
+            "type, client, tx, amount\ndeposit, 1, 2, 10.0",
+            "type, client, tx, amount\ndeposit, 1, 3, 10.0",
+        ];
+        let mut outstream = Vec::new();
+        let mut errstream = Vec::new();
+        let mut log = Vec::new();
+        // Each file is only opened once the previous one is processed.
+        let instreams = inputs.iter().enumerate().map(|(i, input)| {
+            match i {
+                1 => std::fs::write(&path, fees("2")).unwrap(),
+                2 => std::fs::write(&path, fees("two")).unwrap(),
+                _ => {}
+            }
+            input.as_bytes()
+        });
+        run(
+            instreams,
+            &mut outstream,
+            &mut errstream,
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(
+            String::from_utf8(outstream).unwrap(),
+            "client,available,held,total,locked\n1,25,0,25,false\n"
+        );
+        assert!(String::from_utf8(errstream)
+            .unwrap()
+            .starts_with(&format!("reload of {} failed: ", path.display())));
+        insta::assert_snapshot!("watch_config_audit_log", String::from_utf8(log).unwrap());
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use std::io::BufWriter;
+    #[test]
+    fn test_convert() {
+        // Tests that conversions move funds between the base currency and other
+        // currencies at the configured rates less the spread, and that a
+        // conversion without a valid rate or enough funds is rejected.
+        let path = std::env::temp_dir().join(format!("fx-rates-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "
+            base = \"USD\"
+            spread = 0.01
 
-    fn run_snapshot_test(input: &str) {
-        let mut outstream = BufWriter::new(Vec::new());
-        let mut errstream = BufWriter::new(Vec::new());
-        run(input.as_bytes(), &mut outstream, &mut errstream);
-        let outstring = String::from_utf8(outstream.into_inner().unwrap()).unwrap();
-        let errstring = String::from_utf8(errstream.into_inner().unwrap()).unwrap();
-        let all_output = format!("{}Stderr:\n{}", outstring, errstring);
-        insta::assert_snapshot!(all_output);
+            [[rate]]
+            from = \"USD\"
+            to = \"EUR\"
+            rate = 0.9
+            valid_until = 100
+
+            [[rate]]
+            from = \"USD\"
+            to = \"GBP\"
+            rate = 0.8
+            valid_from = 200
+            ",
+        )
+        .unwrap();
+        let options = Options {
+            fx_rates: Some(load_fx_rates(&path).unwrap()),
+            ..Options::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+        let input = "
+            type,    client, tx, amount, from_ccy, to_ccy, timestamp
+            deposit, 1, 1, 100.0, ,    ,    10
+            convert, 1, 2, 50.0, USD, EUR, 20
+            convert, 1, 3, 9.0,  EUR, USD, 30
+            convert, 1, 4, 40.0, EUR, USD, 40
+            convert, 1, 5, 1.0,  USD, GBP, 50
+            convert, 1, 6, 1.0,  USD, EUR, 100
+            convert, 1, 7, 10.0, USD, GBP, 200
+            deposit, 2, 8, 1.0, ,    ,    300";
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_serde() {
-        // Tests that transaction type, integers, optional prices, booleans are correctly
-        // serialized and deserialized.
+    fn test_frozen_disputes() {
+        // Tests that the open disputes of an account frozen by a chargeback can
+        // still be resolved and charged back, while other transactions are rejected.
         let input = "
             type,       client, tx, amount
-            deposit,    1, 3, 1
-            deposit,    1, 5, .5
-            deposit,    1, 6, 0.2
-            withdrawal, 1, 4, .0001
-            dispute,    1, 5,
-            resolve,    1, 5,
-            dispute,    1, 4,
-            dispute,    1, 6,
-            chargeback, 1, 4,
-            deposit,    2, 15, 100.03
-            dispute,    2, 15,
-            chargeback, 2, 15,";
-        run_snapshot_test(input);
+            deposit,    1, 1, 10.0
+            deposit,    1, 2, 5.0
+            deposit,    1, 3, 2.0
+            dispute,    1, 1,
+            dispute,    1, 2,
+            chargeback, 1, 1,
+            resolve,    1, 2,
+            dispute,    1, 3,
+            chargeback, 1, 3,
+            deposit,    1, 4, 1.0";
+        let options = Options {
+            frozen_disputes: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_unknown_transaction_id() {
-        // Tests that disputes, resolves, and chargebacks for unknown clients / transactions
-        // are ignored.
+    fn test_signed_amounts() {
+        // Tests that a negative deposit is a withdrawal, e.g. a refund, and a
+        // negative withdrawal a deposit, and that both can be disputed.
         let input = "
             type,       client, tx, amount
-            deposit,    1, 5, 1.5
-            dispute,    1, 6,
-            chargeback, 1, 6,
-            resolve,    1, 6,
-            deposit,    1, 6, 2.0
-            dispute,    1, 6,
-            dispute,    2, 5,
-            chargeback, 2, 5,
-            resolve,    2, 5,";
-        run_snapshot_test(input);
+            deposit,    1, 1, 10.0
+            deposit,    1, 2, -3.0
+            withdrawal, 1, 3, -1.5
+            deposit,    1, 4, -20.0
+            dispute,    1, 3,";
+        let options = Options {
+            signed_amounts: true,
+            ..Options::default()
+        };
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_duplicate_tx_id_rejected() {
-        // Tests that duplicate transaction ids for a client are rejected
+    fn test_permissions() {
+        // Tests that only the `risk` role may charge back, and that the rejected
+        // chargebacks are recorded in the audit log.
+        let path = std::env::temp_dir().join(format!("roles-{}.toml", std::process::id()));
+        std::fs::write(&path, "[roles]\nrisk = [\"chargeback\"]\n").unwrap();
         let input = "
-            type, client, tx, amount
-            deposit,    1, 1, 1.0
-            withdrawal, 1, 1, 0.5
-            deposit,    1, 1, 2.0";
-        run_snapshot_test(input);
+            type,       client, tx, amount, role
+            deposit,    1, 1, 10.0,
+            deposit,    2, 2, 10.0,
+            dispute,    1, 1,     , ops
+            chargeback, 1, 1,     , ops
+            chargeback, 1, 1,     ,
+            dispute,    2, 2,     ,
+            chargeback, 2, 2,     , risk";
+        let options = Options {
+            permissions: Some(read_toml(&path).unwrap()),
+            ..Options::default()
+        };
+        std::fs::remove_file(&path).unwrap();
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("permissions_audit_log", String::from_utf8(log).unwrap());
     }
 
     #[test]
-    fn test_deposit_withdrawal() {
-        // Tests withdrawing tests the exact available balance works and withdrawing
-        // more than available fails.
+    fn test_min_balance() {
+        // Tests that withdrawals must leave a client's minimum balance, which a
+        // client's own minimum overrides.
+        let path = std::env::temp_dir().join(format!("min-balances-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "
+            [[minimum]]
+            client = 2
+            balance = 1.5
+            ",
+        )
+        .unwrap();
+        let mut options = Options::default();
+        options.min_balance.default = Some(Price4::new(5, 0));
+        load_min_balances(&mut options.min_balance, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
         let input = "
             type,       client, tx, amount
-            deposit,    1, 100, 1.3
-            deposit,    1, 1, 0.2
-            withdrawal, 1, 2, .0001
-            withdrawal, 1, 3, 1.4999
-            withdrawal, 1, 4, .0001
-            deposit,    1, 5, 2.0
-            withdrawal, 1, 6, 2.0001";
-        run_snapshot_test(input);
+            deposit,    1, 1, 10.0
+            deposit,    2, 2, 10.0
+            withdrawal, 1, 3, 6.0
+            withdrawal, 1, 4, 5.0
+            withdrawal, 2, 5, 8.0
+            withdrawal, 2, 6, 1.0";
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_dispute() {
-        // Tests that disputes result in balance being held which
-        // cannot be used for withdrawing.
+    fn test_balance_cap() {
+        // Tests that deposits above a client's balance cap are partially accepted,
+        // with the excess written to the audit log.
+        let path = std::env::temp_dir().join(format!("balance-caps-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "
+            [[cap]]
+            client = 2
+            balance = 100
+            ",
+        )
+        .unwrap();
+        let mut options = Options::default();
+        options.balance_cap.default = Some(Price4::new(10, 0));
+        options.balance_cap.mode = CapMode::Partial;
+        load_balance_caps(&mut options.balance_cap, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
         let input = "
             type,       client, tx, amount
+            deposit,    1, 1, 8.0
+            deposit,    1, 2, 5.0
+            deposit,    1, 3, 1.0
+            withdrawal, 1, 4, 3.0
             deposit,    1, 5, 1.0
-            deposit,    1, 6, 2
-            dispute,    1, 5,
-            withdrawal, 1, 7, 2.5
-            withdrawal, 1, 8, 1.5";
-        run_snapshot_test(input);
+            deposit,    2, 6, 50.0";
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("balance_cap_audit_log", String::from_utf8(log).unwrap());
     }
 
     #[test]
-    fn test_negative_available_on_dispute() {
-        // Tests that disputes can result in negative available
+    fn test_daily_withdrawal_limit() {
+        // Tests that withdrawals are limited per day, with days starting at the
+        // configured time.
+        let mut options = Options::default();
+        options.withdrawal_limit.default = Some(Price4::new(100, 0));
+        options.withdrawal_limit.day_start = 6 * 60 * 60;
         let input = "
-            type,       client, tx, amount
-            deposit,    1, 5, 10
-            deposit,    1, 6, 20
-            withdrawal, 1, 7, 25
-            dispute,    1, 6,";
-        run_snapshot_test(input);
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 500.0, 1700000000
+            withdrawal, 1, 2, 60.0,  1700000000
+            withdrawal, 1, 3, 50.0,  1700001000
+            withdrawal, 1, 4, 40.0,  1700002000
+            withdrawal, 1, 5, 90.0,  1700028000
+            withdrawal, 1, 6, 20.0,  1700029000";
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_negative_held_on_dispute() {
-        // Tests that disputes can result in negative held
+    fn test_adjustment() {
+        // Tests that adjustments ignore the balance and a frozen account, need a
+        // reason code, and are written to the audit log.
+        let input = "
+            type,       client, tx, amount, reason
+            deposit,    1, 1, 10.0,
+            withdrawal, 1, 2, 4.0,
+            dispute,    1, 1,     ,
+            chargeback, 1, 1,     ,
+            adjustment, 1, 3, -5.0, 12
+            adjustment, 1, 4, 2.5,
+            adjustment, 2, 5, 1.0,  3
+            dispute,    2, 5,     ,";
+        let mut log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[input],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!("audit_log", String::from_utf8(log).unwrap());
+    }
+
+    #[test]
+    fn test_rules() {
+        // Tests that rules reject, flag and hold transactions, freeze accounts,
+        // and that their hits are reported.
         let input = "
             type,       client, tx, amount
-            deposit,    1, 5, 10
-            withdrawal, 1, 6, 5
-            dispute,    1, 6,";
-        run_snapshot_test(input);
+            deposit,    1, 1, 500.0
+            deposit,    2, 2, 20.0
+            withdrawal, 2, 3, 15.0
+            resolve,    1, 1,
+            withdrawal, 2, 4, 1.0
+            deposit,    3, 5, 1.0
+            deposit,    3, 6, 1.0";
+        let rules = RuleSet::from_toml(
+            r#"
+            [[rule]]
+            name = "large-deposit"
+            action = "hold"
+            kind = ["deposit"]
+            min_amount = 100
+
+            [[rule]]
+            name = "large-withdrawal"
+            action = "reject"
+            kind = ["withdrawal"]
+            min_amount = 10
+
+            [[rule]]
+            name = "low-balance"
+            action = "flag"
+            kind = ["withdrawal"]
+            available_below = 30
+
+            [[rule]]
+            name = "blocked-client"
+            action = "freeze"
+            clients = [3]
+            "#,
+        )
+        .unwrap();
+        let options = Options {
+            rules: Some(rules),
+            ..Options::default()
+        };
+        run_snapshot_test_with(input, &options, &RunControl::new());
     }
 
     #[test]
-    fn test_cannot_withdraw_on_negative_balance() {
-        // Tests that withdrawing when balance is negative fails, but depositing still works
+    fn test_multiple_inputs() {
+        // Tests that multiple inputs, each with their own header, are processed in
+        // order against the same accounts.
+        let day_1 = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            deposit,    2, 2, 2.0";
+        let day_2 = "
+            type,       client, tx, amount
+            withdrawal, 1, 3, 0.5
+            dispute,    2, 2,";
+        run_inputs_snapshot_test_with(
+            &[day_1, day_2],
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_stop_after() {
+        // Tests that the state as of a record can be rebuilt by stopping after it,
+        // also when resuming from an earlier snapshot of the same input.
         let input = "
             type,       client, tx, amount
-            deposit,    1, 5, 10
-            deposit,    1, 6, 20
-            withdrawal, 1, 7, 25
-            dispute,    1, 6,
-            withdrawal, 1, 8, 4
-            deposit,    1, 9, 5
-            withdrawal, 1,10, 3
-            deposit,    1,11, 25
-            withdrawal, 1,12, 5";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            deposit,    2, 2, 2.0
+            withdrawal, 1, 3, 0.5
+            dispute,    2, 2,
+            deposit,    1, 4, 3.0";
+        let stop_after = |records| Options {
+            stop_after: Some(records),
+            ..Options::default()
+        };
+        let start = run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &stop_after(2),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        assert_eq!(start.records_read, 2);
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &stop_after(4),
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_resolve() {
-        // Tests that resolves result in held money put back in available.
+    fn test_resume() {
+        // Tests that resuming from a snapshot skips the records it already read,
+        // including ones that failed, and keeps the transaction history for disputes.
+        let interrupted = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            deposit,    2, 3, 2.0";
+        let full = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            deposit,    2, 3, 2.0
+            dispute,    1, 1,
+            deposit,    2, 4, 0.5";
+        let start = run(
+            std::iter::once(interrupted.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        assert_eq!(start.records_read, 3);
+        run_inputs_snapshot_test_with(
+            &[full],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_continue_from() {
+        // Tests that a run over the next day's input continues from the previous
+        // day's snapshot, processing all of its records, including late disputes
+        // of the previous day's transactions.
+        let previous_day = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            deposit,    2, 2, 2.0
+            withdrawal, 1, 3, 4.0";
+        let next_day = "
+            type,       client, tx, amount
+            dispute,    1, 1,
+            deposit,    2, 4, 0.5
+            chargeback, 1, 1,";
+        let snapshot = run(
+            std::iter::once(previous_day.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let path = std::env::temp_dir().join(format!("previous-day-{}.json", std::process::id()));
+        snapshot.save(&path).unwrap();
+        let start = continue_from(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(start.records_read, 0);
+        run_inputs_snapshot_test_with(
+            &[next_day],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_close_period() {
+        // Tests that after closing the previous day's period, disputes of its
+        // transactions are rejected and recorded in the audit log, while the new
+        // day's transactions can still be disputed.
+        let previous_day = "
+            type,       client, tx, amount
+            deposit,    1, 1, 10.0
+            withdrawal, 1, 2, 4.0";
+        let next_day = "
+            type,       client, tx, amount
+            dispute,    1, 1,
+            deposit,    1, 3, 1.0
+            dispute,    1, 3,
+            reversal,   1, 2,";
+        let mut start = run(
+            std::iter::once(previous_day.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        start.records_read = 0;
+        start.processor.close_period();
+        let mut audit_log = Vec::new();
+        run_inputs_snapshot_test_with(
+            &[next_day],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                audit_log: Some(&mut audit_log),
+                ..Stores::default()
+            },
+        );
+        insta::assert_snapshot!(
+            "close_period_audit_log",
+            String::from_utf8(audit_log).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_checkpoints() {
+        // Tests that checkpoints are written during the run and can be resumed from.
+        let dir = std::env::temp_dir().join(format!("checkpoints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
         let input = "
             type,       client, tx, amount
-            deposit,    1, 5, 2.0
-            dispute,    1, 5,
-            withdrawal, 1, 6, 1.0
-            resolve,    1, 5,
-            withdrawal, 1, 7, 0.5";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            deposit,    1, 2, 2.0
+            deposit,    1, 3, 3.0
+            deposit,    1, 4, 4.0
+            deposit,    1, 5, 5.0";
+        let options = Options {
+            checkpoint_dir: Some(dir.clone()),
+            checkpoint_every: NonZeroU64::new(2),
+            checkpoint_keep: Some(1),
+            ..Options::default()
+        };
+        let snapshot = run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+        assert_eq!(snapshot.records_read, 5);
+        let checkpoint = Snapshot::load(&dir.join("checkpoint-4.json")).unwrap();
+        assert!(!dir.join("checkpoint-2.json").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(checkpoint.records_read, 4);
+        run_inputs_snapshot_test_with(
+            &[input],
+            checkpoint,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
     }
 
     #[test]
-    fn test_multiple_dispute_disallowed() {
-        // Tests that multiple disputes are disallowed for a transaction,
-        // even if the transaction was resolved.
+    fn test_pause_with_snapshot() {
+        // Tests that pausing with a snapshot writes a checkpoint before blocking.
+        let dir = std::env::temp_dir().join(format!("pause-checkpoints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
         let input = "
             type,       client, tx, amount
-            deposit,    1, 5, 2.0
-            dispute,    1, 5,
-            dispute,    1, 5,
-            resolve,    1, 5,
-            dispute,    1, 5,
-            withdrawal, 1, 6, 0.5";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            deposit,    1, 2, 2.0";
+        let options = Options {
+            checkpoint_dir: Some(dir.clone()),
+            checkpoint_every: NonZeroU64::new(1000),
+            ..Options::default()
+        };
+        let control = std::sync::Arc::new(RunControl::new());
+        control.pause_with_snapshot();
+        let resumer = {
+            let control = control.clone();
+            let checkpoint = dir.join("checkpoint-0.json");
+            std::thread::spawn(move || {
+                while !checkpoint.exists() {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+                control.resume();
+            })
+        };
+        let snapshot = run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &options,
+            &control,
+            Stores::default(),
+        );
+        resumer.join().unwrap();
+        let checkpoint = Snapshot::load(&dir.join("checkpoint-0.json")).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(checkpoint.records_read, 0);
+        assert_eq!(snapshot.records_read, 2);
+        assert!(!control.take_snapshot_request());
     }
 
     #[test]
-    fn test_chargeback() {
-        // Tests that chargebacks result in frozen accounts
-        // where no more transactions are allowed.
+    fn test_health() {
+        // Tests that the run reports its progress, checkpoints and errors.
+        let dir = std::env::temp_dir().join(format!("health-checkpoints-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
         let input = "
             type,       client, tx, amount
-            deposit,    1, 4, 1.0
-            deposit,    1, 5, 2.0
-            dispute,    1, 5,
-            chargeback, 1, 5,
-            withdrawal, 1, 6, 0.5
-            deposit,    1, 7, 0.1
-            dispute,    1, 7,
-            resolve,    1, 7,
-            deposit,    2, 8, 1.0";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 2.0
+            deposit,    1, 3, 3.0";
+        let options = Options {
+            checkpoint_dir: Some(dir.clone()),
+            checkpoint_every: NonZeroU64::new(2),
+            ..Options::default()
+        };
+        let control = RunControl::new();
+        run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &options,
+            &control,
+            Stores::default(),
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+        let health = control.health();
+        assert_eq!(health.records_read, 3);
+        assert_eq!(health.queue_depth, 0);
+        assert!(health.snapshot_age.is_some());
+        assert!(health
+            .last_error
+            .as_ref()
+            .unwrap()
+            .contains("withdrawal client=1 tx=2"));
+        assert!(health.is_ready() && !health.paused);
     }
 
     #[test]
-    fn test_negative_available_on_chargeback() {
-        // Tests that chargebacks can result in negative balances.
+    fn test_diff() {
+        // Tests the summary of the changes between two snapshots of the same input.
         let input = "
             type,       client, tx, amount
-            deposit,    1, 3, 0.7
-            deposit,    1, 4, 0.3
-            deposit,    1, 5, 2.0
-            withdrawal, 1, 6, 2.5
-            dispute,    1, 4,
-            resolve,    1, 4,
-            withdrawal, 1, 7, 0.1
-            dispute,    1, 3,
-            dispute,    1, 5,
-            chargeback, 1, 5,";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            deposit,    2, 2, 2.0
+            deposit,    3, 3, 3.0
+            dispute,    1, 1,
+            chargeback, 1, 1,
+            withdrawal, 2, 4, 0.5
+            dispute,    3, 3,
+            deposit,    4, 5, 1.0";
+        let lines: Vec<&str> = input.lines().collect();
+        let old_input = lines[..5].join("\n");
+        let old = run(
+            std::iter::once(old_input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let new = run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let delta = SnapshotDelta::between(&old, &new).unwrap();
+        let mut outstream = Vec::new();
+        write_delta(&delta, &mut outstream);
+        insta::assert_snapshot!(snapshot_name(), String::from_utf8(outstream).unwrap());
     }
 
     #[test]
-    fn test_multiple_clients() {
-        // Tests using multiple clients
+    fn test_client_statement() {
+        // Tests a client's statement for a range of dates and of its transactions,
+        // with the balances before the range.
+        let input = "
+            type,       client, tx, amount, timestamp
+            deposit,    1, 1, 10.0, 1700000000
+            deposit,    2, 2, 5.0,  1700000100
+            withdrawal, 1, 3, 4.0,  1700086400
+            dispute,    1, 1,     , 1700086500
+            resolve,    1, 1,     , 1700172800";
+        let options = Options {
+            balance_history: Some(HistoryInterval::EveryChange),
+            ..Options::default()
+        };
+        let snapshot = run(
+            std::iter::once(input.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &options,
+            &RunControl::new(),
+            Stores::default(),
+        );
+        let statement = |from: &str, to: &str, format| {
+            let mut output = Vec::new();
+            write_statement(
+                &snapshot.processor,
+                ClientId::from(1),
+                from.parse().ok(),
+                to.parse().ok(),
+                format,
+                &mut output,
+            )
+            .unwrap();
+            String::from_utf8(output).unwrap()
+        };
+        insta::assert_snapshot!(
+            "client_statement_text",
+            statement("2023-11-15", "2023-11-15", StatementFormat::Text)
+        );
+        insta::assert_snapshot!(
+            "client_statement_csv",
+            statement("", "3", StatementFormat::Csv)
+        );
+        assert!(write_statement(
+            &snapshot.processor,
+            ClientId::from(3),
+            None,
+            None,
+            StatementFormat::Csv,
+            std::io::sink()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_state_store() {
+        // Tests that a run continues from the state persisted by an interrupted run,
+        // including the records that failed.
         let input = "
             type,       client, tx, amount
-            withdrawal, 2, 1, 10
-            deposit,    1, 2, 100
-            deposit,    1,10, 50
-            withdrawal, 2, 3, 10
-            deposit,    2, 4, 200
-            withdrawal, 2, 5, 10
-            dispute,    1, 5,
-            resolve,    1, 5,
-            deposit,    3, 6, 75
-            deposit,    3, 7, 10
-            withdrawal, 3, 8, 80
-            dispute,    2, 6,
-            dispute,    3, 6,
-            chargeback, 3, 6,
-            dispute,    1,10,";
-        run_snapshot_test(input);
+            deposit,    1, 1, 1.0
+            withdrawal, 1, 2, 5.0
+            deposit,    2, 3, 2.0
+            dispute,    1, 1,
+            deposit,    2, 4, 0.5";
+        let mut store = MemoryStore::new();
+        // The first run only gets to read three records.
+        let interrupted = input.lines().take(5).collect::<Vec<_>>().join("\n");
+        run(
+            std::iter::once(interrupted.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                state: Some(&mut store),
+                ..Stores::default()
+            },
+        );
+        let start = Snapshot::load_from(&store).unwrap();
+        assert_eq!(start.records_read, 3);
+        run_inputs_snapshot_test_with(
+            &[input],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores::default(),
+        );
+    }
+
+    #[test]
+    fn test_dedup_store() {
+        // Tests that redelivered records are filtered out, both within a run and
+        // across runs sharing a dedup store, while a dispute of a deposit is not
+        // mistaken for a redelivery of it.
+        let day_1 = "
+            type,       client, tx, amount
+            deposit,    1, 1, 1.0
+            deposit,    1, 2, 2.0
+            deposit,    1, 1, 1.0
+            dispute,    1, 1,
+            dispute,    1, 1,";
+        let day_2 = "
+            type,       client, tx, amount
+            deposit,    1, 2, 2.0
+            withdrawal, 1, 3, 0.5
+            resolve,    1, 1,";
+        let mut dedup = MemoryDedupStore::new(Duration::from_secs(60));
+        let start = run(
+            std::iter::once(day_1.as_bytes()),
+            std::io::sink(),
+            std::io::sink(),
+            Snapshot::default(),
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                dedup: Some(&mut dedup),
+                ..Stores::default()
+            },
+        );
+        // The second run reads a new input, so it starts at its first record.
+        let start = Snapshot {
+            records_read: 0,
+            ..start
+        };
+        run_inputs_snapshot_test_with(
+            &[day_2],
+            start,
+            &Options::default(),
+            &RunControl::new(),
+            Stores {
+                dedup: Some(&mut dedup),
+                ..Stores::default()
+            },
+        );
+    }
+
+    #[test]
+    fn test_idempotency_keys() {
+        // Tests that retries of a submission are filtered out by their idempotency
+        // key, whether or not they reuse its transaction id, without a dedup store.
+        run_snapshot_test(
+            "
+            type,       client, tx, amount, idempotency_key
+            deposit,    1, 1, 5.0, a1
+            deposit,    1, 2, 5.0, a1
+            withdrawal, 1, 3, 1.0, b2
+            withdrawal, 1, 3, 1.0, b2
+            withdrawal, 1, 4, 1.0,
+            deposit,    1, 5, 2.0, c3",
+        );
     }
 }
@@ -0,0 +1,159 @@
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// A list of input files, in the order they should be processed, with the
+/// SHA-256 checksum each file is expected to have.
+///
+/// The format is the one written by `sha256sum`: one `<hex checksum>  <path>` line
+/// per file. Relative paths are resolved against the manifest's directory. Empty
+/// lines and lines starting with `#` are ignored.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Manifest {
+    pub entries: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub path: PathBuf,
+    /// The expected checksum as lowercase hex.
+    pub sha256: String,
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestError {
+    #[error("could not read {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("malformed manifest line {0}: expected `<sha256>  <path>`")]
+    Malformed(usize),
+    #[error("checksum mismatch for {path:?} (expected {expected}, found {actual})")]
+    ChecksumMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Manifest, ManifestError> {
+        let contents =
+            std::fs::read_to_string(path).map_err(|e| ManifestError::Io(path.to_owned(), e))?;
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        Manifest::parse(&contents, base_dir)
+    }
+
+    pub fn parse(contents: &str, base_dir: &Path) -> Result<Manifest, ManifestError> {
+        let mut entries = Vec::new();
+        for (index, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let malformed = || ManifestError::Malformed(index + 1);
+            let (sha256, path) = line.split_once(char::is_whitespace).ok_or_else(malformed)?;
+            // `sha256sum` marks files hashed in binary mode with a leading `*`.
+            let path = path.trim_start().trim_start_matches('*');
+            let is_hex = sha256.len() == 64 && sha256.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_hex || path.is_empty() {
+                return Err(malformed());
+            }
+            entries.push(ManifestEntry {
+                path: base_dir.join(path),
+                sha256: sha256.to_ascii_lowercase(),
+            });
+        }
+        Ok(Manifest { entries })
+    }
+}
+
+impl ManifestEntry {
+    /// Checks that the file's contents match the expected checksum.
+    pub fn verify(&self) -> Result<(), ManifestError> {
+        let file =
+            std::fs::File::open(&self.path).map_err(|e| ManifestError::Io(self.path.clone(), e))?;
+        let actual = sha256_hex(file).map_err(|e| ManifestError::Io(self.path.clone(), e))?;
+        if actual != self.sha256 {
+            return Err(ManifestError::ChecksumMismatch {
+                path: self.path.clone(),
+                expected: self.sha256.clone(),
+                actual,
+            });
+        }
+        Ok(())
+    }
+}
+
+/// Returns the SHA-256 checksum of everything in `reader` as lowercase hex.
+pub fn sha256_hex<R: Read>(mut reader: R) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+
+    #[test]
+    fn test_sha256_hex() {
+        assert_eq!(sha256_hex("abc".as_bytes()).unwrap(), ABC_SHA256);
+    }
+
+    #[test]
+    fn test_parse() {
+        let contents = format!(
+            "# daily exports\n{}  day1.csv\n\n{} *sub/day2.csv\n",
+            ABC_SHA256,
+            ABC_SHA256.to_uppercase()
+        );
+        let manifest = Manifest::parse(&contents, Path::new("in")).unwrap();
+        assert_eq!(
+            manifest.entries,
+            vec![
+                ManifestEntry {
+                    path: PathBuf::from("in/day1.csv"),
+                    sha256: ABC_SHA256.to_string(),
+                },
+                ManifestEntry {
+                    path: PathBuf::from("in/sub/day2.csv"),
+                    sha256: ABC_SHA256.to_string(),
+                },
+            ]
+        );
+        assert!(Manifest::parse("abc  day1.csv", Path::new("")).is_err());
+        assert!(Manifest::parse(ABC_SHA256, Path::new("")).is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let path = std::env::temp_dir().join(format!("manifest-test-{}.csv", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let mut entry = ManifestEntry {
+            path: path.clone(),
+            sha256: ABC_SHA256.to_string(),
+        };
+        assert!(entry.verify().is_ok());
+        std::fs::write(&path, "ab").unwrap();
+        assert!(matches!(
+            entry.verify(),
+            Err(ManifestError::ChecksumMismatch { .. })
+        ));
+        std::fs::remove_file(&path).unwrap();
+        entry.path = path;
+        assert!(matches!(entry.verify(), Err(ManifestError::Io(..))));
+    }
+}
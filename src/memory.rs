@@ -0,0 +1,122 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{DisputeNote, FundTransaction, JournalEntry, TransactionProcessor};
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::mem::{size_of, size_of_val};
+use serde::Serialize;
+
+/// An estimate of the heap memory used by a `TransactionProcessor`, in bytes.
+/// Collections are counted by their capacity and the size of their elements, so
+/// allocator overhead is not included.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    /// The accounts: their funds, holds, receivables, balances and balance
+    /// histories.
+    pub accounts: usize,
+    /// The transactions of the accounts, including their dispute notes.
+    pub transactions: usize,
+    /// The authorization expiries and the scheduled transactions.
+    pub indices: usize,
+    /// The audit log and the journal, until they are taken.
+    pub logs: usize,
+}
+
+impl MemoryStats {
+    pub fn total(&self) -> usize {
+        self.accounts + self.transactions + self.indices + self.logs
+    }
+}
+
+/// The bytes of a hash map's table: its entries and a control byte per entry.
+pub(crate) fn hash_map_bytes<K, V>(map: &HashMap<K, V>) -> usize {
+    map.capacity() * (size_of::<(K, V)>() + 1)
+}
+
+/// The bytes of a B-tree map, whose nodes are assumed to be two thirds full.
+fn btree_map_bytes<K, V>(map: &BTreeMap<K, V>) -> usize {
+    map.len() * size_of::<(K, V)>() * 3 / 2
+}
+
+fn btree_set_bytes<T>(set: &BTreeSet<T>) -> usize {
+    set.len() * size_of::<T>() * 3 / 2
+}
+
+pub(crate) fn vec_bytes<T>(vec: &Vec<T>) -> usize {
+    vec.capacity() * size_of::<T>()
+}
+
+fn note_bytes(note: &DisputeNote) -> usize {
+    match note {
+        DisputeNote::Text(text) | DisputeNote::Evidence(text) => text.capacity(),
+    }
+}
+
+impl FundTransaction {
+    /// The bytes of the transaction's dispute notes and tag.
+    pub(crate) fn heap_bytes(&self) -> usize {
+        vec_bytes(&self.notes)
+            + self.notes.iter().map(note_bytes).sum::<usize>()
+            + self.tag.as_ref().map_or(0, String::capacity)
+    }
+}
+
+fn journal_entry_bytes(entry: &JournalEntry) -> usize {
+    let accounts: usize = entry
+        .postings
+        .iter()
+        .map(|posting| posting.account.capacity())
+        .sum();
+    vec_bytes(&entry.postings) + accounts
+}
+
+impl TransactionProcessor {
+    /// Estimates the memory used by the accounts, their transactions and the
+    /// processor's indices, e.g. to decide when to prune histories or evict
+    /// accounts.
+    pub fn memory_stats(&self) -> MemoryStats {
+        let mut stats = MemoryStats {
+            accounts: self.accounts.heap_bytes(),
+            indices: btree_set_bytes(&self.expiries) + vec_bytes(&self.schedule),
+            logs: vec_bytes(&self.audit_log),
+            ..MemoryStats::default()
+        };
+        for (_, account) in self.accounts.iter() {
+            stats.accounts += btree_map_bytes(&account.holds)
+                + vec_bytes(&account.receivables)
+                + btree_map_bytes(&account.balances)
+                + size_of_val(account.history.points());
+            stats.transactions += account.txs.heap_bytes();
+        }
+        if let Some(journal) = &self.journal {
+            stats.logs += vec_bytes(journal);
+            stats.logs += journal.iter().map(journal_entry_bytes).sum::<usize>();
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, Deposit, Price4, Transaction, TransactionId};
+
+    #[test]
+    fn test_memory_stats() {
+        let mut processor = TransactionProcessor::new();
+        assert_eq!(processor.memory_stats().total(), 0);
+        for tx_id in 1..=100 {
+            processor
+                .process(Transaction::Deposit(Deposit {
+                    client_id: ClientId(tx_id as u16 % 10),
+                    tx_id: TransactionId(tx_id),
+                    amount: Price4::new(1, 0),
+                }))
+                .unwrap();
+        }
+        let stats = processor.memory_stats();
+        assert!(stats.accounts >= 10 * size_of::<(ClientId, crate::Account)>());
+        assert!(stats.transactions > stats.accounts);
+        assert_eq!(stats.indices, 0);
+        assert_eq!(stats.total(), stats.accounts + stats.transactions);
+    }
+}
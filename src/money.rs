@@ -0,0 +1,168 @@
+use crate::prelude::*;
+#[cfg(feature = "rusty-money")]
+use crate::Currency;
+use crate::Price4;
+#[cfg(feature = "bigdecimal")]
+use bigdecimal::num_bigint::BigInt;
+#[cfg(feature = "bigdecimal")]
+use bigdecimal::BigDecimal;
+#[cfg(feature = "bigdecimal")]
+use core::convert::TryFrom;
+#[cfg(feature = "rusty-money")]
+use rusty_money::{iso, Money};
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum AmountError {
+    #[error("amount {0} has more than 4 decimals")]
+    TooManyDecimals(String),
+    #[error("amount {0} is out of range")]
+    OutOfRange(String),
+    #[cfg(feature = "rusty-money")]
+    #[error("currency {0} is not an ISO 4217 currency")]
+    UnknownCurrency(Currency),
+}
+
+/// Converts amounts to and from `bigdecimal`'s `BigDecimal` without rounding,
+/// e.g. at the boundary to a database or another library using it.
+#[cfg(feature = "bigdecimal")]
+pub trait BigDecimalAmount: Sized {
+    fn to_big_decimal(&self) -> BigDecimal;
+
+    /// Converts `value`, failing if it has more than 4 decimals after trailing
+    /// zeros are removed, or does not fit an amount.
+    fn from_big_decimal(value: &BigDecimal) -> Result<Self, AmountError>;
+}
+
+#[cfg(feature = "bigdecimal")]
+impl BigDecimalAmount for Price4 {
+    fn to_big_decimal(&self) -> BigDecimal {
+        BigDecimal::new(BigInt::from(self.mantissa()), i64::from(self.scale()))
+    }
+
+    fn from_big_decimal(value: &BigDecimal) -> Result<Price4, AmountError> {
+        let normalized = value.normalized();
+        // A negative scale is a multiple of a power of ten.
+        let normalized = if normalized.fractional_digit_count() < 0 {
+            normalized.with_scale(0)
+        } else {
+            normalized
+        };
+        let (mantissa, scale) = normalized.into_bigint_and_exponent();
+        if scale > 4 {
+            return Err(AmountError::TooManyDecimals(value.to_string()));
+        }
+        let out_of_range = || AmountError::OutOfRange(value.to_string());
+        let mantissa = i128::try_from(mantissa).map_err(|_| out_of_range())?;
+        Price4::try_from_i128_with_scale(mantissa, scale as u32).map_err(|_| out_of_range())
+    }
+}
+
+#[cfg(feature = "rusty-money")]
+impl Currency {
+    /// The ISO 4217 currency of `rusty_money` with this code, if there is one.
+    pub fn to_iso(&self) -> Option<&'static iso::Currency> {
+        iso::find(&self.to_string())
+    }
+}
+
+#[cfg(feature = "rusty-money")]
+impl From<&iso::Currency> for Currency {
+    fn from(currency: &iso::Currency) -> Currency {
+        currency
+            .iso_alpha_code
+            .parse()
+            .expect("ISO 4217 codes are three uppercase letters")
+    }
+}
+
+/// Converts amounts in a currency to and from `rusty_money`'s `Money` without
+/// rounding, even to fewer decimals than amounts have, since the currency's
+/// minor unit only matters when money is formatted or allocated.
+#[cfg(feature = "rusty-money")]
+pub trait MoneyAmount: Sized {
+    /// The amount as money in `currency`, failing if `currency` is not an ISO
+    /// 4217 currency.
+    fn to_money(&self, currency: Currency) -> Result<Money<'static, iso::Currency>, AmountError>;
+
+    /// Converts `money` to an amount and its currency, failing if it has more
+    /// than 4 decimals after trailing zeros are removed.
+    fn from_money(money: &Money<'_, iso::Currency>) -> Result<(Self, Currency), AmountError>;
+}
+
+#[cfg(feature = "rusty-money")]
+impl MoneyAmount for Price4 {
+    fn to_money(&self, currency: Currency) -> Result<Money<'static, iso::Currency>, AmountError> {
+        let iso = currency
+            .to_iso()
+            .ok_or(AmountError::UnknownCurrency(currency))?;
+        Ok(Money::from_decimal(*self, iso))
+    }
+
+    fn from_money(money: &Money<'_, iso::Currency>) -> Result<(Price4, Currency), AmountError> {
+        let amount = money.amount().normalize();
+        if amount.scale() > 4 {
+            return Err(AmountError::TooManyDecimals(money.amount().to_string()));
+        }
+        Ok((amount, Currency::from(money.currency())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::str::FromStr;
+
+    #[cfg(feature = "bigdecimal")]
+    #[test]
+    fn test_big_decimal_amounts() {
+        let amount = Price4::new(-15, 1);
+        assert_eq!(amount.to_big_decimal().to_string(), "-1.5");
+        assert_eq!(
+            Price4::from_big_decimal(&amount.to_big_decimal()),
+            Ok(amount)
+        );
+
+        let value = BigDecimal::from_str("2.50000").unwrap();
+        assert_eq!(Price4::from_big_decimal(&value), Ok(Price4::new(25, 1)));
+        let value = BigDecimal::from_str("1e3").unwrap();
+        assert_eq!(Price4::from_big_decimal(&value), Ok(Price4::new(1000, 0)));
+        let value = BigDecimal::from_str("0.00001").unwrap();
+        assert_eq!(
+            Price4::from_big_decimal(&value),
+            Err(AmountError::TooManyDecimals(value.to_string()))
+        );
+        let value = BigDecimal::from_str("1e40").unwrap();
+        assert_eq!(
+            Price4::from_big_decimal(&value),
+            Err(AmountError::OutOfRange(value.to_string()))
+        );
+    }
+
+    #[cfg(feature = "rusty-money")]
+    #[test]
+    fn test_money_amounts() {
+        let eur = Currency::from_str("EUR").unwrap();
+        let amount = Price4::new(12345, 4);
+        let money = amount.to_money(eur).unwrap();
+        assert_eq!(money.currency(), iso::EUR);
+        assert_eq!(*money.amount(), amount);
+        assert_eq!(Price4::from_money(&money), Ok((amount, eur)));
+
+        // Amounts keep their decimals even in currencies without minor units.
+        let jpy = Price4::new(15, 1).to_money("JPY".parse().unwrap()).unwrap();
+        assert_eq!(jpy.amount().to_string(), "1.5");
+
+        let unknown = Currency::from_str("XYZ").unwrap();
+        assert_eq!(
+            amount.to_money(unknown),
+            Err(AmountError::UnknownCurrency(unknown))
+        );
+        let money = Money::from_str("0.00001", iso::USD).unwrap();
+        assert_eq!(
+            Price4::from_money(&money),
+            Err(AmountError::TooManyDecimals("0.00001".to_string()))
+        );
+        assert_eq!(Currency::from(iso::USD), Currency::from_str("USD").unwrap());
+    }
+}
@@ -0,0 +1,447 @@
+use crate::{
+    Account, AuditEntry, ClientId, Error, JournalEntry, Timestamp, Transaction, TransactionId,
+    TransactionProcessor,
+};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+/// The account of a client, if it exists, lent by the shard it belongs to.
+type Lent = (ClientId, Option<Account>);
+
+/// A request to the thread of a shard, handled in the order it was sent.
+enum Request {
+    /// Processes the transaction at `index` of the input.
+    Process {
+        index: usize,
+        transaction: Transaction,
+    },
+    /// Processes the transaction at `index` of the input with the lent account
+    /// of the other client it changes, and sends that account back.
+    ProcessWith {
+        index: usize,
+        transaction: Transaction,
+        lent: Lent,
+        reply: Sender<Lent>,
+    },
+    /// Sends the payee of the escrow `transaction` releases.
+    Counterparty(Transaction, Sender<Option<ClientId>>),
+    /// Removes and sends the account of a client, for another shard to process
+    /// a transaction changing it.
+    Lend(ClientId, Sender<Lent>),
+    /// Puts back an account lent to another shard.
+    Return(Lent),
+}
+
+/// What a shard's thread processed, by the index of the transactions in the
+/// input.
+#[derive(Default)]
+struct ShardOutput {
+    results: Vec<(usize, Result<(), Error>)>,
+    accounts: Vec<(ClientId, Account)>,
+    expiries: BTreeSet<(Timestamp, ClientId, TransactionId)>,
+    audit_log: Vec<(usize, AuditEntry)>,
+    journal: Vec<(usize, JournalEntry)>,
+}
+
+/// Assigns the accounts of clients to the shards processing them in parallel,
+/// e.g. to match how the input is partitioned upstream. Implemented for
+/// closures taking a client id and the number of shards.
+pub trait Partitioner: Sync {
+    /// The shard of `shards` the account of `client_id` belongs to. Values of
+    /// `shards` or more are taken modulo `shards`.
+    fn shard(&self, client_id: ClientId, shards: usize) -> usize;
+}
+
+impl<F: Fn(ClientId, usize) -> usize + Sync> Partitioner for F {
+    fn shard(&self, client_id: ClientId, shards: usize) -> usize {
+        self(client_id, shards)
+    }
+}
+
+/// Assigns client `n` to shard `n % shards`, the default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ModuloPartitioner;
+
+impl Partitioner for ModuloPartitioner {
+    fn shard(&self, client_id: ClientId, shards: usize) -> usize {
+        client_id.0 as usize % shards
+    }
+}
+
+/// Assigns ranges of clients to shards: shard `i` has the clients from
+/// `bounds[i - 1]` (or 0) up to `bounds[i]`, and the last shard the clients from
+/// the last bound.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RangePartitioner {
+    /// The first client of each shard but the first, in ascending order.
+    pub bounds: Vec<ClientId>,
+}
+
+impl Partitioner for RangePartitioner {
+    fn shard(&self, client_id: ClientId, _shards: usize) -> usize {
+        self.bounds.partition_point(|bound| *bound <= client_id)
+    }
+}
+
+/// Assigns clients to shards by jump consistent hashing, so adding a shard only
+/// moves the clients it is assigned, e.g. to match consistently hashed topic
+/// partitions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct JumpHashPartitioner;
+
+impl Partitioner for JumpHashPartitioner {
+    /// The jump consistent hash of Lamping and Veach.
+    fn shard(&self, client_id: ClientId, shards: usize) -> usize {
+        let mut key = client_id.0 as u64;
+        let (mut shard, mut next) = (0, 0);
+        while next < shards {
+            shard = next;
+            key = key.wrapping_mul(2_862_933_555_777_941_757).wrapping_add(1);
+            next = ((shard + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as usize;
+        }
+        shard
+    }
+}
+
+/// Handles the requests of a shard with `processor`, whose accounts are the
+/// shard's, until they end.
+fn run_shard(mut processor: TransactionProcessor, requests: Receiver<Request>) -> ShardOutput {
+    let mut output = ShardOutput::default();
+    let mut process = |processor: &mut TransactionProcessor, index, transaction| {
+        let result = processor.process(transaction);
+        output.results.push((index, result));
+        let audit_log = processor.take_audit_log();
+        output
+            .audit_log
+            .extend(audit_log.into_iter().map(|entry| (index, entry)));
+        let journal = processor.take_journal();
+        output
+            .journal
+            .extend(journal.into_iter().map(|entry| (index, entry)));
+    };
+    for request in requests {
+        match request {
+            Request::Process { index, transaction } => process(&mut processor, index, transaction),
+            Request::ProcessWith {
+                index,
+                transaction,
+                lent,
+                reply,
+            } => {
+                let client_id = lent.0;
+                put_back(&mut processor, lent);
+                process(&mut processor, index, transaction);
+                let _ = reply.send((client_id, processor.accounts.remove(client_id)));
+            }
+            Request::Counterparty(transaction, reply) => {
+                let _ = reply.send(processor.counterparty(&transaction));
+            }
+            Request::Lend(client_id, reply) => {
+                let _ = reply.send((client_id, processor.accounts.remove(client_id)));
+            }
+            Request::Return(lent) => put_back(&mut processor, lent),
+        }
+    }
+    output.accounts = processor.accounts.drain();
+    output.expiries = std::mem::take(&mut processor.expiries);
+    output
+}
+
+fn put_back(processor: &mut TransactionProcessor, (client_id, account): Lent) {
+    if let Some(account) = account {
+        processor.accounts.insert(client_id, account);
+    }
+}
+
+impl TransactionProcessor {
+    /// Processes `transactions` on `shards` threads, each processing the
+    /// transactions of the clients of its shard in order, and returns their
+    /// results in the order of `transactions`.
+    ///
+    /// The accounts, the audit log and the journal are identical to those of
+    /// processing `transactions` in order with `process`, however the threads are
+    /// scheduled: the transactions of each client are processed in order, those
+    /// changing the accounts of clients of two shards are processed once both are
+    /// done with the transactions before them, and audit log and journal entries
+    /// are merged in the order of their transactions. This requires the
+    /// validators to only depend on the transaction and the accounts it changes.
+    ///
+    /// Each shard's processor is created with `TransactionProcessor::new` and set
+    /// up by `configure`, which must configure it like this processor, e.g. add
+    /// the same validators. The journal is recorded if this processor records it.
+    ///
+    /// This function does not panic, unless `configure` or a validator does.
+    pub fn process_parallel<F>(
+        &mut self,
+        transactions: &[Transaction],
+        shards: usize,
+        configure: F,
+    ) -> Vec<Result<(), Error>>
+    where
+        F: Fn(&mut TransactionProcessor) + Sync,
+    {
+        self.process_parallel_with(transactions, shards, &ModuloPartitioner, configure)
+    }
+
+    /// Like `process_parallel`, but assigns the clients to shards with
+    /// `partitioner`.
+    pub fn process_parallel_with<F>(
+        &mut self,
+        transactions: &[Transaction],
+        shards: usize,
+        partitioner: &dyn Partitioner,
+        configure: F,
+    ) -> Vec<Result<(), Error>>
+    where
+        F: Fn(&mut TransactionProcessor) + Sync,
+    {
+        let shards = shards.max(1);
+        let shard_of = |client_id| partitioner.shard(client_id, shards) % shards;
+        let mut processors: Vec<_> = (0..shards)
+            .map(|_| (HashMap::new(), BTreeSet::new()))
+            .collect();
+        for (client_id, account) in self.accounts.drain() {
+            processors[shard_of(client_id)].0.insert(client_id, account);
+        }
+        for expiry in std::mem::take(&mut self.expiries) {
+            processors[shard_of(expiry.1)].1.insert(expiry);
+        }
+        let journal = self.journal.is_some();
+        let periods = &self.periods;
+        let configure = &configure;
+        let outputs: Vec<ShardOutput> = std::thread::scope(|scope| {
+            let mut threads = Vec::with_capacity(shards);
+            let mut senders = Vec::with_capacity(shards);
+            for (accounts, expiries) in processors {
+                let (sender, requests) = channel();
+                threads.push(scope.spawn(move || {
+                    let mut processor = TransactionProcessor::new();
+                    configure(&mut processor);
+                    processor.set_journal(journal);
+                    processor.periods = periods.clone();
+                    for (client_id, account) in accounts {
+                        processor.accounts.insert(client_id, account);
+                    }
+                    processor.expiries = expiries;
+                    run_shard(processor, requests)
+                }));
+                senders.push(sender);
+            }
+            dispatch(transactions, &senders, &shard_of);
+            drop(senders);
+            threads
+                .into_iter()
+                .map(|thread| {
+                    thread
+                        .join()
+                        .unwrap_or_else(|e| std::panic::resume_unwind(e))
+                })
+                .collect()
+        });
+
+        let mut results: Vec<Option<Result<(), Error>>> =
+            (0..transactions.len()).map(|_| None).collect();
+        let mut audit_log = Vec::new();
+        let mut journal = Vec::new();
+        for output in outputs {
+            for (index, result) in output.results {
+                results[index] = Some(result);
+            }
+            for (client_id, account) in output.accounts {
+                self.accounts.insert(client_id, account);
+            }
+            self.expiries.extend(output.expiries);
+            audit_log.extend(output.audit_log);
+            journal.extend(output.journal);
+        }
+        // The entries of each transaction come from a single shard, in order, so a
+        // stable sort keeps them in order.
+        audit_log.sort_by_key(|(index, _)| *index);
+        self.audit_log
+            .extend(audit_log.into_iter().map(|(_, entry)| entry));
+        if let Some(entries) = self.journal.as_mut() {
+            journal.sort_by_key(|(index, _)| *index);
+            entries.extend(journal.into_iter().map(|(_, entry)| entry));
+        }
+        results
+            .into_iter()
+            .map(|result| result.expect("every transaction is processed"))
+            .collect()
+    }
+}
+
+/// Sends `request` to `shard` and waits for its reply.
+fn call<T>(shard: &Sender<Request>, request: impl FnOnce(Sender<T>) -> Request) -> T {
+    let (reply, replies) = channel();
+    shard
+        .send(request(reply))
+        .expect("shards run until the input ends");
+    replies.recv().expect("shards reply to requests")
+}
+
+/// Sends each of `transactions` to the shard of its client, lending it the
+/// account of the payee of a released escrow if that belongs to another shard.
+fn dispatch(
+    transactions: &[Transaction],
+    shards: &[Sender<Request>],
+    shard_of: &dyn Fn(ClientId) -> usize,
+) {
+    let send = |shard: usize, request: Request| {
+        shards[shard]
+            .send(request)
+            .expect("shards run until the input ends");
+    };
+    for (index, transaction) in transactions.iter().copied().enumerate() {
+        let shard = shard_of(transaction.client_id());
+        let payee = match transaction {
+            Transaction::EscrowRelease(_) => call(&shards[shard], |reply| {
+                Request::Counterparty(transaction, reply)
+            }),
+            _ => None,
+        };
+        match payee.map(|payee| (payee, shard_of(payee))) {
+            Some((payee, payee_shard)) if payee_shard != shard => {
+                let lent = call(&shards[payee_shard], |reply| Request::Lend(payee, reply));
+                let lent = call(&shards[shard], |reply| Request::ProcessWith {
+                    index,
+                    transaction,
+                    lent,
+                    reply,
+                });
+                send(payee_shard, Request::Return(lent));
+            }
+            _ => send(shard, Request::Process { index, transaction }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{BalanceCap, CapMode, Deposit, Dispute, EscrowOpen, EscrowRelease, Price4};
+    use crate::{ManualClock, Withdrawal};
+
+    fn configure(processor: &mut TransactionProcessor) {
+        processor.set_clock(Box::new(ManualClock::new(Timestamp::from_secs(0))));
+        processor.set_balance_cap(Some(BalanceCap {
+            default: Some(Price4::new(100, 0)),
+            per_client: HashMap::new(),
+            mode: CapMode::Partial,
+        }));
+    }
+
+    /// The accounts of `processor` as JSON, whose objects compare regardless of
+    /// the order of their keys.
+    fn accounts_json(processor: &TransactionProcessor) -> serde_json::Value {
+        serde_json::to_value(processor.accounts()).unwrap()
+    }
+
+    #[test]
+    fn test_process_parallel() {
+        let mut transactions = Vec::new();
+        for tx_id in 0..200u32 {
+            let (client_id, amount) = (ClientId(tx_id as u16 % 7), Price4::new(tx_id as i64, 1));
+            transactions.push(match tx_id % 5 {
+                0 | 1 => Transaction::Deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId(tx_id),
+                    amount: Price4::new(tx_id as i64, 0),
+                }),
+                2 => Transaction::Withdrawal(Withdrawal {
+                    client_id,
+                    tx_id: TransactionId(tx_id),
+                    amount,
+                }),
+                3 => Transaction::EscrowOpen(EscrowOpen {
+                    client_id,
+                    tx_id: TransactionId(tx_id),
+                    payee: ClientId(client_id.0 + 1),
+                    amount,
+                }),
+                _ => Transaction::EscrowRelease(EscrowRelease {
+                    client_id: ClientId((tx_id as u16 - 1) % 7),
+                    tx_id: TransactionId(tx_id - 1),
+                }),
+            });
+        }
+        transactions.push(Transaction::Dispute(Dispute {
+            client_id: ClientId(1),
+            tx_id: TransactionId(1),
+        }));
+
+        let mut sequential = TransactionProcessor::new();
+        configure(&mut sequential);
+        sequential.set_journal(true);
+        let expected: Vec<_> = transactions
+            .iter()
+            .map(|transaction| sequential.process(*transaction).is_ok())
+            .collect();
+        // Partial deposits are recorded in the audit log.
+        assert!(!sequential.audit_log.is_empty());
+        let by_range = RangePartitioner {
+            bounds: vec![ClientId(2), ClientId(5)],
+        };
+        let by_parity = |client_id: ClientId, _| client_id.0 as usize % 2;
+        let partitioners: [(&dyn Partitioner, usize); 5] = [
+            (&ModuloPartitioner, 1),
+            (&ModuloPartitioner, 4),
+            (&JumpHashPartitioner, 3),
+            (&by_range, 3),
+            (&by_parity, 2),
+        ];
+        for (partitioner, shards) in partitioners.iter() {
+            let mut parallel = TransactionProcessor::new();
+            configure(&mut parallel);
+            parallel.set_journal(true);
+            let results =
+                parallel.process_parallel_with(&transactions, *shards, *partitioner, configure);
+            let results: Vec<_> = results.iter().map(Result::is_ok).collect();
+            assert_eq!(results, expected);
+            assert_eq!(accounts_json(&parallel), accounts_json(&sequential));
+            assert_eq!(parallel.take_audit_log(), sequential.audit_log);
+            assert_eq!(parallel.take_journal(), sequential.journal.clone().unwrap());
+        }
+
+        assert_eq!(by_range.shard(ClientId(4), 3), 1);
+        // Adding a shard only moves clients to the new shard.
+        for client_id in (0..1000).map(ClientId) {
+            let shard = JumpHashPartitioner.shard(client_id, 5);
+            assert!(shard == 4 || shard == JumpHashPartitioner.shard(client_id, 4));
+        }
+    }
+
+    #[test]
+    fn test_process_parallel_after_close() {
+        let deposit = |client_id, tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id: ClientId(client_id),
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(10, 0),
+            })
+        };
+        let dispute = |client_id, tx_id| {
+            Transaction::Dispute(Dispute {
+                client_id: ClientId(client_id),
+                tx_id: TransactionId(tx_id),
+            })
+        };
+        let mut processor = TransactionProcessor::new();
+        processor.process(deposit(1, 1)).unwrap();
+        processor.close_period();
+        let results =
+            processor.process_parallel(&[deposit(2, 2), dispute(1, 1), dispute(2, 2)], 2, |_| {});
+        assert!(results[0].is_ok());
+        // The transactions of the closed period can't be disputed in the shards
+        // either, and new ones belong to the open period.
+        assert!(matches!(results[1], Err(Error::ClosedPeriod { .. })));
+        assert!(results[2].is_ok());
+        assert_eq!(
+            processor
+                .transaction(ClientId(2), TransactionId(2))
+                .unwrap()
+                .period(),
+            1
+        );
+        assert_eq!(processor.take_audit_log()[0].action, "closed_period");
+    }
+}
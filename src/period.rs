@@ -0,0 +1,240 @@
+use crate::prelude::*;
+use crate::{
+    AuditEntry, ClientId, Error, Operation, Price4, Side, Timestamp, TransactionId,
+    TransactionProcessor,
+};
+use serde::{Deserialize, Serialize};
+
+/// The balances of an account when an accounting period was closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClosingBalance {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    pub available: Price4,
+    pub held: Price4,
+    pub total: Price4,
+}
+
+/// A closed accounting period, with the balances of the accounts at its close.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClosedPeriod {
+    /// The id of the period; periods are numbered from 0 in the order they were
+    /// opened.
+    pub id: u32,
+    /// The processor's time when the period was closed.
+    pub closed_at: Timestamp,
+    /// The balances of the accounts, ordered by client.
+    pub balances: Vec<ClosingBalance>,
+}
+
+/// The closed accounting periods, oldest first. The open period follows the last
+/// of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Periods {
+    closed: Vec<ClosedPeriod>,
+}
+
+impl Periods {
+    pub(crate) fn closed(&self) -> &[ClosedPeriod] {
+        &self.closed
+    }
+
+    #[cfg(feature = "persistence")]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.closed.is_empty()
+    }
+
+    #[cfg(feature = "persistence")]
+    pub(crate) fn push(&mut self, period: ClosedPeriod) {
+        self.closed.push(period);
+    }
+
+    /// The id of the open period.
+    pub(crate) fn open(&self) -> u32 {
+        self.closed.len() as u32
+    }
+}
+
+impl TransactionProcessor {
+    /// Closes the open accounting period, recording the balances of all accounts,
+    /// and opens the next one. The deposits, withdrawals and other transactions
+    /// processed from then on are tagged with the id of the new period.
+    ///
+    /// Transactions of a closed period can no longer be disputed, reversed or
+    /// undone: the dispute, reversal or undo is rejected (`Error::ClosedPeriod`) and recorded in
+    /// the audit log as `closed_period`, with the transaction's amount, so it can
+    /// be settled with an `adjustment` in the open period instead. Disputes that
+    /// were open at the close can still be resolved and charged back.
+    pub fn close_period(&mut self) -> &ClosedPeriod {
+        let mut balances: Vec<_> = self
+            .accounts
+            .iter()
+            .map(|(client_id, account)| ClosingBalance {
+                client_id,
+                available: account.funds.available,
+                held: account.funds.held,
+                total: account.funds.total(),
+            })
+            .collect();
+        balances.sort_by_key(|balance| balance.client_id);
+        let period = ClosedPeriod {
+            id: self.periods.open(),
+            closed_at: self.clock.now(),
+            balances,
+        };
+        self.periods.closed.push(period);
+        self.periods_changed = true;
+        &self.periods.closed[self.periods.closed.len() - 1]
+    }
+
+    /// The id of the open accounting period, which is the number of closed ones.
+    pub fn open_period(&self) -> u32 {
+        self.periods.open()
+    }
+
+    /// The closed accounting periods, oldest first.
+    pub fn closed_periods(&self) -> &[ClosedPeriod] {
+        self.periods.closed()
+    }
+
+    /// Rejects `operation`, a dispute, reversal or undo, of the transaction `tx_id`
+    /// of client `client_id` if it is of a closed period, and records it in the
+    /// audit log. Transactions that don't exist are left to the checks of the
+    /// operation.
+    pub(crate) fn check_open_period(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        operation: Operation,
+    ) -> Result<(), Error> {
+        let tx = match self
+            .accounts
+            .get(client_id)
+            .and_then(|account| account.txs.get(tx_id))
+        {
+            Some(tx) => tx,
+            None => return Ok(()),
+        };
+        if tx.period >= self.periods.open() {
+            return Ok(());
+        }
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            tx_id: Some(tx_id),
+            action: "closed_period",
+            amount: match tx.side {
+                Side::Deposit => tx.amount,
+                Side::Withdrawal => -tx.amount,
+            },
+            operation: Some(operation),
+            ..AuditEntry::default()
+        });
+        Err(Error::ClosedPeriod {
+            tx_id,
+            period: tx.period,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute, Resolve, Reversal, Transaction};
+
+    #[test]
+    fn test_close_period() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId::from(1);
+        for tx_id in 1..=2 {
+            processor
+                .process_deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId::from(tx_id),
+                    amount: Price4::new(10, 0),
+                })
+                .unwrap();
+        }
+        let dispute = |tx_id| {
+            Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId::from(tx_id),
+            })
+        };
+        processor.process(dispute(1)).unwrap();
+
+        let period = processor.close_period();
+        assert_eq!(period.id, 0);
+        assert_eq!(
+            period.balances,
+            [ClosingBalance {
+                client_id,
+                available: Price4::new(10, 0),
+                held: Price4::new(10, 0),
+                total: Price4::new(20, 0),
+            }]
+        );
+        assert_eq!(processor.open_period(), 1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId::from(3),
+                amount: Price4::new(5, 0),
+            })
+            .unwrap();
+        assert_eq!(
+            processor
+                .transaction(client_id, TransactionId::from(3))
+                .unwrap()
+                .period(),
+            1
+        );
+
+        // The dispute open at the close can still be resolved, but the closed
+        // period's transactions can't be disputed or reversed again.
+        processor
+            .process(Transaction::Resolve(Resolve {
+                client_id,
+                tx_id: TransactionId::from(1),
+                amount: None,
+            }))
+            .unwrap();
+        assert!(matches!(
+            processor.process(dispute(2)),
+            Err(Error::ClosedPeriod { period: 0, .. })
+        ));
+        assert!(processor
+            .process(Transaction::Reversal(Reversal {
+                client_id,
+                tx_id: TransactionId::from(2),
+            }))
+            .is_err());
+        assert!(matches!(
+            processor.undo(client_id, TransactionId::from(2)),
+            Err(Error::ClosedPeriod { period: 0, .. })
+        ));
+        processor.process(dispute(3)).unwrap();
+        let log = processor.take_audit_log();
+        assert_eq!(
+            log.iter()
+                .map(|entry| (entry.action, entry.amount, entry.operation))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "closed_period",
+                    Price4::new(10, 0),
+                    Some(Operation::Dispute)
+                ),
+                (
+                    "closed_period",
+                    Price4::new(10, 0),
+                    Some(Operation::Reversal)
+                ),
+                ("closed_period", Price4::new(10, 0), Some(Operation::Undo)),
+            ]
+        );
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(20, 0)
+        );
+    }
+}
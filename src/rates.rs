@@ -0,0 +1,173 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{Currency, Price4, Timestamp};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "io")]
+use std::path::Path;
+#[cfg(feature = "io")]
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RatesError {
+    #[cfg(feature = "io")]
+    #[error("could not read rates {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[cfg(feature = "io")]
+    #[error("rates {0} must be a .csv or .json file")]
+    UnknownFormat(PathBuf),
+    #[error("invalid rates: {0}")]
+    Invalid(String),
+}
+
+/// An exchange rate, valid from `valid_from` (inclusive) until `valid_until`
+/// (exclusive). A rate without them is valid since or until any time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Rate {
+    pub from: Currency,
+    pub to: Currency,
+    /// The units of `to` one unit of `from` buys.
+    pub rate: Price4,
+    #[serde(default)]
+    pub valid_from: Option<Timestamp>,
+    #[serde(default)]
+    pub valid_until: Option<Timestamp>,
+}
+
+impl Rate {
+    pub fn is_valid_at(&self, at: Timestamp) -> bool {
+        self.valid_from.is_none_or(|from| from <= at)
+            && self.valid_until.is_none_or(|until| at < until)
+    }
+}
+
+/// Exchange rates over time, looked up as of the time of a conversion.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RateTable {
+    /// The rates of each currency pair, ordered by the start of their validity.
+    rates: HashMap<(Currency, Currency), Vec<Rate>>,
+}
+
+impl RateTable {
+    /// A table of `rates`, which must be positive and valid for some time.
+    pub fn new(rates: Vec<Rate>) -> Result<RateTable, RatesError> {
+        let mut table = RateTable::default();
+        for rate in rates {
+            table.insert(rate)?;
+        }
+        Ok(table)
+    }
+
+    #[cfg(feature = "io")]
+    pub fn from_csv<R: std::io::Read>(reader: R) -> Result<RateTable, RatesError> {
+        let rates = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_reader(reader)
+            .into_deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| RatesError::Invalid(e.to_string()))?;
+        RateTable::new(rates)
+    }
+
+    pub fn from_json(rates: &str) -> Result<RateTable, RatesError> {
+        let rates = serde_json::from_str(rates).map_err(|e| RatesError::Invalid(e.to_string()))?;
+        RateTable::new(rates)
+    }
+
+    /// Loads rates from a CSV file with a header, or a JSON array, depending on
+    /// its extension.
+    #[cfg(feature = "io")]
+    pub fn load(path: &Path) -> Result<RateTable, RatesError> {
+        let rates =
+            std::fs::read_to_string(path).map_err(|e| RatesError::Io(path.to_owned(), e))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("csv") => RateTable::from_csv(rates.as_bytes()),
+            Some("json") => RateTable::from_json(&rates),
+            _ => Err(RatesError::UnknownFormat(path.to_owned())),
+        }
+    }
+
+    /// Adds `rate`, which must be positive and valid for some time.
+    pub fn insert(&mut self, rate: Rate) -> Result<(), RatesError> {
+        if rate.rate <= Price4::ZERO {
+            return Err(RatesError::Invalid(format!(
+                "rate {} from {} to {} is not positive",
+                rate.rate, rate.from, rate.to
+            )));
+        }
+        if let (Some(from), Some(until)) = (rate.valid_from, rate.valid_until) {
+            if until <= from {
+                return Err(RatesError::Invalid(format!(
+                    "rate from {} to {} is valid until {} before it is valid from {}",
+                    rate.from,
+                    rate.to,
+                    until.as_secs(),
+                    from.as_secs()
+                )));
+            }
+        }
+        let rates = self.rates.entry((rate.from, rate.to)).or_default();
+        let index = rates.partition_point(|other| other.valid_from <= rate.valid_from);
+        rates.insert(index, rate);
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rates.is_empty()
+    }
+
+    /// The rate from `from` to `to` valid at `at`, which is the inverse of the
+    /// rate from `to` to `from` if only that one is known. Of several valid rates,
+    /// the one valid from the latest time is used.
+    pub fn as_of(&self, from: Currency, to: Currency, at: Timestamp) -> Option<Price4> {
+        let valid = |from, to| {
+            self.rates
+                .get(&(from, to))?
+                .iter()
+                .rev()
+                .find(|rate| rate.is_valid_at(at))
+                .map(|rate| rate.rate)
+        };
+        valid(from, to).or_else(|| valid(to, from).and_then(|rate| Price4::ONE.checked_div(rate)))
+    }
+}
+
+#[cfg(all(test, feature = "io"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_table() {
+        let table = RateTable::from_csv(
+            "from, to,  rate, valid_from, valid_until
+             USD,  EUR, 0.9,  ,           100
+             USD,  EUR, 0.8,  100,
+             USD,  EUR, 0.85, 150,        200"
+                .as_bytes(),
+        )
+        .unwrap();
+        let (usd, eur) = ("USD".parse().unwrap(), "EUR".parse().unwrap());
+        let at = Timestamp::from_secs;
+        assert_eq!(table.as_of(usd, eur, at(99)), Some(Price4::new(9, 1)));
+        assert_eq!(table.as_of(usd, eur, at(100)), Some(Price4::new(8, 1)));
+        assert_eq!(table.as_of(usd, eur, at(150)), Some(Price4::new(85, 2)));
+        assert_eq!(table.as_of(usd, eur, at(200)), Some(Price4::new(8, 1)));
+        assert_eq!(
+            table.as_of(eur, usd, at(0)),
+            Price4::ONE.checked_div(Price4::new(9, 1))
+        );
+
+        let table = RateTable::from_json(
+            r#"[{"from": "USD", "to": "EUR", "rate": "0.9", "valid_until": 100}]"#,
+        )
+        .unwrap();
+        assert_eq!(table.as_of(usd, eur, at(100)), None);
+        let err =
+            RateTable::from_json(r#"[{"from": "USD", "to": "EUR", "rate": "0"}]"#).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid rates: rate 0 from USD to EUR is not positive"
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::prelude::*;
+use crate::{Account, ClientId, Price4, Transaction, TransactionId, TransactionProcessor};
+use serde::{Deserialize, Serialize};
+
+/// An amount a client owes because a dispute or chargeback took its available
+/// funds negative. It is reduced as the available funds recover, e.g. with later
+/// deposits, oldest receivable first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Receivable {
+    /// The disputed transaction that opened it.
+    pub tx_id: TransactionId,
+    /// The amount owed when it was opened.
+    pub amount: Price4,
+    /// The amount still owed.
+    pub outstanding: Price4,
+}
+
+impl Receivable {
+    pub fn is_settled(&self) -> bool {
+        self.outstanding.is_zero()
+    }
+}
+
+impl Account {
+    /// The receivables opened on the account, oldest first, including settled
+    /// ones.
+    pub fn receivables(&self) -> &[Receivable] {
+        &self.receivables
+    }
+
+    /// The total amount the client still owes.
+    pub fn outstanding_receivables(&self) -> Price4 {
+        self.receivables
+            .iter()
+            .map(|receivable| receivable.outstanding)
+            .sum()
+    }
+}
+
+impl TransactionProcessor {
+    /// The receivables that are still outstanding, ordered by client and then
+    /// oldest first.
+    pub fn outstanding_receivables(&self) -> Vec<(ClientId, Receivable)> {
+        let mut outstanding: Vec<_> = self
+            .accounts
+            .iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .receivables
+                    .iter()
+                    .filter(|receivable| !receivable.is_settled())
+                    .map(move |receivable| (client_id, *receivable))
+            })
+            .collect();
+        // The sort is stable, so each client's receivables stay oldest first.
+        outstanding.sort_by_key(|(client_id, _)| *client_id);
+        outstanding
+    }
+
+    /// Brings the receivables of the client of a successfully processed
+    /// transaction in line with its available funds: a dispute or chargeback that
+    /// took them further below zero opens a receivable for the difference, and any
+    /// transaction that brought them back up reduces the outstanding amounts.
+    pub(crate) fn track_receivables(&mut self, transaction: &Transaction) {
+        let account = match self.accounts.get_mut(transaction.client_id()) {
+            Some(account) => account,
+            None => return,
+        };
+        let owed = (-account.funds.available).max(Price4::ZERO);
+        let outstanding = account.outstanding_receivables();
+        if owed > outstanding {
+            if !matches!(
+                transaction,
+                Transaction::Dispute(_)
+                    | Transaction::Chargeback(_)
+                    | Transaction::ChargebackConfirm(_)
+            ) {
+                return;
+            }
+            let amount = owed - outstanding;
+            let tx_id = transaction.tx_id();
+            match account.receivables.last_mut() {
+                // A chargeback adds to the receivable of its dispute.
+                Some(last) if last.tx_id == tx_id && !last.is_settled() => {
+                    last.amount += amount;
+                    last.outstanding += amount;
+                }
+                _ => account.receivables.push(Receivable {
+                    tx_id,
+                    amount,
+                    outstanding: amount,
+                }),
+            }
+        } else {
+            let mut repaid = outstanding - owed;
+            for receivable in account.receivables.iter_mut() {
+                if repaid.is_zero() {
+                    break;
+                }
+                let reduction = receivable.outstanding.min(repaid);
+                receivable.outstanding -= reduction;
+                repaid -= reduction;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Adjustment, Chargeback, Deposit, Dispute, ReasonCode, Withdrawal};
+
+    #[test]
+    fn test_receivables() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let deposit = |tx_id, amount| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(amount, 0),
+            })
+        };
+        processor.process(deposit(1, 10)).unwrap();
+        processor
+            .process(Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(8, 0),
+            }))
+            .unwrap();
+        processor
+            .process(Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(1),
+            }))
+            .unwrap();
+        assert_eq!(
+            processor.accounts()[&client_id].receivables(),
+            &[Receivable {
+                tx_id: TransactionId(1),
+                amount: Price4::new(8, 0),
+                outstanding: Price4::new(8, 0),
+            }]
+        );
+        processor.process(deposit(3, 5)).unwrap();
+        let outstanding = processor.outstanding_receivables();
+        assert_eq!(outstanding.len(), 1);
+        assert_eq!(outstanding[0].1.outstanding, Price4::new(3, 0));
+
+        // The chargeback does not change the available funds, and the account it
+        // freezes is repaid with an adjustment.
+        processor
+            .process(Transaction::Chargeback(Chargeback {
+                client_id,
+                tx_id: TransactionId(1),
+            }))
+            .unwrap();
+        assert_eq!(processor.accounts()[&client_id].receivables().len(), 1);
+        processor
+            .process(Transaction::Adjustment(Adjustment {
+                client_id,
+                tx_id: TransactionId(4),
+                amount: Price4::new(5, 0),
+                reason: ReasonCode(1),
+            }))
+            .unwrap();
+        assert!(processor.outstanding_receivables().is_empty());
+        assert!(processor.accounts()[&client_id].receivables()[0].is_settled());
+    }
+}
@@ -0,0 +1,378 @@
+use crate::prelude::*;
+use crate::{
+    Adjustment, Authorize, Capture, Chargeback, ChargebackConfirm, ChargebackRequest, ClientId,
+    Convert, Currency, Deposit, Dispute, Error, EscrowOpen, EscrowRefund, EscrowRelease, Fail,
+    Price4, ReasonCode, Resolve, Reversal, Role, Settle, Timestamp, Transaction, TransactionId,
+    Withdrawal, RECORD_TYPES,
+};
+use core::convert::TryFrom;
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// The `type` column of an input record.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    /// Flags a disputed transaction for a chargeback that must be confirmed.
+    #[serde(rename = "chargeback_request")]
+    ChargebackRequest,
+    /// Charges back a transaction flagged by a `chargeback_request`.
+    #[serde(rename = "chargeback_confirm")]
+    ChargebackConfirm,
+    Authorize,
+    Capture,
+    Settle,
+    Fail,
+    Reversal,
+    /// Moves funds into escrow for the client in the `payee` column.
+    Escrow,
+    /// Pays an escrow to its payee.
+    Release,
+    /// Returns an escrow to the client that opened it.
+    Refund,
+    /// Credits a positive or debits a negative amount regardless of the balance,
+    /// with a `reason` code.
+    Adjustment,
+    /// Converts the amount from the `from_ccy` to the `to_ccy` currency.
+    Convert,
+    /// Starts a batch of the client's records, identified by the `tx` column.
+    Begin,
+    /// Applies the client's batch with the same `tx`, all or nothing.
+    Commit,
+    /// Attaches the free text in the `note` column to the open dispute of `tx`.
+    Note,
+    /// Attaches a reference to external evidence, e.g. a document id or URL, in
+    /// the `note` column to the open dispute of `tx`.
+    Evidence,
+    /// Reactivates the client's dormant account. The `tx` column is not used.
+    Reactivate,
+}
+
+impl RecordKind {
+    /// The value of the `type` column of records of this kind, e.g. `deposit`.
+    pub fn name(&self) -> &'static str {
+        // The kinds are declared in the order of `RECORD_TYPES`.
+        RECORD_TYPES[*self as usize]
+    }
+
+    /// Whether records of this kind introduce a new transaction id, as opposed to
+    /// referring to an existing one.
+    pub fn is_fund_transaction(&self) -> bool {
+        matches!(
+            self,
+            RecordKind::Deposit | RecordKind::Withdrawal | RecordKind::Authorize
+        )
+    }
+
+    /// Whether records of this kind convert to a `Transaction`, as opposed to
+    /// batch markers, dispute notes and reactivations.
+    pub fn is_transaction(&self) -> bool {
+        !matches!(
+            self,
+            RecordKind::Begin
+                | RecordKind::Commit
+                | RecordKind::Note
+                | RecordKind::Evidence
+                | RecordKind::Reactivate
+        )
+    }
+}
+
+/// A row of an input file, with a field per column. Records serialize to the
+/// same columns they are read from, with missing values as empty fields, so
+/// written records can be read back.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TransactionRecord {
+    #[serde(rename = "type")]
+    pub kind: RecordKind,
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    #[serde(rename = "tx")]
+    pub tx_id: TransactionId,
+    pub amount: Option<Price4>,
+    /// When the transaction originally happened, if known.
+    #[serde(default)]
+    pub timestamp: Option<Timestamp>,
+    /// When the transaction takes effect, if later than `timestamp`.
+    #[serde(default)]
+    pub effective: Option<Timestamp>,
+    /// Why an adjustment was made.
+    #[serde(default)]
+    pub reason: Option<ReasonCode>,
+    /// The client an escrow is for.
+    #[serde(default)]
+    pub payee: Option<ClientId>,
+    /// The text of a dispute note or evidence reference.
+    #[serde(default)]
+    pub note: Option<String>,
+    /// The role the record is submitted by.
+    #[serde(default)]
+    pub role: Option<Role>,
+    /// Identifies the submission of the record, so retries of it are filtered out
+    /// even if their `tx` differs.
+    #[serde(default)]
+    pub idempotency_key: Option<String>,
+    /// The currency a conversion debits.
+    #[serde(default)]
+    pub from_ccy: Option<Currency>,
+    /// The currency a conversion credits.
+    #[serde(default)]
+    pub to_ccy: Option<Currency>,
+    /// The free-form category of a deposit or withdrawal, e.g. `payroll`.
+    #[serde(default)]
+    pub tag: Option<String>,
+}
+
+impl TransactionRecord {
+    /// A record of `kind` without any of the optional columns.
+    pub fn new(kind: RecordKind, client_id: ClientId, tx_id: TransactionId) -> TransactionRecord {
+        TransactionRecord {
+            kind,
+            client_id,
+            tx_id,
+            amount: None,
+            timestamp: None,
+            effective: None,
+            reason: None,
+            payee: None,
+            note: None,
+            role: None,
+            idempotency_key: None,
+            from_ccy: None,
+            to_ccy: None,
+            tag: None,
+        }
+    }
+}
+
+/// Formats the record as its type followed by the columns that are set, e.g.
+/// `withdrawal client=1 tx=7 amount=2.5`, for messages about it.
+impl fmt::Display for TransactionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} client={} tx={}",
+            self.kind.name(),
+            self.client_id,
+            self.tx_id
+        )?;
+        if let Some(amount) = self.amount {
+            write!(f, " amount={}", amount)?;
+        }
+        if let Some(timestamp) = self.timestamp {
+            write!(f, " timestamp={}", timestamp.0)?;
+        }
+        if let Some(effective) = self.effective {
+            write!(f, " effective={}", effective.0)?;
+        }
+        if let Some(reason) = self.reason {
+            write!(f, " reason={}", reason.0)?;
+        }
+        if let Some(payee) = self.payee {
+            write!(f, " payee={}", payee)?;
+        }
+        if let Some(note) = self.note.as_ref() {
+            write!(f, " note={:?}", note)?;
+        }
+        if let Some(role) = self.role.as_ref() {
+            write!(f, " role={}", role)?;
+        }
+        if let Some(key) = self.idempotency_key.as_ref() {
+            write!(f, " idempotency_key={:?}", key)?;
+        }
+        if let Some(from) = self.from_ccy {
+            write!(f, " from_ccy={}", from)?;
+        }
+        if let Some(to) = self.to_ccy {
+            write!(f, " to_ccy={}", to)?;
+        }
+        if let Some(tag) = self.tag.as_ref() {
+            write!(f, " tag={:?}", tag)?;
+        }
+        Ok(())
+    }
+}
+
+/// Converts a record to the transaction it describes. Authorizations do not
+/// expire, since records have no expiry column.
+impl TryFrom<&TransactionRecord> for Transaction {
+    type Error = Error;
+
+    fn try_from(record: &TransactionRecord) -> Result<Transaction, Error> {
+        let (client_id, tx_id) = (record.client_id, record.tx_id);
+        let amount = record.amount.ok_or(Error::InvalidPrice);
+        Ok(match record.kind {
+            RecordKind::Deposit => Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: amount?,
+            }),
+            RecordKind::Withdrawal => Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount: amount?,
+            }),
+            RecordKind::Dispute => Transaction::Dispute(Dispute { client_id, tx_id }),
+            RecordKind::Resolve => Transaction::Resolve(Resolve {
+                client_id,
+                tx_id,
+                amount: record.amount,
+            }),
+            RecordKind::Chargeback => Transaction::Chargeback(Chargeback { client_id, tx_id }),
+            RecordKind::ChargebackRequest => {
+                Transaction::ChargebackRequest(ChargebackRequest { client_id, tx_id })
+            }
+            RecordKind::ChargebackConfirm => {
+                Transaction::ChargebackConfirm(ChargebackConfirm { client_id, tx_id })
+            }
+            RecordKind::Authorize => Transaction::Authorize(Authorize {
+                client_id,
+                tx_id,
+                amount: amount?,
+                expires_at: None,
+            }),
+            RecordKind::Capture => Transaction::Capture(Capture { client_id, tx_id }),
+            RecordKind::Settle => Transaction::Settle(Settle { client_id, tx_id }),
+            RecordKind::Fail => Transaction::Fail(Fail { client_id, tx_id }),
+            RecordKind::Reversal => Transaction::Reversal(Reversal { client_id, tx_id }),
+            RecordKind::Escrow => Transaction::EscrowOpen(EscrowOpen {
+                client_id,
+                tx_id,
+                payee: record.payee.ok_or(Error::MissingPayee)?,
+                amount: amount?,
+            }),
+            RecordKind::Release => Transaction::EscrowRelease(EscrowRelease { client_id, tx_id }),
+            RecordKind::Refund => Transaction::EscrowRefund(EscrowRefund { client_id, tx_id }),
+            RecordKind::Convert => Transaction::Convert(Convert {
+                client_id,
+                tx_id,
+                from: record.from_ccy.ok_or(Error::MissingCurrency)?,
+                to: record.to_ccy.ok_or(Error::MissingCurrency)?,
+                amount: amount?,
+            }),
+            RecordKind::Adjustment => Transaction::Adjustment(Adjustment {
+                client_id,
+                tx_id,
+                amount: amount?,
+                reason: record.reason.ok_or(Error::MissingReasonCode)?,
+            }),
+            RecordKind::Begin
+            | RecordKind::Commit
+            | RecordKind::Note
+            | RecordKind::Evidence
+            | RecordKind::Reactivate => return Err(Error::NotATransaction(record.kind)),
+        })
+    }
+}
+
+impl TryFrom<TransactionRecord> for Transaction {
+    type Error = Error;
+
+    fn try_from(record: TransactionRecord) -> Result<Transaction, Error> {
+        Transaction::try_from(&record)
+    }
+}
+
+/// Converts a transaction to a record, e.g. to write input files. The expiry of
+/// authorizations is left out.
+impl From<Transaction> for TransactionRecord {
+    fn from(transaction: Transaction) -> TransactionRecord {
+        let (client_id, tx_id) = (transaction.client_id(), transaction.tx_id());
+        let record = |kind| TransactionRecord::new(kind, client_id, tx_id);
+        let with_amount = |kind, amount| TransactionRecord {
+            amount: Some(amount),
+            ..record(kind)
+        };
+        match transaction {
+            Transaction::Deposit(deposit) => with_amount(RecordKind::Deposit, deposit.amount),
+            Transaction::Withdrawal(withdrawal) => {
+                with_amount(RecordKind::Withdrawal, withdrawal.amount)
+            }
+            Transaction::Dispute(_) => record(RecordKind::Dispute),
+            Transaction::Resolve(resolve) => TransactionRecord {
+                amount: resolve.amount,
+                ..record(RecordKind::Resolve)
+            },
+            Transaction::Chargeback(_) => record(RecordKind::Chargeback),
+            Transaction::ChargebackRequest(_) => record(RecordKind::ChargebackRequest),
+            Transaction::ChargebackConfirm(_) => record(RecordKind::ChargebackConfirm),
+            Transaction::Authorize(authorize) => {
+                with_amount(RecordKind::Authorize, authorize.amount)
+            }
+            Transaction::Capture(_) => record(RecordKind::Capture),
+            Transaction::Settle(_) => record(RecordKind::Settle),
+            Transaction::Fail(_) => record(RecordKind::Fail),
+            Transaction::Reversal(_) => record(RecordKind::Reversal),
+            Transaction::Adjustment(adjustment) => TransactionRecord {
+                reason: Some(adjustment.reason),
+                ..with_amount(RecordKind::Adjustment, adjustment.amount)
+            },
+            Transaction::EscrowOpen(open) => TransactionRecord {
+                payee: Some(open.payee),
+                ..with_amount(RecordKind::Escrow, open.amount)
+            },
+            Transaction::EscrowRelease(_) => record(RecordKind::Release),
+            Transaction::EscrowRefund(_) => record(RecordKind::Refund),
+            Transaction::Convert(convert) => TransactionRecord {
+                from_ccy: Some(convert.from),
+                to_ccy: Some(convert.to),
+                ..with_amount(RecordKind::Convert, convert.amount)
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transaction_record() {
+        let convert = Transaction::Convert(Convert {
+            client_id: ClientId(1),
+            tx_id: TransactionId(2),
+            from: "EUR".parse().unwrap(),
+            to: "USD".parse().unwrap(),
+            amount: Price4::new(15, 1),
+        });
+        let record = TransactionRecord::from(convert);
+        let json = serde_json::to_string(&record).unwrap();
+        let read: TransactionRecord = serde_json::from_str(&json).unwrap();
+        assert_eq!(read, record);
+        assert_eq!(Transaction::try_from(read).unwrap(), convert);
+
+        assert_eq!(format!("{}/{}", record.client_id, record.tx_id), "1/2");
+        assert_eq!(
+            record.to_string(),
+            "convert client=1 tx=2 amount=1.5 from_ccy=EUR to_ccy=USD"
+        );
+        assert_eq!("1".parse::<ClientId>().unwrap().value(), 1);
+        assert!("65536".parse::<ClientId>().is_err());
+
+        let mut note =
+            TransactionRecord::new(RecordKind::Note, ClientId::from(1), TransactionId::from(2));
+        note.note = Some("called the client".to_string());
+        assert_eq!(
+            note.to_string(),
+            "note client=1 tx=2 note=\"called the client\""
+        );
+        assert!(matches!(
+            Transaction::try_from(&note),
+            Err(Error::NotATransaction(RecordKind::Note))
+        ));
+        for name in RECORD_TYPES.iter() {
+            let kind: RecordKind = serde_json::from_value(serde_json::json!(name)).unwrap();
+            assert_eq!(kind.name(), *name);
+        }
+        let escrow = TransactionRecord::new(RecordKind::Escrow, ClientId(1), TransactionId(3));
+        assert!(matches!(
+            Transaction::try_from(escrow),
+            Err(Error::MissingPayee)
+        ));
+    }
+}
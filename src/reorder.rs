@@ -0,0 +1,181 @@
+use crate::prelude::*;
+use crate::Timestamp;
+use alloc::collections::BinaryHeap;
+use core::cmp::{Ordering, Reverse};
+use core::str::FromStr;
+
+/// How long a `ReorderBuffer` holds on to records before releasing them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReorderWindow {
+    /// Holds up to this many records.
+    Records(usize),
+    /// Holds records until a record this many seconds newer has arrived.
+    Seconds(u64),
+}
+
+impl FromStr for ReorderWindow {
+    type Err = String;
+
+    /// Parses a number of records (e.g. `100`) or seconds (e.g. `30s`).
+    fn from_str(s: &str) -> Result<ReorderWindow, String> {
+        let invalid = |_| {
+            format!(
+                "expected a number of records or seconds like `30s`, got `{}`",
+                s
+            )
+        };
+        match s.strip_suffix('s') {
+            Some(secs) => Ok(ReorderWindow::Seconds(secs.parse().map_err(invalid)?)),
+            None => Ok(ReorderWindow::Records(s.parse().map_err(invalid)?)),
+        }
+    }
+}
+
+/// Holds records for a window and releases them ordered by timestamp, so records
+/// that arrive slightly out of order (e.g. a dispute before its deposit) are
+/// applied in the order they happened.
+///
+/// Records with equal timestamps are released in arrival order. A record without
+/// a timestamp is ordered as if it happened at the newest timestamp seen so far.
+pub struct ReorderBuffer<T> {
+    window: ReorderWindow,
+    heap: BinaryHeap<Reverse<Entry<T>>>,
+    /// The number of records pushed so far, used to keep arrival order.
+    arrivals: u64,
+    /// The newest timestamp seen so far.
+    newest: Timestamp,
+}
+
+impl<T> ReorderBuffer<T> {
+    pub fn new(window: ReorderWindow) -> ReorderBuffer<T> {
+        ReorderBuffer {
+            window,
+            heap: BinaryHeap::new(),
+            arrivals: 0,
+            newest: Timestamp::from_secs(0),
+        }
+    }
+
+    /// Adds `record` to the buffer and returns the records that fell out of the
+    /// window, oldest first.
+    pub fn push(&mut self, timestamp: Option<Timestamp>, record: T) -> Vec<T> {
+        let timestamp = timestamp.unwrap_or(self.newest);
+        self.newest = self.newest.max(timestamp);
+        self.heap.push(Reverse(Entry {
+            timestamp,
+            arrival: self.arrivals,
+            record,
+        }));
+        self.arrivals += 1;
+
+        let mut released = Vec::new();
+        while let Some(Reverse(oldest)) = self.heap.peek() {
+            let is_due = match self.window {
+                ReorderWindow::Records(records) => self.heap.len() > records,
+                // `newest` is at least the oldest timestamp, so this can't overflow.
+                ReorderWindow::Seconds(secs) => {
+                    self.newest.as_secs() - oldest.timestamp.as_secs() >= secs
+                }
+            };
+            if !is_due {
+                break;
+            }
+            released.push(self.heap.pop().unwrap().0.record);
+        }
+        released
+    }
+
+    /// The number of records held in the buffer.
+    pub fn len(&self) -> usize {
+        self.heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heap.is_empty()
+    }
+
+    /// Releases all remaining records, oldest first.
+    pub fn drain(&mut self) -> Vec<T> {
+        let mut released = Vec::with_capacity(self.heap.len());
+        while let Some(Reverse(entry)) = self.heap.pop() {
+            released.push(entry.record);
+        }
+        released
+    }
+}
+
+struct Entry<T> {
+    timestamp: Timestamp,
+    arrival: u64,
+    record: T,
+}
+
+impl<T> Entry<T> {
+    fn key(&self) -> (Timestamp, u64) {
+        (self.timestamp, self.arrival)
+    }
+}
+
+impl<T> PartialEq for Entry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<T> Eq for Entry<T> {}
+
+impl<T> PartialOrd for Entry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Entry<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ts(secs: u64) -> Option<Timestamp> {
+        Some(Timestamp::from_secs(secs))
+    }
+
+    #[test]
+    fn test_records_window() {
+        let mut buffer = ReorderBuffer::new(ReorderWindow::Records(2));
+        assert!(buffer.push(ts(3), 'c').is_empty());
+        assert!(buffer.push(ts(1), 'a').is_empty());
+        assert_eq!(buffer.push(ts(2), 'b'), vec!['a']);
+        assert_eq!(buffer.push(None, 'd'), vec!['b']);
+        assert_eq!(buffer.drain(), vec!['c', 'd']);
+    }
+
+    #[test]
+    fn test_seconds_window() {
+        let mut buffer = ReorderBuffer::new(ReorderWindow::Seconds(10));
+        assert!(buffer.push(ts(105), 'b').is_empty());
+        assert!(buffer.push(ts(100), 'a').is_empty());
+        assert_eq!(buffer.push(ts(112), 'c'), vec!['a']);
+        assert_eq!(buffer.push(ts(120), 'd'), vec!['b']);
+        assert_eq!(buffer.drain(), vec!['c', 'd']);
+    }
+
+    #[test]
+    fn test_huge_seconds_window() {
+        let mut buffer = ReorderBuffer::new(ReorderWindow::Seconds(u64::MAX));
+        assert!(buffer.push(ts(100), 'a').is_empty());
+        assert!(buffer.push(ts(u64::MAX), 'b').is_empty());
+        assert_eq!(buffer.drain(), vec!['a', 'b']);
+    }
+
+    #[test]
+    fn test_reorder_window_from_str() {
+        assert_eq!("100".parse(), Ok(ReorderWindow::Records(100)));
+        assert_eq!("30s".parse(), Ok(ReorderWindow::Seconds(30)));
+        assert!("soon".parse::<ReorderWindow>().is_err());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::memory::hash_map_bytes;
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{Account, ClientId};
+use core::ops::Index;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::{Deserialize, Serialize};
+
+/// Holds the accounts of a `TransactionProcessor` by client id, e.g. in memory,
+/// or cached from a SQL or key-value backend. Accounts are borrowed while they
+/// are processed, so a backend keeps the ones it loaded in memory.
+pub trait AccountRepository: Send {
+    fn get(&self, client_id: ClientId) -> Option<&Account>;
+
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut Account>;
+
+    /// The account of `client_id`, which is created with `create` if it does not
+    /// exist.
+    fn get_or_create(
+        &mut self,
+        client_id: ClientId,
+        create: &mut dyn FnMut() -> Account,
+    ) -> &mut Account;
+
+    /// Inserts `account`, returning the account of `client_id` it replaces.
+    fn insert(&mut self, client_id: ClientId, account: Account) -> Option<Account>;
+
+    fn remove(&mut self, client_id: ClientId) -> Option<Account>;
+
+    fn contains(&self, client_id: ClientId) -> bool {
+        self.get(client_id).is_some()
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All accounts, in no particular order.
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &Account)> + '_>;
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (ClientId, &mut Account)> + '_>;
+
+    /// An estimate of the heap memory used by the repository itself, not by the
+    /// accounts, for `memory_stats`. Zero by default.
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+
+    /// Removes all accounts, e.g. to move them to another repository.
+    fn drain(&mut self) -> Vec<(ClientId, Account)> {
+        let client_ids: Vec<_> = self.iter().map(|(client_id, _)| client_id).collect();
+        client_ids
+            .into_iter()
+            .filter_map(|client_id| Some((client_id, self.remove(client_id)?)))
+            .collect()
+    }
+}
+
+impl Index<&ClientId> for dyn AccountRepository + '_ {
+    type Output = Account;
+
+    /// The account of `client_id`, which panics if it does not exist.
+    fn index(&self, client_id: &ClientId) -> &Account {
+        self.get(*client_id).expect("no account for the client id")
+    }
+}
+
+/// Keeps accounts in a hash map, the default.
+#[derive(Clone, Default)]
+pub struct MemoryAccountRepository {
+    accounts: HashMap<ClientId, Account>,
+}
+
+impl AccountRepository for MemoryAccountRepository {
+    fn get(&self, client_id: ClientId) -> Option<&Account> {
+        self.accounts.get(&client_id)
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> Option<&mut Account> {
+        self.accounts.get_mut(&client_id)
+    }
+
+    fn get_or_create(
+        &mut self,
+        client_id: ClientId,
+        create: &mut dyn FnMut() -> Account,
+    ) -> &mut Account {
+        self.accounts.entry(client_id).or_insert_with(create)
+    }
+
+    fn insert(&mut self, client_id: ClientId, account: Account) -> Option<Account> {
+        self.accounts.insert(client_id, account)
+    }
+
+    fn remove(&mut self, client_id: ClientId) -> Option<Account> {
+        self.accounts.remove(&client_id)
+    }
+
+    fn contains(&self, client_id: ClientId) -> bool {
+        self.accounts.contains_key(&client_id)
+    }
+
+    fn len(&self) -> usize {
+        self.accounts.len()
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &Account)> + '_> {
+        Box::new(
+            self.accounts
+                .iter()
+                .map(|(client_id, account)| (*client_id, account)),
+        )
+    }
+
+    fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (ClientId, &mut Account)> + '_> {
+        Box::new(
+            self.accounts
+                .iter_mut()
+                .map(|(client_id, account)| (*client_id, account)),
+        )
+    }
+
+    fn heap_bytes(&self) -> usize {
+        hash_map_bytes(&self.accounts)
+    }
+
+    fn drain(&mut self) -> Vec<(ClientId, Account)> {
+        self.accounts.drain().collect()
+    }
+}
+
+/// Serializes a repository as a map of its accounts by client id.
+impl Serialize for dyn AccountRepository + '_ {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// Deserializes a map of accounts by client id into a `MemoryAccountRepository`.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn AccountRepository>, D::Error> {
+    let accounts = HashMap::deserialize(deserializer)?;
+    Ok(Box::new(MemoryAccountRepository { accounts }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Price4, Transaction, TransactionId, TransactionProcessor};
+    use std::sync::{Arc, Mutex};
+
+    /// Records the clients whose accounts are changed.
+    #[derive(Default)]
+    struct RecordingRepository {
+        inner: MemoryAccountRepository,
+        changed: Arc<Mutex<Vec<ClientId>>>,
+    }
+
+    impl AccountRepository for RecordingRepository {
+        fn get(&self, client_id: ClientId) -> Option<&Account> {
+            self.inner.get(client_id)
+        }
+
+        fn get_mut(&mut self, client_id: ClientId) -> Option<&mut Account> {
+            self.changed.lock().unwrap().push(client_id);
+            self.inner.get_mut(client_id)
+        }
+
+        fn get_or_create(
+            &mut self,
+            client_id: ClientId,
+            create: &mut dyn FnMut() -> Account,
+        ) -> &mut Account {
+            self.changed.lock().unwrap().push(client_id);
+            self.inner.get_or_create(client_id, create)
+        }
+
+        fn insert(&mut self, client_id: ClientId, account: Account) -> Option<Account> {
+            self.inner.insert(client_id, account)
+        }
+
+        fn remove(&mut self, client_id: ClientId) -> Option<Account> {
+            self.inner.remove(client_id)
+        }
+
+        fn len(&self) -> usize {
+            self.inner.len()
+        }
+
+        fn iter(&self) -> Box<dyn Iterator<Item = (ClientId, &Account)> + '_> {
+            self.inner.iter()
+        }
+
+        fn iter_mut(&mut self) -> Box<dyn Iterator<Item = (ClientId, &mut Account)> + '_> {
+            self.inner.iter_mut()
+        }
+    }
+
+    #[test]
+    fn test_account_repository() {
+        let deposit = |client_id, tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id: ClientId(client_id),
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(1, 0),
+            })
+        };
+        let mut processor = TransactionProcessor::new();
+        processor.process(deposit(1, 1)).unwrap();
+        let repository = RecordingRepository::default();
+        let changed = Arc::clone(&repository.changed);
+        processor.set_account_repository(Box::new(repository));
+        assert_eq!(processor.accounts().len(), 1);
+
+        processor.process(deposit(2, 2)).unwrap();
+        processor.process(deposit(1, 3)).unwrap();
+        assert!(changed.lock().unwrap().contains(&ClientId(2)));
+        assert_eq!(
+            processor.accounts()[&ClientId(1)].available_funds(),
+            Price4::new(2, 0)
+        );
+
+        // Serialized repositories read back as hash maps.
+        let json = serde_json::to_string(&processor).unwrap();
+        let processor: TransactionProcessor = serde_json::from_str(&json).unwrap();
+        assert!(processor.accounts().contains(ClientId(2)));
+    }
+}
@@ -0,0 +1,288 @@
+use crate::prelude::*;
+use crate::{Account, AuditEntry, Price4, Transaction, TransactionProcessor, TransactionState};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "io")]
+use std::path::Path;
+#[cfg(feature = "io")]
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RiskConfigError {
+    #[cfg(feature = "io")]
+    #[error("could not read risk config {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("invalid risk config: {0}")]
+    Invalid(String),
+}
+
+/// How much each event adds to a client's risk score, and the scores at which an
+/// account is flagged for review or frozen.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RiskConfig {
+    /// Added for every dispute opened.
+    pub dispute_weight: u32,
+    /// Added for every chargeback.
+    pub chargeback_weight: u32,
+    /// Added for every rapid withdrawal, see `rapid_withdrawals`.
+    pub rapid_withdrawal_weight: u32,
+    /// A withdrawal is rapid if it is at least this many withdrawals in a row,
+    /// without any other transaction of the client in between.
+    pub rapid_withdrawals: u32,
+    /// Flags the account for review once its score reaches this.
+    pub review_threshold: Option<u32>,
+    /// Freezes the account once its score reaches this.
+    pub freeze_threshold: Option<u32>,
+    /// Acts on the account once it has more than this many open disputes.
+    pub max_open_disputes: Option<u32>,
+    /// Acts on the account once its open disputes hold more than this in total.
+    pub max_disputed_amount: Option<Price4>,
+    /// What happens to an account over a dispute limit.
+    pub dispute_limit_action: DisputeLimitAction,
+}
+
+/// What happens to an account once its open disputes are over a limit of the
+/// `RiskConfig`. Either way, it is recorded in the audit log as `dispute_limit`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DisputeLimitAction {
+    /// The account is flagged for review, the default.
+    #[default]
+    Review,
+    Freeze,
+}
+
+impl Default for RiskConfig {
+    fn default() -> RiskConfig {
+        RiskConfig {
+            dispute_weight: 10,
+            chargeback_weight: 50,
+            rapid_withdrawal_weight: 5,
+            rapid_withdrawals: 3,
+            review_threshold: None,
+            freeze_threshold: None,
+            max_open_disputes: None,
+            max_disputed_amount: None,
+            dispute_limit_action: DisputeLimitAction::Review,
+        }
+    }
+}
+
+impl RiskConfig {
+    #[cfg(feature = "io")]
+    pub fn from_toml(config: &str) -> Result<RiskConfig, RiskConfigError> {
+        toml::from_str(config).map_err(|e| RiskConfigError::Invalid(e.to_string()))
+    }
+
+    #[cfg(feature = "io")]
+    pub fn load(path: &Path) -> Result<RiskConfig, RiskConfigError> {
+        let config =
+            std::fs::read_to_string(path).map_err(|e| RiskConfigError::Io(path.to_owned(), e))?;
+        RiskConfig::from_toml(&config)
+    }
+}
+
+/// A client's risk score and what led to it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Risk {
+    score: u32,
+    /// The number of withdrawals in a row, up to the latest transaction.
+    withdrawal_streak: u32,
+    under_review: bool,
+}
+
+impl Risk {
+    pub fn score(&self) -> u32 {
+        self.score
+    }
+
+    /// Whether the score reached the review threshold. An account stays under
+    /// review once flagged.
+    pub fn is_under_review(&self) -> bool {
+        self.under_review
+    }
+
+    pub(crate) fn is_default(&self) -> bool {
+        *self == Risk::default()
+    }
+}
+
+impl TransactionProcessor {
+    /// Scores risk for clients with `config`. Without a config, which is the
+    /// default, scores are not updated.
+    pub fn set_risk_config(&mut self, config: RiskConfig) {
+        self.policies.risk_config = Some(config);
+    }
+
+    /// Updates the risk score of the client of a successfully processed
+    /// transaction, flagging or freezing the account if a threshold or a dispute
+    /// limit is reached.
+    pub(crate) fn score_risk(&mut self, transaction: &Transaction) {
+        let config = match self.policies.risk_config.as_ref() {
+            Some(config) => config,
+            None => return,
+        };
+        let account = match self.accounts.get_mut(transaction.client_id()) {
+            Some(account) => account,
+            None => return,
+        };
+        let risk = &mut account.risk;
+        let points = match transaction {
+            Transaction::Withdrawal(_) => {
+                risk.withdrawal_streak = risk.withdrawal_streak.saturating_add(1);
+                if risk.withdrawal_streak >= config.rapid_withdrawals {
+                    config.rapid_withdrawal_weight
+                } else {
+                    0
+                }
+            }
+            Transaction::Dispute(_) => config.dispute_weight,
+            Transaction::Chargeback(_) | Transaction::ChargebackConfirm(_) => {
+                config.chargeback_weight
+            }
+            Transaction::Deposit(_)
+            | Transaction::Resolve(_)
+            | Transaction::ChargebackRequest(_)
+            | Transaction::Authorize(_)
+            | Transaction::Capture(_)
+            | Transaction::Settle(_)
+            | Transaction::Fail(_)
+            | Transaction::Reversal(_)
+            | Transaction::Adjustment(_)
+            | Transaction::EscrowOpen(_)
+            | Transaction::EscrowRelease(_)
+            | Transaction::EscrowRefund(_)
+            | Transaction::Convert(_) => 0,
+        };
+        if !matches!(transaction, Transaction::Withdrawal(_)) {
+            risk.withdrawal_streak = 0;
+        }
+        risk.score = risk.score.saturating_add(points);
+        if config
+            .review_threshold
+            .is_some_and(|threshold| risk.score >= threshold)
+        {
+            risk.under_review = true;
+        }
+        if config
+            .freeze_threshold
+            .is_some_and(|threshold| risk.score >= threshold)
+        {
+            account.is_frozen = true;
+        }
+
+        if let Transaction::Dispute(dispute) = transaction {
+            if config.max_open_disputes.is_none() && config.max_disputed_amount.is_none() {
+                return;
+            }
+            let (count, amount) = open_disputes(account);
+            let over_limit = config.max_open_disputes.is_some_and(|max| count > max)
+                || config.max_disputed_amount.is_some_and(|max| amount > max);
+            let flag = match config.dispute_limit_action {
+                DisputeLimitAction::Review => &mut account.risk.under_review,
+                DisputeLimitAction::Freeze => &mut account.is_frozen,
+            };
+            if over_limit && !*flag {
+                *flag = true;
+                self.audit(AuditEntry {
+                    client_id: Some(dispute.client_id),
+                    tx_id: Some(dispute.tx_id),
+                    action: "dispute_limit",
+                    amount: Price4::ZERO,
+                    ..AuditEntry::default()
+                });
+            }
+        }
+    }
+}
+
+/// The number of open disputes of `account`, and the total they hold.
+fn open_disputes(account: &Account) -> (u32, Price4) {
+    account
+        .txs
+        .transactions()
+        .filter(|tx| {
+            matches!(
+                tx.state,
+                TransactionState::InDispute | TransactionState::ChargebackRequested
+            )
+        })
+        .fold((0, Price4::ZERO), |(count, amount), tx| {
+            (count + 1, amount + tx.held.unwrap_or(tx.amount))
+        })
+}
+
+#[cfg(all(test, feature = "io"))]
+mod test {
+    use super::*;
+    use crate::{ClientId, Deposit, Dispute, Price4, Resolve, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_risk_score() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_risk_config(
+            RiskConfig::from_toml(
+                "
+                rapid_withdrawals = 2
+                review_threshold = 15
+                freeze_threshold = 25
+                ",
+            )
+            .unwrap(),
+        );
+        let client_id = ClientId(1);
+        let risk = |processor: &TransactionProcessor| processor.accounts()[&client_id].risk();
+        let deposit = |tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(10, 0),
+            })
+        };
+        let withdrawal = |tx_id| {
+            Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(1, 0),
+            })
+        };
+        processor.process(deposit(1)).unwrap();
+        processor.process(withdrawal(2)).unwrap();
+        assert_eq!(risk(&processor).score(), 0);
+        processor.process(withdrawal(3)).unwrap();
+        assert_eq!(risk(&processor).score(), 5);
+        // A failed transaction does not count.
+        assert!(processor
+            .process(Transaction::Resolve(Resolve {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: None,
+            }))
+            .is_err());
+        processor.process(withdrawal(4)).unwrap();
+        assert_eq!(risk(&processor).score(), 10);
+        processor.process(deposit(5)).unwrap();
+        processor.process(withdrawal(6)).unwrap();
+        assert_eq!(risk(&processor).score(), 10);
+        assert!(!risk(&processor).is_under_review());
+
+        processor
+            .process(Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(1),
+            }))
+            .unwrap();
+        assert_eq!(risk(&processor).score(), 20);
+        assert!(risk(&processor).is_under_review());
+        assert!(!processor.accounts()[&client_id].is_frozen());
+        processor
+            .process(Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(5),
+            }))
+            .unwrap();
+        assert_eq!(risk(&processor).score(), 30);
+        assert!(processor.accounts()[&client_id].is_frozen());
+    }
+}
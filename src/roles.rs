@@ -0,0 +1,257 @@
+use crate::prelude::*;
+use crate::{
+    AuditEntry, ClientId, Error, Price4, Transaction, TransactionId, TransactionProcessor,
+};
+use crate::{HashMap, HashSet};
+use core::fmt;
+use serde::{Deserialize, Serialize};
+
+/// The role of the operator or system an operation is performed by, e.g. `risk`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Role(pub String);
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// An operation that can be restricted to roles: a kind of transaction, or an
+/// administrative operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    ChargebackRequest,
+    ChargebackConfirm,
+    Authorize,
+    Capture,
+    Settle,
+    Fail,
+    Reversal,
+    Adjustment,
+    #[serde(rename = "escrow")]
+    EscrowOpen,
+    #[serde(rename = "release")]
+    EscrowRelease,
+    #[serde(rename = "refund")]
+    EscrowRefund,
+    Convert,
+    Undo,
+    Hold,
+    ReleaseHold,
+    Unfreeze,
+    Reactivate,
+}
+
+impl Operation {
+    pub fn of(transaction: &Transaction) -> Operation {
+        match transaction {
+            Transaction::Deposit(_) => Operation::Deposit,
+            Transaction::Withdrawal(_) => Operation::Withdrawal,
+            Transaction::Dispute(_) => Operation::Dispute,
+            Transaction::Resolve(_) => Operation::Resolve,
+            Transaction::Chargeback(_) => Operation::Chargeback,
+            Transaction::ChargebackRequest(_) => Operation::ChargebackRequest,
+            Transaction::ChargebackConfirm(_) => Operation::ChargebackConfirm,
+            Transaction::Authorize(_) => Operation::Authorize,
+            Transaction::Capture(_) => Operation::Capture,
+            Transaction::Settle(_) => Operation::Settle,
+            Transaction::Fail(_) => Operation::Fail,
+            Transaction::Reversal(_) => Operation::Reversal,
+            Transaction::Adjustment(_) => Operation::Adjustment,
+            Transaction::EscrowOpen(_) => Operation::EscrowOpen,
+            Transaction::EscrowRelease(_) => Operation::EscrowRelease,
+            Transaction::EscrowRefund(_) => Operation::EscrowRefund,
+            Transaction::Convert(_) => Operation::Convert,
+        }
+    }
+
+    /// The name of the operation in permissions files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Deposit => "deposit",
+            Operation::Withdrawal => "withdrawal",
+            Operation::Dispute => "dispute",
+            Operation::Resolve => "resolve",
+            Operation::Chargeback => "chargeback",
+            Operation::ChargebackRequest => "chargeback_request",
+            Operation::ChargebackConfirm => "chargeback_confirm",
+            Operation::Authorize => "authorize",
+            Operation::Capture => "capture",
+            Operation::Settle => "settle",
+            Operation::Fail => "fail",
+            Operation::Reversal => "reversal",
+            Operation::Adjustment => "adjustment",
+            Operation::EscrowOpen => "escrow",
+            Operation::EscrowRelease => "release",
+            Operation::EscrowRefund => "refund",
+            Operation::Convert => "convert",
+            Operation::Undo => "undo",
+            Operation::Hold => "hold",
+            Operation::ReleaseHold => "release_hold",
+            Operation::Unfreeze => "unfreeze",
+            Operation::Reactivate => "reactivate",
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The operations each role may perform. An operation that no role is
+/// permitted is open to everyone, including operations without a role; one that
+/// some roles are permitted is restricted to them, e.g. `risk = ["chargeback"]`
+/// means only `risk` may charge back.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Permissions {
+    #[serde(default)]
+    pub roles: HashMap<Role, HashSet<Operation>>,
+}
+
+impl Permissions {
+    pub fn is_restricted(&self, operation: Operation) -> bool {
+        self.roles
+            .values()
+            .any(|operations| operations.contains(&operation))
+    }
+
+    /// Whether `operation` may be performed by `role`, or without a role if it
+    /// is `None`.
+    pub fn permits(&self, role: Option<&Role>, operation: Operation) -> bool {
+        if !self.is_restricted(operation) {
+            return true;
+        }
+        role.and_then(|role| self.roles.get(role))
+            .is_some_and(|operations| operations.contains(&operation))
+    }
+}
+
+impl TransactionProcessor {
+    /// Restricts operations to roles by `permissions`, or allows all operations,
+    /// which is the default.
+    pub fn set_permissions(&mut self, permissions: Option<Permissions>) {
+        self.policies.permissions = permissions;
+    }
+
+    /// Checks that `operation` on the account of client `client_id` may be
+    /// performed by `role`. An unauthorized operation is recorded in the audit log.
+    /// `process` and the administrative operations do not check permissions
+    /// themselves, so callers acting for a role check them with this first.
+    /// Returns `Error::Unauthorized` if the role is not permitted the operation.
+    ///
+    /// This function does not panic.
+    pub fn authorize(
+        &mut self,
+        role: Option<&Role>,
+        operation: Operation,
+        client_id: ClientId,
+        tx_id: Option<TransactionId>,
+    ) -> Result<(), Error> {
+        match &self.policies.permissions {
+            Some(permissions) if !permissions.permits(role, operation) => {}
+            _ => return Ok(()),
+        }
+        self.audit(AuditEntry {
+            client_id: Some(client_id),
+            tx_id,
+            action: "unauthorized",
+            amount: Price4::ZERO,
+            role: role.cloned(),
+            operation: Some(operation),
+            ..AuditEntry::default()
+        });
+        Err(Error::Unauthorized {
+            role: role.cloned(),
+            operation,
+        })
+    }
+
+    /// Processes `transaction` like `process` if `role` is permitted it.
+    pub fn process_as(
+        &mut self,
+        role: Option<&Role>,
+        transaction: Transaction,
+    ) -> Result<(), Error> {
+        self.authorize(
+            role,
+            Operation::of(&transaction),
+            transaction.client_id(),
+            Some(transaction.tx_id()),
+        )?;
+        self.process(transaction)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Chargeback, Deposit, Dispute};
+
+    #[test]
+    fn test_permissions() {
+        let mut processor = TransactionProcessor::new();
+        let (risk, ops) = (Role("risk".to_string()), Role("ops".to_string()));
+        processor.set_permissions(Some(Permissions {
+            roles: core::iter::once((
+                risk.clone(),
+                vec![Operation::Chargeback, Operation::Unfreeze]
+                    .into_iter()
+                    .collect(),
+            ))
+            .collect(),
+        }));
+        let client_id = ClientId(1);
+        let tx_id = TransactionId(1);
+        // Operations no role is permitted are open to everyone.
+        processor
+            .process_as(
+                None,
+                Transaction::Deposit(Deposit {
+                    client_id,
+                    tx_id,
+                    amount: Price4::new(10, 0),
+                }),
+            )
+            .unwrap();
+        processor
+            .process_as(
+                Some(&ops),
+                Transaction::Dispute(Dispute { client_id, tx_id }),
+            )
+            .unwrap();
+
+        let chargeback = Transaction::Chargeback(Chargeback { client_id, tx_id });
+        let err = processor.process_as(Some(&ops), chargeback).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "operation `chargeback` is not permitted for role `ops`"
+        );
+        assert!(processor.process_as(None, chargeback).is_err());
+        let log = processor.take_audit_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!(
+            (log[0].action, log[0].role.as_ref(), log[0].operation),
+            ("unauthorized", Some(&ops), Some(Operation::Chargeback))
+        );
+        assert!(!processor.accounts()[&client_id].is_frozen());
+
+        processor.process_as(Some(&risk), chargeback).unwrap();
+        assert!(processor
+            .authorize(Some(&ops), Operation::Unfreeze, client_id, None)
+            .is_err());
+        processor
+            .authorize(Some(&risk), Operation::Unfreeze, client_id, None)
+            .unwrap();
+        processor.unfreeze(client_id).unwrap();
+        assert!(!processor.accounts()[&client_id].is_frozen());
+    }
+}
@@ -0,0 +1,542 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{hold, Account, ClientId, Price4, RejectReason, Transaction, TransactionProcessor};
+use alloc::collections::VecDeque;
+use serde::Deserialize;
+#[cfg(feature = "io")]
+use std::path::Path;
+#[cfg(feature = "io")]
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RulesError {
+    #[cfg(feature = "io")]
+    #[error("could not read rules {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[cfg(feature = "io")]
+    #[error("rules {0} must be a .toml, .yaml or .yml file")]
+    UnknownFormat(PathBuf),
+    #[error("invalid rules: {0}")]
+    Invalid(String),
+}
+
+/// The kinds of transactions a rule can match.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Kind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+    #[serde(rename = "chargeback_request")]
+    ChargebackRequest,
+    #[serde(rename = "chargeback_confirm")]
+    ChargebackConfirm,
+    Authorize,
+    Capture,
+    Settle,
+    Fail,
+    Reversal,
+    Adjustment,
+    #[serde(rename = "escrow")]
+    EscrowOpen,
+    #[serde(rename = "release")]
+    EscrowRelease,
+    #[serde(rename = "refund")]
+    EscrowRefund,
+    Convert,
+}
+
+impl Kind {
+    fn of(transaction: &Transaction) -> Kind {
+        match transaction {
+            Transaction::Deposit(_) => Kind::Deposit,
+            Transaction::Withdrawal(_) => Kind::Withdrawal,
+            Transaction::Dispute(_) => Kind::Dispute,
+            Transaction::Resolve(_) => Kind::Resolve,
+            Transaction::Chargeback(_) => Kind::Chargeback,
+            Transaction::ChargebackRequest(_) => Kind::ChargebackRequest,
+            Transaction::ChargebackConfirm(_) => Kind::ChargebackConfirm,
+            Transaction::Authorize(_) => Kind::Authorize,
+            Transaction::Capture(_) => Kind::Capture,
+            Transaction::Settle(_) => Kind::Settle,
+            Transaction::Fail(_) => Kind::Fail,
+            Transaction::Reversal(_) => Kind::Reversal,
+            Transaction::Adjustment(_) => Kind::Adjustment,
+            Transaction::EscrowOpen(_) => Kind::EscrowOpen,
+            Transaction::EscrowRelease(_) => Kind::EscrowRelease,
+            Transaction::EscrowRefund(_) => Kind::EscrowRefund,
+            Transaction::Convert(_) => Kind::Convert,
+        }
+    }
+}
+
+/// What happens to a transaction matched by a rule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    /// The transaction is not applied.
+    Reject,
+    /// The transaction is applied and reported.
+    Flag,
+    /// The transaction is applied, and a deposit's funds are held as if it was
+    /// disputed, until a resolve releases them. Other transactions are only
+    /// flagged.
+    Hold,
+    /// The account is frozen and the transaction is not applied.
+    Freeze,
+}
+
+/// A condition on the client's recently applied transactions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Recent {
+    /// How many of the client's latest transactions to look at.
+    last: usize,
+    /// Only count transactions of these kinds.
+    kind: Option<Vec<Kind>>,
+    /// At least this many of them must match, including the current one.
+    min_count: Option<usize>,
+    /// Their amounts, including the current one, must add up to at least this.
+    min_total: Option<Price4>,
+}
+
+/// A rule: all of its given conditions must hold for its action to be taken.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    name: String,
+    action: Action,
+    kind: Option<Vec<Kind>>,
+    min_amount: Option<Price4>,
+    max_amount: Option<Price4>,
+    clients: Option<Vec<ClientId>>,
+    /// The account's available funds before the transaction are below this.
+    available_below: Option<Price4>,
+    locked: Option<bool>,
+    recent: Option<Recent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    #[serde(default, rename = "rule")]
+    rules: Vec<Rule>,
+}
+
+/// A transaction as remembered for `recent` conditions.
+#[derive(Debug, Clone, Copy)]
+struct Applied {
+    kind: Kind,
+    amount: Price4,
+}
+
+/// A set of declarative rules, evaluated before applying each transaction.
+///
+/// Rules are configured as a list of `rule` tables (TOML) or a `rule` list (YAML),
+/// each with a `name`, an `action` (`reject`, `flag`, `hold` or `freeze`) and any
+/// of the conditions `kind`, `min_amount`, `max_amount`, `clients`,
+/// `available_below`, `locked` and `recent`:
+///
+/// ```toml
+/// [[rule]]
+/// name = "rapid-withdrawals"
+/// action = "flag"
+/// kind = ["withdrawal"]
+/// recent = { last = 5, kind = ["withdrawal"], min_count = 3 }
+/// ```
+///
+/// Every rule is evaluated, and the number of transactions each rule matched is
+/// counted. A transaction matched by a `reject` or `freeze` rule is not applied.
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    hits: Vec<u64>,
+    /// The latest applied transactions per client, as many as `recent` conditions
+    /// look at.
+    history: HashMap<ClientId, VecDeque<Applied>>,
+    history_len: usize,
+}
+
+/// The effect of the rules on an applied transaction.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuleOutcome {
+    /// The names of the `flag` rules that matched.
+    pub flagged_by: Vec<String>,
+    /// The name of the first `hold` rule that matched.
+    pub held_by: Option<String>,
+}
+
+impl RuleSet {
+    #[cfg(feature = "io")]
+    pub fn from_toml(config: &str) -> Result<RuleSet, RulesError> {
+        let config = toml::from_str(config).map_err(|e| RulesError::Invalid(e.to_string()))?;
+        RuleSet::new(config)
+    }
+
+    /// Reads rules from JSON, e.g. without the `io` feature.
+    pub fn from_json(config: &str) -> Result<RuleSet, RulesError> {
+        let config =
+            serde_json::from_str(config).map_err(|e| RulesError::Invalid(e.to_string()))?;
+        RuleSet::new(config)
+    }
+
+    #[cfg(feature = "io")]
+    pub fn from_yaml(config: &str) -> Result<RuleSet, RulesError> {
+        let config =
+            serde_yaml::from_str(config).map_err(|e| RulesError::Invalid(e.to_string()))?;
+        RuleSet::new(config)
+    }
+
+    /// Loads rules from a TOML or YAML file, depending on its extension.
+    #[cfg(feature = "io")]
+    pub fn load(path: &Path) -> Result<RuleSet, RulesError> {
+        let config =
+            std::fs::read_to_string(path).map_err(|e| RulesError::Io(path.to_owned(), e))?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("toml") => RuleSet::from_toml(&config),
+            Some("yaml") | Some("yml") => RuleSet::from_yaml(&config),
+            _ => Err(RulesError::UnknownFormat(path.to_owned())),
+        }
+    }
+
+    fn new(config: Config) -> Result<RuleSet, RulesError> {
+        for (index, rule) in config.rules.iter().enumerate() {
+            if config.rules[..index].iter().any(|r| r.name == rule.name) {
+                return Err(RulesError::Invalid(format!(
+                    "duplicate rule name `{}`",
+                    rule.name
+                )));
+            }
+            if rule.recent.as_ref().is_some_and(|recent| recent.last == 0) {
+                return Err(RulesError::Invalid(format!(
+                    "rule `{}` looks at the last 0 transactions",
+                    rule.name
+                )));
+            }
+        }
+        let history_len = config
+            .rules
+            .iter()
+            .filter_map(|rule| rule.recent.as_ref())
+            .map(|recent| recent.last)
+            .max()
+            .unwrap_or(0);
+        Ok(RuleSet {
+            hits: vec![0; config.rules.len()],
+            rules: config.rules,
+            history: HashMap::new(),
+            history_len,
+        })
+    }
+
+    /// The number of transactions each rule matched, in the order of the rules.
+    pub fn hits(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.rules
+            .iter()
+            .map(|rule| rule.name.as_str())
+            .zip(self.hits.iter().copied())
+    }
+
+    /// Returns the indexes of the rules matching `transaction` with the trace of
+    /// their conditions, counting them.
+    fn evaluate(
+        &mut self,
+        account: Option<&Account>,
+        transaction: &Transaction,
+    ) -> Vec<(usize, Vec<String>)> {
+        let history = self.history.get(&transaction.client_id());
+        let matched: Vec<(usize, Vec<String>)> = self
+            .rules
+            .iter()
+            .enumerate()
+            .filter_map(|(index, rule)| {
+                trace(rule, account, transaction, history).map(|trace| (index, trace))
+            })
+            .collect();
+        for (index, _) in matched.iter() {
+            self.hits[*index] += 1;
+        }
+        matched
+    }
+
+    /// Remembers an applied transaction for `recent` conditions.
+    fn record(&mut self, transaction: &Transaction) {
+        if self.history_len == 0 {
+            return;
+        }
+        let history = self.history.entry(transaction.client_id()).or_default();
+        if history.len() == self.history_len {
+            history.pop_back();
+        }
+        history.push_front(Applied {
+            kind: Kind::of(transaction),
+            amount: transaction.amount().unwrap_or(Price4::ZERO),
+        });
+    }
+}
+
+/// Returns the values for which each of `rule`'s conditions holds, e.g.
+/// `amount 150.0000 >= 100.0000`, or `None` if any of them does not.
+fn trace(
+    rule: &Rule,
+    account: Option<&Account>,
+    transaction: &Transaction,
+    history: Option<&VecDeque<Applied>>,
+) -> Option<Vec<String>> {
+    let kind = Kind::of(transaction);
+    let amount = transaction.amount();
+    let available = account.map_or(Price4::ZERO, Account::available_funds);
+    let locked = account.is_some_and(Account::is_frozen);
+    let is_kind =
+        |kinds: &Option<Vec<Kind>>, kind| kinds.as_ref().is_none_or(|k| k.contains(&kind));
+    let mut trace = Vec::new();
+    if rule.kind.is_some() {
+        if !is_kind(&rule.kind, kind) {
+            return None;
+        }
+        trace.push(format!("type {:?}", kind));
+    }
+    // Amount conditions only match transactions with an amount.
+    if let Some(min) = rule.min_amount {
+        let amount = amount.filter(|amount| *amount >= min)?;
+        trace.push(format!("amount {:.4} >= {:.4}", amount, min));
+    }
+    if let Some(max) = rule.max_amount {
+        let amount = amount.filter(|amount| *amount <= max)?;
+        trace.push(format!("amount {:.4} <= {:.4}", amount, max));
+    }
+    if let Some(clients) = rule.clients.as_ref() {
+        if !clients.contains(&transaction.client_id()) {
+            return None;
+        }
+        trace.push(format!("client {} listed", transaction.client_id().0));
+    }
+    if let Some(limit) = rule.available_below {
+        if available >= limit {
+            return None;
+        }
+        trace.push(format!("available {:.4} < {:.4}", available, limit));
+    }
+    if let Some(expected) = rule.locked {
+        if locked != expected {
+            return None;
+        }
+        trace.push(format!("locked {}", locked));
+    }
+    if let Some(recent) = rule.recent.as_ref() {
+        let current = Applied {
+            kind,
+            amount: amount.unwrap_or(Price4::ZERO),
+        };
+        let recent_txs: Vec<Applied> = core::iter::once(current)
+            .chain(history.into_iter().flatten().copied())
+            .take(recent.last)
+            .filter(|applied| is_kind(&recent.kind, applied.kind))
+            .collect();
+        // An overflowing total is above any limit.
+        let total = recent_txs
+            .iter()
+            .try_fold(Price4::ZERO, |total, applied| {
+                total.checked_add(applied.amount)
+            })
+            .unwrap_or(Price4::MAX);
+        if recent.min_count.is_some_and(|min| recent_txs.len() < min)
+            || recent.min_total.is_some_and(|min| total < min)
+        {
+            return None;
+        }
+        trace.push(format!(
+            "{} matching of the last {} transactions totalling {:.4}",
+            recent_txs.len(),
+            recent.last,
+            total
+        ));
+    }
+    Some(trace)
+}
+
+impl TransactionProcessor {
+    /// Evaluates `rules` for `transaction` and then processes it, see `process`.
+    ///
+    /// Returns an error if a `reject` or `freeze` rule matched, in which case the
+    /// transaction is not processed (but the account is frozen for `freeze`), or if
+    /// processing fails.
+    ///
+    /// This function does not panic.
+    pub fn process_with_rules(
+        &mut self,
+        transaction: Transaction,
+        rules: &mut RuleSet,
+    ) -> Result<RuleOutcome, crate::Error> {
+        let client_id = transaction.client_id();
+        let matched = rules.evaluate(self.accounts.get(client_id), &transaction);
+        let first = |action| {
+            matched
+                .iter()
+                .map(|(index, trace)| (&rules.rules[*index], trace))
+                .find(|(rule, _)| rule.action == action)
+                .map(|(rule, trace)| RejectReason::Rule {
+                    name: rule.name.clone(),
+                    trace: match trace.is_empty() {
+                        true => "no conditions".to_string(),
+                        false => trace.join(", "),
+                    },
+                })
+        };
+        if let Some(reason) = first(Action::Freeze) {
+            self.account_mut(client_id).is_frozen = true;
+            return Err(reason.into());
+        }
+        if let Some(reason) = first(Action::Reject) {
+            return Err(reason.into());
+        }
+        let held_by = first(Action::Hold).map(|reason| match reason {
+            RejectReason::Rule { name, .. } => name,
+            _ => unreachable!("`first` only returns rule rejections"),
+        });
+        self.process(transaction)?;
+        let mut outcome = RuleOutcome {
+            flagged_by: matched
+                .iter()
+                .map(|(index, _)| &rules.rules[*index])
+                .filter(|rule| rule.action == Action::Flag)
+                .map(|rule| rule.name.clone())
+                .collect(),
+            held_by: None,
+        };
+        rules.record(&transaction);
+        match (held_by, transaction) {
+            (Some(name), Transaction::Deposit(deposit)) => {
+                let account = self
+                    .accounts
+                    .get_mut(client_id)
+                    .expect("deposit was processed");
+                hold(account, deposit.tx_id)?;
+                outcome.held_by = Some(name);
+            }
+            (Some(name), _) => outcome.flagged_by.push(name),
+            (None, _) => {}
+        }
+        Ok(outcome)
+    }
+}
+
+#[cfg(all(test, feature = "io"))]
+mod test {
+    use super::*;
+    use crate::{Deposit, Error, Resolve, TransactionId, Withdrawal};
+
+    const RULES: &str = r#"
+        [[rule]]
+        name = "blocked-client"
+        action = "freeze"
+        clients = [9]
+
+        [[rule]]
+        name = "large-withdrawal"
+        action = "reject"
+        kind = ["withdrawal"]
+        min_amount = 100
+
+        [[rule]]
+        name = "large-deposit"
+        action = "hold"
+        kind = ["deposit"]
+        min_amount = 1000
+
+        [[rule]]
+        name = "rapid-withdrawals"
+        action = "flag"
+        kind = ["withdrawal"]
+        recent = { last = 3, kind = ["withdrawal"], min_count = 2 }
+    "#;
+
+    fn deposit(client_id: u16, tx_id: u32, amount: i64) -> Transaction {
+        Transaction::Deposit(Deposit {
+            client_id: ClientId(client_id),
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        })
+    }
+
+    fn withdrawal(client_id: u16, tx_id: u32, amount: i64) -> Transaction {
+        Transaction::Withdrawal(Withdrawal {
+            client_id: ClientId(client_id),
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        })
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut rules = RuleSet::from_toml(RULES).unwrap();
+        let mut processor = TransactionProcessor::new();
+        let outcome = processor
+            .process_with_rules(deposit(1, 1, 1000), &mut rules)
+            .unwrap();
+        assert_eq!(outcome.held_by.as_deref(), Some("large-deposit"));
+        assert_eq!(
+            processor.accounts[&ClientId(1)].held_funds(),
+            Price4::new(1000, 0)
+        );
+        processor
+            .process(Transaction::Resolve(Resolve {
+                client_id: ClientId(1),
+                tx_id: TransactionId(1),
+                amount: None,
+            }))
+            .unwrap();
+
+        assert!(matches!(
+            processor.process_with_rules(withdrawal(1, 2, 100), &mut rules),
+            Err(Error::Rejected(RejectReason::Rule { name, trace }))
+                if name == "large-withdrawal"
+                    && trace == "type Withdrawal, amount 100.0000 >= 100.0000"
+        ));
+        let outcome = processor
+            .process_with_rules(withdrawal(1, 3, 10), &mut rules)
+            .unwrap();
+        assert!(outcome.flagged_by.is_empty());
+        let outcome = processor
+            .process_with_rules(withdrawal(1, 4, 10), &mut rules)
+            .unwrap();
+        assert_eq!(outcome.flagged_by, vec!["rapid-withdrawals".to_string()]);
+
+        assert!(processor
+            .process_with_rules(deposit(9, 5, 1), &mut rules)
+            .is_err());
+        assert!(processor.accounts[&ClientId(9)].is_frozen());
+
+        let hits: Vec<_> = rules.hits().collect();
+        assert_eq!(
+            hits,
+            vec![
+                ("blocked-client", 1),
+                ("large-withdrawal", 1),
+                ("large-deposit", 1),
+                ("rapid-withdrawals", 1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_yaml_rules() {
+        let rules = RuleSet::from_yaml(
+            "
+            rule:
+              - name: small-balance
+                action: flag
+                available_below: 5.5
+            ",
+        )
+        .unwrap();
+        assert_eq!(rules.rules[0].available_below, Some(Price4::new(55, 1)));
+        assert!(matches!(
+            RuleSet::from_toml("[[rule]]\nname = \"a\"\naction = \"ban\""),
+            Err(RulesError::Invalid(_))
+        ));
+    }
+}
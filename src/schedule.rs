@@ -0,0 +1,284 @@
+use crate::prelude::*;
+use crate::{
+    ClientId, Deposit, Error, Timestamp, Transaction, TransactionId, TransactionProcessor,
+    Withdrawal,
+};
+use core::num::{NonZeroU32, NonZeroU64};
+use serde::{Deserialize, Serialize};
+
+/// A transaction held until its effective time.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ScheduledTransaction {
+    pub effective_at: Timestamp,
+    pub transaction: Transaction,
+    /// How the transaction recurs, if it is an occurrence of a series.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recurrence: Option<Recurrence>,
+}
+
+/// The occurrences of a recurring transaction that follow a scheduled one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    /// The transaction id of the first occurrence, which identifies the series.
+    pub series: TransactionId,
+    /// The time between occurrences, in seconds.
+    pub every_secs: NonZeroU64,
+    /// The number of occurrences after the scheduled one.
+    pub remaining: u32,
+}
+
+impl ScheduledTransaction {
+    /// The next occurrence of a recurring transaction, if any. Its transaction id
+    /// follows this one's.
+    fn next(&self) -> Option<ScheduledTransaction> {
+        let recurrence = self
+            .recurrence
+            .filter(|recurrence| recurrence.remaining > 0)?;
+        let tx_id = TransactionId(self.transaction.tx_id().0.checked_add(1)?);
+        let transaction = match self.transaction {
+            Transaction::Deposit(deposit) => Transaction::Deposit(Deposit { tx_id, ..deposit }),
+            Transaction::Withdrawal(withdrawal) => Transaction::Withdrawal(Withdrawal {
+                tx_id,
+                ..withdrawal
+            }),
+            _ => return None,
+        };
+        let effective_at = self
+            .effective_at
+            .as_secs()
+            .saturating_add(recurrence.every_secs.get());
+        Some(ScheduledTransaction {
+            effective_at: Timestamp::from_secs(effective_at),
+            transaction,
+            recurrence: Some(Recurrence {
+                remaining: recurrence.remaining - 1,
+                ..recurrence
+            }),
+        })
+    }
+}
+
+impl TransactionProcessor {
+    /// Holds `transaction` until a `tick` at or after `effective_at`. Transactions
+    /// with the same effective time are applied in the order they were scheduled.
+    pub fn schedule(&mut self, transaction: Transaction, effective_at: Timestamp) {
+        self.insert_scheduled(ScheduledTransaction {
+            effective_at,
+            transaction,
+            recurrence: None,
+        });
+    }
+
+    /// Schedules `count` occurrences of the deposit or withdrawal `transaction`,
+    /// the first at `start` and then every `every_secs` seconds. The occurrences
+    /// get consecutive transaction ids starting with the id of `transaction`,
+    /// which identifies the series.
+    /// Returns an error if:
+    ///  - `transaction` is not a deposit or withdrawal, or the ids of its
+    ///    occurrences would overflow
+    ///  - the series is already scheduled, or its first id is already used by the
+    ///    client (`Error::DuplicateTx`), e.g. because it was scheduled by an
+    ///    earlier run
+    ///
+    /// This function does not panic.
+    pub fn schedule_recurring(
+        &mut self,
+        transaction: Transaction,
+        start: Timestamp,
+        every_secs: NonZeroU64,
+        count: NonZeroU32,
+    ) -> Result<(), Error> {
+        let (client_id, tx_id) = (transaction.client_id(), transaction.tx_id());
+        if !matches!(
+            transaction,
+            Transaction::Deposit(_) | Transaction::Withdrawal(_)
+        ) {
+            return Err(Error::InvalidRecurrence(format!(
+                "{:?} is not a deposit or withdrawal",
+                tx_id
+            )));
+        }
+        if tx_id.0.checked_add(count.get() - 1).is_none() {
+            return Err(Error::InvalidRecurrence(format!(
+                "{} occurrences starting at {:?} overflow the transaction ids",
+                count, tx_id
+            )));
+        }
+        let is_used = self
+            .accounts
+            .get(client_id)
+            .is_some_and(|account| account.txs.contains(tx_id));
+        if is_used || self.find_series(client_id, tx_id).is_some() {
+            return Err(Error::DuplicateTx(tx_id));
+        }
+        self.insert_scheduled(ScheduledTransaction {
+            effective_at: start,
+            transaction,
+            recurrence: Some(Recurrence {
+                series: tx_id,
+                every_secs,
+                remaining: count.get() - 1,
+            }),
+        });
+        Ok(())
+    }
+
+    /// Cancels the remaining occurrences of the recurring transaction `series`
+    /// of `client_id`.
+    /// Returns an error if no occurrence of the series is scheduled, e.g. because
+    /// all of them were applied.
+    ///
+    /// This function does not panic.
+    pub fn cancel_recurring(
+        &mut self,
+        client_id: ClientId,
+        series: TransactionId,
+    ) -> Result<(), Error> {
+        let index = self
+            .find_series(client_id, series)
+            .ok_or(Error::InvalidTx(series))?;
+        self.schedule.remove(index);
+        self.schedule_changed = true;
+        Ok(())
+    }
+
+    /// The index of the scheduled occurrence of `series`. A series only has its
+    /// next occurrence scheduled.
+    fn find_series(&self, client_id: ClientId, series: TransactionId) -> Option<usize> {
+        self.schedule.iter().position(|scheduled| {
+            scheduled.transaction.client_id() == client_id
+                && scheduled
+                    .recurrence
+                    .is_some_and(|recurrence| recurrence.series == series)
+        })
+    }
+
+    fn insert_scheduled(&mut self, scheduled: ScheduledTransaction) {
+        let index = self
+            .schedule
+            .partition_point(|other| other.effective_at <= scheduled.effective_at);
+        self.schedule.insert(index, scheduled);
+        self.schedule_changed = true;
+    }
+
+    /// The scheduled transactions, ordered by effective time.
+    pub fn scheduled(&self) -> &[ScheduledTransaction] {
+        &self.schedule
+    }
+
+    /// Removes and returns the scheduled transactions that are effective at or
+    /// before `now`, without applying them, e.g. to apply them with rules. The
+    /// next occurrences of recurring transactions are scheduled as they are
+    /// taken, so all occurrences up to `now` are returned.
+    pub fn take_due(&mut self, now: Timestamp) -> Vec<ScheduledTransaction> {
+        let mut due = Vec::new();
+        while self
+            .schedule
+            .first()
+            .is_some_and(|scheduled| scheduled.effective_at <= now)
+        {
+            let scheduled = self.schedule.remove(0);
+            if let Some(next) = scheduled.next() {
+                self.insert_scheduled(next);
+            }
+            due.push(scheduled);
+            self.schedule_changed = true;
+        }
+        due
+    }
+
+    /// Applies the scheduled transactions that are effective at or before `now`,
+    /// in order, returning each with its result.
+    ///
+    /// This function does not panic.
+    pub fn tick(&mut self, now: Timestamp) -> Vec<(Transaction, Result<(), Error>)> {
+        self.take_due(now)
+            .into_iter()
+            .map(|scheduled| {
+                let transaction = scheduled.transaction;
+                (transaction, self.process(transaction))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Price4;
+
+    #[test]
+    fn test_tick() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let withdrawal = Transaction::Withdrawal(Withdrawal {
+            client_id,
+            tx_id: TransactionId(2),
+            amount: Price4::new(5, 0),
+        });
+        let deposit = Transaction::Deposit(Deposit {
+            client_id,
+            tx_id: TransactionId(1),
+            amount: Price4::new(10, 0),
+        });
+        processor.schedule(withdrawal, Timestamp::from_secs(200));
+        processor.schedule(deposit, Timestamp::from_secs(100));
+        assert!(processor.tick(Timestamp::from_secs(99)).is_empty());
+        assert!(processor.accounts().is_empty());
+
+        let applied = processor.tick(Timestamp::from_secs(200));
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[0].0, deposit);
+        assert!(applied.iter().all(|(_, result)| result.is_ok()));
+        assert_eq!(
+            processor.accounts()[&client_id].available_funds(),
+            Price4::new(5, 0)
+        );
+        assert!(processor.scheduled().is_empty());
+    }
+
+    #[test]
+    fn test_recurring() {
+        let mut processor = TransactionProcessor::new();
+        let client_id = ClientId(1);
+        let deposit = |tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(tx_id),
+                amount: Price4::new(10, 0),
+            })
+        };
+        let every = NonZeroU64::new(100).unwrap();
+        let count = NonZeroU32::new(3).unwrap();
+        let start = Timestamp::from_secs(1000);
+        processor
+            .schedule_recurring(deposit(10), start, every, count)
+            .unwrap();
+        assert!(matches!(
+            processor.schedule_recurring(deposit(10), start, every, count),
+            Err(Error::DuplicateTx(_))
+        ));
+
+        // All occurrences up to now are applied, with consecutive ids.
+        let applied = processor.tick(Timestamp::from_secs(1150));
+        assert_eq!(applied.len(), 2);
+        assert_eq!(applied[1].0, deposit(11));
+        assert_eq!(processor.scheduled()[0].transaction, deposit(12));
+
+        processor
+            .cancel_recurring(client_id, TransactionId(10))
+            .unwrap();
+        assert!(processor.tick(Timestamp::from_secs(2000)).is_empty());
+        assert!(processor
+            .cancel_recurring(client_id, TransactionId(10))
+            .is_err());
+        // An earlier run's series is not scheduled again.
+        assert!(matches!(
+            processor.schedule_recurring(deposit(10), start, every, count),
+            Err(Error::DuplicateTx(_))
+        ));
+        assert!(processor
+            .schedule_recurring(deposit(u32::MAX), start, every, NonZeroU32::new(2).unwrap())
+            .is_err());
+    }
+}
@@ -0,0 +1,354 @@
+use crate::prelude::*;
+use crate::{RecordKind, TransactionRecord};
+use core::fmt;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+
+/// The values of the `type` column of input records.
+pub const RECORD_TYPES: [&str; 22] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "chargeback_request",
+    "chargeback_confirm",
+    "authorize",
+    "capture",
+    "settle",
+    "fail",
+    "reversal",
+    "escrow",
+    "release",
+    "refund",
+    "adjustment",
+    "convert",
+    "begin",
+    "commit",
+    "note",
+    "evidence",
+    "reactivate",
+];
+
+/// A decimal with at most 4 decimals, e.g. `1`, `.5` or `-0.0001`.
+const AMOUNT_PATTERN: &str = r"^-?([0-9]+(\.[0-9]{0,4})?|\.[0-9]{1,4})$";
+/// An amount that is not negative.
+const NON_NEGATIVE_AMOUNT_PATTERN: &str = r"^([0-9]+(\.[0-9]{0,4})?|\.[0-9]{1,4})$";
+
+/// The values of a column of input records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColumnType {
+    /// One of `RECORD_TYPES`.
+    RecordType,
+    /// An integer from 0 up to `max`.
+    Integer {
+        max: u64,
+    },
+    /// A decimal with at most 4 decimals, which must not be negative unless the
+    /// record is an adjustment.
+    Amount,
+    /// A three-letter currency code, e.g. `EUR`.
+    Currency,
+    Text,
+}
+
+impl fmt::Display for ColumnType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColumnType::RecordType => write!(f, "record type"),
+            ColumnType::Integer { max } => write!(f, "integer from 0 to {}", max),
+            ColumnType::Amount => write!(f, "decimal with at most 4 decimals"),
+            ColumnType::Currency => write!(f, "currency code"),
+            ColumnType::Text => write!(f, "text"),
+        }
+    }
+}
+
+/// Which records must have a value in a column.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Requirement {
+    Always,
+    /// Records of these types.
+    ForTypes(&'static [&'static str]),
+    Optional,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Requirement::Always => write!(f, "always"),
+            Requirement::ForTypes(types) => write!(f, "for {}", types.join(", ")),
+            Requirement::Optional => write!(f, "no"),
+        }
+    }
+}
+
+/// A column of input records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecordColumn {
+    pub name: &'static str,
+    pub kind: ColumnType,
+    pub required: Requirement,
+    pub description: &'static str,
+}
+
+const CLIENT_ID: ColumnType = ColumnType::Integer {
+    max: u16::MAX as u64,
+};
+
+/// The columns of input records. Other columns are ignored, and so are the
+/// values of columns a record's type does not use.
+pub const RECORD_COLUMNS: [RecordColumn; 14] = [
+    RecordColumn {
+        name: "type",
+        kind: ColumnType::RecordType,
+        required: Requirement::Always,
+        description: "The operation of the record",
+    },
+    RecordColumn {
+        name: "client",
+        kind: CLIENT_ID,
+        required: Requirement::Always,
+        description: "The client whose account the record is for",
+    },
+    RecordColumn {
+        name: "tx",
+        kind: ColumnType::Integer {
+            max: u32::MAX as u64,
+        },
+        required: Requirement::Always,
+        description: "The id of a new transaction, of the transaction the record refers to, \
+                      or of a batch",
+    },
+    RecordColumn {
+        name: "amount",
+        kind: ColumnType::Amount,
+        required: Requirement::ForTypes(&[
+            "deposit",
+            "withdrawal",
+            "authorize",
+            "escrow",
+            "adjustment",
+            "convert",
+        ]),
+        description: "The amount of the transaction, or the part of the held funds a resolve \
+                      releases, not negative unless it is an adjustment",
+    },
+    RecordColumn {
+        name: "timestamp",
+        kind: ColumnType::Integer { max: u64::MAX },
+        required: Requirement::Optional,
+        description: "When the transaction originally happened, in seconds since the unix epoch",
+    },
+    RecordColumn {
+        name: "effective",
+        kind: ColumnType::Integer { max: u64::MAX },
+        required: Requirement::Optional,
+        description: "When the transaction takes effect, if later than `timestamp`",
+    },
+    RecordColumn {
+        name: "reason",
+        kind: ColumnType::Integer {
+            max: u16::MAX as u64,
+        },
+        required: Requirement::ForTypes(&["adjustment"]),
+        description: "The reason code of an administrative operation",
+    },
+    RecordColumn {
+        name: "payee",
+        kind: CLIENT_ID,
+        required: Requirement::ForTypes(&["escrow"]),
+        description: "The client an escrow is for",
+    },
+    RecordColumn {
+        name: "note",
+        kind: ColumnType::Text,
+        required: Requirement::ForTypes(&["note", "evidence"]),
+        description: "The text of a dispute note, or a reference to evidence",
+    },
+    RecordColumn {
+        name: "role",
+        kind: ColumnType::Text,
+        required: Requirement::Optional,
+        description: "The role the record is submitted by",
+    },
+    RecordColumn {
+        name: "idempotency_key",
+        kind: ColumnType::Text,
+        required: Requirement::Optional,
+        description: "Identifies the submission of the record, whose retries are skipped",
+    },
+    RecordColumn {
+        name: "from_ccy",
+        kind: ColumnType::Currency,
+        required: Requirement::ForTypes(&["convert"]),
+        description: "The currency a conversion debits",
+    },
+    RecordColumn {
+        name: "to_ccy",
+        kind: ColumnType::Currency,
+        required: Requirement::ForTypes(&["convert"]),
+        description: "The currency a conversion credits",
+    },
+    RecordColumn {
+        name: "tag",
+        kind: ColumnType::Text,
+        required: Requirement::Optional,
+        description: "The free-form category of a deposit or withdrawal, e.g. `payroll`",
+    },
+];
+
+/// How an input record breaks the requirements of `RECORD_COLUMNS`.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum SchemaViolation {
+    #[error("{kind} record without a value in column `{column}`")]
+    MissingValue {
+        kind: &'static str,
+        column: &'static str,
+    },
+    #[error("{kind} record with an amount")]
+    UnexpectedAmount { kind: &'static str },
+}
+
+/// Checks `record` against the requirements of `RECORD_COLUMNS`: the columns
+/// its type requires must have a value, and its amount must be empty unless its
+/// type requires one or it is a resolve, which may release part of the held
+/// funds.
+/// Returns an error for the first column that breaks them.
+///
+/// This function does not panic.
+pub fn check_record(record: &TransactionRecord) -> Result<(), SchemaViolation> {
+    let kind = record.kind.name();
+    let requires = |column: &RecordColumn| match column.required {
+        Requirement::Always => true,
+        Requirement::ForTypes(types) => types.contains(&kind),
+        Requirement::Optional => false,
+    };
+    for column in RECORD_COLUMNS.iter() {
+        let has_value = match column.name {
+            "amount" => record.amount.is_some(),
+            "reason" => record.reason.is_some(),
+            "payee" => record.payee.is_some(),
+            "note" => record.note.as_ref().is_some_and(|note| !note.is_empty()),
+            "from_ccy" => record.from_ccy.is_some(),
+            "to_ccy" => record.to_ccy.is_some(),
+            _ => true,
+        };
+        if requires(column) && !has_value {
+            return Err(SchemaViolation::MissingValue {
+                kind,
+                column: column.name,
+            });
+        }
+        if column.name == "amount"
+            && has_value
+            && !requires(column)
+            && record.kind != RecordKind::Resolve
+        {
+            return Err(SchemaViolation::UnexpectedAmount { kind });
+        }
+    }
+    Ok(())
+}
+
+/// A JSON Schema of input records as JSON objects of their columns, with empty
+/// columns left out and amounts as strings to keep their decimals, e.g. to
+/// validate files before sending them.
+pub fn record_schema() -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    let mut rules = Vec::new();
+    for column in RECORD_COLUMNS.iter() {
+        let mut property = match column.kind {
+            ColumnType::RecordType => json!({ "enum": RECORD_TYPES }),
+            ColumnType::Integer { max } => json!({
+                "type": "integer",
+                "minimum": 0,
+                "maximum": max,
+            }),
+            ColumnType::Amount => json!({ "type": "string", "pattern": AMOUNT_PATTERN }),
+            ColumnType::Currency => json!({ "type": "string", "pattern": "^[A-Z]{3}$" }),
+            ColumnType::Text => json!({ "type": "string" }),
+        };
+        property["description"] = json!(column.description);
+        properties.insert(column.name.to_string(), property);
+        match column.required {
+            Requirement::Always => required.push(column.name),
+            Requirement::ForTypes(types) => rules.push(json!({
+                "if": { "properties": { "type": { "enum": types } } },
+                "then": { "required": [column.name] },
+            })),
+            Requirement::Optional => {}
+        }
+    }
+    rules.push(json!({
+        "if": { "properties": { "type": { "not": { "const": "adjustment" } } } },
+        "then": { "properties": { "amount": { "pattern": NON_NEGATIVE_AMOUNT_PATTERN } } },
+    }));
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "Transaction record",
+        "type": "object",
+        "properties": properties,
+        "required": required,
+        "allOf": rules,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_schema() {
+        let schema = record_schema();
+        assert_eq!(schema["required"], json!(["type", "client", "tx"]));
+        assert_eq!(schema["properties"]["client"]["maximum"], json!(65535));
+        assert_eq!(
+            schema["allOf"][0],
+            json!({
+                "if": { "properties": { "type": { "enum": [
+                    "deposit", "withdrawal", "authorize", "escrow", "adjustment", "convert"
+                ] } } },
+                "then": { "required": ["amount"] },
+            })
+        );
+        assert_eq!(
+            Requirement::ForTypes(&["note", "evidence"]).to_string(),
+            "for note, evidence"
+        );
+    }
+
+    #[test]
+    fn test_check_record() {
+        use crate::{ClientId, Price4, TransactionId};
+        let record = |kind, amount| TransactionRecord {
+            amount,
+            ..TransactionRecord::new(kind, ClientId::from(1), TransactionId::from(2))
+        };
+        let amount = Some(Price4::new(1, 0));
+        assert_eq!(check_record(&record(RecordKind::Deposit, amount)), Ok(()));
+        assert_eq!(check_record(&record(RecordKind::Resolve, amount)), Ok(()));
+        assert_eq!(check_record(&record(RecordKind::Dispute, None)), Ok(()));
+        assert_eq!(
+            check_record(&record(RecordKind::Withdrawal, None)),
+            Err(SchemaViolation::MissingValue {
+                kind: "withdrawal",
+                column: "amount"
+            })
+        );
+        assert_eq!(
+            check_record(&record(RecordKind::Chargeback, amount))
+                .unwrap_err()
+                .to_string(),
+            "chargeback record with an amount"
+        );
+        assert!(matches!(
+            check_record(&record(RecordKind::Adjustment, amount)),
+            Err(SchemaViolation::MissingValue {
+                column: "reason",
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,260 @@
+use crate::{Account, RejectReason, Transaction, TransactionValidator};
+use rhai::{Dynamic, Engine, ImmutableString, Map, Scope, AST};
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+use thiserror::Error;
+
+/// The function a rules script must define.
+const CHECK_FN: &str = "check";
+
+#[derive(Error, Debug)]
+pub enum ScriptError {
+    #[error("could not load rules script: {0}")]
+    Load(String),
+    #[error("rules script does not define `fn check(tx, account)`")]
+    MissingCheck,
+}
+
+/// What a script decided about a transaction.
+#[derive(Debug, Clone)]
+enum Decision {
+    Accept,
+    Reject(String),
+    Flag(String),
+}
+
+/// A transaction that a script flagged, but accepted.
+#[derive(Debug, Clone)]
+pub struct Flag {
+    pub transaction: Transaction,
+    pub reason: String,
+}
+
+/// The transactions flagged by a `ScriptValidator`, shared with it so they can be
+/// read after the validator was added to a processor.
+#[derive(Debug, Clone, Default)]
+pub struct Flags(Rc<RefCell<Vec<Flag>>>);
+
+impl Flags {
+    /// Removes and returns the flags recorded so far.
+    pub fn take(&self) -> Vec<Flag> {
+        std::mem::take(&mut *self.0.borrow_mut())
+    }
+}
+
+/// Validates transactions with a rhai script, so rules can be changed without
+/// rebuilding the crate.
+///
+/// The script defines `fn check(tx, account)`, where `tx` has the fields `kind`
+/// (e.g. `"withdrawal"`), `client`, `tx` and, for deposits, withdrawals and
+/// authorizations, `amount`, and `account` has `available`, `held`, `pending`,
+/// `pending_out`, `total` and `locked`. Amounts are decimals, and so are number literals like `1.5`. The
+/// function returns `accept()` (or nothing), `reject(reason)` or `flag(reason)`;
+/// flagged transactions are applied but recorded in `flags`.
+///
+/// A script that fails while checking a transaction rejects it.
+pub struct ScriptValidator {
+    engine: Engine,
+    ast: AST,
+    flags: Flags,
+}
+
+impl ScriptValidator {
+    pub fn load(path: &Path) -> Result<ScriptValidator, ScriptError> {
+        let script = std::fs::read_to_string(path)
+            .map_err(|e| ScriptError::Load(format!("{}: {}", path.display(), e)))?;
+        ScriptValidator::compile(&script)
+    }
+
+    pub fn compile(script: &str) -> Result<ScriptValidator, ScriptError> {
+        let mut engine = Engine::new();
+        engine
+            .register_type_with_name::<Decision>("Decision")
+            .register_fn("accept", || Decision::Accept)
+            .register_fn("reject", |reason: ImmutableString| {
+                Decision::Reject(reason.to_string())
+            })
+            .register_fn("flag", |reason: ImmutableString| {
+                Decision::Flag(reason.to_string())
+            });
+        let ast = engine
+            .compile(script)
+            .map_err(|e| ScriptError::Load(e.to_string()))?;
+        if !ast.iter_functions().any(|f| f.name == CHECK_FN) {
+            return Err(ScriptError::MissingCheck);
+        }
+        Ok(ScriptValidator {
+            engine,
+            ast,
+            flags: Flags::default(),
+        })
+    }
+
+    /// The transactions this validator flagged.
+    pub fn flags(&self) -> Flags {
+        self.flags.clone()
+    }
+
+    fn decide(&self, account: &Account, transaction: &Transaction) -> Result<Decision, String> {
+        let result: Dynamic = self
+            .engine
+            .call_fn(
+                &mut Scope::new(),
+                &self.ast,
+                CHECK_FN,
+                (transaction_map(transaction), account_map(account)),
+            )
+            .map_err(|e| e.to_string())?;
+        if result.is_unit() {
+            return Ok(Decision::Accept);
+        }
+        let type_name = result.type_name();
+        result
+            .try_cast::<Decision>()
+            .ok_or_else(|| format!("`check` returned a {}", type_name))
+    }
+}
+
+impl TransactionValidator for ScriptValidator {
+    fn validate(&self, account: &Account, transaction: &Transaction) -> Result<(), RejectReason> {
+        match self.decide(account, transaction) {
+            Ok(Decision::Accept) => Ok(()),
+            Ok(Decision::Reject(reason)) => Err(RejectReason::Custom(reason)),
+            Ok(Decision::Flag(reason)) => {
+                self.flags.0.borrow_mut().push(Flag {
+                    transaction: *transaction,
+                    reason,
+                });
+                Ok(())
+            }
+            Err(e) => Err(RejectReason::Custom(format!("rules script failed: {}", e))),
+        }
+    }
+}
+
+fn transaction_map(transaction: &Transaction) -> Map {
+    let kind = match transaction {
+        Transaction::Deposit(_) => "deposit",
+        Transaction::Withdrawal(_) => "withdrawal",
+        Transaction::Dispute(_) => "dispute",
+        Transaction::Resolve(_) => "resolve",
+        Transaction::Chargeback(_) => "chargeback",
+        Transaction::ChargebackRequest(_) => "chargeback_request",
+        Transaction::ChargebackConfirm(_) => "chargeback_confirm",
+        Transaction::Authorize(_) => "authorize",
+        Transaction::Capture(_) => "capture",
+        Transaction::Settle(_) => "settle",
+        Transaction::Fail(_) => "fail",
+        Transaction::Reversal(_) => "reversal",
+        Transaction::Adjustment(_) => "adjustment",
+        Transaction::EscrowOpen(_) => "escrow",
+        Transaction::EscrowRelease(_) => "release",
+        Transaction::EscrowRefund(_) => "refund",
+        Transaction::Convert(_) => "convert",
+    };
+    let mut map = Map::new();
+    map.insert("kind".into(), kind.into());
+    map.insert("client".into(), i64::from(transaction.client_id().0).into());
+    map.insert("tx".into(), i64::from(transaction.tx_id().0).into());
+    if let Some(amount) = transaction.amount() {
+        map.insert("amount".into(), Dynamic::from_decimal(amount));
+    }
+    map
+}
+
+fn account_map(account: &Account) -> Map {
+    let mut map = Map::new();
+    map.insert(
+        "available".into(),
+        Dynamic::from_decimal(account.available_funds()),
+    );
+    map.insert("held".into(), Dynamic::from_decimal(account.held_funds()));
+    map.insert(
+        "pending".into(),
+        Dynamic::from_decimal(account.pending_funds()),
+    );
+    map.insert(
+        "pending_out".into(),
+        Dynamic::from_decimal(account.pending_out_funds()),
+    );
+    map.insert("total".into(), Dynamic::from_decimal(account.total_funds()));
+    map.insert("locked".into(), account.is_frozen().into());
+    map
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        ClientId, Deposit, Error, Price4, TransactionId, TransactionProcessor, Withdrawal,
+    };
+
+    const SCRIPT: &str = r#"
+        fn check(tx, account) {
+            if tx.kind == "withdrawal" && tx.amount > account.available / 2 {
+                return reject("withdrawals are limited to half the balance");
+            }
+            if tx.kind == "deposit" && tx.amount >= 1000 {
+                return flag("large deposit");
+            }
+        }
+    "#;
+
+    #[test]
+    fn test_script_validator() {
+        let validator = ScriptValidator::compile(SCRIPT).unwrap();
+        let flags = validator.flags();
+        let mut processor = TransactionProcessor::new();
+        processor.add_validator(Box::new(validator));
+        let client_id = ClientId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(1000, 0),
+            })
+            .unwrap();
+        let withdrawal = |tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        assert!(matches!(
+            processor.process_withdrawal(withdrawal(2, 600)),
+            Err(Error::Rejected(RejectReason::Custom(reason)))
+                if reason == "withdrawals are limited to half the balance"
+        ));
+        assert!(processor.process_withdrawal(withdrawal(3, 500)).is_ok());
+        let flagged = flags.take();
+        assert_eq!(flagged.len(), 1);
+        assert_eq!(flagged[0].transaction.tx_id(), TransactionId(1));
+        assert_eq!(flagged[0].reason, "large deposit");
+        assert!(flags.take().is_empty());
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(matches!(
+            ScriptValidator::compile("fn other() {}"),
+            Err(ScriptError::MissingCheck)
+        ));
+        assert!(matches!(
+            ScriptValidator::compile("fn check(tx, account) {"),
+            Err(ScriptError::Load(_))
+        ));
+        // A failing script rejects the transaction.
+        let validator =
+            ScriptValidator::compile("fn check(tx, account) { tx.amount + 1 }").unwrap();
+        let mut processor = TransactionProcessor::new();
+        processor.add_validator(Box::new(validator));
+        let err = processor
+            .process_deposit(Deposit {
+                client_id: ClientId(1),
+                tx_id: TransactionId(1),
+                amount: Price4::new(1, 0),
+            })
+            .unwrap_err();
+        assert!(err.to_string().starts_with("rules script failed"));
+    }
+}
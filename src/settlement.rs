@@ -0,0 +1,182 @@
+use crate::{
+    calculate_amount, ClientId, Error, Price4, Side, Transaction, TransactionProcessor,
+    TransactionState,
+};
+use alloc::collections::BTreeMap;
+use serde::Serialize;
+
+/// A client's movement of funds over a run, for the settlement instructions to
+/// the banking partner. Holds of disputed withdrawals are negative, like their
+/// effect on the held funds.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Settlement {
+    #[serde(rename = "client")]
+    pub client_id: ClientId,
+    /// The deposits and captured authorizations.
+    pub deposits: Price4,
+    pub withdrawals: Price4,
+    /// The deposits minus the withdrawals.
+    pub net: Price4,
+    /// The funds put on hold by disputes.
+    pub held: Price4,
+    /// The held funds released by resolves.
+    pub released: Price4,
+    /// The held funds removed by chargebacks.
+    pub charged_back: Price4,
+}
+
+/// Sums up the movement of funds of each client from the transactions processed
+/// over a run.
+#[derive(Debug, Clone, Default)]
+pub struct SettlementTracker {
+    settlements: BTreeMap<ClientId, Settlement>,
+}
+
+impl SettlementTracker {
+    pub fn new() -> SettlementTracker {
+        SettlementTracker::default()
+    }
+
+    /// Records `transaction`, which `processor` processed successfully. Only
+    /// deposits, withdrawals, captures and the steps of disputes move funds to
+    /// settle; requested chargebacks count once they are confirmed.
+    /// Returns an error if a sum overflows.
+    ///
+    /// This function does not panic.
+    pub fn record(
+        &mut self,
+        processor: &TransactionProcessor,
+        transaction: &Transaction,
+    ) -> Result<(), Error> {
+        let tx = match processor.transaction(transaction.client_id(), transaction.tx_id()) {
+            Some(tx) => tx,
+            None => return Ok(()),
+        };
+        // The amount a dispute holds, or held before it was closed.
+        let held = match tx.side {
+            Side::Deposit => tx.held.unwrap_or(tx.amount),
+            Side::Withdrawal => -tx.held.unwrap_or(tx.amount),
+        };
+        let client_id = transaction.client_id();
+        let settlement = self
+            .settlements
+            .entry(client_id)
+            .or_insert_with(|| Settlement::new(client_id));
+        let (total, amount) = match transaction {
+            Transaction::Deposit(deposit) => (&mut settlement.deposits, deposit.amount),
+            Transaction::Capture(_) => (&mut settlement.deposits, tx.amount),
+            Transaction::Withdrawal(withdrawal) => (&mut settlement.withdrawals, withdrawal.amount),
+            Transaction::Dispute(_) => (&mut settlement.held, held),
+            Transaction::Resolve(resolve) => (
+                &mut settlement.released,
+                match (resolve.amount, tx.side) {
+                    (Some(amount), Side::Deposit) => amount,
+                    (Some(amount), Side::Withdrawal) => -amount,
+                    (None, _) => held,
+                },
+            ),
+            Transaction::Chargeback(_) | Transaction::ChargebackConfirm(_)
+                if tx.state == TransactionState::DisputeHandled =>
+            {
+                (&mut settlement.charged_back, held)
+            }
+            _ => return Ok(()),
+        };
+        *total = calculate_amount(*total, Side::Deposit, amount)?;
+        settlement.net = calculate_amount(
+            settlement.deposits,
+            Side::Withdrawal,
+            settlement.withdrawals,
+        )?;
+        Ok(())
+    }
+
+    /// The movement of funds of the clients with recorded transactions, ordered by
+    /// client.
+    pub fn settlements(&self) -> impl Iterator<Item = &Settlement> {
+        self.settlements.values()
+    }
+}
+
+impl Settlement {
+    fn new(client_id: ClientId) -> Settlement {
+        Settlement {
+            client_id,
+            deposits: Price4::ZERO,
+            withdrawals: Price4::ZERO,
+            net: Price4::ZERO,
+            held: Price4::ZERO,
+            released: Price4::ZERO,
+            charged_back: Price4::ZERO,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Chargeback, Deposit, Dispute, Resolve, TransactionId, Withdrawal};
+
+    #[test]
+    fn test_settlement_tracker() {
+        let mut processor = TransactionProcessor::new();
+        let mut tracker = SettlementTracker::new();
+        let client_id = ClientId(1);
+        let transactions = [
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            }),
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(5, 0),
+            }),
+            Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(3),
+                amount: Price4::new(3, 0),
+            }),
+            Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(1),
+            }),
+            Transaction::Dispute(Dispute {
+                client_id,
+                tx_id: TransactionId(2),
+            }),
+            Transaction::Resolve(Resolve {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Some(Price4::new(4, 0)),
+            }),
+            Transaction::Resolve(Resolve {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: None,
+            }),
+            Transaction::Chargeback(Chargeback {
+                client_id,
+                tx_id: TransactionId(1),
+            }),
+        ];
+        for transaction in transactions.iter() {
+            processor.process(*transaction).unwrap();
+            tracker.record(&processor, transaction).unwrap();
+        }
+        let settlements: Vec<_> = tracker.settlements().cloned().collect();
+        assert_eq!(
+            settlements,
+            [Settlement {
+                client_id,
+                deposits: Price4::new(15, 0),
+                withdrawals: Price4::new(3, 0),
+                net: Price4::new(12, 0),
+                held: Price4::new(15, 0),
+                released: Price4::new(9, 0),
+                charged_back: Price4::new(6, 0),
+            }]
+        );
+    }
+}
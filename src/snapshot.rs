@@ -0,0 +1,300 @@
+use crate::TransactionProcessor;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::{Read, Write};
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// The version of the snapshot format written by this release. Bump it whenever
+/// a change to the processor state changes the format, and add a migration from
+/// the previous version to `MIGRATIONS`.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// A function upgrading a snapshot, as a JSON object, by one format version.
+type Migration = fn(&mut Map<String, Value>) -> Result<(), String>;
+
+/// `MIGRATIONS[n]` upgrades a snapshot from version `n` to version `n + 1`.
+const MIGRATIONS: [Migration; SNAPSHOT_VERSION as usize] = [migrate_v0_to_v1];
+
+/// Version 0 snapshots had no version field, but otherwise the same format.
+fn migrate_v0_to_v1(_snapshot: &mut Map<String, Value>) -> Result<(), String> {
+    Ok(())
+}
+
+/// A point-in-time copy of the processor state, along with how far into the
+/// input it got. A run can be resumed from a snapshot by skipping the first
+/// `records_read` records of the same input.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The number of input records read so far, counted across all input files
+    /// in order. This includes records that failed to parse or process.
+    pub records_read: u64,
+    pub processor: TransactionProcessor,
+}
+
+/// Adds the format version to a serialized snapshot.
+#[derive(Serialize)]
+struct Versioned<'a, T> {
+    version: u32,
+    #[serde(flatten)]
+    snapshot: &'a T,
+}
+
+/// Serializes like `Snapshot`, but borrows the processor so a snapshot can be
+/// written in the middle of a run.
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    records_read: u64,
+    processor: &'a TransactionProcessor,
+}
+
+#[derive(Error, Debug)]
+pub enum SnapshotError {
+    #[error("could not access snapshot {0:?}: {1}")]
+    Io(PathBuf, std::io::Error),
+    #[error("malformed snapshot: {0}")]
+    Malformed(#[from] serde_json::Error),
+    #[error(
+        "snapshot version {0} is newer than the supported version {supported}",
+        supported = SNAPSHOT_VERSION
+    )]
+    UnsupportedVersion(u64),
+    #[error("could not migrate snapshot from version {version}: {reason}")]
+    Migration { version: u32, reason: String },
+    #[error("newer snapshot is not a later state of the same input: {0}")]
+    Unrelated(String),
+    #[error("delta applies to a snapshot after {expected} records, not {actual}")]
+    DeltaMismatch { expected: u64, actual: u64 },
+    #[error("could not compute delta: {0}")]
+    Diff(crate::Error),
+    #[error("could not apply delta: {0}")]
+    Apply(crate::Error),
+}
+
+impl Snapshot {
+    /// Reads a snapshot, migrating it first if it was written in an older format.
+    pub fn read<R: Read>(reader: R) -> Result<Snapshot, SnapshotError> {
+        let mut snapshot: Map<String, Value> = serde_json::from_reader(reader)?;
+        let version = match snapshot.get("version") {
+            None => 0,
+            Some(version) => u64::deserialize(version)?,
+        };
+        if version > SNAPSHOT_VERSION as u64 {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        for (version, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migrate(&mut snapshot).map_err(|reason| SnapshotError::Migration {
+                version: version as u32,
+                reason,
+            })?;
+        }
+        Ok(serde_json::from_value(Value::Object(snapshot))?)
+    }
+
+    pub fn write<W: Write>(&self, writer: W) -> Result<(), SnapshotError> {
+        write_versioned(writer, self)
+    }
+
+    pub fn load(path: &Path) -> Result<Snapshot, SnapshotError> {
+        let file = std::fs::File::open(path).map_err(|e| SnapshotError::Io(path.to_owned(), e))?;
+        Snapshot::read(std::io::BufReader::new(file))
+    }
+
+    /// Writes the snapshot to `path`. The snapshot is written to a temporary file
+    /// first and then renamed, so an existing snapshot is never left half-written.
+    pub fn save(&self, path: &Path) -> Result<(), SnapshotError> {
+        save(self, path)
+    }
+}
+
+pub(crate) fn write_versioned<W: Write, T: Serialize>(
+    writer: W,
+    snapshot: &T,
+) -> Result<(), SnapshotError> {
+    let versioned = Versioned {
+        version: SNAPSHOT_VERSION,
+        snapshot,
+    };
+    Ok(serde_json::to_writer(writer, &versioned)?)
+}
+
+pub(crate) fn save<T: Serialize>(snapshot: &T, path: &Path) -> Result<(), SnapshotError> {
+    let io_error = |e| SnapshotError::Io(path.to_owned(), e);
+    let mut tmp_path = path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut writer = std::io::BufWriter::new(std::fs::File::create(&tmp_path).map_err(io_error)?);
+    write_versioned(&mut writer, snapshot)?;
+    writer.flush().map_err(io_error)?;
+    std::fs::rename(&tmp_path, path).map_err(io_error)
+}
+
+/// Periodically writes snapshots of a running processor to a directory as
+/// `checkpoint-<records read>.json`, keeping only the newest few.
+pub struct Checkpointer {
+    dir: PathBuf,
+    every_records: Option<NonZeroU64>,
+    interval: Option<Duration>,
+    keep: usize,
+    /// The number of records read and the time of the last checkpoint.
+    last: (u64, Instant),
+}
+
+impl Checkpointer {
+    /// Creates a checkpointer that writes a checkpoint once `every_records` more
+    /// records have been read, or `interval` has passed, whichever comes first.
+    /// Keeps the newest `keep` checkpoints in `dir`, and at least one.
+    pub fn new(
+        dir: PathBuf,
+        every_records: Option<NonZeroU64>,
+        interval: Option<Duration>,
+        keep: usize,
+        records_read: u64,
+    ) -> Checkpointer {
+        Checkpointer {
+            dir,
+            every_records,
+            interval,
+            keep: keep.max(1),
+            last: (records_read, Instant::now()),
+        }
+    }
+
+    /// Whether a checkpoint should be written after `records_read` records.
+    pub fn is_due(&self, records_read: u64) -> bool {
+        let (last_records_read, last_time) = self.last;
+        if records_read <= last_records_read {
+            return false;
+        }
+        let records_due = self
+            .every_records
+            .is_some_and(|every| records_read - last_records_read >= every.get());
+        let time_due = self
+            .interval
+            .is_some_and(|interval| last_time.elapsed() >= interval);
+        records_due || time_due
+    }
+
+    /// Writes a checkpoint of `processor` after `records_read` records and removes
+    /// older checkpoints beyond the ones to keep. Returns the checkpoint's path.
+    pub fn write(
+        &mut self,
+        records_read: u64,
+        processor: &TransactionProcessor,
+    ) -> Result<PathBuf, SnapshotError> {
+        self.last = (records_read, Instant::now());
+        let path = self.dir.join(format!("checkpoint-{}.json", records_read));
+        let snapshot = SnapshotRef {
+            records_read,
+            processor,
+        };
+        save(&snapshot, &path)?;
+
+        let io_error = |e| SnapshotError::Io(self.dir.clone(), e);
+        let mut checkpoints = Vec::new();
+        for entry in std::fs::read_dir(&self.dir).map_err(io_error)? {
+            let entry = entry.map_err(io_error)?;
+            let file_name = entry.file_name();
+            let records_read = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix("checkpoint-"))
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|records_read| records_read.parse::<u64>().ok());
+            if let Some(records_read) = records_read {
+                checkpoints.push((records_read, entry.path()));
+            }
+        }
+        checkpoints.sort();
+        let stale = checkpoints.len().saturating_sub(self.keep);
+        for (_, stale_path) in checkpoints.drain(..stale) {
+            std::fs::remove_file(&stale_path).map_err(|e| SnapshotError::Io(stale_path, e))?;
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, Deposit, Dispute, Price4, TransactionId};
+
+    #[test]
+    fn test_round_trip() {
+        let mut processor = TransactionProcessor::new();
+        let client_id: ClientId = serde_json::from_str("7").unwrap();
+        let tx_id: TransactionId = serde_json::from_str("3").unwrap();
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(15, 1),
+            })
+            .unwrap();
+        processor
+            .process_dispute(Dispute { client_id, tx_id })
+            .unwrap();
+        let snapshot = Snapshot {
+            records_read: 2,
+            processor,
+        };
+
+        let mut buf = Vec::new();
+        snapshot.write(&mut buf).unwrap();
+        let restored = Snapshot::read(buf.as_slice()).unwrap();
+        assert_eq!(restored.records_read, 2);
+        let account = &restored.processor.accounts()[&client_id];
+        assert_eq!(account.held_funds(), Price4::new(15, 1));
+        // The restored transaction history still allows resolving the dispute.
+        let mut processor = restored.processor;
+        assert!(processor
+            .process_resolve(crate::Resolve {
+                client_id,
+                tx_id,
+                amount: None,
+            })
+            .is_ok());
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut buf = Vec::new();
+        Snapshot::default().write(&mut buf).unwrap();
+        let written: Value = serde_json::from_slice(&buf).unwrap();
+        assert_eq!(written["version"], SNAPSHOT_VERSION);
+
+        // Unversioned snapshots are migrated from version 0.
+        let v0 = r#"{"records_read":4,"processor":{"accounts":{}}}"#;
+        assert_eq!(Snapshot::read(v0.as_bytes()).unwrap().records_read, 4);
+
+        let future = r#"{"version":99,"records_read":4,"processor":{"accounts":{}}}"#;
+        assert!(matches!(
+            Snapshot::read(future.as_bytes()),
+            Err(SnapshotError::UnsupportedVersion(99))
+        ));
+    }
+
+    #[test]
+    fn test_checkpointer_keeps_newest() {
+        let dir = std::env::temp_dir().join(format!("checkpoint-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let every_records = NonZeroU64::new(2);
+        let mut checkpointer = Checkpointer::new(dir.clone(), every_records, None, 2, 0);
+        let processor = TransactionProcessor::new();
+        for records_read in 1..=7 {
+            if checkpointer.is_due(records_read) {
+                checkpointer.write(records_read, &processor).unwrap();
+            }
+        }
+        let mut files: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, vec!["checkpoint-4.json", "checkpoint-6.json"]);
+        let restored = Snapshot::load(&dir.join("checkpoint-6.json")).unwrap();
+        assert_eq!(restored.records_read, 6);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
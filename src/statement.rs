@@ -0,0 +1,258 @@
+use crate::{ClientId, Deposit, Price4, Timestamp, Transaction, TransactionId, Withdrawal};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum StatementError {
+    #[error("could not read statement {0}: {1}")]
+    Io(PathBuf, #[source] std::io::Error),
+    #[error("statement {0} must be a .ofx, .qfx or .qif file")]
+    UnknownFormat(PathBuf),
+    #[error("malformed statement: {0}")]
+    Malformed(String),
+}
+
+/// A transaction of a bank statement: a credit if `amount` is positive, a debit
+/// otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatementEntry {
+    /// Derived from the entry, so reading the same statement again gives the same
+    /// ids.
+    pub tx_id: TransactionId,
+    /// The start of the day the entry was posted, in UTC.
+    pub timestamp: Timestamp,
+    pub amount: Price4,
+}
+
+impl StatementEntry {
+    /// A deposit of a credit, or a withdrawal of a debit, into client `client_id`'s
+    /// account.
+    pub fn to_transaction(&self, client_id: ClientId) -> Transaction {
+        let tx_id = self.tx_id;
+        if self.amount.is_sign_negative() {
+            Transaction::Withdrawal(Withdrawal {
+                client_id,
+                tx_id,
+                amount: -self.amount,
+            })
+        } else {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: self.amount,
+            })
+        }
+    }
+}
+
+/// Derives a transaction id from the hash of `key`. Distinct keys collide with a
+/// probability of about 1 in 2^32 per pair.
+pub(crate) fn derive_tx_id(key: &str) -> TransactionId {
+    let hash = Sha256::digest(key.as_bytes());
+    TransactionId(u32::from_be_bytes([hash[0], hash[1], hash[2], hash[3]]))
+}
+
+fn parse_amount(amount: &str) -> Result<Price4, StatementError> {
+    amount
+        .trim()
+        .replace(',', "")
+        .parse()
+        .map_err(|_| StatementError::Malformed(format!("invalid amount `{}`", amount)))
+}
+
+/// Reads the transactions of an OFX (or QFX) statement, in SGML or XML syntax.
+/// Their ids are derived from their `FITID`s.
+pub fn read_ofx(statement: &str) -> Result<Vec<StatementEntry>, StatementError> {
+    let mut entries = Vec::new();
+    // The fields of the `STMTTRN` element being read, if any.
+    let mut fields: Option<HashMap<String, String>> = None;
+    for element in statement.split('<').skip(1) {
+        let (tag, value) = element.split_once('>').unwrap_or((element, ""));
+        let tag = tag.trim().to_ascii_uppercase();
+        match tag.as_str() {
+            "STMTTRN" => fields = Some(HashMap::new()),
+            "/STMTTRN" => {
+                let fields = fields.take().unwrap_or_default();
+                let field = |name: &str| {
+                    fields.get(name).ok_or_else(|| {
+                        StatementError::Malformed(format!("transaction without {}", name))
+                    })
+                };
+                entries.push(StatementEntry {
+                    tx_id: derive_tx_id(&format!("ofx:{}", field("FITID")?)),
+                    timestamp: parse_ofx_date(field("DTPOSTED")?)?,
+                    amount: parse_amount(field("TRNAMT")?)?,
+                });
+            }
+            _ => {
+                if let Some(fields) = fields.as_mut() {
+                    fields.insert(tag, value.trim().to_string());
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses an OFX date like `20231114` or `20231114120000.000[-5:EST]`, of which
+/// only the day is used.
+fn parse_ofx_date(date: &str) -> Result<Timestamp, StatementError> {
+    let invalid = || StatementError::Malformed(format!("invalid date `{}`", date));
+    let digits = date.get(..8).ok_or_else(invalid)?;
+    let number = |range: std::ops::Range<usize>| {
+        digits
+            .get(range)
+            .and_then(|digits| digits.parse().ok())
+            .ok_or_else(invalid)
+    };
+    Timestamp::from_date(number(0..4)?, number(4..6)? as u32, number(6..8)? as u32)
+        .ok_or_else(invalid)
+}
+
+/// Reads the transactions of a QIF statement. As QIF has no transaction ids, they
+/// are derived from each transaction's date, amount and payee, and how many
+/// transactions with the same ones came before it.
+pub fn read_qif(statement: &str) -> Result<Vec<StatementEntry>, StatementError> {
+    let mut entries = Vec::new();
+    let mut seen: HashMap<String, u32> = HashMap::new();
+    let (mut date, mut amount, mut payee) = (None, None, "");
+    for line in statement.lines().map(str::trim) {
+        let (code, value) = match line.chars().next() {
+            Some(code) => (code, &line[code.len_utf8()..]),
+            None => continue,
+        };
+        match code {
+            'D' => date = Some(value),
+            'T' | 'U' => amount = Some(value),
+            'P' => payee = value,
+            '^' => {
+                let missing = |field| StatementError::Malformed(format!("entry without {}", field));
+                let (date, amount) = (date.take(), amount.take());
+                let (date, amount) = (
+                    date.ok_or_else(|| missing("date"))?,
+                    amount.ok_or_else(|| missing("amount"))?,
+                );
+                let key = format!("qif:{}:{}:{}", date, amount, payee);
+                let count = seen.entry(key.clone()).or_insert(0);
+                *count += 1;
+                entries.push(StatementEntry {
+                    tx_id: derive_tx_id(&format!("{}:{}", key, count)),
+                    timestamp: parse_qif_date(date)?,
+                    amount: parse_amount(amount)?,
+                });
+                payee = "";
+            }
+            _ => {}
+        }
+    }
+    Ok(entries)
+}
+
+/// Parses a QIF date like `11/14/2023`, `11/14'23` or `11/14/23`, in US order.
+/// Two-digit years are in 1970 to 2069.
+fn parse_qif_date(date: &str) -> Result<Timestamp, StatementError> {
+    let invalid = || StatementError::Malformed(format!("invalid date `{}`", date));
+    let parts: Vec<_> = date
+        .split(['/', '\'', '-'])
+        .map(|part| part.trim().parse::<u64>().map_err(|_| invalid()))
+        .collect::<Result<_, _>>()?;
+    let (month, day, year) = match parts[..] {
+        [month, day, year] => (month, day, year),
+        _ => return Err(invalid()),
+    };
+    let year = match year {
+        0..=69 => 2000 + year,
+        70..=99 => 1900 + year,
+        _ => year,
+    };
+    let (month, day) = (u32::try_from(month), u32::try_from(day));
+    match (month, day) {
+        (Ok(month), Ok(day)) => Timestamp::from_date(year, month, day).ok_or_else(invalid),
+        _ => Err(invalid()),
+    }
+}
+
+/// Reads the transactions of an OFX, QFX or QIF statement, depending on its
+/// extension.
+pub fn read_statement(path: &Path) -> Result<Vec<StatementEntry>, StatementError> {
+    let statement =
+        std::fs::read_to_string(path).map_err(|e| StatementError::Io(path.to_owned(), e))?;
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    match extension.map(str::to_ascii_lowercase).as_deref() {
+        Some("ofx") | Some("qfx") => read_ofx(&statement),
+        Some("qif") => read_qif(&statement),
+        _ => Err(StatementError::UnknownFormat(path.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_statements() {
+        let ofx = read_ofx(
+            "OFXHEADER:100
+             <OFX><BANKMSGSRSV1><STMTTRNRS><STMTRS><BANKTRANLIST>
+             <STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20231114120000.000[-5:EST]
+             <TRNAMT>1,250.00<FITID>A-1</STMTTRN>
+             <STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20231115<TRNAMT>-42.5<FITID>A-2</STMTTRN>
+             </BANKTRANLIST></STMTRS></STMTTRNRS></BANKMSGSRSV1></OFX>",
+        )
+        .unwrap();
+        assert_eq!(ofx.len(), 2);
+        assert_eq!(ofx[0].timestamp, Timestamp::from_secs(1_699_920_000));
+        assert_eq!(ofx[0].amount, Price4::new(1250, 0));
+        assert_eq!(ofx[0].tx_id, derive_tx_id("ofx:A-1"));
+        let client_id = ClientId(1);
+        assert!(matches!(
+            ofx[1].to_transaction(client_id),
+            Transaction::Withdrawal(Withdrawal { amount, .. }) if amount == Price4::new(425, 1)
+        ));
+
+        let qif = read_qif(
+            "!Type:Bank
+             D11/14/2023
+             T-4.50
+             PCoffee
+             ^
+             D11/14'23
+             T-4.50
+             PCoffee
+             ^",
+        )
+        .unwrap();
+        assert_eq!(qif[0].timestamp, qif[1].timestamp);
+        // Identical entries still get distinct, deterministic ids.
+        assert_ne!(qif[0].tx_id, qif[1].tx_id);
+        assert_eq!(
+            qif[0].tx_id,
+            read_qif("D11/14/2023\nT-4.50\nPCoffee\n^").unwrap()[0].tx_id
+        );
+        assert!(read_qif("D11/14/2023\n^").is_err());
+    }
+
+    #[test]
+    fn test_malformed_dates() {
+        let ofx = |date| {
+            read_ofx(&format!(
+                "<STMTTRN><DTPOSTED>{}<TRNAMT>1<FITID>A-1</STMTTRN>",
+                date
+            ))
+        };
+        assert!(matches!(ofx("2023é114"), Err(StatementError::Malformed(_))));
+        assert!(matches!(ofx("20231314"), Err(StatementError::Malformed(_))));
+        let qif = |date| read_qif(&format!("D{}\nT1\n^", date));
+        assert!(matches!(
+            qif("11/14/18446744073709551615"),
+            Err(StatementError::Malformed(_))
+        ));
+        assert!(matches!(
+            qif("4294967297/14/2023"),
+            Err(StatementError::Malformed(_))
+        ));
+    }
+}
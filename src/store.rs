@@ -0,0 +1,636 @@
+use crate::{
+    is_false, is_zero, Account, BalanceHistory, ClientId, Currency, DailyWithdrawals,
+    FundTransaction, Funds, ManualHold, Price4, Receivable, Risk, Snapshot, Timestamp,
+    TransactionId, TransactionProcessor,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// A key-value store the processor state can be persisted to, so a run survives
+/// restarts without keeping snapshots.
+///
+/// Each account, each of its transactions, the schedule, the closed accounting
+/// periods and the number of records read are stored under separate keys, so
+/// persisting the effect of a record only writes the entries it changed.
+pub trait StateStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError>;
+
+    /// Returns all entries whose key starts with `prefix`, ordered by key.
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<StoreEntry>, StoreError>;
+
+    /// Writes all `entries` atomically: after a crash, either all or none of them
+    /// are stored.
+    fn write_batch(&mut self, entries: Vec<StoreEntry>) -> Result<(), StoreError>;
+
+    /// Makes all written entries durable.
+    fn flush(&mut self) -> Result<(), StoreError> {
+        Ok(())
+    }
+}
+
+/// A key and its value.
+pub type StoreEntry = (Vec<u8>, Vec<u8>);
+
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("state store failed: {0}")]
+    Backend(String),
+    #[error("malformed state store entry: {0}")]
+    Malformed(#[from] serde_json::Error),
+}
+
+const RECORDS_READ_KEY: &[u8] = b"records_read";
+const ACCOUNT_PREFIX: &[u8] = b"account/";
+const TX_PREFIX: &[u8] = b"tx/";
+const SCHEDULE_KEY: &[u8] = b"schedule";
+const PERIODS_KEY: &[u8] = b"periods";
+
+fn account_key(client_id: ClientId) -> Vec<u8> {
+    [ACCOUNT_PREFIX, &client_id.0.to_be_bytes()].concat()
+}
+
+/// Transactions are keyed by client first, so they can be loaded per account.
+fn tx_key(client_id: ClientId, tx_id: TransactionId) -> Vec<u8> {
+    [
+        TX_PREFIX,
+        &client_id.0.to_be_bytes(),
+        &tx_id.0.to_be_bytes(),
+    ]
+    .concat()
+}
+
+/// An account without its transactions, as stored under its account key.
+#[derive(Serialize, Deserialize)]
+struct StoredAccount {
+    funds: Funds,
+    is_frozen: bool,
+    #[serde(default, skip_serializing_if = "Risk::is_default")]
+    risk: Risk,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    holds: BTreeMap<u32, ManualHold>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    daily_withdrawals: Option<DailyWithdrawals>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    receivables: Vec<Receivable>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    balances: BTreeMap<Currency, Price4>,
+    #[serde(default, skip_serializing_if = "BalanceHistory::is_empty")]
+    history: BalanceHistory,
+    #[serde(default, skip_serializing_if = "is_zero")]
+    chargebacks: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_activity: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    dormant: bool,
+}
+
+impl TransactionProcessor {
+    /// Persists the effect of a record on the accounts and transactions it
+    /// touched, and on the schedule and periods if they changed, along with the
+    /// number of records read, in one atomic batch. An account or transaction
+    /// that does not exist (e.g. because the record failed) is not written.
+    pub fn persist(
+        &mut self,
+        store: &mut dyn StateStore,
+        records_read: u64,
+        touched: &[(ClientId, TransactionId)],
+    ) -> Result<(), StoreError> {
+        let mut entries = vec![(
+            RECORDS_READ_KEY.to_vec(),
+            serde_json::to_vec(&records_read)?,
+        )];
+        for (client_id, tx_id) in touched.iter() {
+            let account = match self.accounts.get(*client_id) {
+                Some(account) => account,
+                None => continue,
+            };
+            entries.push(account_entry(*client_id, account)?);
+            if let Some(tx) = account.txs.get(*tx_id) {
+                entries.push((tx_key(*client_id, *tx_id), serde_json::to_vec(&tx)?));
+                // Releasing an escrow changes the payee's account as well.
+                if let Some(payee) = tx.payee {
+                    if let Some(payee_account) = self.accounts.get(payee) {
+                        entries.push(account_entry(payee, payee_account)?);
+                    }
+                }
+            }
+        }
+        if self.schedule_changed {
+            entries.push((SCHEDULE_KEY.to_vec(), serde_json::to_vec(&self.schedule)?));
+        }
+        if self.periods_changed {
+            entries.push((PERIODS_KEY.to_vec(), serde_json::to_vec(&self.periods)?));
+        }
+        store.write_batch(entries)?;
+        self.schedule_changed = false;
+        self.periods_changed = false;
+        Ok(())
+    }
+}
+
+fn account_entry(client_id: ClientId, account: &Account) -> Result<StoreEntry, StoreError> {
+    let stored = StoredAccount {
+        funds: account.funds,
+        is_frozen: account.is_frozen,
+        risk: account.risk,
+        holds: account.holds.clone(),
+        daily_withdrawals: account.daily_withdrawals,
+        receivables: account.receivables.clone(),
+        balances: account.balances.clone(),
+        history: account.history.clone(),
+        chargebacks: account.chargebacks,
+        last_activity: account.last_activity,
+        dormant: account.dormant,
+    };
+    Ok((account_key(client_id), serde_json::to_vec(&stored)?))
+}
+
+impl Snapshot {
+    /// Loads the state persisted in `store`. An empty store gives an empty snapshot.
+    pub fn load_from(store: &dyn StateStore) -> Result<Snapshot, StoreError> {
+        let records_read = match store.get(RECORDS_READ_KEY)? {
+            Some(value) => serde_json::from_slice(&value)?,
+            None => 0,
+        };
+        let mut processor = TransactionProcessor::new();
+        for (key, value) in store.scan_prefix(ACCOUNT_PREFIX)? {
+            let client_id = parse_key(&key[ACCOUNT_PREFIX.len()..])?.0;
+            let stored: StoredAccount = serde_json::from_slice(&value)?;
+            let account = processor.account_mut(client_id);
+            account.funds = stored.funds;
+            account.is_frozen = stored.is_frozen;
+            account.risk = stored.risk;
+            account.holds = stored.holds;
+            account.daily_withdrawals = stored.daily_withdrawals;
+            account.receivables = stored.receivables;
+            account.balances = stored.balances;
+            account.history = stored.history;
+            account.chargebacks = stored.chargebacks;
+            account.last_activity = stored.last_activity;
+            account.dormant = stored.dormant;
+        }
+        for (key, value) in store.scan_prefix(TX_PREFIX)? {
+            let client_id = parse_key(&key[TX_PREFIX.len()..])?.0;
+            let tx: FundTransaction = serde_json::from_slice(&value)?;
+            processor.track_expiry(client_id, &tx);
+            processor.account_mut(client_id).txs.insert(tx);
+        }
+        if let Some(value) = store.get(SCHEDULE_KEY)? {
+            processor.schedule = serde_json::from_slice(&value)?;
+        }
+        if let Some(value) = store.get(PERIODS_KEY)? {
+            processor.periods = serde_json::from_slice(&value)?;
+        }
+        Ok(Snapshot {
+            records_read,
+            processor,
+        })
+    }
+
+    /// Writes the whole snapshot to `store` in one atomic batch.
+    pub fn save_to(&self, store: &mut dyn StateStore) -> Result<(), StoreError> {
+        let mut entries = vec![(
+            RECORDS_READ_KEY.to_vec(),
+            serde_json::to_vec(&self.records_read)?,
+        )];
+        for (client_id, account) in self.processor.accounts.iter() {
+            entries.push(account_entry(client_id, account)?);
+            for tx in account.txs.transactions() {
+                entries.push((tx_key(client_id, tx.tx_id), serde_json::to_vec(&tx)?));
+            }
+        }
+        if !self.processor.schedule.is_empty() {
+            entries.push((
+                SCHEDULE_KEY.to_vec(),
+                serde_json::to_vec(&self.processor.schedule)?,
+            ));
+        }
+        if !self.processor.periods.is_empty() {
+            entries.push((
+                PERIODS_KEY.to_vec(),
+                serde_json::to_vec(&self.processor.periods)?,
+            ));
+        }
+        store.write_batch(entries)?;
+        store.flush()
+    }
+}
+
+/// Parses the client id and, for transaction keys, the transaction id following
+/// a key's prefix.
+fn parse_key(key: &[u8]) -> Result<(ClientId, TransactionId), StoreError> {
+    let malformed = || StoreError::Backend(format!("malformed key {:?}", key));
+    let client_id = key.get(..2).ok_or_else(malformed)?;
+    let client_id = ClientId(u16::from_be_bytes([client_id[0], client_id[1]]));
+    let tx_id = match key.get(2..) {
+        Some([a, b, c, d]) => u32::from_be_bytes([*a, *b, *c, *d]),
+        Some([]) => 0,
+        _ => return Err(malformed()),
+    };
+    Ok((client_id, TransactionId(tx_id)))
+}
+
+/// Keeps the state in memory only, e.g. for tests.
+#[derive(Default)]
+pub struct MemoryStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        Ok(self.entries.get(key).cloned())
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        Ok(self
+            .entries
+            .range(prefix.to_vec()..)
+            .take_while(|(key, _)| key.starts_with(prefix))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect())
+    }
+
+    fn write_batch(&mut self, entries: Vec<StoreEntry>) -> Result<(), StoreError> {
+        self.entries.extend(entries);
+        Ok(())
+    }
+}
+
+/// Persists the state in a sled database.
+#[cfg(feature = "sled")]
+pub struct SledStore {
+    db: sled::Db,
+}
+
+#[cfg(feature = "sled")]
+impl SledStore {
+    pub fn open(path: &std::path::Path) -> Result<SledStore, StoreError> {
+        let db = sled::open(path).map_err(backend_error)?;
+        Ok(SledStore { db })
+    }
+}
+
+#[cfg(feature = "sled")]
+impl StateStore for SledStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let value = self.db.get(key).map_err(backend_error)?;
+        Ok(value.map(|value| value.to_vec()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        self.db
+            .scan_prefix(prefix)
+            .map(|entry| {
+                let (key, value) = entry.map_err(backend_error)?;
+                Ok((key.to_vec(), value.to_vec()))
+            })
+            .collect()
+    }
+
+    fn write_batch(&mut self, entries: Vec<StoreEntry>) -> Result<(), StoreError> {
+        let mut batch = sled::Batch::default();
+        for (key, value) in entries {
+            batch.insert(key, value);
+        }
+        self.db.apply_batch(batch).map_err(backend_error)
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        self.db.flush().map(|_| ()).map_err(backend_error)
+    }
+}
+
+/// Persists the state in a RocksDB database.
+#[cfg(feature = "rocksdb")]
+pub struct RocksDbStore {
+    db: rocksdb::DB,
+}
+
+#[cfg(feature = "rocksdb")]
+impl RocksDbStore {
+    pub fn open(path: &std::path::Path) -> Result<RocksDbStore, StoreError> {
+        let db = rocksdb::DB::open_default(path).map_err(backend_error)?;
+        Ok(RocksDbStore { db })
+    }
+}
+
+#[cfg(feature = "rocksdb")]
+impl StateStore for RocksDbStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        self.db.get(key).map_err(backend_error)
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        let mut entries = Vec::new();
+        for entry in self.db.prefix_iterator(prefix) {
+            let (key, value) = entry.map_err(backend_error)?;
+            // Without a prefix extractor, the iterator continues past the prefix.
+            if !key.starts_with(prefix) {
+                break;
+            }
+            entries.push((key.to_vec(), value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    fn write_batch(&mut self, entries: Vec<StoreEntry>) -> Result<(), StoreError> {
+        let mut batch = rocksdb::WriteBatch::default();
+        for (key, value) in entries {
+            batch.put(key, value);
+        }
+        self.db.write(batch).map_err(backend_error)
+    }
+
+    fn flush(&mut self) -> Result<(), StoreError> {
+        self.db.flush().map_err(backend_error)
+    }
+}
+
+/// Persists the state in PostgreSQL, with accounts and transactions as rows of
+/// the `accounts` and `transactions` tables so they can be queried and replicated
+/// with standard tooling. Each batch is written in one database transaction.
+#[cfg(feature = "postgres")]
+pub struct PostgresStore {
+    // Queries need a mutable client, while reads only borrow the store.
+    client: std::cell::RefCell<postgres::Client>,
+}
+
+#[cfg(feature = "postgres")]
+const POSTGRES_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS progress (
+        id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+        records_read BIGINT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS accounts (
+        client_id INTEGER PRIMARY KEY,
+        state JSONB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS transactions (
+        client_id INTEGER NOT NULL,
+        tx_id BIGINT NOT NULL,
+        state JSONB NOT NULL,
+        PRIMARY KEY (client_id, tx_id)
+    );
+    CREATE TABLE IF NOT EXISTS schedule (
+        id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+        state JSONB NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS periods (
+        id BOOLEAN PRIMARY KEY DEFAULT TRUE CHECK (id),
+        state JSONB NOT NULL
+    );";
+
+/// The row a store key maps to.
+#[cfg(feature = "postgres")]
+enum Row {
+    Progress,
+    Schedule,
+    Periods,
+    Account(i32),
+    Transaction(i32, i64),
+}
+
+#[cfg(feature = "postgres")]
+impl Row {
+    fn from_key(key: &[u8]) -> Result<Row, StoreError> {
+        if key == RECORDS_READ_KEY {
+            return Ok(Row::Progress);
+        }
+        if key == SCHEDULE_KEY {
+            return Ok(Row::Schedule);
+        }
+        if key == PERIODS_KEY {
+            return Ok(Row::Periods);
+        }
+        if let Some(id) = key.strip_prefix(ACCOUNT_PREFIX) {
+            let (client_id, _) = parse_key(id)?;
+            return Ok(Row::Account(client_id.0.into()));
+        }
+        if let Some(ids) = key.strip_prefix(TX_PREFIX) {
+            let (client_id, tx_id) = parse_key(ids)?;
+            return Ok(Row::Transaction(client_id.0.into(), tx_id.0.into()));
+        }
+        Err(StoreError::Backend(format!("unexpected key {:?}", key)))
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl PostgresStore {
+    /// Connects to the database at `url` (e.g. `postgres://user@host/db`) and
+    /// creates the tables if they do not exist yet.
+    pub fn connect(url: &str) -> Result<PostgresStore, StoreError> {
+        let mut client = postgres::Client::connect(url, postgres::NoTls).map_err(backend_error)?;
+        client
+            .batch_execute(POSTGRES_SCHEMA)
+            .map_err(backend_error)?;
+        Ok(PostgresStore {
+            client: std::cell::RefCell::new(client),
+        })
+    }
+}
+
+#[cfg(feature = "postgres")]
+impl StateStore for PostgresStore {
+    fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>, StoreError> {
+        let mut client = self.client.borrow_mut();
+        let row = match Row::from_key(key)? {
+            Row::Progress => client.query_opt("SELECT records_read::TEXT FROM progress", &[]),
+            Row::Schedule => client.query_opt("SELECT state::TEXT FROM schedule", &[]),
+            Row::Periods => client.query_opt("SELECT state::TEXT FROM periods", &[]),
+            Row::Account(client_id) => client.query_opt(
+                "SELECT state::TEXT FROM accounts WHERE client_id = $1",
+                &[&client_id],
+            ),
+            Row::Transaction(client_id, tx_id) => client.query_opt(
+                "SELECT state::TEXT FROM transactions WHERE client_id = $1 AND tx_id = $2",
+                &[&client_id, &tx_id],
+            ),
+        };
+        let row = row.map_err(backend_error)?;
+        Ok(row.map(|row| row.get::<_, String>(0).into_bytes()))
+    }
+
+    fn scan_prefix(&self, prefix: &[u8]) -> Result<Vec<StoreEntry>, StoreError> {
+        let mut client = self.client.borrow_mut();
+        let rows = match prefix {
+            ACCOUNT_PREFIX => client.query(
+                "SELECT client_id, 0::BIGINT, state::TEXT FROM accounts ORDER BY client_id",
+                &[],
+            ),
+            TX_PREFIX => client.query(
+                "SELECT client_id, tx_id, state::TEXT FROM transactions \
+                 ORDER BY client_id, tx_id",
+                &[],
+            ),
+            _ => {
+                return Err(StoreError::Backend(format!(
+                    "unexpected prefix {:?}",
+                    prefix
+                )))
+            }
+        };
+        rows.map_err(backend_error)?
+            .into_iter()
+            .map(|row| {
+                let client_id: u16 =
+                    std::convert::TryFrom::try_from(row.get::<_, i32>(0)).map_err(backend_error)?;
+                let tx_id: u32 =
+                    std::convert::TryFrom::try_from(row.get::<_, i64>(1)).map_err(backend_error)?;
+                let key = match prefix {
+                    ACCOUNT_PREFIX => account_key(ClientId(client_id)),
+                    _ => tx_key(ClientId(client_id), TransactionId(tx_id)),
+                };
+                Ok((key, row.get::<_, String>(2).into_bytes()))
+            })
+            .collect()
+    }
+
+    fn write_batch(&mut self, entries: Vec<StoreEntry>) -> Result<(), StoreError> {
+        let mut client = self.client.borrow_mut();
+        let mut tx = client.transaction().map_err(backend_error)?;
+        for (key, value) in entries {
+            let value = String::from_utf8(value).map_err(backend_error)?;
+            let result = match Row::from_key(&key)? {
+                Row::Progress => tx.execute(
+                    "INSERT INTO progress (records_read) VALUES ($1::TEXT::BIGINT) \
+                     ON CONFLICT (id) DO UPDATE SET records_read = EXCLUDED.records_read",
+                    &[&value],
+                ),
+                Row::Schedule => tx.execute(
+                    "INSERT INTO schedule (state) VALUES ($1::TEXT::JSONB) \
+                     ON CONFLICT (id) DO UPDATE SET state = EXCLUDED.state",
+                    &[&value],
+                ),
+                Row::Periods => tx.execute(
+                    "INSERT INTO periods (state) VALUES ($1::TEXT::JSONB) \
+                     ON CONFLICT (id) DO UPDATE SET state = EXCLUDED.state",
+                    &[&value],
+                ),
+                Row::Account(client_id) => tx.execute(
+                    "INSERT INTO accounts (client_id, state) VALUES ($1, $2::TEXT::JSONB) \
+                     ON CONFLICT (client_id) DO UPDATE SET state = EXCLUDED.state",
+                    &[&client_id, &value],
+                ),
+                Row::Transaction(client_id, tx_id) => tx.execute(
+                    "INSERT INTO transactions (client_id, tx_id, state) \
+                     VALUES ($1, $2, $3::TEXT::JSONB) \
+                     ON CONFLICT (client_id, tx_id) DO UPDATE SET state = EXCLUDED.state",
+                    &[&client_id, &tx_id, &value],
+                ),
+            };
+            result.map_err(backend_error)?;
+        }
+        tx.commit().map_err(backend_error)
+    }
+}
+
+#[cfg(any(feature = "sled", feature = "rocksdb", feature = "postgres"))]
+fn backend_error<E: std::fmt::Display>(e: E) -> StoreError {
+    StoreError::Backend(e.to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Dispute, Price4, ReasonCode, Timestamp, Transaction};
+
+    #[test]
+    fn test_persist_and_load() {
+        let mut store = MemoryStore::new();
+        let mut processor = TransactionProcessor::new();
+        let (client_id, tx_id) = (ClientId(1), TransactionId(70000));
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(25, 1),
+            })
+            .unwrap();
+        processor
+            .persist(&mut store, 1, &[(client_id, tx_id)])
+            .unwrap();
+        processor
+            .process_dispute(Dispute { client_id, tx_id })
+            .unwrap();
+        processor
+            .persist(&mut store, 2, &[(client_id, tx_id)])
+            .unwrap();
+        // A failed record is only counted.
+        processor
+            .persist(&mut store, 3, &[(ClientId(2), TransactionId(1))])
+            .unwrap();
+        processor
+            .hold(client_id, Price4::new(1, 0), ReasonCode(1))
+            .unwrap();
+        processor
+            .persist(&mut store, 4, &[(client_id, tx_id)])
+            .unwrap();
+        processor.schedule(
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(2),
+                amount: Price4::new(1, 0),
+            }),
+            Timestamp::from_secs(60),
+        );
+        processor.persist(&mut store, 5, &[]).unwrap();
+
+        let loaded = Snapshot::load_from(&store).unwrap();
+        assert_eq!(loaded.records_read, 5);
+        assert_eq!(loaded.processor.scheduled().len(), 1);
+        assert_eq!(
+            serde_json::to_value(&loaded.processor).unwrap(),
+            serde_json::to_value(&processor).unwrap()
+        );
+
+        // Saving a whole snapshot to a fresh store gives the same entries.
+        let mut saved = MemoryStore::new();
+        loaded.save_to(&mut saved).unwrap();
+        assert_eq!(saved.entries, store.entries);
+    }
+
+    /// Tests that the Postgres backend has a row for every key a snapshot writes.
+    #[cfg(feature = "postgres")]
+    #[test]
+    fn test_postgres_rows() {
+        let mut processor = TransactionProcessor::new();
+        let (client_id, tx_id) = (ClientId(1), TransactionId(1));
+        let deposit = |tx_id| {
+            Transaction::Deposit(Deposit {
+                client_id,
+                tx_id,
+                amount: Price4::new(1, 0),
+            })
+        };
+        processor.process(deposit(tx_id)).unwrap();
+        processor.schedule(deposit(TransactionId(2)), Timestamp::from_secs(60));
+        processor.close_period();
+        let snapshot = Snapshot {
+            records_read: 1,
+            processor,
+        };
+        let mut store = MemoryStore::new();
+        snapshot.save_to(&mut store).unwrap();
+        let keys: Vec<_> = store.entries.keys().collect();
+        for key in [RECORDS_READ_KEY, SCHEDULE_KEY, PERIODS_KEY] {
+            assert!(keys.contains(&&key.to_vec()), "{:?} not written", key);
+        }
+        for key in keys {
+            Row::from_key(key).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_empty_store() {
+        let loaded = Snapshot::load_from(&MemoryStore::new()).unwrap();
+        assert_eq!(loaded.records_read, 0);
+        assert!(loaded.processor.accounts().is_empty());
+    }
+}
@@ -0,0 +1,232 @@
+use crate::prelude::*;
+use crate::{
+    calculate_amount, ClientId, Error, FundTransaction, Price4, Side, TransactionId,
+    TransactionProcessor, TransactionState,
+};
+use alloc::collections::BTreeMap;
+use serde::Serialize;
+
+/// The amounts of the transactions with a tag, of one client or of all of them,
+/// for category reports.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagTotal {
+    /// The client, or `None` for the totals of all clients.
+    #[serde(rename = "client")]
+    pub client_id: Option<ClientId>,
+    pub tag: String,
+    /// The number of tagged transactions.
+    pub count: u64,
+    /// The total amount of the tagged deposits.
+    pub deposits: Price4,
+    /// The total amount of the tagged withdrawals.
+    pub withdrawals: Price4,
+}
+
+impl TransactionProcessor {
+    /// Tags the transaction `tx_id` of client `client_id` with the free-form
+    /// `tag`, e.g. `payroll`, `refund` or `fee`, replacing any tag it had, so its
+    /// flows can be segmented. The tag is kept with the transaction and added to
+    /// its audit log entries.
+    /// Returns an error if the transaction id `tx_id` doesn't exist for client
+    /// `client_id`.
+    ///
+    /// This function does not panic.
+    pub fn set_tag(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        tag: String,
+    ) -> Result<(), Error> {
+        let account = self
+            .accounts
+            .get_mut(client_id)
+            .ok_or(Error::InvalidClientId(client_id))?;
+        if !account.txs.contains(tx_id) {
+            return Err(Error::InvalidTx(tx_id));
+        }
+        account
+            .txs
+            .update(tx_id, &mut |tx| tx.tag = Some(tag.clone()));
+        Ok(())
+    }
+
+    /// The transaction `tx_id` of client `client_id`, if it exists.
+    pub fn transaction(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> Option<FundTransaction> {
+        self.accounts
+            .get(client_id)
+            .and_then(|account| account.txs.get(tx_id))
+    }
+
+    /// The tag of the transaction `tx_id` of client `client_id`, if it has one.
+    pub fn transaction_tag(&self, client_id: ClientId, tx_id: TransactionId) -> Option<String> {
+        self.transaction(client_id, tx_id).and_then(|tx| tx.tag)
+    }
+
+    /// The transactions tagged with `tag`, ordered by client and transaction id.
+    pub fn tagged_transactions(&self, tag: &str) -> Vec<(ClientId, FundTransaction)> {
+        let mut txs: Vec<_> = self
+            .accounts
+            .iter()
+            .flat_map(|(client_id, account)| {
+                account
+                    .txs
+                    .transactions()
+                    .filter(|tx| tx.tag() == Some(tag))
+                    .map(move |tx| (client_id, tx))
+            })
+            .collect();
+        txs.sort_by_key(|(client_id, tx)| (*client_id, tx.tx_id));
+        txs
+    }
+
+    /// The amounts of the tagged transactions per client and tag, ordered by
+    /// client and tag, followed by the amounts per tag of all clients, ordered by
+    /// tag. Transactions that were reversed or failed to settle are left out.
+    /// Returns an error if a total overflows.
+    ///
+    /// This function does not panic.
+    pub fn tag_totals(&self) -> Result<Vec<TagTotal>, Error> {
+        let mut per_client = BTreeMap::new();
+        let mut per_tag = BTreeMap::new();
+        for (client_id, account) in self.accounts.iter() {
+            for tx in account.txs.transactions() {
+                if matches!(
+                    tx.state,
+                    TransactionState::Reversed | TransactionState::Failed
+                ) {
+                    continue;
+                }
+                let tag = match tx.tag.as_ref() {
+                    Some(tag) => tag,
+                    None => continue,
+                };
+                for total in [
+                    per_client
+                        .entry((client_id, tag.clone()))
+                        .or_insert_with(|| TagTotal::new(Some(client_id), tag)),
+                    per_tag
+                        .entry(tag.clone())
+                        .or_insert_with(|| TagTotal::new(None, tag)),
+                ] {
+                    total.add(&tx)?;
+                }
+            }
+        }
+        Ok(per_client
+            .into_values()
+            .chain(per_tag.into_values())
+            .collect())
+    }
+}
+
+impl TagTotal {
+    fn new(client_id: Option<ClientId>, tag: &str) -> TagTotal {
+        TagTotal {
+            client_id,
+            tag: tag.to_string(),
+            count: 0,
+            deposits: Price4::ZERO,
+            withdrawals: Price4::ZERO,
+        }
+    }
+
+    fn add(&mut self, tx: &FundTransaction) -> Result<(), Error> {
+        let total = match tx.side {
+            Side::Deposit => &mut self.deposits,
+            Side::Withdrawal => &mut self.withdrawals,
+        };
+        *total = calculate_amount(*total, Side::Deposit, tx.amount)?;
+        self.count += 1;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, Price4, Withdrawal};
+
+    #[test]
+    fn test_tags() {
+        let mut processor = TransactionProcessor::new();
+        for client in [2, 1] {
+            let client_id = ClientId(client);
+            processor
+                .process_deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId(client.into()),
+                    amount: Price4::new(10, 0),
+                })
+                .unwrap();
+            processor
+                .set_tag(
+                    client_id,
+                    TransactionId(client.into()),
+                    "payroll".to_string(),
+                )
+                .unwrap();
+        }
+        let client_id = ClientId(1);
+        processor
+            .process_withdrawal(Withdrawal {
+                client_id,
+                tx_id: TransactionId(3),
+                amount: Price4::new(2, 0),
+            })
+            .unwrap();
+        processor
+            .set_tag(client_id, TransactionId(3), "fees".to_string())
+            .unwrap();
+        assert_eq!(
+            processor.transaction_tag(client_id, TransactionId(3)),
+            Some("fees".to_string())
+        );
+        let tagged: Vec<_> = processor
+            .tagged_transactions("payroll")
+            .iter()
+            .map(|(client_id, tx)| (*client_id, tx.tx_id()))
+            .collect();
+        assert_eq!(
+            tagged,
+            [
+                (ClientId(1), TransactionId(1)),
+                (ClientId(2), TransactionId(2))
+            ]
+        );
+        assert!(matches!(
+            processor.set_tag(client_id, TransactionId(4), "fees".to_string()),
+            Err(Error::InvalidTx(_))
+        ));
+
+        let totals: Vec<_> = processor
+            .tag_totals()
+            .unwrap()
+            .into_iter()
+            .map(|total| (total.client_id, total.tag, total.count, total.deposits))
+            .collect();
+        assert_eq!(
+            totals,
+            [
+                (Some(ClientId(1)), "fees".to_string(), 1, Price4::ZERO),
+                (
+                    Some(ClientId(1)),
+                    "payroll".to_string(),
+                    1,
+                    Price4::new(10, 0)
+                ),
+                (
+                    Some(ClientId(2)),
+                    "payroll".to_string(),
+                    1,
+                    Price4::new(10, 0)
+                ),
+                (None, "fees".to_string(), 1, Price4::ZERO),
+                (None, "payroll".to_string(), 2, Price4::new(20, 0)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+use crate::{Operation, TransactionProcessor};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::time::{Duration, Instant};
+
+/// The number of buckets of a `LatencyHistogram`. Bucket `i` holds latencies
+/// below 2^i nanoseconds, and the last one all latencies of about a second or more.
+const BUCKETS: usize = 32;
+
+/// Counts latencies in buckets of powers of two nanoseconds, so recording one is
+/// cheap and quantiles are accurate to a factor of two.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    total: Duration,
+    max: Duration,
+}
+
+impl LatencyHistogram {
+    pub fn record(&mut self, latency: Duration) {
+        let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (64 - nanos.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.total += latency;
+        self.max = self.max.max(latency);
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            count => self.total / count.min(u32::MAX as u64) as u32,
+        }
+    }
+
+    pub fn max(&self) -> Duration {
+        self.max
+    }
+
+    /// An upper bound of the `q` quantile, e.g. of the 99th percentile for 0.99.
+    pub fn quantile(&self, q: f64) -> Duration {
+        let rank = ((q * self.count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = Duration::from_nanos((1u64 << bucket) - 1);
+                return upper.min(self.max);
+            }
+        }
+        self.max
+    }
+}
+
+/// The processing latencies of the transactions passed to
+/// `TransactionProcessor::process`, per operation, and their throughput.
+#[derive(Debug, Clone, Default)]
+pub struct Telemetry {
+    latencies: HashMap<Operation, LatencyHistogram>,
+    /// When the first transaction started and the last one ended processing.
+    first: Option<Instant>,
+    last: Option<Instant>,
+}
+
+impl Telemetry {
+    /// Records that a transaction of `operation` was processed from `started` until
+    /// now.
+    pub(crate) fn record(&mut self, operation: Operation, started: Instant) {
+        let now = Instant::now();
+        self.latencies
+            .entry(operation)
+            .or_default()
+            .record(now - started);
+        self.first.get_or_insert(started);
+        self.last = Some(now);
+    }
+
+    /// The latencies of each operation processed, ordered by operation.
+    pub fn latencies(&self) -> Vec<(Operation, &LatencyHistogram)> {
+        let mut latencies: Vec<_> = self
+            .latencies
+            .iter()
+            .map(|(operation, histogram)| (*operation, histogram))
+            .collect();
+        latencies.sort_by_key(|(operation, _)| operation.to_string());
+        latencies
+    }
+
+    /// The number of transactions processed.
+    pub fn count(&self) -> u64 {
+        self.latencies.values().map(LatencyHistogram::count).sum()
+    }
+
+    /// The transactions processed per second, from the start of the first to the
+    /// end of the last, including the time between them.
+    pub fn throughput(&self) -> Option<f64> {
+        let elapsed = self.last?.duration_since(self.first?).as_secs_f64();
+        Some(self.count() as f64 / elapsed).filter(|throughput| throughput.is_finite())
+    }
+}
+
+impl TransactionProcessor {
+    /// The latencies and throughput of the transactions processed so far.
+    pub fn telemetry(&self) -> &Telemetry {
+        &self.telemetry
+    }
+
+    /// Starts the telemetry over, e.g. to measure each phase of a run.
+    pub fn reset_telemetry(&mut self) {
+        self.telemetry = Telemetry::default();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, Deposit, Price4, Transaction, TransactionId};
+
+    #[test]
+    fn test_telemetry() {
+        let mut histogram = LatencyHistogram::default();
+        for nanos in [100, 200, 300, 5000].iter() {
+            histogram.record(Duration::from_nanos(*nanos));
+        }
+        assert_eq!(histogram.count(), 4);
+        assert_eq!(histogram.mean(), Duration::from_nanos(1400));
+        assert_eq!(histogram.quantile(0.5), Duration::from_nanos(255));
+        // 300ns is in the bucket of 256ns to 511ns.
+        assert_eq!(histogram.quantile(0.75), Duration::from_nanos(511));
+        assert_eq!(histogram.quantile(1.0), Duration::from_nanos(5000));
+
+        let mut processor = TransactionProcessor::new();
+        for tx_id in 1..=3 {
+            processor
+                .process(Transaction::Deposit(Deposit {
+                    client_id: ClientId(1),
+                    tx_id: TransactionId(tx_id),
+                    amount: Price4::new(1, 0),
+                }))
+                .unwrap();
+        }
+        let latencies = processor.telemetry().latencies();
+        assert_eq!(latencies.len(), 1);
+        assert_eq!(latencies[0].0, Operation::Deposit);
+        assert_eq!(processor.telemetry().count(), 3);
+        processor.reset_telemetry();
+        assert!(processor.telemetry().throughput().is_none());
+    }
+}
@@ -0,0 +1,376 @@
+//! Helpers for tests of code using this crate, in the style of the tests of the
+//! `transactions` binary: feed an input file as a string, then compare the
+//! accounts and errors it results in with a snapshot, or inspect the processor,
+//! or build a sequence of transactions with `Scenario` and check the accounts
+//! after each step.
+
+use crate::{Chargeback, ClientId, Deposit, Dispute, Error, Resolve, TransactionId, Withdrawal};
+use crate::{Price4, Transaction, TransactionProcessor, TransactionRecord};
+use core::convert::TryFrom;
+use core::str::FromStr;
+use serde::Serialize;
+use std::fmt::Write;
+
+/// The result of running an input file through a processor.
+pub struct RunOutput {
+    /// The accounts, as written by the binary: a csv file with the columns
+    /// `client,available,held,total,locked`, ordered by client.
+    pub output: String,
+    /// One line for each record that could not be read or processed.
+    pub errors: String,
+    /// The processor, after processing all records.
+    pub processor: TransactionProcessor,
+}
+
+impl RunOutput {
+    /// The accounts followed by the errors, in the format of the snapshots of
+    /// the binary's tests.
+    pub fn snapshot(&self) -> String {
+        format!("{}Stderr:\n{}", self.output, self.errors)
+    }
+}
+
+#[derive(Serialize)]
+struct AccountRow {
+    client: u16,
+    available: Price4,
+    held: Price4,
+    total: Price4,
+    locked: bool,
+}
+
+/// Processes the records of the input file `input` with a new processor, like
+/// `run_csv_with`.
+pub fn run_csv(input: &str) -> RunOutput {
+    run_csv_with(TransactionProcessor::new(), input)
+}
+
+/// Processes the records of the input file `input` with `processor`. Fields
+/// are trimmed, so the input can be indented to line up with the test's code.
+/// Records that cannot be read or processed are reported in the errors as the
+/// binary does, and don't stop the run.
+///
+/// This function does not panic.
+pub fn run_csv_with(mut processor: TransactionProcessor, input: &str) -> RunOutput {
+    let mut errors = String::new();
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(input.as_bytes());
+    for record in reader.deserialize::<TransactionRecord>() {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) => {
+                let _ = writeln!(errors, "deserialize failed: {}", e);
+                continue;
+            }
+        };
+        let result = Transaction::try_from(&record)
+            .and_then(|tx| processor.process(tx))
+            .and_then(|()| match record.tag.clone() {
+                Some(tag) => processor.set_tag(record.client_id, record.tx_id, tag),
+                None => Ok(()),
+            });
+        if let Err(e) = result {
+            let _ = writeln!(errors, "failed to process `{}`: {}", record, e);
+        }
+    }
+    RunOutput {
+        output: accounts_csv(&processor),
+        errors,
+        processor,
+    }
+}
+
+/// The accounts of `processor` as a csv file with the columns
+/// `client,available,held,total,locked`, ordered by client.
+pub fn accounts_csv(processor: &TransactionProcessor) -> String {
+    let mut rows: Vec<_> = processor
+        .accounts()
+        .iter()
+        .map(|(client_id, account)| AccountRow {
+            client: client_id.0,
+            available: account.available_funds(),
+            held: account.held_funds(),
+            total: account.total_funds(),
+            locked: account.is_frozen(),
+        })
+        .collect();
+    rows.sort_by_key(|row| row.client);
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows.iter() {
+        writer.serialize(row).expect("accounts serialize");
+    }
+    String::from_utf8(writer.into_inner().expect("write to a Vec")).expect("csv is UTF-8")
+}
+
+/// A sequence of transactions processed one by one, with checks of the accounts
+/// between them:
+///
+/// ```
+/// use transactions::testing::Scenario;
+///
+/// Scenario::new()
+///     .deposit(1, 5, "2.0")
+///     .dispute(1, 5)
+///     .expect_held(1, "2.0")
+///     .withdrawal(1, 6, "1.0")
+///     .expect_error("insufficient_funds")
+///     .chargeback(1, 5)
+///     .expect_total(1, "0")
+///     .expect_locked(1, true);
+/// ```
+///
+/// A transaction that fails must be followed by `expect_error`, or the next
+/// step panics, as does dropping the scenario.
+pub struct Scenario {
+    processor: TransactionProcessor,
+    /// The last transaction, if it failed and `expect_error` did not check it yet.
+    failed: Option<(Transaction, Error)>,
+}
+
+impl Default for Scenario {
+    fn default() -> Scenario {
+        Scenario::new()
+    }
+}
+
+impl Scenario {
+    /// A scenario on a new processor.
+    pub fn new() -> Scenario {
+        Scenario::with_processor(TransactionProcessor::new())
+    }
+
+    /// A scenario on `processor`, e.g. one from a `ProcessorBuilder` with the
+    /// policies under test.
+    pub fn with_processor(processor: TransactionProcessor) -> Scenario {
+        Scenario {
+            processor,
+            failed: None,
+        }
+    }
+
+    /// Processes `transaction`.
+    #[track_caller]
+    pub fn process(mut self, transaction: Transaction) -> Scenario {
+        self.check_failed();
+        if let Err(e) = self.processor.process(transaction) {
+            self.failed = Some((transaction, e));
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn deposit(self, client: u16, tx: u32, amount: &str) -> Scenario {
+        self.process(Transaction::Deposit(Deposit {
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+            amount: price(amount),
+        }))
+    }
+
+    #[track_caller]
+    pub fn withdrawal(self, client: u16, tx: u32, amount: &str) -> Scenario {
+        self.process(Transaction::Withdrawal(Withdrawal {
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+            amount: price(amount),
+        }))
+    }
+
+    #[track_caller]
+    pub fn dispute(self, client: u16, tx: u32) -> Scenario {
+        self.process(Transaction::Dispute(Dispute {
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+        }))
+    }
+
+    /// Resolves the whole disputed amount of the transaction.
+    #[track_caller]
+    pub fn resolve(self, client: u16, tx: u32) -> Scenario {
+        self.process(Transaction::Resolve(Resolve {
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+            amount: None,
+        }))
+    }
+
+    #[track_caller]
+    pub fn chargeback(self, client: u16, tx: u32) -> Scenario {
+        self.process(Transaction::Chargeback(Chargeback {
+            client_id: ClientId(client),
+            tx_id: TransactionId(tx),
+        }))
+    }
+
+    /// Checks that the last transaction failed with the error with code `code`,
+    /// e.g. `insufficient_funds`.
+    #[track_caller]
+    pub fn expect_error(mut self, code: &str) -> Scenario {
+        match self.failed.take() {
+            Some((_, e)) if e.code() == code => {}
+            Some((transaction, e)) => panic!(
+                "expected `{:?}` to fail with {}, but it failed with {}: {}",
+                transaction,
+                code,
+                e.code(),
+                e
+            ),
+            None => panic!("expected the last transaction to fail with {}", code),
+        }
+        self
+    }
+
+    #[track_caller]
+    pub fn expect_available(self, client: u16, amount: &str) -> Scenario {
+        self.expect_funds(client, "available", amount, crate::Account::available_funds)
+    }
+
+    #[track_caller]
+    pub fn expect_held(self, client: u16, amount: &str) -> Scenario {
+        self.expect_funds(client, "held", amount, crate::Account::held_funds)
+    }
+
+    #[track_caller]
+    pub fn expect_total(self, client: u16, amount: &str) -> Scenario {
+        self.expect_funds(client, "total", amount, crate::Account::total_funds)
+    }
+
+    /// Checks whether the account of client `client` is frozen.
+    #[track_caller]
+    pub fn expect_locked(mut self, client: u16, locked: bool) -> Scenario {
+        self.check_failed();
+        let account = self.account(client);
+        assert_eq!(account.is_frozen(), locked, "locked of client {}", client);
+        self
+    }
+
+    /// The processor, after the transactions so far.
+    #[track_caller]
+    pub fn processor(mut self) -> TransactionProcessor {
+        self.check_failed();
+        core::mem::take(&mut self.processor)
+    }
+
+    #[track_caller]
+    fn expect_funds(
+        mut self,
+        client: u16,
+        funds: &str,
+        amount: &str,
+        get: fn(&crate::Account) -> Price4,
+    ) -> Scenario {
+        self.check_failed();
+        assert_eq!(
+            get(self.account(client)),
+            price(amount),
+            "{} funds of client {}",
+            funds,
+            client
+        );
+        self
+    }
+
+    #[track_caller]
+    fn account(&self, client: u16) -> &crate::Account {
+        self.processor
+            .accounts()
+            .get(ClientId(client))
+            .unwrap_or_else(|| panic!("client {} has no account", client))
+    }
+
+    /// Panics if the last transaction failed and that was not expected.
+    #[track_caller]
+    fn check_failed(&mut self) {
+        if let Some((transaction, e)) = self.failed.take() {
+            panic!("`{:?}` failed: {}", transaction, e);
+        }
+    }
+}
+
+impl Drop for Scenario {
+    fn drop(&mut self) {
+        if !std::thread::panicking() {
+            self.check_failed();
+        }
+    }
+}
+
+/// Parses `amount`, panicking if it is not a valid amount.
+#[track_caller]
+fn price(amount: &str) -> Price4 {
+    Price4::from_str(amount).unwrap_or_else(|e| panic!("invalid amount `{}`: {}", amount, e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_csv() {
+        let run = run_csv(
+            "
+            type,       client, tx, amount
+            deposit,    2, 1, 1.5
+            deposit,    1, 2, 2.0
+            withdrawal, 1, 3, 3.0
+            dispute,    2, 1,
+            bogus,      1, 4, 1.0",
+        );
+        assert_eq!(
+            run.output,
+            "client,available,held,total,locked\n1,2,0,2,false\n2,0.0,1.5,1.5,false\n"
+        );
+        let errors: Vec<_> = run.errors.lines().collect();
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("failed to process `withdrawal client=1 tx=3 amount=3`"));
+        assert!(errors[1].starts_with("deserialize failed: "));
+        assert!(run
+            .snapshot()
+            .ends_with(&format!("Stderr:\n{}", run.errors)));
+        let account = run.processor.accounts().get(ClientId(2)).unwrap();
+        assert_eq!(account.held_funds(), Price4::new(15, 1));
+    }
+
+    #[test]
+    fn test_scenario() {
+        let processor = Scenario::new()
+            .deposit(1, 5, "2.0")
+            .deposit(2, 6, "1.5")
+            .dispute(1, 5)
+            .expect_available(1, "0")
+            .expect_held(1, "2.0")
+            .withdrawal(1, 7, "1.0")
+            .expect_error("insufficient_funds")
+            .resolve(1, 5)
+            .expect_available(1, "2.0")
+            .withdrawal(1, 7, "0.5")
+            .expect_total(1, "1.5")
+            .dispute(2, 6)
+            .chargeback(2, 6)
+            .expect_total(2, "0")
+            .expect_locked(2, true)
+            .expect_locked(1, false)
+            .processor();
+        assert_eq!(processor.accounts().iter().count(), 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "failed: ")]
+    fn test_scenario_unexpected_error() {
+        Scenario::new().withdrawal(1, 1, "1.0").deposit(1, 2, "1.0");
+    }
+
+    #[test]
+    #[should_panic(expected = "failed: ")]
+    fn test_scenario_unchecked_error() {
+        Scenario::new().deposit(1, 1, "1.0").dispute(1, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "held funds of client 1")]
+    fn test_scenario_expectation() {
+        Scenario::new().deposit(1, 1, "1.0").expect_held(1, "1.0");
+    }
+}
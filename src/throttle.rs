@@ -0,0 +1,150 @@
+use crate::Timestamp;
+use std::num::NonZeroU32;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Limits how many records per second are handed to the processor.
+pub struct Throttle {
+    /// The minimum time between two records.
+    interval: Duration,
+    /// The earliest time the next record may be processed.
+    next: Option<Instant>,
+}
+
+impl Throttle {
+    /// Creates a throttle that allows at most `records_per_sec` records per second.
+    pub fn new(records_per_sec: NonZeroU32) -> Throttle {
+        Throttle {
+            interval: Duration::from_secs(1) / records_per_sec.get(),
+            next: None,
+        }
+    }
+
+    /// Blocks until the next record may be processed.
+    pub fn wait(&mut self) {
+        let now = Instant::now();
+        let next = match self.next {
+            Some(next) if next > now => {
+                std::thread::sleep(next - now);
+                next
+            }
+            _ => now,
+        };
+        self.next = Some(next + self.interval);
+    }
+}
+
+/// How fast timestamped records are replayed relative to their original timing.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ReplaySpeed {
+    /// Records are processed as fast as possible, ignoring their timestamps.
+    #[default]
+    Max,
+    /// The gaps between record timestamps are replayed divided by this factor,
+    /// e.g. `10.0` replays ten times faster than the records originally arrived.
+    Multiplier(f64),
+}
+
+impl FromStr for ReplaySpeed {
+    type Err = String;
+
+    /// Parses `max`, or a positive multiplier with an optional `x` suffix (e.g. `10x`).
+    fn from_str(s: &str) -> Result<ReplaySpeed, String> {
+        if s == "max" {
+            return Ok(ReplaySpeed::Max);
+        }
+        let multiplier: f64 = s
+            .strip_suffix('x')
+            .unwrap_or(s)
+            .parse()
+            .map_err(|_| format!("expected `max` or a multiplier like `10x`, got `{}`", s))?;
+        if !multiplier.is_finite() || multiplier <= 0.0 {
+            return Err(format!("multiplier must be positive, got `{}`", s));
+        }
+        Ok(ReplaySpeed::Multiplier(multiplier))
+    }
+}
+
+/// Paces timestamped records so they are processed with (a multiple of) the same
+/// gaps they originally had.
+pub struct ReplayPacer {
+    multiplier: f64,
+    /// The timestamp of the first record and when it was processed.
+    origin: Option<(Timestamp, Instant)>,
+}
+
+impl ReplayPacer {
+    /// Returns `None` for `ReplaySpeed::Max`, since no pacing is needed.
+    pub fn new(speed: ReplaySpeed) -> Option<ReplayPacer> {
+        match speed {
+            ReplaySpeed::Max => None,
+            ReplaySpeed::Multiplier(multiplier) => Some(ReplayPacer {
+                multiplier,
+                origin: None,
+            }),
+        }
+    }
+
+    /// Blocks until the record with `timestamp` is due. Records with a timestamp
+    /// before the first record are not delayed.
+    pub fn wait(&mut self, timestamp: Timestamp) {
+        let (origin_timestamp, origin_instant) = *self
+            .origin
+            .get_or_insert_with(|| (timestamp, Instant::now()));
+        let delay = self.delay(origin_timestamp, timestamp);
+        let remaining = delay.saturating_sub(origin_instant.elapsed());
+        if !remaining.is_zero() {
+            std::thread::sleep(remaining);
+        }
+    }
+
+    /// How long after the first record, with `origin`, the record with
+    /// `timestamp` is due. A delay too long to represent saturates.
+    fn delay(&self, origin: Timestamp, timestamp: Timestamp) -> Duration {
+        let gap = timestamp.as_secs().saturating_sub(origin.as_secs());
+        Duration::try_from_secs_f64(gap as f64 / self.multiplier).unwrap_or(Duration::MAX)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_throttle_spaces_records() {
+        let mut throttle = Throttle::new(NonZeroU32::new(200).unwrap());
+        let start = Instant::now();
+        for _ in 0..5 {
+            throttle.wait();
+        }
+        // The first record is not delayed, the other four are 5ms apart.
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_replay_speed_from_str() {
+        assert_eq!("max".parse(), Ok(ReplaySpeed::Max));
+        assert_eq!("10x".parse(), Ok(ReplaySpeed::Multiplier(10.0)));
+        assert_eq!("0.5".parse(), Ok(ReplaySpeed::Multiplier(0.5)));
+        assert!("0x".parse::<ReplaySpeed>().is_err());
+        assert!("fast".parse::<ReplaySpeed>().is_err());
+    }
+
+    #[test]
+    fn test_replay_pacer_keeps_gaps() {
+        let mut pacer = ReplayPacer::new(ReplaySpeed::Multiplier(1000.0)).unwrap();
+        let start = Instant::now();
+        pacer.wait(Timestamp::from_secs(100));
+        pacer.wait(Timestamp::from_secs(90));
+        pacer.wait(Timestamp::from_secs(120));
+        // 20 seconds of original gap replayed 1000 times faster.
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn test_replay_pacer_saturates() {
+        let pacer = ReplayPacer::new("1e-300x".parse().unwrap()).unwrap();
+        let delay = pacer.delay(Timestamp::from_secs(100), Timestamp::from_secs(101));
+        assert_eq!(delay, Duration::MAX);
+    }
+}
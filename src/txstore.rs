@@ -0,0 +1,254 @@
+use crate::memory::{hash_map_bytes, vec_bytes};
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{FundTransaction, TransactionId};
+use core::fmt;
+use core::ops::Deref;
+use serde::de::Deserializer;
+use serde::ser::Serializer;
+use serde::Deserialize;
+
+/// Stores the transactions of an account by their ids, e.g. in memory, in a
+/// compact form, or on disk. Transactions are returned by value, so stores do
+/// not need to keep them in memory.
+pub trait TransactionStore: Send {
+    fn get(&self, tx_id: TransactionId) -> Option<FundTransaction>;
+
+    fn contains(&self, tx_id: TransactionId) -> bool {
+        self.get(tx_id).is_some()
+    }
+
+    /// Inserts `tx`, returning the transaction with the same id it replaces.
+    fn insert(&mut self, tx: FundTransaction) -> Option<FundTransaction>;
+
+    /// Changes the transaction `tx_id` with `update`, returning whether it exists.
+    fn update(
+        &mut self,
+        tx_id: TransactionId,
+        update: &mut dyn FnMut(&mut FundTransaction),
+    ) -> bool {
+        match self.get(tx_id) {
+            Some(mut tx) => {
+                update(&mut tx);
+                self.insert(tx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// All transactions, in no particular order.
+    fn transactions(&self) -> Box<dyn Iterator<Item = FundTransaction> + '_>;
+
+    /// A store with the same transactions, for copies of the account.
+    fn clone_store(&self) -> Box<dyn TransactionStore>;
+
+    /// An estimate of the heap memory used, for `memory_stats`. Zero by default,
+    /// e.g. for stores on disk.
+    fn heap_bytes(&self) -> usize {
+        0
+    }
+}
+
+impl Clone for Box<dyn TransactionStore> {
+    fn clone(&self) -> Self {
+        self.clone_store()
+    }
+}
+
+/// Formats a store as a map of its transactions by id, in id order.
+impl fmt::Debug for dyn TransactionStore + '_ {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut txs: Vec<_> = self.transactions().collect();
+        txs.sort_by_key(|tx| tx.tx_id);
+        f.debug_map()
+            .entries(txs.iter().map(|tx| (tx.tx_id, tx)))
+            .finish()
+    }
+}
+
+/// Stores are equal if they have the same transactions, however they store
+/// them.
+impl PartialEq for dyn TransactionStore + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .transactions()
+                .all(|tx| other.get(tx.tx_id).as_ref() == Some(&tx))
+    }
+}
+
+/// Creates the transaction store of each new account.
+pub type TransactionStoreFactory = Box<dyn Fn() -> Box<dyn TransactionStore>>;
+
+/// Keeps transactions in a hash map, the default.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransactionStore {
+    txs: HashMap<TransactionId, FundTransaction>,
+}
+
+impl TransactionStore for MemoryTransactionStore {
+    fn get(&self, tx_id: TransactionId) -> Option<FundTransaction> {
+        self.txs.get(&tx_id).cloned()
+    }
+
+    fn contains(&self, tx_id: TransactionId) -> bool {
+        self.txs.contains_key(&tx_id)
+    }
+
+    fn insert(&mut self, tx: FundTransaction) -> Option<FundTransaction> {
+        self.txs.insert(tx.tx_id, tx)
+    }
+
+    fn update(
+        &mut self,
+        tx_id: TransactionId,
+        update: &mut dyn FnMut(&mut FundTransaction),
+    ) -> bool {
+        self.txs.get_mut(&tx_id).map(update).is_some()
+    }
+
+    fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = FundTransaction> + '_> {
+        Box::new(self.txs.values().cloned())
+    }
+
+    fn clone_store(&self) -> Box<dyn TransactionStore> {
+        Box::new(self.clone())
+    }
+
+    fn heap_bytes(&self) -> usize {
+        hash_map_bytes(&self.txs)
+            + self
+                .txs
+                .values()
+                .map(FundTransaction::heap_bytes)
+                .sum::<usize>()
+    }
+}
+
+/// Keeps transactions in a vector sorted by id, which takes less memory than a
+/// hash map but makes inserting transactions out of order slower.
+#[derive(Debug, Clone, Default)]
+pub struct SortedTransactionStore {
+    txs: Vec<FundTransaction>,
+}
+
+impl SortedTransactionStore {
+    fn position(&self, tx_id: TransactionId) -> Result<usize, usize> {
+        self.txs.binary_search_by_key(&tx_id, |tx| tx.tx_id)
+    }
+}
+
+impl TransactionStore for SortedTransactionStore {
+    fn get(&self, tx_id: TransactionId) -> Option<FundTransaction> {
+        let index = self.position(tx_id).ok()?;
+        Some(self.txs[index].clone())
+    }
+
+    fn contains(&self, tx_id: TransactionId) -> bool {
+        self.position(tx_id).is_ok()
+    }
+
+    fn insert(&mut self, tx: FundTransaction) -> Option<FundTransaction> {
+        match self.position(tx.tx_id) {
+            Ok(index) => Some(core::mem::replace(&mut self.txs[index], tx)),
+            Err(index) => {
+                self.txs.insert(index, tx);
+                None
+            }
+        }
+    }
+
+    fn update(
+        &mut self,
+        tx_id: TransactionId,
+        update: &mut dyn FnMut(&mut FundTransaction),
+    ) -> bool {
+        match self.position(tx_id) {
+            Ok(index) => {
+                update(&mut self.txs[index]);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    fn transactions(&self) -> Box<dyn Iterator<Item = FundTransaction> + '_> {
+        Box::new(self.txs.iter().cloned())
+    }
+
+    fn clone_store(&self) -> Box<dyn TransactionStore> {
+        Box::new(self.clone())
+    }
+
+    fn heap_bytes(&self) -> usize {
+        vec_bytes(&self.txs)
+            + self
+                .txs
+                .iter()
+                .map(FundTransaction::heap_bytes)
+                .sum::<usize>()
+    }
+}
+
+/// Serializes a store as a map of its transactions by id.
+pub(crate) fn serialize<S, T>(store: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Deref<Target = dyn TransactionStore>,
+{
+    serializer.collect_map(store.transactions().map(|tx| (tx.tx_id, tx)))
+}
+
+/// Deserializes a map of transactions by id into a `MemoryTransactionStore`.
+pub(crate) fn deserialize<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Box<dyn TransactionStore>, D::Error> {
+    let txs = HashMap::deserialize(deserializer)?;
+    Ok(Box::new(MemoryTransactionStore { txs }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{ClientId, Deposit, Price4, Transaction, TransactionProcessor};
+
+    #[test]
+    fn test_transaction_stores() {
+        let mut processor = TransactionProcessor::new();
+        processor.set_transaction_store(Box::new(|| Box::new(SortedTransactionStore::default())));
+        for tx_id in [3, 1, 2, 1].iter() {
+            let _ = processor.process(Transaction::Deposit(Deposit {
+                client_id: ClientId(1),
+                tx_id: TransactionId(*tx_id),
+                amount: Price4::new(1, 0),
+            }));
+        }
+        let account = &processor.accounts()[&ClientId(1)];
+        assert_eq!(account.total_funds(), Price4::new(3, 0));
+        let ids: Vec<_> = account.txs.transactions().map(|tx| tx.tx_id).collect();
+        assert_eq!(ids, [TransactionId(1), TransactionId(2), TransactionId(3)]);
+
+        // Serialized stores read back as hash maps.
+        let json = serde_json::to_string(account).unwrap();
+        let account: crate::Account = serde_json::from_str(&json).unwrap();
+        assert_eq!(account.txs.len(), 3);
+        assert!(account.txs.contains(TransactionId(2)));
+        assert!(account.txs.heap_bytes() > 0);
+        assert_eq!(&account, &processor.accounts()[&ClientId(1)]);
+    }
+}
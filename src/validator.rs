@@ -0,0 +1,279 @@
+use crate::prelude::*;
+use crate::HashMap;
+use crate::{Account, ClientId, Price4, Transaction};
+use thiserror::Error;
+
+/// Decides whether a transaction is allowed on an account, before the processor
+/// applies it.
+///
+/// The processor first checks that the transaction is well-formed for the
+/// account's history (e.g. its transaction id is unused, or the disputed
+/// transaction exists and is in the right state), then runs its validators in the
+/// order they were added, and applies the transaction if all of them accept it.
+/// The account is the state before the transaction; a deposit or withdrawal for a
+/// new client gets an empty account.
+pub trait TransactionValidator {
+    fn validate(&self, account: &Account, transaction: &Transaction) -> Result<(), RejectReason>;
+}
+
+/// Why a validator rejected a transaction, with the values that led to it so the
+/// outcome can be explained.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RejectReason {
+    #[error("invalid price provided: amount {amount:.4} is negative")]
+    NegativeAmount { amount: Price4 },
+    #[error("invalid price provided: requested {requested:.4} > available {available:.4}")]
+    InsufficientFunds {
+        requested: Price4,
+        available: Price4,
+    },
+    #[error(
+        "requested {requested:.4} would take available {available:.4} below the minimum \
+         balance {minimum:.4}"
+    )]
+    BelowMinimumBalance {
+        requested: Price4,
+        available: Price4,
+        minimum: Price4,
+    },
+    #[error("account is frozen")]
+    AccountFrozen,
+    #[error("account is dormant")]
+    AccountDormant,
+    #[error("dispute would take held funds to {held:.4}")]
+    NegativeHeldFunds { held: Price4 },
+    #[error("fee {fee:.4} exceeds the deposited amount {amount:.4}")]
+    FeeExceedsAmount { fee: Price4, amount: Price4 },
+    /// A rejection by a rule of a `RuleSet`, with the conditions that matched,
+    /// e.g. `amount 150.0000 >= 100.0000`.
+    #[error("rejected by rule `{name}`: {trace}")]
+    Rule { name: String, trace: String },
+    /// A rejection by a custom validator.
+    #[error("{0}")]
+    Custom(String),
+}
+
+impl RejectReason {
+    /// A stable name of the reason, e.g. `account_frozen`, like `Error::code`.
+    pub fn code(&self) -> &'static str {
+        match self {
+            RejectReason::NegativeAmount { .. } => "negative_amount",
+            RejectReason::InsufficientFunds { .. } => "insufficient_funds",
+            RejectReason::BelowMinimumBalance { .. } => "below_minimum_balance",
+            RejectReason::AccountFrozen => "account_frozen",
+            RejectReason::AccountDormant => "account_dormant",
+            RejectReason::NegativeHeldFunds { .. } => "negative_held_funds",
+            RejectReason::FeeExceedsAmount { .. } => "fee_exceeds_amount",
+            RejectReason::Rule { .. } => "rule_rejected",
+            RejectReason::Custom(_) => "rejected",
+        }
+    }
+}
+
+/// Rejects deposits and withdrawals of negative amounts.
+pub struct NonNegativeAmount;
+
+impl TransactionValidator for NonNegativeAmount {
+    fn validate(&self, _account: &Account, transaction: &Transaction) -> Result<(), RejectReason> {
+        match transaction.amount() {
+            Some(amount) if amount < Price4::ZERO => Err(RejectReason::NegativeAmount { amount }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects withdrawals, and escrows, of more than the available funds. Deposits
+/// are still allowed when the available funds are negative.
+pub struct SufficientFunds;
+
+impl TransactionValidator for SufficientFunds {
+    fn validate(&self, account: &Account, transaction: &Transaction) -> Result<(), RejectReason> {
+        let requested = match transaction {
+            Transaction::Withdrawal(withdrawal) => withdrawal.amount,
+            Transaction::EscrowOpen(open) => open.amount,
+            _ => return Ok(()),
+        };
+        // An overflow is left to the processor to report.
+        let available = account.available_funds();
+        match available.checked_sub(requested) {
+            Some(available_funds) if available_funds < Price4::ZERO => {
+                Err(RejectReason::InsufficientFunds {
+                    requested,
+                    available,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects withdrawals, and escrows, that would take the available funds below a
+/// minimum balance, e.g. for products that require a maintenance balance. The
+/// processor validates withdrawals including their fee.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MinimumBalance {
+    /// The minimum of clients without their own.
+    pub default: Option<Price4>,
+    /// The minimums of specific clients, which take precedence over the default.
+    pub per_client: HashMap<ClientId, Price4>,
+}
+
+impl MinimumBalance {
+    /// The minimum balance of `client_id`, if it has one.
+    pub fn minimum(&self, client_id: ClientId) -> Option<Price4> {
+        self.per_client.get(&client_id).copied().or(self.default)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.default.is_none() && self.per_client.is_empty()
+    }
+}
+
+impl TransactionValidator for MinimumBalance {
+    fn validate(&self, account: &Account, transaction: &Transaction) -> Result<(), RejectReason> {
+        let requested = match transaction {
+            Transaction::Withdrawal(withdrawal) => withdrawal.amount,
+            Transaction::EscrowOpen(open) => open.amount,
+            _ => return Ok(()),
+        };
+        let minimum = match self.minimum(transaction.client_id()) {
+            Some(minimum) => minimum,
+            None => return Ok(()),
+        };
+        // An overflow is left to the processor to report.
+        let available = account.available_funds();
+        match available.checked_sub(requested) {
+            Some(available_funds) if available_funds < minimum => {
+                Err(RejectReason::BelowMinimumBalance {
+                    requested,
+                    available,
+                    minimum,
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects all transactions on frozen accounts.
+pub struct NotFrozen;
+
+impl TransactionValidator for NotFrozen {
+    fn validate(&self, account: &Account, _transaction: &Transaction) -> Result<(), RejectReason> {
+        if account.is_frozen() {
+            return Err(RejectReason::AccountFrozen);
+        }
+        Ok(())
+    }
+}
+
+/// The validators a new processor starts with.
+pub(crate) fn builtin_validators() -> Vec<Box<dyn TransactionValidator>> {
+    vec![
+        Box::new(NonNegativeAmount),
+        Box::new(NotFrozen),
+        Box::new(SufficientFunds),
+    ]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Deposit, TransactionId, TransactionProcessor, Withdrawal};
+
+    /// Rejects withdrawals above a fixed amount.
+    struct MaxWithdrawal(Price4);
+
+    impl TransactionValidator for MaxWithdrawal {
+        fn validate(
+            &self,
+            _account: &Account,
+            transaction: &Transaction,
+        ) -> Result<(), RejectReason> {
+            match transaction {
+                Transaction::Withdrawal(withdrawal) if withdrawal.amount > self.0 => Err(
+                    RejectReason::Custom(format!("withdrawals are limited to {}", self.0)),
+                ),
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_custom_validator() {
+        let mut processor = TransactionProcessor::new();
+        processor.add_validator(Box::new(MaxWithdrawal(Price4::new(5, 0))));
+        let client_id = ClientId(1);
+        processor
+            .process_deposit(Deposit {
+                client_id,
+                tx_id: TransactionId(1),
+                amount: Price4::new(10, 0),
+            })
+            .unwrap();
+        let withdrawal = |tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        let err = processor.process_withdrawal(withdrawal(2, 6)).unwrap_err();
+        assert_eq!(err.to_string(), "withdrawals are limited to 5");
+        assert_eq!(err.code(), "rejected");
+        assert!(processor.process_withdrawal(withdrawal(3, 5)).is_ok());
+        assert!(processor.process_withdrawal(withdrawal(4, 4)).is_ok());
+        // The built-in checks still apply.
+        let err = processor.process_withdrawal(withdrawal(5, 2)).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Rejected(RejectReason::InsufficientFunds { .. })
+        ));
+        // The rejection explains itself.
+        assert_eq!(
+            err.to_string(),
+            "invalid price provided: requested 2.0000 > available 1.0000"
+        );
+        assert_eq!(err.code(), "insufficient_funds");
+        assert!(matches!(
+            processor.process_withdrawal(withdrawal(6, -1)),
+            Err(crate::Error::Rejected(RejectReason::NegativeAmount { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_minimum_balance() {
+        let mut processor = TransactionProcessor::new();
+        let (client, other) = (ClientId(1), ClientId(2));
+        processor.add_validator(Box::new(MinimumBalance {
+            default: Some(Price4::new(5, 0)),
+            per_client: core::iter::once((other, Price4::ZERO)).collect(),
+        }));
+        for client_id in [client, other] {
+            processor
+                .process_deposit(Deposit {
+                    client_id,
+                    tx_id: TransactionId(1),
+                    amount: Price4::new(10, 0),
+                })
+                .unwrap();
+        }
+        let withdrawal = |client_id, tx_id, amount| Withdrawal {
+            client_id,
+            tx_id: TransactionId(tx_id),
+            amount: Price4::new(amount, 0),
+        };
+        let err = processor
+            .process_withdrawal(withdrawal(client, 2, 6))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "requested 6.0000 would take available 10.0000 below the minimum balance 5.0000"
+        );
+        assert!(processor
+            .process_withdrawal(withdrawal(client, 3, 5))
+            .is_ok());
+        // A client's own minimum takes precedence over the default.
+        assert!(processor
+            .process_withdrawal(withdrawal(other, 2, 10))
+            .is_ok());
+    }
+}